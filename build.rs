@@ -0,0 +1,9 @@
+fn main() {
+    println!("cargo:rerun-if-changed=proto/kalkul.proto");
+    #[cfg(feature = "grpc")]
+    {
+        // No protoc need be installed.
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+        tonic_build::compile_protos("proto/kalkul.proto").unwrap();
+    }
+}
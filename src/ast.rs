@@ -0,0 +1,287 @@
+use std::ops::Index;
+
+use crate::kalkul::parser::{self, Build};
+use crate::kalkul::{Expr, OpKind, Result, Syntax, Value};
+
+/// Refers to a node of an `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A node of an `Ast`, like an `Expr` but pointing at its operands by id.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Node {
+    Lit(Value),
+    Var(String),
+    Neg(NodeId),
+    Binary(OpKind, [NodeId; 2]),
+    Call(String, Vec<NodeId>),
+    List(Vec<NodeId>),
+    Tuple(Vec<NodeId>),
+}
+
+impl Node {
+    /// The operands, in the order they are written.
+    pub fn children(&self) -> &[NodeId] {
+        match self {
+            Node::Lit(_) | Node::Var(_) => &[],
+            Node::Neg(a)                => std::slice::from_ref(a),
+            Node::Binary(_, operands)   => operands,
+            Node::Call(_, args) | Node::List(args) | Node::Tuple(args) => args,
+        }
+    }
+}
+
+/// An expression kept in a single vector of nodes rather than as a tree
+/// of boxes, which saves an allocation per node and keeps large
+/// generated expressions together in memory. Every node comes after its
+/// operands, so the root is last, a walk in id order never meets a node
+/// before its operands, and the nodes under any one form a contiguous
+/// run ending at it. Nothing here recurses, so expressions too deep for
+/// the evaluator's stack can still be built and walked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ast {
+    nodes: Vec<Node>,
+}
+
+enum Visit<'e> {
+    Enter(&'e Expr),
+    Leave(&'e Expr),
+}
+
+impl Ast {
+    /// Parses `src` straight into nodes, never building an `Expr`.
+    pub fn parse(src: &str, syntax: Syntax) -> Result<Ast> {
+        let mut ast = Ast { nodes: Vec::new() };
+        parser::parse_into(src, syntax, &mut ast)?;
+        Ok(ast)
+    }
+
+    pub fn from_expr(expr: &Expr) -> Ast {
+        let mut nodes = Vec::new();
+        // Ids of finished nodes whose parent is not yet built.
+        let mut done: Vec<NodeId> = Vec::new();
+        let mut stack = vec![Visit::Enter(expr)];
+        while let Some(visit) = stack.pop() {
+            match visit {
+                Visit::Enter(e) => {
+                    stack.push(Visit::Leave(e));
+                    let children: &[Expr] = match e {
+                        Expr::Lit(_) | Expr::Var(_) => &[],
+                        Expr::Neg(a) => std::slice::from_ref(&**a),
+                        Expr::Binary(_, a, b) => {
+                            // Pushed in reverse so that they are left in order.
+                            stack.push(Visit::Enter(b));
+                            stack.push(Visit::Enter(a));
+                            &[]
+                        },
+                        Expr::Call(_, args) | Expr::List(args) | Expr::Tuple(args) => args,
+                    };
+                    stack.extend(children.iter().rev().map(Visit::Enter));
+                },
+                Visit::Leave(e) => {
+                    let mut take = |n: usize| done.split_off(done.len() - n);
+                    let node = match e {
+                        Expr::Lit(v)           => Node::Lit(v.clone()),
+                        Expr::Var(name)        => Node::Var(name.clone()),
+                        Expr::Neg(_)           => Node::Neg(take(1)[0]),
+                        Expr::Binary(kind, ..) => {
+                            let operands = take(2);
+                            Node::Binary(*kind, [operands[0], operands[1]])
+                        },
+                        Expr::Call(name, args) => Node::Call(name.clone(), take(args.len())),
+                        Expr::List(items)      => Node::List(take(items.len())),
+                        Expr::Tuple(items)     => Node::Tuple(take(items.len())),
+                    };
+                    done.push(NodeId(nodes.len() as u32));
+                    nodes.push(node);
+                },
+            }
+        }
+        Ast { nodes }
+    }
+
+    /// Builds the equivalent `Expr`.
+    pub fn to_expr(&self) -> Expr {
+        // Each node is the operand of exactly one other, so each built
+        // operand is moved into its parent once.
+        let mut built: Vec<Option<Expr>> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let mut take = |id: &NodeId| built[id.index()].take().unwrap();
+            let expr = match node {
+                Node::Lit(v)                 => Expr::Lit(v.clone()),
+                Node::Var(name)              => Expr::Var(name.clone()),
+                Node::Neg(a)                 => Expr::Neg(Box::new(take(a))),
+                Node::Binary(kind, [a, b])   => Expr::Binary(*kind, Box::new(take(a)), Box::new(take(b))),
+                Node::Call(name, args)       => Expr::Call(name.clone(), args.iter().map(take).collect()),
+                Node::List(items)            => Expr::List(items.iter().map(take).collect()),
+                Node::Tuple(items)           => Expr::Tuple(items.iter().map(take).collect()),
+            };
+            built.push(Some(expr));
+        }
+        built.pop().flatten().unwrap()
+    }
+
+    fn push(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        NodeId(self.nodes.len() as u32 - 1)
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(self.nodes.len() as u32 - 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Always false, since even a lone literal is a node.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.index()]
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        self.node(id).children()
+    }
+
+    /// Every id, operands before the nodes that use them.
+    pub fn ids(&self) -> impl DoubleEndedIterator<Item = NodeId> + ExactSizeIterator {
+        (0..self.nodes.len() as u32).map(NodeId)
+    }
+
+    /// Every node with its id, in the order of `ids`.
+    pub fn nodes(&self) -> impl DoubleEndedIterator<Item = (NodeId, &Node)> + ExactSizeIterator {
+        self.ids().zip(&self.nodes)
+    }
+
+    /// The ids of `id` and every node under it, in the order of `ids`.
+    pub fn subtree(&self, id: NodeId) -> impl DoubleEndedIterator<Item = NodeId> + ExactSizeIterator {
+        // The first node of a subtree is its leftmost leaf.
+        let mut first = id;
+        while let Some(&child) = self.children(first).first() {
+            first = child;
+        }
+        (first.0..id.0 + 1).map(NodeId)
+    }
+}
+
+/// The parser makes every node from operands it made before, and only
+/// ever combines the latest ones, so the nodes come out in the order an
+/// `Ast` keeps them in.
+impl Build for Ast {
+    type Node = NodeId;
+
+    fn lit(&mut self, val: Value) -> NodeId {
+        self.push(Node::Lit(val))
+    }
+
+    fn var(&mut self, name: String) -> NodeId {
+        self.push(Node::Var(name))
+    }
+
+    fn neg(&mut self, a: NodeId) -> NodeId {
+        self.push(Node::Neg(a))
+    }
+
+    fn binary(&mut self, kind: OpKind, a: NodeId, b: NodeId) -> NodeId {
+        self.push(Node::Binary(kind, [a, b]))
+    }
+
+    fn call(&mut self, name: String, args: Vec<NodeId>) -> NodeId {
+        self.push(Node::Call(name, args))
+    }
+
+    fn list(&mut self, items: Vec<NodeId>) -> NodeId {
+        self.push(Node::List(items))
+    }
+
+    fn tuple(&mut self, items: Vec<NodeId>) -> NodeId {
+        self.push(Node::Tuple(items))
+    }
+
+    fn lit_mut<'n>(&'n mut self, node: &'n mut NodeId) -> Option<&'n mut Value> {
+        match &mut self.nodes[node.index()] {
+            Node::Lit(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+impl Index<NodeId> for Ast {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        self.node(id)
+    }
+}
+
+impl From<&Expr> for Ast {
+    fn from(expr: &Expr) -> Ast {
+        Ast::from_expr(expr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let exprs = [
+            "1 + 2 * 3",
+            "-(x - y) ^ 2",
+            "max(1, [2, 3], (a, b) -> a + b)",
+            "f()",
+            "[]",
+            "42",
+        ];
+        let lens = [5, 6, 12, 1, 1, 1];
+
+        for (src, len) in std::iter::zip(exprs, lens) {
+            let expr = Expr::parse(src).unwrap();
+            let ast = Ast::from_expr(&expr);
+            assert_eq!(len, ast.len(), "{}", src);
+            assert_eq!(expr, ast.to_expr(), "{}", src);
+            assert_eq!(ast, Ast::parse(src, Syntax::default()).unwrap(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_walk() {
+        let ast = Ast::parse("x * x + f(y, 2)", Syntax::default()).unwrap();
+        let vars: Vec<_> = ast.nodes().filter_map(|(_, node)| match node {
+            Node::Var(name) => Some(name.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(["x", "x", "y"], vars.as_slice());
+
+        for id in ast.ids() {
+            assert!(ast.children(id).iter().all(|child| *child < id));
+        }
+        let Node::Binary(OpKind::Plus, [left, right]) = ast[ast.root()] else { panic!() };
+        assert_eq!(3, ast.subtree(left).len());
+        assert_eq!(3, ast.subtree(right).len());
+        assert_eq!(ast.ids().collect::<Vec<_>>(), ast.subtree(ast.root()).collect::<Vec<_>>());
+        let Node::Call(name, args) = &ast[right] else { panic!() };
+        assert_eq!("f", name);
+        assert!(matches!(ast[args[1]], Node::Lit(Value::Int(2))));
+    }
+
+    #[test]
+    fn test_deep() {
+        let src = "-(".repeat(100_000) + "1" + &")".repeat(100_000);
+        let ast = Ast::parse(&src, Syntax::default()).unwrap();
+        assert_eq!(100_001, ast.len());
+        assert!(matches!(ast[ast.root()], Node::Neg(_)));
+    }
+}
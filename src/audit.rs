@@ -0,0 +1,126 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::json::Json;
+use crate::kalkul::{OpKind, Value};
+
+/// What an audited operation did.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum AuditOp {
+    Binary(OpKind),
+    Neg,
+    /// A call to a builtin function.
+    Call(String),
+}
+
+/// One operation performed during evaluation, as handed to an
+/// `AuditSink`. Events carry no times or addresses, so evaluating the
+/// same input in the same state always records the same events.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct AuditEvent {
+    /// Position among the events recorded by the environment, from 0.
+    pub seq: u64,
+    pub op: AuditOp,
+    pub operands: Vec<Value>,
+    /// The value, or the message of the error, produced.
+    pub result: std::result::Result<Value, String>,
+}
+
+impl AuditEvent {
+    pub fn to_json(&self) -> Json {
+        let op = match &self.op {
+            AuditOp::Binary(kind) => Json::str(format!("{:?}", kind)),
+            AuditOp::Neg => Json::str("Neg"),
+            AuditOp::Call(name) => Json::str(format!("call {}", name)),
+        };
+        let result = match &self.result {
+            Ok(val) => ("result", Json::str(val.to_string())),
+            Err(msg) => ("error", Json::str(msg.as_str())),
+        };
+        Json::object([
+            ("seq", Json::Num(self.seq as f64)),
+            ("op", op),
+            ("operands", Json::Array(self.operands.iter().map(|v| Json::str(v.to_string())).collect())),
+            result,
+        ])
+    }
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_json())
+    }
+}
+
+/// Receives every operation an environment performs, once set with
+/// `Environment::set_audit`, for keeping a trace of how results were
+/// calculated.
+pub trait AuditSink: fmt::Debug + Send + Sync {
+    fn record(&self, event: AuditEvent);
+}
+
+/// An audit sink that keeps the events in memory.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    events: Mutex<Vec<AuditEvent>>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        AuditLog::default()
+    }
+
+    /// Returns the events recorded since they were last taken.
+    pub fn take(&self) -> Vec<AuditEvent> {
+        std::mem::take(&mut self.events.lock().unwrap())
+    }
+}
+
+impl AuditSink for AuditLog {
+    fn record(&self, event: AuditEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::env::Environment;
+
+    #[test]
+    fn test_audit() {
+        let log = Arc::new(AuditLog::new());
+        let mut env = Environment::new();
+        env.set_audit(Some(log.clone()));
+        env.set("a", Value::Int(2));
+        env.eval("-sqrt(a * 8) + 1").unwrap();
+
+        let events = log.take();
+        let ops: Vec<_> = events.iter().map(|e| (e.seq, e.op.clone(), e.operands.len())).collect();
+        assert_eq!(vec![
+            (0, AuditOp::Binary(OpKind::Multiply), 2),
+            (1, AuditOp::Call("sqrt".to_string()), 1),
+            (2, AuditOp::Neg, 1),
+            (3, AuditOp::Binary(OpKind::Plus), 2),
+        ], ops);
+        assert_eq!(vec![Value::Int(2), Value::Int(8)], events[0].operands);
+        assert_eq!(Ok(Value::Float(-3.0)), events[3].result);
+        assert_eq!(
+            r#"{"op":"Multiply","operands":["2","8"],"result":"16","seq":0}"#,
+            events[0].to_json().to_string(),
+        );
+
+        assert!(env.eval("1 / 0").is_err());
+        let events = log.take();
+        assert_eq!(4, events[0].seq);
+        assert_eq!(Err("division by zero".to_string()), events[0].result);
+
+        env.set_audit(None);
+        env.eval("1 + 1").unwrap();
+        assert!(log.take().is_empty());
+    }
+}
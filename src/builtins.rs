@@ -0,0 +1,1196 @@
+use crate::audit::AuditOp;
+use crate::clock::Time;
+use crate::env::Environment;
+use crate::finance;
+use crate::format;
+use crate::function::Function;
+use crate::integer;
+use crate::kalkul::{self, Error, Expr, OpKind, Result, Token, Value};
+use crate::net::Addr;
+use crate::numeric;
+use crate::solve;
+use crate::stats;
+use crate::symbolic;
+use crate::units;
+
+type BuiltinFn = fn(&[Value], &mut Environment) -> Result<Value>;
+type FormFn = fn(&[Expr], &mut Environment) -> Result<Value>;
+
+/// A function callable from expressions.
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: usize,
+    func: BuiltinFn,
+}
+
+/// Like a `Builtin`, but receives its arguments unevaluated.
+pub struct Form {
+    pub name: &'static str,
+    pub arity: usize,
+    /// Whether `=` may appear in the arguments, as in `solve(x + 1 = 2, x)`.
+    pub equation: bool,
+    /// Whether a single list may be given instead, as in `sum(1..100)`.
+    pub list: bool,
+    func: FormFn,
+}
+
+impl Form {
+    pub fn call(&self, args: &[Expr], env: &mut Environment) -> Result<Value> {
+        if args.len() != self.arity && !(self.list && args.len() == 1) {
+            return Err(Error::ArgumentCount);
+        }
+        (self.func)(args, env)
+    }
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin { name: "store",      arity: 2, func: store },
+    Builtin { name: "recall",     arity: 1, func: recall },
+    Builtin { name: "sqrt",       arity: 1, func: sqrt },
+    Builtin { name: "exp",        arity: 1, func: exp },
+    Builtin { name: "ln",         arity: 1, func: ln },
+    Builtin { name: "sin",        arity: 1, func: sin },
+    Builtin { name: "cos",        arity: 1, func: cos },
+    Builtin { name: "tan",        arity: 1, func: tan },
+    Builtin { name: "divmod",     arity: 2, func: divmod },
+    Builtin { name: "xor",        arity: 2, func: xor },
+    Builtin { name: "not",        arity: 1, func: not },
+    Builtin { name: "tohex",      arity: 2, func: tohex },
+    Builtin { name: "tobin",      arity: 2, func: tobin },
+    Builtin { name: "signext",    arity: 2, func: signext },
+    Builtin { name: "bit",        arity: 2, func: bit },
+    Builtin { name: "bits",       arity: 3, func: bit_field },
+    Builtin { name: "setbit",     arity: 2, func: setbit },
+    Builtin { name: "popcount",   arity: 1, func: popcount },
+    Builtin { name: "bits",       arity: 1, func: bits },
+    Builtin { name: "frombits",   arity: 1, func: frombits },
+    Builtin { name: "ulp",        arity: 1, func: ulp },
+    Builtin { name: "nextafter",  arity: 2, func: nextafter },
+    Builtin { name: "frac",       arity: 1, func: frac },
+    Builtin { name: "approx",     arity: 2, func: approx },
+    Builtin { name: "scientific", arity: 1, func: scientific },
+    Builtin { name: "dms",        arity: 1, func: dms },
+    Builtin { name: "factorize",  arity: 1, func: factorize },
+    Builtin { name: "divisors",   arity: 1, func: divisors },
+    Builtin { name: "powmod",     arity: 3, func: powmod },
+    Builtin { name: "invmod",     arity: 2, func: invmod },
+    Builtin { name: "map",        arity: 2, func: map },
+    Builtin { name: "filter",     arity: 2, func: filter },
+    Builtin { name: "reduce",     arity: 3, func: reduce },
+    Builtin { name: "num",        arity: 1, func: num },
+    Builtin { name: "str",        arity: 1, func: str },
+    Builtin { name: "parsehex",   arity: 1, func: parsehex },
+    Builtin { name: "base",       arity: 2, func: base },
+    Builtin { name: "tobase",     arity: 2, func: tobase },
+    Builtin { name: "roman",      arity: 1, func: roman },
+    Builtin { name: "toroman",    arity: 1, func: toroman },
+    Builtin { name: "netmask",    arity: 1, func: netmask },
+    Builtin { name: "contains",   arity: 2, func: contains },
+    Builtin { name: "normcdf",    arity: 1, func: normcdf },
+    Builtin { name: "normcdf",    arity: 3, func: normcdf },
+    Builtin { name: "norminv",    arity: 1, func: norminv },
+    Builtin { name: "norminv",    arity: 3, func: norminv },
+    Builtin { name: "binompdf",   arity: 3, func: binompdf },
+    Builtin { name: "poissonpdf", arity: 2, func: poissonpdf },
+    Builtin { name: "growth",     arity: 2, func: growth },
+    Builtin { name: "compound",   arity: 3, func: compound },
+    Builtin { name: "pmt",        arity: 3, func: pmt },
+    Builtin { name: "pmt",        arity: 4, func: pmt },
+    Builtin { name: "fv",         arity: 3, func: fv },
+    Builtin { name: "fv",         arity: 4, func: fv },
+    Builtin { name: "npv",        arity: 2, func: npv },
+    Builtin { name: "irr",        arity: 1, func: irr },
+    Builtin { name: "now",        arity: 0, func: now },
+    Builtin { name: "time",       arity: 1, func: time },
+];
+
+pub const FORMS: &[Form] = &[
+    Form { name: "diff",      arity: 2, equation: false, list: false, func: diff },
+    Form { name: "simplify",  arity: 1, equation: false, list: false, func: simplify },
+    Form { name: "solve",     arity: 2, equation: true,  list: false, func: solve },
+    Form { name: "invert",    arity: 3, equation: false, list: false, func: invert },
+    Form { name: "findroot",  arity: 4, equation: false, list: false, func: findroot },
+    Form { name: "root",      arity: 3, equation: false, list: false, func: root },
+    Form { name: "integrate", arity: 4, equation: false, list: false, func: integrate },
+    Form { name: "sum",       arity: 4, equation: false, list: true,  func: sum },
+    Form { name: "prod",      arity: 4, equation: false, list: true,  func: prod },
+    Form { name: "col",       arity: 1, equation: false, list: false, func: col },
+    Form { name: "equiv",     arity: 2, equation: false, list: false, func: equiv },
+    Form { name: "with",      arity: 2, equation: true,  list: false, func: with },
+    Form { name: "assert",    arity: 1, equation: false, list: false, func: assert },
+    Form { name: "assert_eq", arity: 2, equation: false, list: false, func: assert_eq },
+    Form { name: "help",      arity: 1, equation: false, list: false, func: help },
+    Form { name: "dims",      arity: 1, equation: false, list: false, func: dims },
+];
+
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+/// Builtins may share a name when they take different numbers of
+/// arguments, like `bits(x)` and `bits(x, hi, lo)`.
+fn lookup_arity(name: &str, arity: usize) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name && b.arity == arity).or_else(|| lookup(name))
+}
+
+pub fn lookup_form(name: &str) -> Option<&'static Form> {
+    FORMS.iter().find(|f| f.name == name)
+}
+
+pub fn call(name: &str, args: &[Value], env: &mut Environment) -> Result<Value> {
+    let alias = env.alias_of(name);
+    let name = alias.as_deref().unwrap_or(name);
+    // Compiled programs call aliases of forms with their arguments
+    // already evaluated.
+    if let Some(form) = lookup_form(name) {
+        env.check_allowed(form.name)?;
+        let args: Vec<Expr> = args.iter().cloned().map(Expr::Lit).collect();
+        return form.call(&args, env);
+    }
+    let builtin = match lookup_arity(name, args.len()) {
+        Some(builtin) => builtin,
+        None => match (env.function(name), env.get(name)) {
+            (Some(f), _) => return f.call(args, env),
+            (None, Some(Value::Func(f))) => return f.clone().call(args, env),
+            (None, _) => return Err(env.unknown_function(name)),
+        },
+    };
+    if args.len() != builtin.arity {
+        return Err(Error::ArgumentCount);
+    }
+    env.check_allowed(builtin.name)?;
+    let res = (builtin.func)(args, env);
+    if env.auditing() {
+        env.audit(AuditOp::Call(name.to_string()), args.to_vec(), &res);
+    }
+    res
+}
+
+fn store(args: &[Value], env: &mut Environment) -> Result<Value> {
+    args[0].as_f64()?;
+    env.store(args[1].as_str()?, args[0].clone());
+    Ok(args[0].clone())
+}
+
+fn recall(args: &[Value], env: &mut Environment) -> Result<Value> {
+    Ok(env.recall(args[0].as_str()?))
+}
+
+fn sqrt(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(args[0].as_f64()?.sqrt()))
+}
+
+fn exp(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(args[0].as_f64()?.exp()))
+}
+
+fn ln(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(args[0].as_f64()?.ln()))
+}
+
+fn sin(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(args[0].as_f64()?.sin()))
+}
+
+fn cos(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(args[0].as_f64()?.cos()))
+}
+
+fn tan(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(args[0].as_f64()?.tan()))
+}
+
+/// Floor quotient and remainder, the remainder taking the sign of the
+/// divisor: `divmod(-7, 2)` is `[-4, 1]`. Overflow is handled as the
+/// options say, as for the operators.
+fn divmod(args: &[Value], env: &mut Environment) -> Result<Value> {
+    let (a, b) = (args[0].clone(), args[1].clone());
+    let q = env.apply(OpKind::FloorDivide, a.clone(), b.clone())?;
+    let bq = env.apply(OpKind::Multiply, b, q.clone())?;
+    let r = env.apply(OpKind::Minus, a, bq)?;
+    Ok(Value::Tuple(vec![q, r]))
+}
+
+fn xor(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Int(args[0].as_int()? ^ args[1].as_int()?))
+}
+
+/// Bitwise complement within the current word size, so `not(0)` is 255
+/// for unsigned 8-bit integers.
+fn not(args: &[Value], env: &mut Environment) -> Result<Value> {
+    let n = args[0].as_int()?;
+    let options = env.options();
+    if options.width.signed() {
+        return Ok(Value::Int(!n));
+    }
+    let n = options.overflow.fit(options.width.max() - n as i128, options.width)?;
+    Ok(Value::Int(n.ok_or(Error::Overflow)?))
+}
+
+/// Reads a bit count argument, between 1 and 64.
+fn bits_arg(arg: &Value) -> Result<u32> {
+    match arg.as_int()? {
+        n @ 1..=64 => Ok(n as u32),
+        _ => Err(Error::TypeError),
+    }
+}
+
+/// The two's complement bit pattern of `x` in a word of `bits` bits.
+/// Fails if `x` fits neither as a signed nor as an unsigned number.
+fn bit_pattern(x: i64, bits: u32) -> Result<u64> {
+    let (x, bits) = (x as i128, bits as i128);
+    if x < -(1 << (bits - 1)) || x >= 1 << bits {
+        return Err(Error::Overflow);
+    }
+    Ok((x & ((1 << bits) - 1)) as u64)
+}
+
+/// `tohex(-1, 16)` is `0xFFFF`.
+fn tohex(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let bits = bits_arg(&args[1])?;
+    let pattern = bit_pattern(args[0].as_int()?, bits)?;
+    Ok(Value::Str(format!("0x{:0w$X}", pattern, w = bits.div_ceil(4) as usize)))
+}
+
+/// `tobin(-2, 4)` is `0b1110`.
+fn tobin(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let bits = bits_arg(&args[1])?;
+    let pattern = bit_pattern(args[0].as_int()?, bits)?;
+    Ok(Value::Str(format!("0b{:0w$b}", pattern, w = bits as usize)))
+}
+
+/// Reads the low `bits` bits of `x` as a signed number, so
+/// `signext(0xFF, 8)` is -1.
+fn signext(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let bits = bits_arg(&args[1])?;
+    let shift = 64 - bits;
+    Ok(Value::Int((args[0].as_int()? << shift) >> shift))
+}
+
+/// Reads a bit number argument, from 0 for the lowest bit to 63.
+fn bit_arg(arg: &Value) -> Result<u32> {
+    match arg.as_int()? {
+        n @ 0..=63 => Ok(n as u32),
+        _ => Err(Error::TypeError),
+    }
+}
+
+/// Bit `n` of `x`, 0 or 1.
+fn bit(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Int((args[0].as_int()? >> bit_arg(&args[1])?) & 1))
+}
+
+/// The field from bit `hi` down to bit `lo` of `x`, both included, so
+/// `bits(0xABCD, 11, 4)` is `0xBC`.
+fn bit_field(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let (hi, lo) = (bit_arg(&args[1])?, bit_arg(&args[2])?);
+    if hi < lo {
+        return Err(Error::TypeError);
+    }
+    let mask = (1u64 << (hi - lo) << 1).wrapping_sub(1);
+    Ok(Value::Int(((args[0].as_int()? as u64 >> lo) & mask) as i64))
+}
+
+/// `x` with bit `n` set.
+fn setbit(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Int(args[0].as_int()? | 1 << bit_arg(&args[1])?))
+}
+
+/// Number of bits set in `x`, counting those of a negative number within
+/// the current word size.
+fn popcount(args: &[Value], env: &mut Environment) -> Result<Value> {
+    let bits = env.options().width.bits();
+    let pattern = args[0].as_int()? as i128 & ((1 << bits) - 1);
+    Ok(Value::Int(pattern.count_ones() as i64))
+}
+
+/// The IEEE-754 encoding of `x` as a 64-bit integer, which `tohex(bits(x), 64)`
+/// shows in the usual form.
+fn bits(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Int(args[0].as_f64()?.to_bits() as i64))
+}
+
+/// The float encoded by a bit pattern, undoing `bits`.
+fn frombits(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(f64::from_bits(args[0].as_int()? as u64)))
+}
+
+/// Gap between `|x|` and the next float further from zero.
+fn ulp(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let x = args[0].as_f64()?.abs();
+    Ok(Value::Float(x.next_up() - x))
+}
+
+/// The float next to `a` in the direction of `b`.
+fn nextafter(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let (a, b) = (args[0].as_f64()?, args[1].as_f64()?);
+    let next = if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a < b {
+        a.next_up()
+    } else if a > b {
+        a.next_down()
+    } else {
+        b
+    };
+    Ok(Value::Float(next))
+}
+
+/// Reads a number argument that has to be finite.
+fn finite_arg(arg: &Value) -> Result<f64> {
+    let x = arg.as_f64()?;
+    if x.is_finite() { Ok(x) } else { Err(Error::NotFinite) }
+}
+
+/// The simplest fraction that agrees with `x` to nine significant
+/// digits, as a tuple of numerator and denominator: `frac(0.75)` is
+/// `(3, 4)` and `frac(3.14159265358979)` is `(103993, 33102)`.
+fn frac(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let x = finite_arg(&args[0])?;
+    // Convergents of the continued fraction of x, each the best
+    // approximation with a denominator that small.
+    let (mut p, mut q, mut p_prev, mut q_prev) = (x.floor(), 1.0, 1.0, 0.0);
+    let mut rest = x - x.floor();
+    while (x - p / q).abs() > 1e-9 * x.abs().max(1.0) && q < 1e12 {
+        rest = 1.0 / rest;
+        let a = rest.floor();
+        rest -= a;
+        (p, p_prev) = (a * p + p_prev, p);
+        (q, q_prev) = (a * q + q_prev, q);
+    }
+    if p.abs() > i64::MAX as f64 {
+        return Err(Error::Overflow);
+    }
+    Ok(Value::Tuple(vec![Value::Int(p as i64), Value::Int(q as i64)]))
+}
+
+/// The fraction with the smallest denominator within `tol` of `x`, as a
+/// tuple: `approx(3.14159265, 1e-6)` is `(355, 113)`.
+fn approx(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let x = finite_arg(&args[0])?;
+    let tol = finite_arg(&args[1])?;
+    if tol <= 0.0 {
+        return Err(Error::TypeError);
+    }
+    let within = |p: f64, q: f64| (x - p / q).abs() <= tol;
+    // Between two convergents lie the semiconvergents `(p_prev + j * p) /
+    // (q_prev + j * q)`, closing in on `x` as `j` grows, so the first one
+    // close enough is found by bisection.
+    let (mut p, mut q, mut p_prev, mut q_prev) = (x.floor(), 1.0, 1.0, 0.0);
+    let mut rest = x - x.floor();
+    while !within(p, q) {
+        rest = 1.0 / rest;
+        let a = rest.floor();
+        if a > 1e15 {
+            return Err(Error::Overflow);
+        }
+        rest -= a;
+        let (mut lo, mut hi) = (0.0, a);
+        while hi - lo > 1.0 {
+            let mid = ((lo + hi) / 2.0).floor();
+            if within(p_prev + mid * p, q_prev + mid * q) { hi = mid } else { lo = mid }
+        }
+        (p, p_prev) = (p_prev + hi * p, p);
+        (q, q_prev) = (q_prev + hi * q, q);
+        if q > 1e15 {
+            return Err(Error::Overflow);
+        }
+    }
+    Ok(Value::Tuple(vec![Value::Int(p as i64), Value::Int(q as i64)]))
+}
+
+/// `x` as a mantissa between 1 and 10 and a power of ten, so
+/// `scientific(1234.5)` is `(1.2345, 3)`.
+fn scientific(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let x = finite_arg(&args[0])?;
+    if x == 0.0 {
+        return Ok(Value::Tuple(vec![Value::Float(0.0), Value::Int(0)]));
+    }
+    // Parsing the exponent back avoids the rounding of log10.
+    let text = format!("{:e}", x);
+    let (mantissa, exponent) = text.split_once('e').ok_or(Error::ParseError)?;
+    let mantissa = mantissa.parse().map_err(|_| Error::ParseError)?;
+    let exponent = exponent.parse().map_err(|_| Error::ParseError)?;
+    Ok(Value::Tuple(vec![Value::Float(mantissa), Value::Int(exponent)]))
+}
+
+/// An angle in decimal degrees as whole degrees, whole minutes and
+/// seconds, the sign going on the first of them that is not zero:
+/// `dms(-0.51)` is `(0, -30, 36)`.
+fn dms(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let x = finite_arg(&args[0])?;
+    // Rounded to a nanosecond, so 10.2 degrees has no stray 1e-11
+    // seconds.
+    let total = (x.abs() * 3600.0 * 1e9).round() / 1e9;
+    let degrees = (total / 3600.0).floor();
+    let minutes = ((total - degrees * 3600.0) / 60.0).floor();
+    let seconds = total - degrees * 3600.0 - minutes * 60.0;
+    let mut parts = [degrees, minutes, seconds];
+    if x < 0.0 {
+        if let Some(part) = parts.iter_mut().find(|part| **part != 0.0) {
+            *part = -*part;
+        }
+    }
+    let [degrees, minutes, seconds] = parts;
+    Ok(Value::Tuple(vec![Value::Int(degrees as i64), Value::Int(minutes as i64), Value::Float(seconds)]))
+}
+
+fn int_list(items: Vec<i64>) -> Value {
+    Value::List(items.into_iter().map(Value::Int).collect())
+}
+
+/// `factorize(360)` is `[2, 2, 2, 3, 3, 5]`.
+fn factorize(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(int_list(integer::factorize(args[0].as_int()?)?))
+}
+
+fn divisors(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(int_list(integer::divisors(args[0].as_int()?)?))
+}
+
+/// `powmod(b, e, m)`, `b ^ e` modulo `m`.
+fn powmod(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Int(integer::powmod(args[0].as_int()?, args[1].as_int()?, args[2].as_int()?)?))
+}
+
+/// `invmod(a, m)`, the inverse of `a` modulo `m`.
+fn invmod(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Int(integer::invmod(args[0].as_int()?, args[1].as_int()?)?))
+}
+
+/// Returns the variable named by a form argument such as the `x` in
+/// `diff(expr, x)`.
+fn var_arg(arg: &Expr) -> Result<&str> {
+    match arg {
+        Expr::Var(name) => Ok(name),
+        _ => Err(Error::TypeError),
+    }
+}
+
+/// Wraps a symbolic result, unless it turned out to be a plain number.
+fn symbolic_value(e: Expr) -> Value {
+    match e {
+        Expr::Lit(val) => val,
+        e => Value::Expr(Box::new(e)),
+    }
+}
+
+fn diff(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let expr = symbolic::resolve(&args[0], env)?;
+    let d = symbolic::derivative(&expr, var_arg(&args[1])?)?;
+    Ok(symbolic_value(symbolic::simplify(&d, env)?))
+}
+
+fn solve(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let equation = symbolic::resolve(&args[0], env)?;
+    solve::solve(&equation, var_arg(&args[1])?, env)
+}
+
+fn invert(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let expr = symbolic::resolve(&args[0], env)?;
+    let target = args[2].eval(env)?;
+    solve::invert(&expr, var_arg(&args[1])?, target, env)
+}
+
+fn findroot(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let expr = symbolic::resolve(&args[0], env)?;
+    let a = numeric::number_arg(&args[2], env)?;
+    let b = numeric::number_arg(&args[3], env)?;
+    Ok(Value::Float(numeric::findroot(&expr, var_arg(&args[1])?, a, b, env)?))
+}
+
+/// Shorthand for `findroot` over the variable `x`.
+fn root(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let expr = symbolic::resolve(&args[0], env)?;
+    let a = numeric::number_arg(&args[1], env)?;
+    let b = numeric::number_arg(&args[2], env)?;
+    Ok(Value::Float(numeric::findroot(&expr, "x", a, b, env)?))
+}
+
+fn integrate(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let expr = symbolic::resolve(&args[0], env)?;
+    let a = numeric::number_arg(&args[2], env)?;
+    let b = numeric::number_arg(&args[3], env)?;
+    Ok(Value::Float(numeric::integrate(&expr, var_arg(&args[1])?, a, b, env)?))
+}
+
+fn sum(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    fold_range(args, OpKind::Plus, env)
+}
+
+fn prod(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    fold_range(args, OpKind::Multiply, env)
+}
+
+fn fold_range(args: &[Expr], kind: OpKind, env: &mut Environment) -> Result<Value> {
+    if let [list] = args {
+        let mut acc = Value::Int(if kind == OpKind::Multiply { 1 } else { 0 });
+        for item in list_arg(&list.eval(env)?)? {
+            acc = env.apply(kind, acc, item.clone())?;
+        }
+        return Ok(acc);
+    }
+    let expr = symbolic::resolve(&args[0], env)?;
+    let from = numeric::integer_arg(&args[2], env)?;
+    let to = numeric::integer_arg(&args[3], env)?;
+    numeric::fold_range(&expr, var_arg(&args[1])?, from, to, kind, env)
+}
+
+/// Reads a variable by name. Unlike plain variables, the name may be
+/// given as a string, for CSV columns whose headers are not identifiers.
+fn col(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let name = match &args[0] {
+        Expr::Var(name) => name.as_str(),
+        Expr::Lit(Value::Str(name)) => name.as_str(),
+        _ => return Err(Error::TypeError),
+    };
+    env.get(name).cloned().ok_or_else(|| env.unknown_variable(name))
+}
+
+fn simplify(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let expr = symbolic::resolve(&args[0], env)?;
+    Ok(symbolic_value(symbolic::simplify(&expr, env)?))
+}
+
+/// 1 if both arguments are the same function, else 0.
+fn equiv(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let a = symbolic::resolve(&args[0], env)?;
+    let b = symbolic::resolve(&args[1], env)?;
+    Ok(Value::Int(symbolic::equiv(&a, &b, env)? as i64))
+}
+
+/// Reads a number written the way it would be in an expression, such as
+/// `num("-1.5e3")` or `num("0x1F")`.
+fn num(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let s = match &args[0] {
+        Value::Str(s) => s,
+        Value::Int(_) | Value::Float(_) => return Ok(args[0].clone()),
+        _ => return Err(Error::TypeError),
+    };
+    match kalkul::tokenize(s)?.as_slice() {
+        [Token::Num(n)] => Ok(n.clone()),
+        [Token::Op(OpKind::Minus), Token::Num(n)] => n.clone().negate(),
+        _ => Err(Error::ParseError),
+    }
+}
+
+fn str(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Str(args[0].to_string()))
+}
+
+/// Reads hexadecimal digits, with or without a `0x` in front.
+fn parsehex(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let s = args[0].as_str()?.trim();
+    let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    let n = u64::from_str_radix(digits, 16).map_err(|_| Error::ParseError)?;
+    Ok(Value::Int(i64::try_from(n).map_err(|_| Error::Overflow)?))
+}
+
+/// Reads a radix argument, between 2 and 36.
+fn radix_arg(arg: &Value) -> Result<u32> {
+    match arg.as_int()? {
+        n @ 2..=36 => Ok(n as u32),
+        _ => Err(Error::TypeError),
+    }
+}
+
+/// Reads digits in any base up to 36, so `base(36, "z1")` is 1261.
+fn base(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let radix = radix_arg(&args[0])?;
+    let n = i64::from_str_radix(args[1].as_str()?.trim(), radix).map_err(|_| Error::ParseError)?;
+    Ok(Value::Int(n))
+}
+
+/// `tobase(1261, 36)` is `"z1"`, undoing `base`.
+fn tobase(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Str(format::to_radix(args[0].as_int()?, radix_arg(&args[1])?)))
+}
+
+const NUMERALS: &[(i64, &str)] = &[
+    (1000, "M"), (900, "CM"), (500, "D"), (400, "CD"),
+    (100,  "C"), (90,  "XC"), (50,  "L"), (40,  "XL"),
+    (10,   "X"), (9,   "IX"), (5,   "V"), (4,   "IV"),
+    (1,    "I"),
+];
+
+fn to_roman(mut n: i64) -> String {
+    let mut out = String::new();
+    for (value, numeral) in NUMERALS {
+        while n >= *value {
+            out += numeral;
+            n -= value;
+        }
+    }
+    out
+}
+
+/// Reads a Roman numeral such as `XIV`, in either case. Only numerals
+/// written the usual way are accepted, so `IIII` and `IC` are not.
+fn roman(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let s = args[0].as_str()?.trim().to_ascii_uppercase();
+    let mut rest = s.as_str();
+    let mut n = 0;
+    for (value, numeral) in NUMERALS {
+        while let Some(after) = rest.strip_prefix(numeral) {
+            n += value;
+            rest = after;
+        }
+    }
+    if !rest.is_empty() || n == 0 || to_roman(n) != s {
+        return Err(Error::ParseError);
+    }
+    Ok(Value::Int(n))
+}
+
+/// `toroman(14)` is `"XIV"`, for numbers from 1 to 3999.
+fn toroman(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    match args[0].as_int()? {
+        n @ 1..=3999 => Ok(Value::Str(to_roman(n))),
+        _ => Err(Error::TypeError),
+    }
+}
+
+fn addr_arg(arg: &Value) -> Result<Addr> {
+    match arg {
+        Value::Addr(addr) => Ok(*addr),
+        _ => Err(Error::TypeError),
+    }
+}
+
+/// The netmask of a prefix length, so `netmask(24)` is `255.255.255.0`.
+fn netmask(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Addr(Addr::netmask(args[0].as_int()?)?))
+}
+
+/// 1 if the address or network given second lies in the network given
+/// first, as in `contains(10.0.0.0/8, 10.1.2.3)`.
+fn contains(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Int(addr_arg(&args[0])?.contains(addr_arg(&args[1])?) as i64))
+}
+
+/// Reads the mean and standard deviation of `normcdf` and `norminv`,
+/// which are 0 and 1 unless given.
+fn normal_args(args: &[Value]) -> Result<(f64, f64)> {
+    let [_, mu, sigma] = args else {
+        return Ok((0.0, 1.0));
+    };
+    match (mu.as_f64()?, sigma.as_f64()?) {
+        (mu, sigma) if sigma > 0.0 => Ok((mu, sigma)),
+        _ => Err(Error::TypeError),
+    }
+}
+
+/// Reads a count, a whole number that is not negative.
+fn count_arg(arg: &Value) -> Result<u64> {
+    u64::try_from(arg.as_int()?).map_err(|_| Error::TypeError)
+}
+
+/// `normcdf(x)` for the standard normal distribution, or
+/// `normcdf(x, mu, sigma)`: the probability of a value at most `x`.
+fn normcdf(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let (mu, sigma) = normal_args(args)?;
+    Ok(Value::Float(stats::normcdf((args[0].as_f64()? - mu) / sigma)))
+}
+
+/// The value a normally distributed variable stays below with
+/// probability `p`, undoing `normcdf`.
+fn norminv(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let (mu, sigma) = normal_args(args)?;
+    Ok(Value::Float(mu + sigma * stats::norminv(args[0].as_f64()?)?))
+}
+
+/// `binompdf(n, p, k)`, the probability of `k` successes in `n` trials.
+fn binompdf(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(stats::binompdf(count_arg(&args[0])?, args[1].as_f64()?, count_arg(&args[2])?)?))
+}
+
+/// `poissonpdf(lambda, k)`, the probability of `k` events.
+fn poissonpdf(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(stats::poissonpdf(args[0].as_f64()?, count_arg(&args[1])?)?))
+}
+
+/// Reads a list of cash flows.
+fn flows_arg(arg: &Value) -> Result<Vec<f64>> {
+    list_arg(arg)?.iter().map(Value::as_f64).collect()
+}
+
+/// `growth(start, end)`, the change from `start` to `end` in percent.
+fn growth(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(finance::percent_change(args[0].as_f64()?, args[1].as_f64()?)?))
+}
+
+/// `compound(principal, rate, periods)`, the rate a fraction such as
+/// 0.05 for five percent.
+fn compound(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(finance::compound(args[0].as_f64()?, args[1].as_f64()?, args[2].as_f64()?)))
+}
+
+/// `pmt(rate, nper, pv)`, or `pmt(rate, nper, pv, fv)` to leave `fv`
+/// at the end.
+fn pmt(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let fv = args.get(3).map_or(Ok(0.0), Value::as_f64)?;
+    Ok(Value::Float(finance::pmt(args[0].as_f64()?, args[1].as_f64()?, args[2].as_f64()?, fv)))
+}
+
+/// `fv(rate, nper, pmt)`, or `fv(rate, nper, pmt, pv)` starting from `pv`.
+fn fv(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    let pv = args.get(3).map_or(Ok(0.0), Value::as_f64)?;
+    Ok(Value::Float(finance::fv(args[0].as_f64()?, args[1].as_f64()?, args[2].as_f64()?, pv)))
+}
+
+/// `npv(rate, flows)`, the flows given as a list.
+fn npv(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(finance::npv(args[0].as_f64()?, &flows_arg(&args[1])?)))
+}
+
+fn irr(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Float(finance::irr(&flows_arg(&args[0])?)?))
+}
+
+/// The current time, in UTC until converted with `in`.
+fn now(_args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Time(Time::now()))
+}
+
+/// Reads a time such as `time("14:30 PST")` or `time("2024-03-10 09:00")`.
+fn time(args: &[Value], _env: &mut Environment) -> Result<Value> {
+    Ok(Value::Time(Time::parse(args[0].as_str()?)?))
+}
+
+fn list_arg(arg: &Value) -> Result<&[Value]> {
+    match arg {
+        Value::List(items) | Value::Tuple(items) => Ok(items),
+        _ => Err(Error::TypeError),
+    }
+}
+
+fn func_arg(arg: &Value) -> Result<&Function> {
+    match arg {
+        Value::Func(f) => Ok(f),
+        _ => Err(Error::TypeError),
+    }
+}
+
+/// Applies a function to every item of a list.
+fn map(args: &[Value], env: &mut Environment) -> Result<Value> {
+    let f = func_arg(&args[1])?;
+    let items = list_arg(&args[0])?.iter()
+        .map(|item| f.call(std::slice::from_ref(item), env))
+        .collect::<Result<_>>()?;
+    Ok(Value::List(items))
+}
+
+/// The items of a list for which a function returns something other
+/// than zero.
+fn filter(args: &[Value], env: &mut Environment) -> Result<Value> {
+    let f = func_arg(&args[1])?;
+    let mut items = Vec::new();
+    for item in list_arg(&args[0])? {
+        if f.call(std::slice::from_ref(item), env)?.as_f64()? != 0.0 {
+            items.push(item.clone());
+        }
+    }
+    Ok(Value::List(items))
+}
+
+/// Combines the items of a list from left to right, starting from the
+/// second argument, as in `reduce([1, 2, 3], 0, (acc, x) -> acc + x)`.
+fn reduce(args: &[Value], env: &mut Environment) -> Result<Value> {
+    let f = func_arg(&args[2])?;
+    let mut acc = args[1].clone();
+    for item in list_arg(&args[0])? {
+        acc = f.call(&[acc, item.clone()], env)?;
+    }
+    Ok(acc)
+}
+
+/// Evaluates the second argument with a name bound by the first, as in
+/// `with(y = x * 2, y + y)`, leaving the variable of that name as it was.
+fn with(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let (name, val) = match &args[0] {
+        Expr::Binary(OpKind::Equals, name, val) => (var_arg(name)?, val.eval(env)?),
+        _ => return Err(Error::TypeError),
+    };
+    numeric::with_var(env, name, |env| {
+        env.set(name, val);
+        args[1].eval(env)
+    })
+}
+
+/// Fails unless the argument is nonzero, quoting it in the error.
+fn assert(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    if args[0].eval(env)?.as_f64()? == 0.0 {
+        return Err(Error::AssertionFailed(args[0].to_string()));
+    }
+    Ok(Value::Int(1))
+}
+
+/// Fails unless both arguments are equal, with numbers compared by
+/// value so `assert_eq(2, 4 / 2.0)` holds.
+fn assert_eq(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let (a, b) = (args[0].eval(env)?, args[1].eval(env)?);
+    let equal = match (a.as_f64(), b.as_f64()) {
+        (Ok(x), Ok(y)) => x == y,
+        _ => a == b,
+    };
+    if !equal {
+        return Err(Error::AssertionFailed(format!("{} == {} (left: {}, right: {})", args[0], args[1], a, b)));
+    }
+    Ok(Value::Int(1))
+}
+
+/// Describes a function or operator, as in `help(sqrt)` or `help("^")`.
+fn help(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    let topic = match &args[0] {
+        Expr::Var(name) => name.as_str(),
+        Expr::Lit(Value::Str(name)) => name.as_str(),
+        _ => return Err(Error::TypeError),
+    };
+    Ok(Value::Str(crate::help::topic(topic, env)?))
+}
+
+fn dims(args: &[Expr], env: &mut Environment) -> Result<Value> {
+    Ok(Value::Str(units::dims(&args[0], env)?.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::{EvalOptions, IntWidth, Overflow};
+
+    #[test]
+    fn test_store_recall() {
+        let mut env = Environment::new();
+        assert_eq!(Value::Int(6), env.eval("store(2 * 3, \"m1\")").unwrap());
+        assert_eq!(Value::Int(7), env.eval("recall(\"m1\") + 1").unwrap());
+        assert_eq!(Value::Int(0), env.eval("recall(\"m2\")").unwrap());
+
+        assert!(matches!(env.eval("store(1)"), Err(Error::ArgumentCount)));
+        assert!(matches!(env.eval("store(1, 2)"), Err(Error::TypeError)));
+        assert!(matches!(env.eval("nope(1)"), Err(Error::UnknownFunction(_))));
+    }
+
+    #[test]
+    fn test_divmod() {
+        let exprs = ["divmod(7, 2)", "divmod(-7, 2)", "divmod(7, -2)", "divmod(7.5, 2)"];
+        let answers = ["(3, 1)", "(-4, 1)", "(-4, -1)", "(3, 1.5)"];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert!(matches!(env.eval("divmod(1, 0)"), Err(Error::DivisionByZero)));
+
+        let min = "divmod(-9223372036854775807 - 1, -1)";
+        env.set_options(EvalOptions { overflow: Overflow::Wrap, ..EvalOptions::default() });
+        assert_eq!("(-9223372036854775808, 0)", env.eval(min).unwrap().to_string());
+        env.set_options(EvalOptions { overflow: Overflow::Error, ..EvalOptions::default() });
+        assert!(matches!(env.eval(min), Err(Error::Overflow)));
+    }
+
+    #[test]
+    fn test_decomposition() {
+        let exprs = [
+            "frac(0.75)",
+            "frac(-1.5)",
+            "frac(0.1 + 0.2)",
+            "frac(1 / 3)",
+            "frac(4)",
+            "frac(3.14159265358979)",
+            "approx(3.14159, 1e-4)",
+            "approx(3.14159265, 1e-6)",
+            "approx(-0.333, 0.01)",
+            "approx(2.5, 0.6)",
+            "approx(0.1, 1e-20)",
+            "scientific(1234.5)",
+            "scientific(-0.00012)",
+            "scientific(0)",
+            "dms(10.2)",
+            "dms(-0.51)",
+            "dms(-1.5)",
+        ];
+        let answers = [
+            "(3, 4)",
+            "(-3, 2)",
+            "(3, 10)",
+            "(1, 3)",
+            "(4, 1)",
+            "(103993, 33102)",
+            "(333, 106)",
+            "(355, 113)",
+            "(-1, 3)",
+            "(2, 1)",
+            "(1, 10)",
+            "(1.2345, 3)",
+            "(-1.2, -4)",
+            "(0, 0)",
+            "(10, 12, 0)",
+            "(0, -30, 36)",
+            "(-1, 30, 0)",
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert!(matches!(env.eval("frac(1.0 / 0)"), Err(Error::NotFinite)));
+        assert!(matches!(env.eval("approx(1.5, 0)"), Err(Error::TypeError)));
+    }
+
+    #[test]
+    fn test_bit_fields() {
+        let exprs = ["bit(5, 0)", "bit(5, 1)", "bit(-1, 63)", "bits(0xABCD, 11, 4)", "bits(0xABCD, 3, 3)", "bits(-1, 63, 0)", "setbit(0, 4)", "setbit(5, 0)", "popcount(0xFF)", "popcount(-1)", "bits(2.0)"];
+        let answers = ["1", "0", "1", "188", "1", "-1", "16", "5", "8", "64", "4611686018427387904"];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        for expr in ["bit(1, 64)", "bits(1, 2, 3)", "setbit(1, -1)"] {
+            assert!(matches!(env.eval(expr), Err(Error::TypeError)), "{}", expr);
+        }
+        assert!(matches!(env.eval("bits(1, 2)"), Err(Error::ArgumentCount)));
+
+        env.set_options(EvalOptions { width: IntWidth::I8, overflow: Overflow::Wrap, ..EvalOptions::default() });
+        assert_eq!(Value::Int(8), env.eval("popcount(-1)").unwrap());
+    }
+
+    #[test]
+    fn test_twos_complement() {
+        let exprs = [
+            "tohex(-1, 16)",
+            "tohex(255, 8)",
+            "tohex(5, 12)",
+            "tobin(-2, 4)",
+            "tobin(5, 8)",
+            "signext(0xFF, 8)",
+            "signext(0x7F, 8)",
+            "signext(0x1F80, 8)",
+            "tohex(signext(0x80, 8), 32)",
+        ];
+        let answers = [
+            "0xFFFF",
+            "0xFF",
+            "0x005",
+            "0b1110",
+            "0b00000101",
+            "-1",
+            "127",
+            "-128",
+            "0xFFFFFF80",
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert!(matches!(env.eval("tohex(256, 8)"), Err(Error::Overflow)));
+        assert!(matches!(env.eval("tohex(-129, 8)"), Err(Error::Overflow)));
+        assert!(matches!(env.eval("tobin(1, 0)"), Err(Error::TypeError)));
+    }
+
+    #[test]
+    fn test_float_inspection() {
+        let exprs = [
+            "tohex(bits(1), 64)",
+            "tohex(bits(-2.5), 64)",
+            "frombits(bits(0.1)) - 0.1",
+            "frombits(0x7FF0000000000000)",
+            "ulp(1)",
+            "ulp(-1e300)",
+            "ulp(0)",
+            "nextafter(1, 2) - 1",
+            "1 - nextafter(1, 0)",
+            "nextafter(0, -1)",
+        ];
+        let answers = [
+            "0x3FF0000000000000",
+            "0xC004000000000000",
+            "0",
+            "inf",
+            "2.220446049250313e-16",
+            "1.487016908477783e284",
+            "5e-324",
+            "2.220446049250313e-16",
+            "1.1102230246251565e-16",
+            "-5e-324",
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let exprs = [
+            "diff(x^2 + 3*x, x)",
+            "diff(5, x)",
+            "diff(x*y, y)",
+            "diff(1/x, x)",
+            "diff(sin(2*x), x)",
+            "diff(diff(x^3, x), x)",
+        ];
+        let answers = [
+            "2*x + 3",
+            "0",
+            "x",
+            "-1/x^2",
+            "2*cos(2*x)",
+            "6*x",
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert!(matches!(env.eval("diff(x, 2)"), Err(Error::TypeError)));
+    }
+
+    #[test]
+    fn test_simplify() {
+        let mut env = Environment::new();
+        let d = env.eval("simplify(x*y + y*x - x/x)").unwrap();
+        assert_eq!("2*x*y - 1", d.to_string());
+        assert_eq!(Value::Int(2), env.eval("simplify(x + 2 - x)").unwrap());
+
+        env.set("d", d);
+        assert_eq!("2*y", env.eval("diff(d, x)").unwrap().to_string());
+    }
+
+    #[test]
+    fn test_with() {
+        let exprs = [
+            "with(y = x * 2, y + y)",
+            "with(x = x + 1, x * x)",
+            "with(a = 2, with(b = a + 1, a * b))",
+            "x",
+        ];
+        let answers = ["12", "16", "6", "3"];
+
+        let mut env = Environment::new();
+        env.set("x", Value::Int(3));
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert_eq!(None, env.get("y"));
+        assert!(matches!(env.eval("with(2, 3)"), Err(Error::TypeError)));
+    }
+
+    #[test]
+    fn test_higher_order() {
+        let exprs = [
+            "map([1, 2, 3], x -> x * x)",
+            "filter([1, 2, 3, 4, 5], x -> x // 2 * 2 == x)",
+            "reduce([1, 2, 3, 4], 0, (acc, x) -> acc + x)",
+            "map(divmod(7, 2), n -> n + 1)",
+            "map([], x -> x)",
+            "(a, b) -> a + b",
+            "sq(5)",
+            "map([1, 2], sq)",
+            "map([[1, 2], [3]], l -> reduce(l, 1, (p, x) -> p * x))",
+        ];
+        let answers = [
+            "[1, 4, 9]",
+            "[2, 4]",
+            "10",
+            "[4, 2]",
+            "[]",
+            "(a, b) -> a + b",
+            "25",
+            "[1, 4]",
+            "[2, 3]",
+        ];
+
+        let mut env = Environment::new();
+        env.set("sq", env.clone().eval("x -> x^2").unwrap());
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert!(matches!(env.eval("map(1, x -> x)"), Err(Error::TypeError)));
+        assert!(matches!(env.eval("map([1], (a, b) -> a)"), Err(Error::ArgumentCount)));
+        assert!(matches!(env.eval("1 -> 2"), Err(Error::TypeError)));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let exprs = [
+            "1..5",
+            "1..10 step 3",
+            "5..1 step -2",
+            "0..1 step 0.25",
+            "3..1",
+            "1.5..3",
+            "sum(1..100)",
+            "prod(1..5)",
+            "sum(map(1..3, x -> x^2))",
+            "0..6 step step",
+        ];
+        let answers = [
+            "[1, 2, 3, 4, 5]",
+            "[1, 4, 7, 10]",
+            "[5, 3, 1]",
+            "[0, 0.25, 0.5, 0.75, 1]",
+            "[]",
+            "[1.5, 2.5]",
+            "5050",
+            "120",
+            "14",
+            "[0, 3, 6]",
+        ];
+
+        let mut env = Environment::new();
+        env.set("step", Value::Int(3));
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert!(matches!(env.eval("1..10 step 0"), Err(Error::TypeError)));
+        assert!(matches!(env.eval("1..10^9"), Err(Error::LimitExceeded)));
+        assert!(matches!(env.eval("0..9223372036854775807 step 4611686018427387904"), Err(Error::Overflow)));
+        env.set_limits(crate::env::Limits { max_steps: Some(1000), ..Default::default() });
+        assert!(matches!(env.eval("1..10^6"), Err(Error::LimitExceeded)));
+        env.set_limits(crate::env::Limits::default());
+        assert!(matches!(env.eval("sum(1, 2)"), Err(Error::ArgumentCount)));
+    }
+
+    #[test]
+    fn test_conversions() {
+        let exprs = [
+            "num(\"42\") + 1",
+            "num(\" -1.5e3 \")",
+            "num(\"0x1F\")",
+            "num(7)",
+            "str(3.14)",
+            "str(1 / 4)",
+            "str(divmod(7, 2))",
+            "parsehex(\"ff\")",
+            "parsehex(\"0x7FFF\")",
+            "num(str(0.1 + 0.2))",
+            "base(36, \"Z1\")",
+            "base(2, \"-101\")",
+            "tobase(1261, 36)",
+            "tobase(-255, 16)",
+            "base(7, tobase(12345, 7))",
+            "roman(\"XIV\") + 1",
+            "roman(\"mcmxciv\")",
+            "toroman(2024)",
+            "toroman(3999)",
+        ];
+        let answers = ["43", "-1500", "31", "7", "3.14", "0.25", "(3, 1)", "255", "32767", "0.30000000000000004", "1261", "-5", "z1", "-ff", "12345", "15", "1994", "MMXXIV", "MMMCMXCIX"];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert!(matches!(env.eval("num(\"1 + 1\")"), Err(Error::ParseError)));
+        assert!(matches!(env.eval("parsehex(\"fg\")"), Err(Error::ParseError)));
+        assert!(matches!(env.eval("parsehex(\"ffffffffffffffff\")"), Err(Error::Overflow)));
+        assert!(matches!(env.eval("base(8, \"19\")"), Err(Error::ParseError)));
+        assert!(matches!(env.eval("tobase(10, 37)"), Err(Error::TypeError)));
+        for expr in ["roman(\"IIII\")", "roman(\"IC\")", "roman(\"\")", "roman(\"XIZ\")"] {
+            assert!(matches!(env.eval(expr), Err(Error::ParseError)), "{}", expr);
+        }
+        assert!(matches!(env.eval("toroman(0)"), Err(Error::TypeError)));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::kalkul::{Error, Result};
+
+/// A CBOR data item, as defined by RFC 8949. Just enough of CBOR for the
+/// compact encoding of compiled programs, see `Program::to_bytes`.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Cbor {
+    Null,
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Array(Vec<Cbor>),
+}
+
+/// How deeply arrays may be nested in decoded input.
+const MAX_DEPTH: usize = 256;
+
+impl Cbor {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Cbor::Null => out.push(0xf6),
+            Cbor::Int(n) if *n >= 0 => head(out, 0, *n as u64),
+            // -1 - n, which cannot overflow.
+            Cbor::Int(n) => head(out, 1, !*n as u64),
+            Cbor::Float(x) => {
+                out.push(0xfb);
+                out.extend_from_slice(&x.to_be_bytes());
+            },
+            Cbor::Text(s) => {
+                head(out, 3, s.len() as u64);
+                out.extend_from_slice(s.as_bytes());
+            },
+            Cbor::Array(items) => {
+                head(out, 4, items.len() as u64);
+                for item in items {
+                    item.encode(out);
+                }
+            },
+        }
+    }
+
+    /// Decodes the single item making up the whole of `bytes`. Anything
+    /// else, including the types of CBOR not supported, is a parse error.
+    pub fn decode(bytes: &[u8]) -> Result<Cbor> {
+        let mut d = Decoder { bytes, i: 0 };
+        let item = d.item(0)?;
+        if d.i != bytes.len() {
+            return Err(Error::ParseError);
+        }
+        Ok(item)
+    }
+
+    pub fn as_int(&self) -> Result<i64> {
+        match self {
+            Cbor::Int(n) => Ok(*n),
+            _ => Err(Error::ParseError),
+        }
+    }
+
+    pub fn as_f64(&self) -> Result<f64> {
+        match self {
+            Cbor::Float(x) => Ok(*x),
+            _ => Err(Error::ParseError),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str> {
+        match self {
+            Cbor::Text(s) => Ok(s),
+            _ => Err(Error::ParseError),
+        }
+    }
+
+    pub fn as_array(&self) -> Result<&[Cbor]> {
+        match self {
+            Cbor::Array(items) => Ok(items),
+            _ => Err(Error::ParseError),
+        }
+    }
+
+    pub fn text(s: impl Into<String>) -> Cbor {
+        Cbor::Text(s.into())
+    }
+}
+
+/// Writes the initial byte of an item of the `major` type with its
+/// argument `n`, in as few bytes as it fits.
+fn head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    match n {
+        0..=23 => out.push(major | n as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        },
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        },
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&n.to_be_bytes());
+        },
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    i: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.i.checked_add(n).filter(|end| *end <= self.bytes.len()).ok_or(Error::ParseError)?;
+        let bytes = &self.bytes[self.i..end];
+        self.i = end;
+        Ok(bytes)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64> {
+        Ok(self.take(len)?.iter().fold(0, |n, b| n << 8 | *b as u64))
+    }
+
+    fn item(&mut self, depth: usize) -> Result<Cbor> {
+        let initial = self.take(1)?[0];
+        match initial {
+            0xf6 => return Ok(Cbor::Null),
+            0xfb => return Ok(Cbor::Float(f64::from_bits(self.uint(8)?))),
+            _ => {},
+        }
+        let n = match initial & 0x1f {
+            info @ 0..=23 => info as u64,
+            24 => self.uint(1)?,
+            25 => self.uint(2)?,
+            26 => self.uint(4)?,
+            27 => self.uint(8)?,
+            _ => return Err(Error::ParseError),
+        };
+        match initial >> 5 {
+            0 => Ok(Cbor::Int(i64::try_from(n).map_err(|_| Error::Overflow)?)),
+            1 => Ok(Cbor::Int(!i64::try_from(n).map_err(|_| Error::Overflow)?)),
+            3 => {
+                let len = usize::try_from(n).map_err(|_| Error::ParseError)?;
+                let text = std::str::from_utf8(self.take(len)?).map_err(|_| Error::ParseError)?;
+                Ok(Cbor::Text(text.to_string()))
+            },
+            4 if depth < MAX_DEPTH => {
+                // Not allocated up front, as the length comes from the input.
+                let mut items = Vec::new();
+                for _ in 0..n {
+                    items.push(self.item(depth + 1)?);
+                }
+                Ok(Cbor::Array(items))
+            },
+            _ => Err(Error::ParseError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cbor() {
+        let items = [
+            Cbor::Int(0),
+            Cbor::Int(23),
+            Cbor::Int(-1),
+            Cbor::Int(1000),
+            Cbor::Int(i64::MIN),
+            Cbor::Float(1.5),
+            Cbor::text("ü"),
+            Cbor::Array(vec![Cbor::Int(1), Cbor::Null, Cbor::Array(vec![])]),
+        ];
+        // As in the examples of RFC 8949, appendix A.
+        let answers: [&[u8]; 8] = [
+            &[0x00],
+            &[0x17],
+            &[0x20],
+            &[0x19, 0x03, 0xe8],
+            &[0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            &[0xfb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0],
+            &[0x62, 0xc3, 0xbc],
+            &[0x83, 0x01, 0xf6, 0x80],
+        ];
+
+        for (item, ans) in std::iter::zip(items, answers) {
+            let mut bytes = Vec::new();
+            item.encode(&mut bytes);
+            assert_eq!(ans, bytes.as_slice(), "{:?}", item);
+            assert_eq!(item, Cbor::decode(&bytes).unwrap());
+        }
+
+        let nested = [0x81; MAX_DEPTH + 1].iter().copied().chain([0x80]).collect::<Vec<u8>>();
+        for bytes in [&[][..], &[0x18], &[0x62, 0x61], &[0x01, 0x01], &[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], &[0xf5], &nested] {
+            assert!(Cbor::decode(bytes).is_err(), "{:?}", bytes);
+        }
+    }
+}
@@ -0,0 +1,621 @@
+use std::io::{BufRead, Write};
+
+use crate::csv;
+use crate::env::Environment;
+use crate::help;
+use crate::lsp;
+use crate::kalkul::{tokenize, Error, Expr, OpKind, Result, Token, Value};
+use crate::numeric::with_var;
+use crate::plot::Plot;
+use crate::program::Program;
+use crate::repl::Repl;
+
+/// Runs a command-line subcommand, reading any input it needs from
+/// `input` and writing its output to `out`.
+pub fn run(args: &[String], input: &mut impl BufRead, out: &mut impl Write) -> Result<()> {
+    let (cmd, rest) = args.split_first().ok_or(Error::Usage)?;
+    match cmd.as_str() {
+        "plot"     => plot(rest, out),
+        "run"      => script(rest, out),
+        "table"    => table(rest, out),
+        "--csv"    => csv_columns(rest, out),
+        "--reduce" => reduce(rest, input, out),
+        "--lsp" if rest.is_empty() => lsp::run(input, out),
+        "--help-syntax" if rest.is_empty() => Ok(write!(out, "{}", help::syntax())?),
+        #[cfg(feature = "server")]
+        "serve"    => serve(rest),
+        "completions" => completions(rest, out),
+        "-q"       => expression(rest, Verbosity::Quiet, out),
+        "-v"       => expression(rest, Verbosity::Verbose, out),
+        "--time"   => expression(args, Verbosity::Normal, out),
+        _ if !cmd.starts_with("--") => expression(args, Verbosity::Normal, out),
+        _ => Err(Error::Usage),
+    }
+}
+
+/// Arguments of a subcommand.
+struct Args<'a> {
+    positional: Vec<&'a str>,
+    /// `--flag value` pairs, in the order given.
+    values: Vec<(&'a str, &'a str)>,
+    /// Flags that take no value, such as `--csv`.
+    switches: Vec<&'a str>,
+}
+
+impl<'a> Args<'a> {
+    fn parse(args: &'a [String], flags: &[&str], switches: &[&str]) -> Result<Args<'a>> {
+        let mut parsed = Args {
+            positional: Vec::new(),
+            values: Vec::new(),
+            switches: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(flag) if flags.contains(&flag) => {
+                    let val = args.next().ok_or(Error::Usage)?;
+                    parsed.values.push((flag, val.as_str()));
+                },
+                Some(flag) if switches.contains(&flag) => parsed.switches.push(flag),
+                Some(_) => return Err(Error::Usage),
+                None => parsed.positional.push(arg.as_str()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Returns the only positional argument.
+    fn single(&self) -> Result<&'a str> {
+        match self.positional[..] {
+            [arg] => Ok(arg),
+            _ => Err(Error::Usage),
+        }
+    }
+
+    fn has(&self, switch: &str) -> bool {
+        self.switches.contains(&switch)
+    }
+}
+
+/// How much `kalkul EXPR` prints besides the result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Verbosity {
+    /// Only the result.
+    Quiet,
+    /// The result and any warnings.
+    Normal,
+    /// Also how the expression was tokenized and parsed.
+    Verbose,
+}
+
+/// Evaluates a single expression given on the command line.
+fn expression(args: &[String], verbosity: Verbosity, out: &mut impl Write) -> Result<()> {
+    let args = Args::parse(args, &[], &["time"])?;
+    let src = args.single()?;
+
+    if verbosity == Verbosity::Verbose {
+        let tokens: Vec<String> = tokenize(src)?.iter().map(|t| format!("{:?}", t)).collect();
+        writeln!(out, "tokens: {}", tokens.join(" "))?;
+    }
+    let details = Environment::new().eval_detailed(src)?;
+    if verbosity == Verbosity::Verbose {
+        writeln!(out, "parsed: {}", details.normalized)?;
+        writeln!(out, "steps: {}", details.steps)?;
+    }
+    writeln!(out, "{}", details.value)?;
+    if verbosity != Verbosity::Quiet {
+        for warning in &details.warnings {
+            writeln!(out, "warning: {}", warning)?;
+        }
+    }
+    if args.has("time") {
+        writeln!(out, "time: {}", details.timing())?;
+    }
+    Ok(())
+}
+
+/// Parses `from:to`, where both ends may be expressions.
+fn parse_range(s: &str, env: &mut Environment) -> Result<(f64, f64)> {
+    let (from, to) = s.split_once(':').ok_or(Error::Usage)?;
+    Ok((env.eval(from)?.as_f64()?, env.eval(to)?.as_f64()?))
+}
+
+fn plot(args: &[String], out: &mut impl Write) -> Result<()> {
+    let args = Args::parse(args, &["range", "width", "height", "svg"], &[])?;
+    let src = args.single()?;
+
+    let mut env = Environment::new();
+    let mut plot = Plot::default();
+    let mut svg = None;
+    for &(flag, val) in &args.values {
+        match flag {
+            "range"  => (plot.from, plot.to) = parse_range(val, &mut env)?,
+            "width"  => plot.width = val.parse().map_err(|_| Error::Usage)?,
+            "height" => plot.height = val.parse().map_err(|_| Error::Usage)?,
+            "svg"    => svg = Some(val),
+            _ => unreachable!(),
+        }
+    }
+    if plot.from >= plot.to || plot.width < 2 || plot.height < 2 {
+        return Err(Error::Usage);
+    }
+
+    let expr = Expr::parse(src)?;
+    write!(out, "{}", plot.ascii(&expr, "x", &mut env)?)?;
+    if let Some(path) = svg {
+        std::fs::write(path, plot.svg(&expr, "x", &mut env)?)?;
+    }
+    Ok(())
+}
+
+/// Most rows `table` prints.
+const MAX_TABLE_ROWS: u64 = 100_000;
+
+fn table(args: &[String], out: &mut impl Write) -> Result<()> {
+    let args = Args::parse(args, &["from", "to", "step"], &["csv"])?;
+    let src = args.single()?;
+
+    let mut env = Environment::new();
+    let (mut from, mut to, mut step) = (Value::Int(0), Value::Int(10), Value::Int(1));
+    for &(flag, val) in &args.values {
+        let val = env.eval(val)?;
+        val.as_f64()?;
+        match flag {
+            "from" => from = val,
+            "to"   => to = val,
+            "step" => step = val,
+            _ => unreachable!(),
+        }
+    }
+    let (a, b, d) = (from.as_f64()?, to.as_f64()?, step.as_f64()?);
+    if !a.is_finite() || !b.is_finite() || !d.is_finite() || d <= 0.0 {
+        return Err(Error::Usage);
+    }
+    let count = ((b - a) / d).floor().max(-1.0) + 1.0;
+    if count > MAX_TABLE_ROWS as f64 {
+        return Err(Error::TooMany("table rows", count as u64, MAX_TABLE_ROWS));
+    }
+
+    let expr = Expr::parse(src)?;
+    let f = Program::compile(&expr);
+    let rows = with_var(&mut env, "x", |env| {
+        let mut rows = Vec::new();
+        // One more than counted, in case rounding left out the last row.
+        for k in 0..=count as i64 {
+            // Stepping by multiplication keeps errors from accumulating.
+            let offset = Value::apply(OpKind::Multiply, Value::Int(k), step.clone())?;
+            let x = match Value::apply(OpKind::Plus, from.clone(), offset)? {
+                // Round away representation error, so -0.2 + 3*0.1 is 0.1.
+                Value::Float(x) => Value::Float(format!("{:.14e}", x).parse().unwrap()),
+                x => x,
+            };
+            if x.as_f64()? > to.as_f64()? {
+                break;
+            }
+            env.set("x", x.clone());
+            let y = match f.run(env) {
+                Ok(y)  => y.to_string(),
+                Err(e) => e.to_string(),
+            };
+            rows.push((x.to_string(), y));
+        }
+        Ok(rows)
+    })?;
+
+    if args.has("csv") {
+        csv::write_record(out, &["x", src])?;
+        for (x, y) in rows {
+            csv::write_record(out, &[x, y])?;
+        }
+        return Ok(());
+    }
+
+    let width = rows.iter().map(|(x, _)| x.len()).max().unwrap_or(0).max(1);
+    writeln!(out, "{:>w$} | {}", "x", src, w = width)?;
+    writeln!(out, "{}-+-{}", "-".repeat(width), "-".repeat(src.len()))?;
+    for (x, y) in rows {
+        writeln!(out, "{:>w$} | {}", x, y, w = width)?;
+    }
+    Ok(())
+}
+
+/// Runs a script file line by line, like input typed into the REPL,
+/// printing the results. Lines starting with `#` are comments. The first
+/// failing line, such as a failed `assert`, stops the script.
+fn script(args: &[String], out: &mut impl Write) -> Result<()> {
+    let args = Args::parse(args, &[], &[])?;
+    let src = std::fs::read_to_string(args.single()?)?;
+
+    let mut repl = Repl::new();
+    for (i, line) in src.lines().enumerate() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        match repl.handle(line) {
+            Ok(Some(res)) => writeln!(out, "{}", res)?,
+            Ok(None) => {},
+            Err(e) => return Err(Error::AtLine(i + 1, Box::new(e))),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+fn serve(args: &[String]) -> Result<()> {
+    let args = Args::parse(args, &["addr"], &[])?;
+    if !args.positional.is_empty() {
+        return Err(Error::Usage);
+    }
+    let mut config = crate::server::Config::default();
+    if let Some((_, addr)) = args.values.last() {
+        config.addr = addr.to_string();
+    }
+    crate::server::serve(config)?;
+    Ok(())
+}
+
+/// What the positional argument of a command is, for completion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    None,
+    Expr,
+    File,
+    Word(&'static [&'static str]),
+}
+
+/// The commands `run` accepts, with their flags, for shell completion.
+const COMMANDS: &[(&str, Operand, &[&str])] = &[
+    ("plot",          Operand::Expr, &["--range", "--width", "--height", "--svg"]),
+    ("table",         Operand::Expr, &["--from", "--to", "--step", "--csv"]),
+    ("run",           Operand::File, &[]),
+    ("completions",   Operand::Word(&["bash", "zsh", "fish"]), &[]),
+    ("serve",         Operand::None, &["--addr"]),
+    ("--csv",         Operand::File, &["--name"]),
+    ("--reduce",      Operand::Word(&["sum", "prod", "mean", "min", "max", "count"]), &[]),
+    ("--lsp",         Operand::None, &[]),
+    ("--help-syntax", Operand::None, &[]),
+    ("-q",            Operand::Expr, &["--time"]),
+    ("-v",            Operand::Expr, &["--time"]),
+    ("--time",        Operand::Expr, &[]),
+];
+
+/// Prints a completion script for the given shell.
+fn completions(args: &[String], out: &mut impl Write) -> Result<()> {
+    let args = Args::parse(args, &[], &[])?;
+    let commands: Vec<_> = COMMANDS.iter()
+        .filter(|(cmd, _, _)| *cmd != "serve" || cfg!(feature = "server"))
+        .collect();
+    let names: Vec<&str> = commands.iter().map(|(cmd, _, _)| *cmd).collect();
+
+    match args.single()? {
+        "bash" => {
+            writeln!(out, "_kalkul() {{")?;
+            writeln!(out, "    local cur=${{COMP_WORDS[COMP_CWORD]}}")?;
+            writeln!(out, "    if [ \"$COMP_CWORD\" -eq 1 ]; then")?;
+            writeln!(out, "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", names.join(" "))?;
+            writeln!(out, "        return")?;
+            writeln!(out, "    fi")?;
+            writeln!(out, "    case ${{COMP_WORDS[1]}} in")?;
+            for (cmd, operand, flags) in &commands {
+                let mut words = flags.join(" ");
+                if let Operand::Word(ws) = operand {
+                    words = ws.join(" ");
+                }
+                let files = if *operand == Operand::File { "-f " } else { "" };
+                writeln!(out, "        {}) COMPREPLY=($(compgen {}-W \"{}\" -- \"$cur\")) ;;", cmd, files, words)?;
+            }
+            writeln!(out, "    esac")?;
+            writeln!(out, "}}")?;
+            writeln!(out, "complete -F _kalkul kalkul")?;
+        },
+        "zsh" => {
+            writeln!(out, "#compdef kalkul")?;
+            writeln!(out, "_kalkul() {{")?;
+            writeln!(out, "    if (( CURRENT == 2 )); then")?;
+            writeln!(out, "        compadd -- {}", names.join(" "))?;
+            writeln!(out, "        return")?;
+            writeln!(out, "    fi")?;
+            writeln!(out, "    case $words[2] in")?;
+            for (cmd, operand, flags) in &commands {
+                let mut actions = Vec::new();
+                match operand {
+                    Operand::File => actions.push("_files".to_string()),
+                    Operand::Word(ws) => actions.push(format!("compadd -- {}", ws.join(" "))),
+                    Operand::None | Operand::Expr => {},
+                }
+                if !flags.is_empty() {
+                    actions.push(format!("compadd -- {}", flags.join(" ")));
+                }
+                if !actions.is_empty() {
+                    writeln!(out, "        {}) {} ;;", cmd, actions.join("; "))?;
+                }
+            }
+            writeln!(out, "    esac")?;
+            writeln!(out, "}}")?;
+            writeln!(out, "compdef _kalkul kalkul")?;
+        },
+        "fish" => {
+            writeln!(out, "complete -c kalkul -f")?;
+            for (cmd, operand, flags) in &commands {
+                let (option, condition) = match (cmd.strip_prefix("--"), cmd.strip_prefix('-')) {
+                    (Some(long), _) => (format!("-l {}", long), format!("__fish_contains_opt {}", long)),
+                    (None, Some(short)) => (format!("-s {}", short), format!("__fish_contains_opt -s {}", short)),
+                    (None, None) => (format!("-a {}", cmd), format!("__fish_seen_subcommand_from {}", cmd)),
+                };
+                writeln!(out, "complete -c kalkul -n __fish_use_subcommand {}", option)?;
+                match operand {
+                    Operand::File => writeln!(out, "complete -c kalkul -n '{}' -F", condition)?,
+                    Operand::Word(ws) => writeln!(out, "complete -c kalkul -n '{}' -a '{}'", condition, ws.join(" "))?,
+                    Operand::None | Operand::Expr => {},
+                }
+                for flag in flags.iter() {
+                    writeln!(out, "complete -c kalkul -n '{}' -l {} -r", condition, &flag[2..])?;
+                }
+            }
+        },
+        _ => return Err(Error::Usage),
+    }
+    Ok(())
+}
+
+/// Turns a CSV cell into a number where possible.
+fn cell_value(cell: &str) -> Value {
+    match tokenize(cell.trim()).as_deref() {
+        Ok([Token::Num(n)]) => n.clone(),
+        // Not every number can be negated, such as the address in `-1.2.3.4`.
+        Ok([Token::Op(OpKind::Minus), Token::Num(n)]) => n.clone().negate().unwrap_or_else(|_| Value::Str(cell.to_string())),
+        _ => Value::Str(cell.to_string()),
+    }
+}
+
+/// Evaluates an expression for every row of a CSV file, with the
+/// columns bound as variables, and writes the file back out with the
+/// results as an extra column.
+fn csv_columns(args: &[String], out: &mut impl Write) -> Result<()> {
+    let args = Args::parse(args, &["name"], &[])?;
+    let (path, src) = match args.positional[..] {
+        [path, src] => (path, src),
+        _ => return Err(Error::Usage),
+    };
+    let name = args.values.iter().rev().find(|(flag, _)| *flag == "name").map_or(src, |(_, name)| name);
+
+    let data = std::fs::read_to_string(path)?;
+    let mut lines = data.lines().filter(|line| !line.is_empty());
+    let header = csv::parse_record(lines.next().ok_or(Error::ParseError)?);
+    let expr = Expr::parse(src)?;
+    let f = Program::compile(&expr);
+
+    let mut columns = header.clone();
+    columns.push(name.to_string());
+    csv::write_record(out, &columns)?;
+
+    let mut env = Environment::new();
+    for line in lines {
+        let mut row = csv::parse_record(line);
+        for (col, cell) in header.iter().zip(&row) {
+            env.set(col.trim(), cell_value(cell));
+        }
+        let result = match f.run(&mut env) {
+            Ok(val) => val.to_string(),
+            Err(e)  => e.to_string(),
+        };
+        for col in &header {
+            env.unset(col.trim());
+        }
+        row.push(result);
+        csv::write_record(out, &row)?;
+    }
+    Ok(())
+}
+
+/// Folds numbers read one per line from `input`.
+fn reduce(args: &[String], input: &mut impl BufRead, out: &mut impl Write) -> Result<()> {
+    let args = Args::parse(args, &[], &[])?;
+    let op = args.single()?;
+
+    let mut nums = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match cell_value(&line) {
+            Value::Str(_) => return Err(Error::ParseError),
+            n => nums.push(n),
+        }
+    }
+
+    let fold = |kind: OpKind, init: Value, nums: Vec<Value>| {
+        nums.into_iter().try_fold(init, |acc, n| Value::apply(kind, acc, n))
+    };
+    let pick = |nums: Vec<Value>, max: bool| -> Result<Value> {
+        let mut best: Option<Value> = None;
+        for n in nums {
+            best = match best {
+                Some(b) if (n.as_f64()? > b.as_f64()?) != max => Some(b),
+                _ => Some(n),
+            };
+        }
+        best.ok_or(Error::NotEnoughElements)
+    };
+
+    let count = nums.len() as i64;
+    let result = match op {
+        "+" | "sum"  => fold(OpKind::Plus, Value::Int(0), nums)?,
+        "*" | "prod" => fold(OpKind::Multiply, Value::Int(1), nums)?,
+        "count"      => Value::Int(count),
+        "min"        => pick(nums, false)?,
+        "max"        => pick(nums, true)?,
+        "mean"       => {
+            if count == 0 {
+                return Err(Error::NotEnoughElements);
+            }
+            Value::apply(OpKind::Divide, fold(OpKind::Plus, Value::Int(0), nums)?, Value::Int(count))?
+        },
+        _ => return Err(Error::Usage),
+    };
+    writeln!(out, "{}", result)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run_with_input(args: &[&str], input: &str) -> Result<String> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        let mut out = Vec::new();
+        run(&args, &mut input.as_bytes(), &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    fn run_args(args: &[&str]) -> Result<String> {
+        run_with_input(args, "")
+    }
+
+    #[test]
+    fn test_expression() {
+        assert_eq!("7\n", run_args(&["1 + 2*3"]).unwrap());
+        assert_eq!("-2\n", run_args(&["-5+3"]).unwrap());
+        let lossy = "9007199254740993 * 1.0";
+        assert_eq!("9007199254740992\n", run_args(&["-q", lossy]).unwrap());
+        assert_eq!(
+            "9007199254740992\nwarning: 9007199254740993 was rounded converting it to a float\n",
+            run_args(&[lossy]).unwrap(),
+        );
+        assert_eq!(
+            "tokens: Num(Int(1)) Op(Plus) Num(Int(2))\nparsed: 1 + 2\nsteps: 3\n3\n",
+            run_args(&["-v", "1+2"]).unwrap(),
+        );
+
+        let timed = run_args(&["--time", "2^10"]).unwrap();
+        assert!(timed.starts_with("1024\ntime: parse "), "{}", timed);
+        assert!(run_args(&["-q", "2^10", "--time"]).unwrap().contains("\ntime: parse "));
+
+        let e = run_args(&["1/0"]).unwrap_err();
+        assert_eq!(1, e.exit_code());
+        assert_eq!(2, run_args(&["-q"]).unwrap_err().exit_code());
+        assert_eq!(2, run_args(&["-q", "1", "2"]).unwrap_err().exit_code());
+        assert_eq!(2, run_args(&["--bogus"]).unwrap_err().exit_code());
+    }
+
+    #[test]
+    fn test_completions() {
+        let bash = run_args(&["completions", "bash"]).unwrap();
+        assert!(bash.contains("compgen -W \"plot table run completions"));
+        assert!(bash.contains("plot) COMPREPLY=($(compgen -W \"--range --width --height --svg\""));
+        assert!(bash.contains("run) COMPREPLY=($(compgen -f -W"));
+        assert!(bash.ends_with("complete -F _kalkul kalkul\n"));
+
+        let zsh = run_args(&["completions", "zsh"]).unwrap();
+        assert!(zsh.starts_with("#compdef kalkul\n"));
+        assert!(zsh.contains("--csv) _files; compadd -- --name ;;"));
+        assert!(zsh.contains("--reduce) compadd -- sum prod mean min max count ;;"));
+
+        let fish = run_args(&["completions", "fish"]).unwrap();
+        assert!(fish.contains("complete -c kalkul -n '__fish_seen_subcommand_from table' -l step -r\n"));
+        assert!(fish.contains("complete -c kalkul -n '__fish_contains_opt csv' -F\n"));
+        assert!(fish.contains("complete -c kalkul -n __fish_use_subcommand -s q\n"));
+
+        assert!(matches!(run_args(&["completions", "tcsh"]), Err(Error::Usage)));
+        assert!(matches!(run_args(&["completions"]), Err(Error::Usage)));
+    }
+
+    #[test]
+    fn test_script() {
+        let path = std::env::temp_dir().join(format!("kalkul-test-{}.calc", std::process::id()));
+        let path_str = path.to_str().unwrap();
+
+        std::fs::write(&path, "# squares\nsq(x) = x^2\nn = sq(3)\nassert(n > 8)\nassert_eq(sq(2), 8 / 2.0)\n").unwrap();
+        assert_eq!("9\n1\n1\n", run_args(&["run", path_str]).unwrap());
+
+        std::fs::write(&path, "n = 3\n\nassert_eq(n * 2, 7)\nn\n").unwrap();
+        let e = run_args(&["run", path_str]).unwrap_err();
+        assert_eq!("line 3: assertion failed: n*2 == 7 (left: 6, right: 7)", e.to_string());
+        std::fs::write(&path, "assert(1 - 1)").unwrap();
+        assert_eq!("line 1: assertion failed: 1 - 1", run_args(&["run", path_str]).unwrap_err().to_string());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_plot_command() {
+        let out = run_args(&["plot", "sin(x)", "--range", "-3.14:3.14", "--height", "9"]).unwrap();
+        assert_eq!(10, out.lines().count());
+        assert!(out.contains('*'));
+
+        assert!(matches!(run_args(&["plot"]), Err(Error::Usage)));
+        assert!(matches!(run_args(&["plot", "x", "--range", "1:0"]), Err(Error::Usage)));
+        assert!(matches!(run_args(&["plot", "x", "--bogus", "1"]), Err(Error::Usage)));
+    }
+
+    #[test]
+    fn test_table_command() {
+        let out = run_args(&["table", "x^2", "--from", "0", "--to", "10", "--step", "5"]).unwrap();
+        let expected = [
+            " x | x^2",
+            "---+----",
+            " 0 | 0",
+            " 5 | 25",
+            "10 | 100",
+            "",
+        ];
+        assert_eq!(expected.join("\n"), out);
+
+        let out = run_args(&["table", "1/x", "--from", "-0.2", "--to", "0.2", "--step", "0.1", "--csv"]).unwrap();
+        let expected = [
+            "x,1/x",
+            "-0.2,-5",
+            "-0.1,-10",
+            "0,inf",
+            "0.1,10",
+            "0.2,5",
+            "",
+        ];
+        assert_eq!(expected.join("\n"), out);
+
+        assert!(matches!(run_args(&["table", "x", "--step", "0"]), Err(Error::Usage)));
+        assert!(matches!(run_args(&["table", "x", "--step", "-1"]), Err(Error::Usage)));
+        assert!(matches!(run_args(&["table", "x", "--from", "0/0.0"]), Err(Error::Usage)));
+        assert!(matches!(run_args(&["table", "x", "--to", "1/0.0"]), Err(Error::Usage)));
+        assert!(matches!(run_args(&["table", "x", "--to", "1e9", "--step", "0.001"]), Err(Error::TooMany("table rows", _, 100_000))));
+    }
+
+    #[test]
+    fn test_csv_columns() {
+        let path = std::env::temp_dir().join(format!("kalkul-test-{}.csv", std::process::id()));
+        std::fs::write(&path, "item,price,qty,unit price\n\"nut, hex\",0.5,4,1\nwasher,-1.2.3.4,1,1\nbolt,2,3,x\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let out = run_args(&["--csv", path, "col(price) * qty", "--name", "total"]).unwrap();
+        let expected = [
+            "item,price,qty,unit price,total",
+            "\"nut, hex\",0.5,4,1,2",
+            "washer,-1.2.3.4,1,1,wrong type of argument",
+            "bolt,2,3,x,6",
+            "",
+        ];
+        assert_eq!(expected.join("\n"), out);
+
+        let out = run_args(&["--csv", path, "col(\"unit price\") * 2"]).unwrap();
+        assert!(out.ends_with("x,wrong type of argument\n"), "{}", out);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reduce() {
+        let input = "3\n1.5\n\n-2\n4\n";
+        let ops = ["+", "*", "mean", "min", "max", "count"];
+        let answers = ["6.5", "-36", "1.625", "-2", "4", "4"];
+        for (op, ans) in std::iter::zip(ops, answers) {
+            assert_eq!(format!("{}\n", ans), run_with_input(&["--reduce", op], input).unwrap(), "{}", op);
+        }
+
+        assert_eq!("0\n", run_with_input(&["--reduce", "sum"], "").unwrap());
+        assert!(matches!(run_with_input(&["--reduce", "max"], ""), Err(Error::NotEnoughElements)));
+        assert!(matches!(run_with_input(&["--reduce", "+"], "1\nfoo\n"), Err(Error::ParseError)));
+        assert!(matches!(run_with_input(&["--reduce", "+"], "1\n-1.2.3.4\n"), Err(Error::ParseError)));
+        assert!(matches!(run_with_input(&["--reduce", "median"], "1\n"), Err(Error::Usage)));
+    }
+}
@@ -0,0 +1,35 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::kalkul::{Error, Result};
+
+/// Programs that put their standard input on the clipboard, tried in
+/// order: Wayland, X11, macOS and Windows.
+const PROGRAMS: &[(&str, &[&str])] = &[
+    ("wl-copy", &[]),
+    ("xclip",   &["-selection", "clipboard"]),
+    ("xsel",    &["--clipboard", "--input"]),
+    ("pbcopy",  &[]),
+    ("clip",    &[]),
+];
+
+/// Puts `text` on the system clipboard, through the first clipboard
+/// program found on the system.
+pub fn copy(text: &str) -> Result<()> {
+    for (program, args) in PROGRAMS {
+        let child = Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            continue;
+        };
+        child.stdin.take().unwrap().write_all(text.as_bytes())?;
+        if child.wait()?.success() {
+            return Ok(());
+        }
+    }
+    Err(Error::NoClipboard)
+}
@@ -0,0 +1,321 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::kalkul::{Error, OpKind, Result, Value};
+#[cfg(feature = "tzdata")]
+use crate::tzif::TzData;
+use crate::units::{self, Quantity};
+
+/// Zones known by their abbreviation, with their offset from UTC in
+/// minutes. Abbreviations that stand for several zones, such as `CST`,
+/// are read the North American way.
+const ZONES: &[(&str, i32)] = &[
+    ("UTC",  0),
+    ("GMT",  0),
+    ("WET",  0),
+    ("WEST", 60),
+    ("CET",  60),
+    ("CEST", 120),
+    ("EET",  120),
+    ("EEST", 180),
+    ("MSK",  180),
+    ("IST",  330),
+    ("SGT",  480),
+    ("HKT",  480),
+    ("AWST", 480),
+    ("JST",  540),
+    ("KST",  540),
+    ("ACST", 570),
+    ("AEST", 600),
+    ("AEDT", 660),
+    ("NZST", 720),
+    ("NZDT", 780),
+    ("BRT",  -180),
+    ("AST",  -240),
+    ("EST",  -300),
+    ("EDT",  -240),
+    ("CST",  -360),
+    ("CDT",  -300),
+    ("MST",  -420),
+    ("MDT",  -360),
+    ("PST",  -480),
+    ("PDT",  -420),
+    ("AKST", -540),
+    ("AKDT", -480),
+    ("HST",  -600),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Rule {
+    /// Seconds east of UTC.
+    Fixed(i32),
+    /// A zone of the system's time zone database, such as `Europe/Paris`.
+    #[cfg(feature = "tzdata")]
+    Database(std::sync::Arc<TzData>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    name: String,
+    rule: Rule,
+}
+
+impl Zone {
+    pub fn utc() -> Zone {
+        Zone { name: "UTC".to_string(), rule: Rule::Fixed(0) }
+    }
+
+    /// Finds a zone by abbreviation, as an offset such as `UTC+5:30`, or,
+    /// with the `tzdata` feature, by its name in the time zone database.
+    pub fn lookup(name: &str) -> Result<Zone> {
+        if let Some(&(_, minutes)) = ZONES.iter().find(|(zone, _)| *zone == name) {
+            return Ok(Zone { name: name.to_string(), rule: Rule::Fixed(minutes * 60) });
+        }
+        if let Some(offset) = name.strip_prefix("UTC").and_then(parse_offset) {
+            return Ok(Zone { name: name.to_string(), rule: Rule::Fixed(offset) });
+        }
+        #[cfg(feature = "tzdata")]
+        if let Some(data) = TzData::load(name) {
+            return Ok(Zone { name: name.to_string(), rule: Rule::Database(std::sync::Arc::new(data)) });
+        }
+        Err(Error::UnknownUnit(name.to_string()))
+    }
+
+    /// Seconds east of UTC at the instant `secs`, and what to call the
+    /// zone then.
+    #[cfg_attr(not(feature = "tzdata"), allow(unused_variables))]
+    fn offset(&self, secs: i64) -> (i32, &str) {
+        match &self.rule {
+            Rule::Fixed(offset) => (*offset, &self.name),
+            #[cfg(feature = "tzdata")]
+            Rule::Database(data) => data.offset(secs),
+        }
+    }
+}
+
+/// Reads an offset such as `+5:30` or `-8` as seconds east of UTC.
+fn parse_offset(s: &str) -> Option<i32> {
+    let sign = match s.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let (hours, minutes) = s[1..].split_once(':').unwrap_or((&s[1..], "0"));
+    let (hours, minutes): (i32, i32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours <= 14 && minutes < 60).then_some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// An instant, shown in a time zone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Time {
+    /// Seconds since 1970-01-01 00:00 UTC.
+    pub secs: i64,
+    pub zone: Zone,
+}
+
+impl Time {
+    pub fn now() -> Time {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+        Time { secs, zone: Zone::utc() }
+    }
+
+    /// Reads `[YYYY-MM-DD] HH:MM[:SS] [ZONE]`, the zone being UTC unless
+    /// given and the date today's in that zone.
+    pub fn parse(s: &str) -> Result<Time> {
+        let mut words = s.split_whitespace().peekable();
+        let date = match words.peek() {
+            Some(word) if word.contains('-') => Some(parse_date(words.next().unwrap()).ok_or(Error::ParseError)?),
+            _ => None,
+        };
+        let clock = parse_clock(words.next().ok_or(Error::ParseError)?).ok_or(Error::ParseError)?;
+        let zone = match words.next() {
+            Some(name) => Zone::lookup(name)?,
+            None => Zone::utc(),
+        };
+        if words.next().is_some() {
+            return Err(Error::ParseError);
+        }
+        let days = match date {
+            Some(days) => days,
+            None => {
+                let now = Time::now().secs;
+                (now + zone.offset(now).0 as i64).div_euclid(86400)
+            },
+        };
+        let local = days * 86400 + clock;
+        // The offset in effect an offset's width before may differ by an
+        // hour around a change of daylight saving time; the second guess
+        // is right unless the local time is skipped or repeated.
+        let guess = local - zone.offset(local).0 as i64;
+        Ok(Time { secs: local - zone.offset(guess).0 as i64, zone })
+    }
+
+    fn in_zone(&self, zone: Zone) -> Time {
+        Time { secs: self.secs, zone }
+    }
+}
+
+impl std::fmt::Display for Time {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (offset, name) = self.zone.offset(self.secs);
+        let local = self.secs + offset as i64;
+        let (year, month, day) = civil_from_days(local.div_euclid(86400));
+        let clock = local.rem_euclid(86400);
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, clock / 3600, clock / 60 % 60)?;
+        if clock % 60 != 0 {
+            write!(f, ":{:02}", clock % 60)?;
+        }
+        write!(f, " {}", name)
+    }
+}
+
+/// Reads `YYYY-MM-DD` as days since 1970-01-01.
+fn parse_date(s: &str) -> Option<i64> {
+    let mut parts = s.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    ((1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day))
+        .then(|| days_from_civil(year, month, day))
+}
+
+/// Reads `HH:MM` or `HH:MM:SS` as seconds since midnight.
+fn parse_clock(s: &str) -> Option<i64> {
+    let parts: Vec<i64> = s.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    match parts[..] {
+        [h, m] if h < 24 && m < 60 => Some(h * 3600 + m * 60),
+        [h, m, s] if h < 24 && m < 60 && s < 60 => Some(h * 3600 + m * 60 + s),
+        _ => None,
+    }
+}
+
+pub fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar,
+/// after Howard Hinnant's `days_from_civil`.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The date `days` after 1970-01-01, undoing `days_from_civil`.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Converts a time to the zone named `name`, for `in`.
+pub fn convert(time: &Time, name: &str) -> Result<Value> {
+    Ok(Value::Time(time.in_zone(Zone::lookup(name)?)))
+}
+
+fn duration_secs(q: &Quantity) -> Result<i64> {
+    let secs = q.to(units::lookup("s").ok_or(Error::TypeError)?)?.amount;
+    if !secs.is_finite() {
+        return Err(Error::NotFinite);
+    }
+    // Rounding would saturate at the ends of the range.
+    if secs.abs() >= i64::MAX as f64 {
+        return Err(Error::Overflow);
+    }
+    Ok(secs.round() as i64)
+}
+
+/// Applies an operator to operands of which at least one is a time, or
+/// returns `None` if neither is. A duration such as `90 min` can be added
+/// to or subtracted from a time, and subtracting two times gives the
+/// duration between them.
+pub fn apply(kind: OpKind, lhs: &Value, rhs: &Value) -> Option<Result<Value>> {
+    let res = match (kind, lhs, rhs) {
+        (OpKind::Plus, Value::Time(t), Value::Quantity(q)) | (OpKind::Plus, Value::Quantity(q), Value::Time(t)) => {
+            let secs = duration_secs(q).and_then(|secs| t.secs.checked_add(secs).ok_or(Error::Overflow));
+            secs.map(|secs| Value::Time(Time { secs, zone: t.zone.clone() }))
+        },
+        (OpKind::Minus, Value::Time(t), Value::Quantity(q)) => {
+            let secs = duration_secs(q).and_then(|secs| t.secs.checked_sub(secs).ok_or(Error::Overflow));
+            secs.map(|secs| Value::Time(Time { secs, zone: t.zone.clone() }))
+        },
+        (OpKind::Minus, Value::Time(a), Value::Time(b)) => match (units::lookup("s"), a.secs.checked_sub(b.secs)) {
+            (Some(unit), Some(secs)) => Ok(Value::Quantity(Quantity { amount: secs as f64, unit })),
+            (None, _) => Err(Error::TypeError),
+            (_, None) => Err(Error::Overflow),
+        },
+        (OpKind::Less | OpKind::LessEqual | OpKind::Greater | OpKind::GreaterEqual | OpKind::Equal | OpKind::NotEqual,
+         Value::Time(a), Value::Time(b)) => Value::apply(kind, Value::Int(a.secs), Value::Int(b.secs)),
+        (_, Value::Time(_), _) | (_, _, Value::Time(_)) => Err(Error::TypeError),
+        _ => return None,
+    };
+    Some(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::Environment;
+
+    #[test]
+    fn test_civil() {
+        for (date, days) in [((1970, 1, 1), 0), ((2000, 3, 1), 11017), ((1969, 12, 31), -1), ((2024, 2, 29), 19782)] {
+            assert_eq!(days, days_from_civil(date.0, date.1, date.2));
+            assert_eq!(date, civil_from_days(days));
+        }
+    }
+
+    #[test]
+    fn test_times() {
+        let exprs = [
+            "time(\"2024-03-10 14:30 UTC\") in PST",
+            "time(\"2024-03-10 14:30\") in \"UTC+5:30\"",
+            "time(\"2024-03-10 23:30 EST\") in JST",
+            "time(\"2024-03-10 14:30 UTC\") + 90 min",
+            "time(\"2024-03-10 14:30 UTC\") - 1 d + 15 s",
+            "(time(\"2024-03-10 14:30 UTC\") - time(\"2024-03-10 12:00 UTC\")) in h",
+            "time(\"2024-03-10 14:30 CET\") < time(\"2024-03-10 14:30 UTC\")",
+        ];
+        let answers = [
+            "2024-03-10 06:30 PST",
+            "2024-03-10 20:00 UTC+5:30",
+            "2024-03-11 13:30 JST",
+            "2024-03-10 16:00 UTC",
+            "2024-03-09 14:30:15 UTC",
+            "2.5 h",
+            "1",
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        for expr in ["time(\"25:00\")", "time(\"2024-02-30 10:00\")", "time(\"10:00\") + 1", "time(\"10:00\") + 1 GB", "now() * 2"] {
+            assert!(env.eval(expr).is_err(), "{}", expr);
+        }
+        assert!(matches!(env.eval("time(\"10:00 XYZ\")"), Err(Error::UnknownUnit(_))));
+        assert!(matches!(env.eval("now() in Mars"), Err(Error::UnknownUnit(_))));
+        for expr in ["time(\"10:00\") + 1e18 d", "time(\"10:00\") - 1e18 d", "time(\"10:00\") + 1e5 s + 9223372036854775000 s"] {
+            assert!(matches!(env.eval(expr), Err(Error::Overflow)), "{}", expr);
+        }
+    }
+}
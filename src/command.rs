@@ -0,0 +1,193 @@
+use crate::env::{IntWidth, Modulus};
+use crate::format::{self, Notation};
+use crate::kalkul::{Dialect, Error, Result, Strictness};
+
+/// Register used by the memory commands when none is named.
+pub const DEFAULT_REGISTER: &str = "M";
+
+/// A REPL command, the part of a line after its leading `:`. Commands
+/// change how the session behaves rather than computing anything, so
+/// they have a syntax of their own: a name followed by words.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Command<'a> {
+    /// `:norm`, `:sci`, `:eng` or `:fix DIGITS`.
+    Notation(Notation),
+    /// `:base dec|hex|oct|bin`, the radix integer results are shown in.
+    Base(u32),
+    /// `:width u8|i16|...`, or `None` for `:width off`.
+    Width(Option<IntWidth>),
+    /// `:mod M`, integer arithmetic modulo `M`, or `None` for `:mod off`.
+    Modulus(Option<Modulus>),
+    /// `:digits N`, results computed to `N` significant digits where
+    /// they can be, or `None` for `:digits off`.
+    #[cfg(feature = "precise")]
+    Digits(Option<usize>),
+    /// `:mode float` carries infinities and NaN along like IEEE-754 does,
+    /// while `:mode strict` fails as soon as one comes up.
+    StrictFloat(bool),
+    /// `:syntax strict|permissive`, how forgiving parsing is.
+    Syntax(Strictness),
+    /// `:dialect kalkul|python|excel|c`, the language expressions are written in.
+    Dialect(Dialect),
+    /// `:time on|off`, whether results are followed by how long they took.
+    Time(bool),
+    /// `:si on|off`, whether numbers may end in an SI prefix, as in `4.7k`.
+    SiPrefixes(bool),
+    /// `:multibase`, switches showing integers in hex and binary too
+    /// while `:width` is set.
+    Multibase,
+    /// `:vars`, lists the variables.
+    Vars,
+    /// `:funcs`, lists the user-defined functions.
+    Funcs,
+    /// `:clear`, removes every variable and function.
+    Clear,
+    Undo,
+    /// `:m+ [REGISTER]`.
+    MemoryAdd(&'a str),
+    /// `:m- [REGISTER]`.
+    MemorySubtract(&'a str),
+    /// `:mc [REGISTER]`.
+    MemoryClear(&'a str),
+    /// `:mr [REGISTER]`.
+    MemoryRecall(&'a str),
+    /// `:copy`, puts the last result on the system clipboard.
+    #[cfg(feature = "clipboard")]
+    Copy,
+    /// `:alias NAME TARGET` makes `NAME` call the function `TARGET`, and
+    /// `:alias` alone lists the aliases.
+    Alias(Option<(&'a str, &'a str)>),
+    /// `:export FILE`, saves the session's results as CSV or JSON.
+    Export(&'a str),
+    /// `:simplify EXPR`.
+    Simplify(&'a str),
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(src: &'a str) -> Result<Command<'a>> {
+        let src = src.trim();
+        let (name, rest) = match src.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest.trim()),
+            None => (src, ""),
+        };
+        match name {
+            "simplify" if !rest.is_empty() => return Ok(Command::Simplify(rest)),
+            "export" if !rest.is_empty()   => return Ok(Command::Export(rest)),
+            _ => {},
+        }
+
+        let mut args = rest.split_whitespace();
+        if name == "alias" {
+            return match (args.next(), args.next(), args.next()) {
+                (None, _, _) => Ok(Command::Alias(None)),
+                (Some(alias), Some(target), None) => Ok(Command::Alias(Some((alias, target)))),
+                _ => Err(Error::UnknownCommand),
+            };
+        }
+        let arg = args.next();
+        if args.next().is_some() {
+            return Err(Error::UnknownCommand);
+        }
+
+        let register = arg.unwrap_or(DEFAULT_REGISTER);
+        let cmd = match (name, arg) {
+            ("norm", None)  => Command::Notation(Notation::Normal),
+            ("sci", None)   => Command::Notation(Notation::Scientific),
+            ("eng", None)   => Command::Notation(Notation::Engineering),
+            ("fix", Some(digits)) => match digits.parse() {
+                Ok(digits @ 0..=format::MAX_FIXED_DIGITS) => Command::Notation(Notation::Fixed(digits)),
+                _ => return Err(Error::ParseError),
+            },
+            ("base", Some(base)) => Command::Base(match base {
+                "dec" => 10,
+                "hex" => 16,
+                "oct" => 8,
+                "bin" => 2,
+                _ => return Err(Error::UnknownCommand),
+            }),
+            ("width", Some("off")) => Command::Width(None),
+            ("width", Some(name))  => Command::Width(Some(IntWidth::from_name(name).ok_or(Error::UnknownCommand)?)),
+            ("mod", Some("off")) => Command::Modulus(None),
+            ("mod", Some(m)) => match m.parse().ok().and_then(Modulus::new) {
+                Some(m) => Command::Modulus(Some(m)),
+                None => return Err(Error::ParseError),
+            },
+            #[cfg(feature = "precise")]
+            ("digits", Some("off")) => Command::Digits(None),
+            #[cfg(feature = "precise")]
+            ("digits", Some(n)) => match n.parse() {
+                Ok(n @ 1..=crate::precise::MAX_DIGITS) => Command::Digits(Some(n)),
+                _ => return Err(Error::ParseError),
+            },
+            ("mode", Some("float"))  => Command::StrictFloat(false),
+            ("mode", Some("strict")) => Command::StrictFloat(true),
+            ("syntax", Some("strict"))     => Command::Syntax(Strictness::Strict),
+            ("syntax", Some("permissive")) => Command::Syntax(Strictness::Permissive),
+            ("dialect", Some("kalkul")) => Command::Dialect(Dialect::Kalkul),
+            ("dialect", Some("python")) => Command::Dialect(Dialect::Python),
+            ("dialect", Some("excel"))  => Command::Dialect(Dialect::Excel),
+            ("dialect", Some("c"))      => Command::Dialect(Dialect::C),
+            ("time", Some(switch)) => Command::Time(on_off(switch)?),
+            ("si", Some(switch))   => Command::SiPrefixes(on_off(switch)?),
+            ("multibase", None) => Command::Multibase,
+            ("vars", None)  => Command::Vars,
+            ("funcs", None) => Command::Funcs,
+            ("clear", None) => Command::Clear,
+            ("undo", None)  => Command::Undo,
+            #[cfg(feature = "clipboard")]
+            ("copy", None)  => Command::Copy,
+            ("m+", _) => Command::MemoryAdd(register),
+            ("m-", _) => Command::MemorySubtract(register),
+            ("mc", _) => Command::MemoryClear(register),
+            ("mr", _) => Command::MemoryRecall(register),
+            _ => return Err(Error::UnknownCommand),
+        };
+        Ok(cmd)
+    }
+}
+
+fn on_off(switch: &str) -> Result<bool> {
+    match switch {
+        "on"  => Ok(true),
+        "off" => Ok(false),
+        _ => Err(Error::UnknownCommand),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let cmds = ["fix 3", "base hex", "width off", "mod 97", "mode strict", "multibase", "dialect python", " time  on ", "si off", "vars", "m+", "mr m1", "export my results.csv", "alias avg mean", "simplify x +  x"];
+        let answers = [
+            Command::Notation(Notation::Fixed(3)),
+            Command::Base(16),
+            Command::Width(None),
+            Command::Modulus(Modulus::new(97)),
+            Command::StrictFloat(true),
+            Command::Multibase,
+            Command::Dialect(Dialect::Python),
+            Command::Time(true),
+            Command::SiPrefixes(false),
+            Command::Vars,
+            Command::MemoryAdd(DEFAULT_REGISTER),
+            Command::MemoryRecall("m1"),
+            Command::Export("my results.csv"),
+            Command::Alias(Some(("avg", "mean"))),
+            Command::Simplify("x +  x"),
+        ];
+        for (cmd, ans) in std::iter::zip(cmds, answers) {
+            assert_eq!(ans, Command::parse(cmd).unwrap(), "{}", cmd);
+        }
+
+        for cmd in ["", "base 7", "width u64", "time maybe", "vars x", "multibase on", "mode", "dialect fortran", "m+ a b", "simplify", "export", "alias avg"] {
+            assert!(matches!(Command::parse(cmd), Err(Error::UnknownCommand)), "{}", cmd);
+        }
+        assert!(matches!(Command::parse("fix x"), Err(Error::ParseError)));
+        assert!(matches!(Command::parse("fix 1000000000"), Err(Error::ParseError)));
+        assert!(matches!(Command::parse("mod 1"), Err(Error::ParseError)));
+    }
+}
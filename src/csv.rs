@@ -0,0 +1,59 @@
+use std::io::Write;
+
+use crate::kalkul::Result;
+
+/// Quotes a field if it contains a separator, quote or line break.
+pub fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn write_record<S: AsRef<str>>(out: &mut impl Write, fields: &[S]) -> Result<()> {
+    let fields: Vec<String> = fields.iter().map(|f| quote(f.as_ref())).collect();
+    writeln!(out, "{}", fields.join(","))?;
+    Ok(())
+}
+
+/// Splits a line into fields, undoing the quoting done by `quote`.
+/// Quoted fields spanning several lines are not supported.
+pub fn parse_record(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut cs = line.trim_end_matches(['\r', '\n']).chars().peekable();
+
+    while let Some(c) = cs.next() {
+        match (c, quoted) {
+            ('"', true) if cs.peek() == Some(&'"') => {
+                field.push('"');
+                cs.next();
+            },
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_record() {
+        let mut out = Vec::new();
+        write_record(&mut out, &["x", "f(x, y)", "say \"hi\""]).unwrap();
+        assert_eq!("x,\"f(x, y)\",\"say \"\"hi\"\"\"\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_parse_record() {
+        assert_eq!(vec!["a", "", "b c"], parse_record("a,,b c\n"));
+        assert_eq!(vec!["x", "f(x, y)", "say \"hi\""], parse_record("x,\"f(x, y)\",\"say \"\"hi\"\"\""));
+    }
+}
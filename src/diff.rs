@@ -0,0 +1,107 @@
+use std::fmt;
+
+use crate::kalkul::Expr;
+
+/// A subexpression that differs between two expressions.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Change {
+    /// Where it is, as the positions of the operands or arguments taken
+    /// on the way down from the whole expression.
+    pub path: Vec<usize>,
+    /// What was there, or `None` for an argument or item added.
+    pub old: Option<Expr>,
+    /// What is there now, or `None` for one removed.
+    pub new: Option<Expr>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, "{} changed to {}", old, new),
+            (Some(old), None)      => write!(f, "{} removed", old),
+            (None, Some(new))      => write!(f, "{} added", new),
+            (None, None)           => Ok(()),
+        }
+    }
+}
+
+/// The smallest subexpressions in which `a` and `b` differ, from left
+/// to right: changing the rate in `price * (1 + 0.05)` to 0.07 gives
+/// just the change from `0.05` to `0.07`. Where the operator, function
+/// or kind of expression differs, the whole subexpression has changed.
+/// Arguments and items are compared by position, so one added or
+/// removed at the end is reported on its own.
+pub fn diff_exprs(a: &Expr, b: &Expr) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_into(a, b, &mut Vec::new(), &mut changes);
+    changes
+}
+
+fn diff_into(a: &Expr, b: &Expr, path: &mut Vec<usize>, changes: &mut Vec<Change>) {
+    if a == b {
+        return;
+    }
+    let children = match (a, b) {
+        (Expr::Neg(u), Expr::Neg(v)) => Some((std::slice::from_ref(&**u), std::slice::from_ref(&**v))),
+        (Expr::Binary(k, u1, v1), Expr::Binary(l, u2, v2)) if k == l => {
+            for (i, (u, v)) in [(u1, u2), (v1, v2)].into_iter().enumerate() {
+                path.push(i);
+                diff_into(u, v, path, changes);
+                path.pop();
+            }
+            return;
+        },
+        (Expr::Call(f, args1), Expr::Call(g, args2)) if f == g => Some((&args1[..], &args2[..])),
+        (Expr::List(items1), Expr::List(items2)) | (Expr::Tuple(items1), Expr::Tuple(items2)) => Some((&items1[..], &items2[..])),
+        _ => None,
+    };
+    let Some((old, new)) = children else {
+        changes.push(Change { path: path.clone(), old: Some(a.clone()), new: Some(b.clone()) });
+        return;
+    };
+    for i in 0..old.len().max(new.len()) {
+        path.push(i);
+        match (old.get(i), new.get(i)) {
+            (Some(u), Some(v)) => diff_into(u, v, path, changes),
+            (u, v) => changes.push(Change { path: path.clone(), old: u.cloned(), new: v.cloned() }),
+        }
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff_exprs() {
+        let pairs = [
+            ("price * (1 + 0.05)", "price * (1 + 0.07)"),
+            ("a + b * c", "a - b * c"),
+            ("a + b * c", "a + b * d"),
+            ("max(x, y)", "max(x, y, 0)"),
+            ("[1, 2, 3]", "[1, 5]"),
+            ("f(x) + g(y)", "h(x) + g(z)"),
+            ("-x", "-x"),
+        ];
+        let answers: [&[&str]; 7] = [
+            &["0.05 changed to 0.07"],
+            &["a + b*c changed to a - b*c"],
+            &["c changed to d"],
+            &["0 added"],
+            &["2 changed to 5", "3 removed"],
+            &["f(x) changed to h(x)", "y changed to z"],
+            &[],
+        ];
+
+        for ((a, b), ans) in std::iter::zip(pairs, answers) {
+            let changes = diff_exprs(&Expr::parse(a).unwrap(), &Expr::parse(b).unwrap());
+            let changes: Vec<String> = changes.iter().map(Change::to_string).collect();
+            assert_eq!(ans, changes.as_slice(), "{} {}", a, b);
+        }
+
+        let changes = diff_exprs(&Expr::parse("a + b * c").unwrap(), &Expr::parse("a + b * d").unwrap());
+        assert_eq!(vec![1, 1], changes[0].path);
+    }
+}
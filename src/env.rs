@@ -0,0 +1,1293 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::audit::{AuditEvent, AuditOp, AuditSink};
+use crate::builtins;
+use crate::function::{self, Function};
+use crate::symbol::{SymbolId, SymbolTable};
+use crate::units::{self, Unit};
+
+use crate::kalkul::{self, Dialect, Error, EvalDetails, EvalOutput, Expr, Known, OpKind, Result, Syntax, Unknown, Usage, Value, Warning};
+
+/// Bounds on the work done while evaluating, for expressions coming
+/// from untrusted sources.
+#[derive(Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct Limits {
+    /// Evaluation steps allowed before failing with `Error::LimitExceeded`.
+    pub max_steps: Option<u64>,
+    /// Point in time after which evaluation fails with `Error::Timeout`.
+    pub deadline: Option<Instant>,
+    /// Steps a call of a user-defined function may take, counting those
+    /// of the calls it makes in turn, before failing with
+    /// `Error::FunctionStepLimit`, so that a definition such as
+    /// `f(n) = f(n - 1) + f(n - 1)` cannot run for ages.
+    pub call_steps: Option<u64>,
+    /// Bytes a call of a user-defined function may allocate for the
+    /// values it makes, roughly, before failing with
+    /// `Error::FunctionMemoryLimit`.
+    pub call_memory: Option<u64>,
+}
+
+/// Built up from `Limits::default()`, which sets none, as in
+/// `Limits::default().with_max_steps(100_000).with_deadline(deadline)`.
+impl Limits {
+    pub fn with_max_steps(self, steps: u64) -> Self {
+        Limits { max_steps: Some(steps), ..self }
+    }
+
+    pub fn with_deadline(self, deadline: Instant) -> Self {
+        Limits { deadline: Some(deadline), ..self }
+    }
+
+    pub fn with_call_steps(self, steps: u64) -> Self {
+        Limits { call_steps: Some(steps), ..self }
+    }
+
+    pub fn with_call_memory(self, bytes: u64) -> Self {
+        Limits { call_memory: Some(bytes), ..self }
+    }
+}
+
+/// What integer arithmetic does with a result that does not fit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[non_exhaustive]
+pub enum Overflow {
+    /// Carry on in floating point.
+    #[default]
+    Float,
+    /// Keep the low bits, like machine registers do.
+    Wrap,
+    /// Clamp to the largest or smallest integer.
+    Saturate,
+    /// Fail with `Error::Overflow`.
+    Error,
+}
+
+impl Overflow {
+    /// Fits an exact result into an integer of the given width, or returns
+    /// `None` when the calculation should be redone in floating point.
+    pub fn fit(self, n: i128, width: IntWidth) -> Result<Option<i64>> {
+        let n = if width.contains(n) {
+            n
+        } else {
+            match self {
+                Overflow::Float    => return Ok(None),
+                Overflow::Wrap     => width.wrap(n),
+                Overflow::Saturate => n.clamp(width.min(), width.max()),
+                Overflow::Error    => return Err(Error::Overflow),
+            }
+        };
+        // Every width `IntWidth::new` accepts fits in an i64.
+        Ok(Some(n as i64))
+    }
+}
+
+/// Word size of integer arithmetic, for checking what fixed-width machine
+/// arithmetic would compute. Only widths whose every value fits in the
+/// 64-bit signed integers of `Value::Int` can be had, so the widest
+/// unsigned one is `U63`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntWidth {
+    bits: u32,
+    signed: bool,
+}
+
+impl IntWidth {
+    pub const I8: IntWidth  = IntWidth { bits: 8,  signed: true };
+    pub const I16: IntWidth = IntWidth { bits: 16, signed: true };
+    pub const I32: IntWidth = IntWidth { bits: 32, signed: true };
+    pub const I64: IntWidth = IntWidth { bits: 64, signed: true };
+    pub const U8: IntWidth  = IntWidth { bits: 8,  signed: false };
+    pub const U16: IntWidth = IntWidth { bits: 16, signed: false };
+    pub const U32: IntWidth = IntWidth { bits: 32, signed: false };
+    pub const U63: IntWidth = IntWidth { bits: 63, signed: false };
+
+    /// A width of `bits` bits, or `None` for one with values out of the
+    /// range of `Value::Int`, such as 64 unsigned bits.
+    pub fn new(bits: u32, signed: bool) -> Option<IntWidth> {
+        let widest = if signed { 64 } else { 63 };
+        (1..=widest).contains(&bits).then_some(IntWidth { bits, signed })
+    }
+
+    pub fn bits(self) -> u32 {
+        self.bits
+    }
+
+    pub fn signed(self) -> bool {
+        self.signed
+    }
+
+    /// Looks a width up by its Rust type name, such as `u8`, or `u63`
+    /// for the widest unsigned one.
+    pub fn from_name(name: &str) -> Option<IntWidth> {
+        match name {
+            "i8"  => Some(IntWidth::I8),
+            "i16" => Some(IntWidth::I16),
+            "i32" => Some(IntWidth::I32),
+            "i64" => Some(IntWidth::I64),
+            "u8"  => Some(IntWidth::U8),
+            "u16" => Some(IntWidth::U16),
+            "u32" => Some(IntWidth::U32),
+            "u63" => Some(IntWidth::U63),
+            _ => None,
+        }
+    }
+
+    pub fn min(self) -> i128 {
+        if self.signed { -(1 << (self.bits - 1)) } else { 0 }
+    }
+
+    pub fn max(self) -> i128 {
+        if self.signed { (1 << (self.bits - 1)) - 1 } else { (1 << self.bits) - 1 }
+    }
+
+    pub fn contains(self, n: i128) -> bool {
+        self.min() <= n && n <= self.max()
+    }
+
+    /// Keeps the low `bits` bits of `n`, read back as signed or unsigned.
+    pub fn wrap(self, n: i128) -> i128 {
+        let low = n & ((1 << self.bits) - 1);
+        if self.signed && low > self.max() { low - (1 << self.bits) } else { low }
+    }
+}
+
+impl Default for IntWidth {
+    fn default() -> Self {
+        IntWidth::I64
+    }
+}
+
+/// The modulus of `:mod`, at least 2, as there is no arithmetic modulo
+/// zero or a negative number and everything is 0 modulo 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Modulus(i64);
+
+impl Modulus {
+    /// `m` as a modulus, or `None` if it is less than 2.
+    pub fn new(m: i64) -> Option<Modulus> {
+        (m > 1).then_some(Modulus(m))
+    }
+
+    pub fn get(self) -> i64 {
+        self.0
+    }
+}
+
+/// Switches that change how expressions are evaluated.
+#[derive(Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct EvalOptions {
+    /// Fail with `Error::NotFinite` where a float calculation first comes
+    /// out infinite or NaN, instead of carrying the value along.
+    pub strict_float: bool,
+    pub overflow: Overflow,
+    pub width: IntWidth,
+    pub syntax: Syntax,
+    /// `:mod m`: integers are added, subtracted, multiplied, divided and
+    /// raised to powers modulo `m`. Negation is left alone, so that
+    /// `2 ^ -1` is the inverse of 2.
+    pub modulus: Option<Modulus>,
+    /// The only operators and builtin functions expressions may use, by
+    /// how they are written, see `allow_only`. `-` covers negation too.
+    pub allowed: Option<&'static [&'static str]>,
+}
+
+impl EvalOptions {
+    /// Options under which expressions can only use the operators and
+    /// builtin functions named, such as
+    /// `EvalOptions::allow_only(&["+", "-", "*", "/", "sqrt", "sum"])`, for
+    /// services evaluating formulas entered by their users. Anything else
+    /// fails with `Error::NotAllowed` when it is reached. Functions defined
+    /// by the embedder stay callable.
+    pub fn allow_only(names: &'static [&'static str]) -> Self {
+        EvalOptions { allowed: Some(names), ..EvalOptions::default() }
+    }
+
+    /// Options for expressions written in `dialect`, such as
+    /// `EvalOptions::dialect(Dialect::Python)` for `7 / 2` to give `3.5`
+    /// and `2 ** 10 ^ 1` to give `1025`.
+    pub fn dialect(dialect: Dialect) -> Self {
+        let options = EvalOptions {
+            syntax: Syntax { dialect, ..Syntax::default() },
+            ..EvalOptions::default()
+        };
+        match dialect {
+            // Integer arithmetic is done on C's `int`.
+            Dialect::C => EvalOptions { overflow: Overflow::Wrap, width: IntWidth::I32, ..options },
+            _ => options,
+        }
+    }
+
+    pub fn with_strict_float(self, strict_float: bool) -> Self {
+        EvalOptions { strict_float, ..self }
+    }
+
+    pub fn with_overflow(self, overflow: Overflow) -> Self {
+        EvalOptions { overflow, ..self }
+    }
+
+    pub fn with_width(self, width: IntWidth) -> Self {
+        EvalOptions { width, ..self }
+    }
+
+    pub fn with_syntax(self, syntax: Syntax) -> Self {
+        EvalOptions { syntax, ..self }
+    }
+
+    pub fn with_modulus(self, modulus: Option<Modulus>) -> Self {
+        EvalOptions { modulus, ..self }
+    }
+
+    pub fn with_allowed(self, allowed: Option<&'static [&'static str]>) -> Self {
+        EvalOptions { allowed, ..self }
+    }
+}
+
+/// Told about the variables an environment reads and writes, once set
+/// with `Environment::set_observer`, for tracking which inputs a formula
+/// used or loading variables only when they are first needed.
+pub trait VarObserver: std::fmt::Debug + Send + Sync {
+    /// Called for each read of the variable `name` by an expression, with
+    /// its value, or `None` if it is not set. A value returned for an
+    /// unset variable is set and read in its place.
+    fn on_var_read(&self, name: &str, val: Option<&Value>) -> Option<Value> {
+        let _ = (name, val);
+        None
+    }
+
+    /// Called for each variable set, with its new value.
+    fn on_var_write(&self, name: &str, val: &Value) {
+        let _ = (name, val);
+    }
+}
+
+/// Where an environment finds the variables that are not set in it, see
+/// `Environment::with_provider`. Values are fetched each time they are
+/// read and never kept, so the variables need not all be known up front.
+pub trait VariableProvider: std::fmt::Debug + Send + Sync {
+    /// The value of the variable `name`, or `None` if there is no such
+    /// variable. Failures to fetch one are usually `Error::FetchFailed`.
+    fn fetch(&self, name: &str) -> Result<Option<Value>>;
+}
+
+/// How many steps go by between checks of the deadline.
+const DEADLINE_INTERVAL: u64 = 1024;
+
+/// Roughly how many bytes the heap holds for `val`, for
+/// `Limits::call_memory`.
+fn heap_size(val: &Value) -> u64 {
+    match val {
+        Value::Str(s) => s.len() as u64,
+        Value::Expr(_) => std::mem::size_of::<Expr>() as u64,
+        Value::List(vs) | Value::Tuple(vs) => {
+            vs.iter().map(|v| std::mem::size_of::<Value>() as u64 + heap_size(v)).sum()
+        },
+        _ => 0,
+    }
+}
+
+/// State shared by every expression evaluated in a session.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    /// Names of the variables ever set here. This and the other maps of
+    /// names are shared with the `Unknown` errors raised, see `Names`,
+    /// and copied only if changed while one is kept.
+    symbols: Arc<SymbolTable>,
+    /// Values of the variables, by `SymbolId`.
+    vars: Arc<Vec<Option<Value>>>,
+    registers: HashMap<String, Value>,
+    /// Read-only scope consulted for names not set here, see `child`.
+    parent: Option<Arc<Environment>>,
+    limits: Limits,
+    steps: u64,
+    options: EvalOptions,
+    warnings: Vec<Warning>,
+    functions: Arc<HashMap<String, Arc<Function>>>,
+    /// Other names functions can be called by, such as `avg` for `mean`.
+    aliases: Arc<HashMap<String, String>>,
+    /// Units declared at runtime, see `units::define`.
+    units: HashMap<String, Unit>,
+    /// Number of user-defined function calls in progress.
+    depth: usize,
+    /// Where operations are recorded, see `set_audit`.
+    audit: Option<Arc<dyn AuditSink>>,
+    /// Number of operations recorded so far.
+    audited: u64,
+    /// Told about reads and writes of variables, see `set_observer`.
+    observer: Option<Arc<dyn VarObserver>>,
+    /// Asked for the variables not set here, see `with_provider`.
+    provider: Option<Arc<dyn VariableProvider>>,
+    /// Number of expressions being evaluated, each inside the last.
+    eval_depth: usize,
+    /// Resources used since `eval_output` started, less the operations.
+    usage: Usage,
+    /// Steps taken and bytes allocated when the outermost call of a
+    /// user-defined function in progress started.
+    call_start: Option<(u64, u64)>,
+    /// Bytes allocated for values, counted only while there is a budget
+    /// for calls to keep to.
+    allocated: u64,
+}
+
+/// Names of the variables set in `vars`, followed by the parent's.
+fn set_names<'a>(symbols: &'a SymbolTable, vars: &[Option<Value>], parent: Option<&'a Environment>) -> Vec<&'a str> {
+    let mut names: Vec<&str> = symbols.iter()
+        .filter(|(id, _)| matches!(vars.get(id.index()), Some(Some(_))))
+        .map(|(_, name)| name)
+        .collect();
+    if let Some(parent) = parent {
+        names.extend(parent.names());
+    }
+    names
+}
+
+/// The names of an environment an `Unknown` it raised could have meant,
+/// as they were then. Only the maps are shared, so raising one does not
+/// copy every name, as when `symbolic::partial_eval` leaves unknown
+/// variables be.
+enum Names {
+    Variables {
+        symbols: Arc<SymbolTable>,
+        vars: Arc<Vec<Option<Value>>>,
+        parent: Option<Arc<Environment>>,
+    },
+    Functions {
+        functions: Arc<HashMap<String, Arc<Function>>>,
+        aliases: Arc<HashMap<String, String>>,
+        parent: Option<Arc<Environment>>,
+    },
+}
+
+impl Known for Names {
+    fn names(&self) -> Vec<&str> {
+        match self {
+            Names::Variables { symbols, vars, parent } => set_names(symbols, vars, parent.as_deref()),
+            Names::Functions { functions, aliases, parent } => {
+                let mut names: Vec<&str> = builtins::BUILTINS.iter().map(|b| b.name)
+                    .chain(builtins::FORMS.iter().map(|f| f.name))
+                    .chain(functions.keys().chain(aliases.keys()).map(String::as_str))
+                    .collect();
+                let mut env = parent.as_deref();
+                while let Some(e) = env {
+                    names.extend(e.functions.keys().chain(e.aliases.keys()).map(String::as_str));
+                    env = e.parent.as_deref();
+                }
+                names
+            },
+        }
+    }
+}
+
+/// A saved copy of an environment's variables and registers, see
+/// `Environment::snapshot`.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    vars: Arc<Vec<Option<Value>>>,
+    registers: HashMap<String, Value>,
+    functions: Arc<HashMap<String, Arc<Function>>>,
+    aliases: Arc<HashMap<String, String>>,
+    units: HashMap<String, Unit>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment {
+            symbols: Arc::new(SymbolTable::new()),
+            vars: Arc::new(Vec::new()),
+            registers: HashMap::new(),
+            parent: None,
+            limits: Limits::default(),
+            steps: 0,
+            options: EvalOptions::default(),
+            warnings: Vec::new(),
+            functions: Arc::new(HashMap::new()),
+            aliases: Arc::new(HashMap::new()),
+            units: HashMap::new(),
+            depth: 0,
+            audit: None,
+            audited: 0,
+            observer: None,
+            provider: None,
+            eval_depth: 0,
+            usage: Usage::default(),
+            call_start: None,
+            allocated: 0,
+        }
+    }
+
+    /// Creates an empty environment that can read, but never change, the
+    /// variables and registers of `parent`. Setting a name the parent
+    /// already has shadows it for this environment only, so one set of
+    /// globals can back any number of independent sessions.
+    pub fn child(parent: &Arc<Environment>) -> Self {
+        Environment {
+            parent: Some(Arc::clone(parent)),
+            ..Environment::new()
+        }
+    }
+
+    /// Creates an empty environment that fetches the variables not set
+    /// in it from `provider` whenever an expression reads them.
+    pub fn with_provider(provider: Arc<dyn VariableProvider>) -> Self {
+        Environment {
+            provider: Some(provider),
+            ..Environment::new()
+        }
+    }
+
+    /// Sets the limits for subsequent evaluations and resets the count
+    /// of steps taken so far.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.steps = 0;
+    }
+
+    /// Sends every operator applied, value negated and builtin called
+    /// from now on to `sink`, with its operands and result, or stops
+    /// doing so for `None`. Copies of the environment share the sink.
+    pub fn set_audit(&mut self, sink: Option<Arc<dyn AuditSink>>) {
+        self.audit = sink;
+    }
+
+    pub fn auditing(&self) -> bool {
+        self.audit.is_some()
+    }
+
+    /// Tells `observer` about every variable read by expressions and
+    /// every variable set from now on, or stops doing so for `None`.
+    /// Copies of the environment share the observer.
+    pub fn set_observer(&mut self, observer: Option<Arc<dyn VarObserver>>) {
+        self.observer = observer;
+    }
+
+    /// Fails unless the options allow the operator or builtin `name`.
+    pub fn check_allowed(&self, name: &'static str) -> Result<()> {
+        match self.options.allowed {
+            Some(allowed) if !allowed.contains(&name) => Err(Error::NotAllowed(name)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hands an operation to the audit sink, if there is one.
+    pub fn audit(&mut self, op: AuditOp, operands: Vec<Value>, result: &Result<Value>) {
+        if let Some(sink) = &self.audit {
+            let result = match result {
+                Ok(val) => Ok(val.clone()),
+                Err(e) => Err(e.to_string()),
+            };
+            sink.record(AuditEvent { seq: self.audited, op, operands, result });
+            self.audited += 1;
+        }
+    }
+
+    pub fn set_options(&mut self, options: EvalOptions) {
+        self.options = options;
+    }
+
+    pub fn options(&self) -> EvalOptions {
+        self.options
+    }
+
+    /// Passes a freshly computed value through, unless the options rule
+    /// it out. Integers, including literals, are brought into the word
+    /// size.
+    pub fn check_value(&mut self, val: Value) -> Result<Value> {
+        if matches!(val, Value::Str(_) | Value::Expr(_) | Value::List(_) | Value::Tuple(_)) {
+            self.usage.allocations += 1;
+        }
+        if let (Some(max), Some((_, start))) = (self.limits.call_memory, self.call_start) {
+            self.allocated += heap_size(&val);
+            if self.allocated - start > max {
+                return Err(Error::FunctionMemoryLimit);
+            }
+        }
+        if let Value::Int(n) = val {
+            return match self.options.overflow.fit(n as i128, self.options.width)? {
+                Some(n) => Ok(Value::Int(n)),
+                None => self.check_value(Value::Float(n as f64)),
+            };
+        }
+        fn finite(val: &Value) -> bool {
+            match val {
+                Value::Float(x) => x.is_finite(),
+                Value::List(vs) | Value::Tuple(vs) => vs.iter().all(finite),
+                _ => true,
+            }
+        }
+        if self.options.strict_float && !finite(&val) {
+            return Err(Error::NotFinite);
+        }
+        Ok(val)
+    }
+
+    /// Notes that an expression is being evaluated inside the current
+    /// one, until the matching `leave_eval`.
+    pub fn enter_eval(&mut self) {
+        self.eval_depth += 1;
+        self.note_depth(self.eval_depth);
+    }
+
+    pub fn leave_eval(&mut self) {
+        self.eval_depth -= 1;
+    }
+
+    /// Accounts for `depth` nested expressions or stacked values in the
+    /// usage of the current evaluation.
+    pub fn note_depth(&mut self, depth: usize) {
+        self.usage.max_depth = self.usage.max_depth.max(depth);
+    }
+
+    /// Number of evaluation steps taken since the limits were last set.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Accounts for one evaluation step, failing once a limit is hit.
+    pub fn tick(&mut self) -> Result<()> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(Error::LimitExceeded);
+            }
+        }
+        if let (Some(max), Some((start, _))) = (self.limits.call_steps, self.call_start) {
+            if self.steps - start > max {
+                return Err(Error::FunctionStepLimit);
+            }
+        }
+        if let Some(deadline) = self.limits.deadline {
+            if self.steps.is_multiple_of(DEADLINE_INTERVAL) && Instant::now() >= deadline {
+                return Err(Error::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn eval(&mut self, src: &str) -> Result<Value> {
+        self.parse(src)?.eval(self)
+    }
+
+    /// Parses `src` as strictly as the options say.
+    pub fn parse(&self, src: &str) -> Result<Expr> {
+        Expr::parse_with_units(src, self.options.syntax, &|name| self.unit(name))
+    }
+
+    /// Like `eval`, also returning the warnings raised along the way.
+    pub fn eval_output(&mut self, src: &str) -> Result<EvalOutput> {
+        self.warnings.clear();
+        self.usage = Usage::default();
+        let steps = self.steps;
+        let value = self.eval(src)?;
+        let usage = Usage { operations: self.steps - steps, ..self.usage };
+        Ok(EvalOutput { value, warnings: self.take_warnings(), usage })
+    }
+
+    /// Like `eval_output`, also measuring the work done.
+    pub fn eval_detailed(&mut self, src: &str) -> Result<EvalDetails> {
+        let start = Instant::now();
+        let tokens = kalkul::tokenize(src)?.len();
+        let expr = self.parse(src)?;
+        let parse_time = start.elapsed();
+        let steps = self.steps;
+        self.warnings.clear();
+        let value = expr.eval(self)?;
+        Ok(EvalDetails {
+            value,
+            warnings: self.take_warnings(),
+            tokens,
+            steps: self.steps - steps,
+            parse_time,
+            eval_time: start.elapsed() - parse_time,
+            normalized: expr.to_string(),
+        })
+    }
+
+    /// Records a warning, unless the same one was already raised.
+    pub fn warn(&mut self, warning: Warning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Returns the warnings raised since they were last taken.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Applies a binary operator as the options say, warning about
+    /// precision lost on the way.
+    pub fn apply(&mut self, kind: OpKind, lhs: Value, rhs: Value) -> Result<Value> {
+        let operands = [lhs.as_int().ok(), rhs.as_int().ok()];
+        let audited = self.auditing().then(|| vec![lhs.clone(), rhs.clone()]);
+        let res = Value::apply_with(kind, lhs, rhs, &self.options);
+        if let Some(audited) = audited {
+            self.audit(AuditOp::Binary(kind), audited, &res);
+        }
+        let val = res?;
+        if let Some(warning) = Warning::check(&operands, &val, &self.options) {
+            self.warn(warning);
+        }
+        Ok(val)
+    }
+
+    /// Negates a value as the options say, warning about precision lost
+    /// on the way.
+    pub fn negate(&mut self, val: Value) -> Result<Value> {
+        let operand = val.as_int().ok();
+        let audited = self.auditing().then(|| vec![val.clone()]);
+        let res = val.negate_with(&self.options);
+        if let Some(audited) = audited {
+            self.audit(AuditOp::Neg, audited, &res);
+        }
+        let res = res?;
+        if let Some(warning) = Warning::check(&[operand], &res, &self.options) {
+            self.warn(warning);
+        }
+        Ok(res)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self.symbols.lookup(name) {
+            Some(id) => self.get_symbol(id),
+            None => self.parent.as_ref()?.get(name),
+        }
+    }
+
+    /// The id variables named `name` are kept under, for reading and
+    /// writing them with `get_symbol` and `set_symbol` without hashing
+    /// the name each time.
+    pub fn intern(&mut self, name: &str) -> SymbolId {
+        match self.symbols.lookup(name) {
+            Some(id) => id,
+            None => Arc::make_mut(&mut self.symbols).intern(name),
+        }
+    }
+
+    pub fn symbol_name(&self, id: SymbolId) -> &str {
+        self.symbols.name(id)
+    }
+
+    pub fn get_symbol(&self, id: SymbolId) -> Option<&Value> {
+        match self.vars.get(id.index()) {
+            Some(Some(val)) => Some(val),
+            _ => self.parent.as_ref()?.get(self.symbols.name(id)),
+        }
+    }
+
+    /// The value of the variable `name` as an expression reads it, which
+    /// the observer is told about. Variables not set are fetched from the
+    /// provider, failing with `UnknownVariable` if it has none either.
+    pub fn read(&mut self, name: &str) -> Result<Value> {
+        match self.symbols.lookup(name) {
+            Some(id) => self.read_symbol(id),
+            None if self.observer.is_none() => match self.get(name) {
+                Some(val) => Ok(val.clone()),
+                None => self.fetch(name)?.ok_or_else(|| self.unknown_variable(name)),
+            },
+            None => {
+                let id = self.intern(name);
+                self.read_symbol(id)
+            },
+        }
+    }
+
+    /// Like `read`, for the variable kept under `id`.
+    pub fn read_symbol(&mut self, id: SymbolId) -> Result<Value> {
+        let val = match self.get_symbol(id) {
+            Some(val) => Some(val.clone()),
+            None => self.fetch(self.symbols.name(id))?,
+        };
+        let loaded = match &self.observer {
+            Some(observer) => observer.on_var_read(self.symbols.name(id), val.as_ref()),
+            None => None,
+        };
+        match (val, loaded) {
+            (Some(val), _) => Ok(val),
+            (None, Some(val)) => {
+                // Loaded rather than written, so the observer is not told.
+                let vars = Arc::make_mut(&mut self.vars);
+                if vars.len() <= id.index() {
+                    vars.resize(id.index() + 1, None);
+                }
+                vars[id.index()] = Some(val.clone());
+                Ok(val)
+            },
+            (None, None) => Err(self.unknown_variable(self.symbols.name(id))),
+        }
+    }
+
+    /// Asks the provider, or the parent's, for the variable `name`.
+    fn fetch(&self, name: &str) -> Result<Option<Value>> {
+        match (&self.provider, &self.parent) {
+            (Some(provider), _) => provider.fetch(name),
+            (None, Some(parent)) => parent.fetch(name),
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub fn set_symbol(&mut self, id: SymbolId, val: Value) {
+        if let Some(observer) = &self.observer {
+            observer.on_var_write(self.symbols.name(id), &val);
+        }
+        let vars = Arc::make_mut(&mut self.vars);
+        if vars.len() <= id.index() {
+            vars.resize(id.index() + 1, None);
+        }
+        vars[id.index()] = Some(val);
+    }
+
+    /// Names of all variables that are set, including the parent's.
+    pub fn names(&self) -> Vec<&str> {
+        set_names(&self.symbols, &self.vars, self.parent.as_deref())
+    }
+
+    /// Defines a function callable from expressions. Builtins cannot be
+    /// redefined.
+    pub fn define(&mut self, name: &str, f: Arc<Function>) -> Result<()> {
+        if builtins::lookup(name).is_some() || builtins::lookup_form(name).is_some() {
+            return Err(Error::Usage);
+        }
+        Arc::make_mut(&mut self.functions).insert(name.to_string(), f);
+        Ok(())
+    }
+
+    /// Names of the functions defined here, not including the parent's.
+    pub fn function_names(&self) -> Vec<&str> {
+        self.functions.keys().map(String::as_str).collect()
+    }
+
+    /// Removes every variable and function, keeping the memory registers.
+    pub fn clear(&mut self) {
+        Arc::make_mut(&mut self.vars).clear();
+        Arc::make_mut(&mut self.functions).clear();
+    }
+
+    /// Makes `name` call the function `target`, which may be a builtin or
+    /// a function defined now or later. Builtin names cannot be aliases.
+    pub fn alias(&mut self, name: &str, target: &str) -> Result<()> {
+        if builtins::lookup(name).is_some() || builtins::lookup_form(name).is_some() {
+            return Err(Error::Usage);
+        }
+        // Aliases of aliases call the final target directly, so following
+        // an alias never loops.
+        let target = self.alias_of(target).unwrap_or_else(|| target.to_string());
+        if target == name {
+            return Err(Error::CircularReference(vec![name.to_string(), name.to_string()]));
+        }
+        Arc::make_mut(&mut self.aliases).insert(name.to_string(), target);
+        Ok(())
+    }
+
+    /// The function an alias stands for, if `name` is one.
+    pub fn alias_of(&self, name: &str) -> Option<String> {
+        match self.aliases.get(name) {
+            Some(target) => Some(target.clone()),
+            None => self.parent.as_ref()?.alias_of(name),
+        }
+    }
+
+    /// The aliases defined here, not including the parent's, by name.
+    pub fn aliases(&self) -> Vec<(&str, &str)> {
+        let mut aliases: Vec<(&str, &str)> = self.aliases.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        aliases.sort();
+        aliases
+    }
+
+    /// The unit named `name`, built in or declared here or in the parent.
+    pub fn unit(&self, name: &str) -> Option<Unit> {
+        if let Some(unit) = units::lookup(name) {
+            return Some(unit);
+        }
+        match self.units.get(name) {
+            Some(unit) => Some(unit.clone()),
+            None => self.parent.as_ref()?.unit(name),
+        }
+    }
+
+    pub(crate) fn set_unit(&mut self, unit: Unit) {
+        self.units.insert(unit.name.clone(), unit);
+    }
+
+    pub fn function(&self, name: &str) -> Option<Arc<Function>> {
+        match self.functions.get(name) {
+            Some(f) => Some(Arc::clone(f)),
+            None => self.parent.as_ref()?.function(name),
+        }
+    }
+
+    /// An `UnknownFunction` error, suggesting the builtin or defined
+    /// function with the closest name once it is shown.
+    pub fn unknown_function(&self, name: &str) -> Error {
+        Error::UnknownFunction(Unknown::lazy(name, Arc::new(Names::Functions {
+            functions: Arc::clone(&self.functions),
+            aliases: Arc::clone(&self.aliases),
+            parent: self.parent.clone(),
+        })))
+    }
+
+    /// Accounts for entering a user-defined function, failing once calls
+    /// nest too deeply. The budgets of `Limits` apply from the outermost
+    /// call.
+    pub fn enter_call(&mut self) -> Result<()> {
+        if self.depth >= function::MAX_CALL_DEPTH {
+            return Err(Error::RecursionLimit);
+        }
+        if self.depth == 0 {
+            self.call_start = Some((self.steps, self.allocated));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn leave_call(&mut self) {
+        self.depth -= 1;
+        if self.depth == 0 {
+            self.call_start = None;
+        }
+    }
+
+    /// An `UnknownVariable` error, suggesting the variable with the
+    /// closest name once it is shown.
+    pub fn unknown_variable(&self, name: &str) -> Error {
+        Error::UnknownVariable(Unknown::lazy(name, Arc::new(Names::Variables {
+            symbols: Arc::clone(&self.symbols),
+            vars: Arc::clone(&self.vars),
+            parent: self.parent.clone(),
+        })))
+    }
+
+    pub fn set(&mut self, name: &str, val: Value) {
+        let id = self.intern(name);
+        self.set_symbol(id, val);
+    }
+
+    /// Removes a variable, returning its value if it was set. Variables of
+    /// a parent environment are left alone.
+    pub fn unset(&mut self, name: &str) -> Option<Value> {
+        let id = self.symbols.lookup(name)?;
+        Arc::make_mut(&mut self.vars).get_mut(id.index())?.take()
+    }
+
+    /// Stores `val` in the memory register `name`.
+    pub fn store(&mut self, name: &str, val: Value) {
+        self.registers.insert(name.to_string(), val);
+    }
+
+    /// Returns the contents of a memory register. Like the memory on a
+    /// pocket calculator, an empty register reads as zero.
+    pub fn recall(&self, name: &str) -> Value {
+        match (self.registers.get(name), &self.parent) {
+            (Some(val), _) => val.clone(),
+            (None, Some(parent)) => parent.recall(name),
+            (None, None) => Value::Int(0),
+        }
+    }
+
+    pub fn clear_register(&mut self, name: &str) {
+        self.registers.remove(name);
+    }
+
+    /// Captures the current state so it can later be brought back with
+    /// `restore`, discarding every change made in between.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            vars: self.vars.clone(),
+            registers: self.registers.clone(),
+            functions: self.functions.clone(),
+            aliases: self.aliases.clone(),
+            units: self.units.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.vars = snapshot.vars;
+        self.registers = snapshot.registers;
+        self.functions = snapshot.functions;
+        self.aliases = snapshot.aliases;
+        self.units = snapshot.units;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::program::{self, Program};
+
+    #[test]
+    fn test_aliases() {
+        let mut env = Environment::new();
+        env.alias("root2", "sqrt").unwrap();
+        env.alias("total", "sum").unwrap();
+        env.alias("avg", "mean").unwrap();
+        env.alias("average", "avg").unwrap();
+        env.define("mean", Arc::new(Function::parse(vec!["l".to_string()], "sum(l) / 2").unwrap())).unwrap();
+
+        let exprs = ["root2(16)", "total(1..4)", "total(k^2, k, 1, 3)", "avg([3, 5])", "average([3, 5])"];
+        let answers = [Value::Float(4.0), Value::Int(10), Value::Int(14), Value::Int(4), Value::Int(4)];
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap(), "{}", expr);
+        }
+        // Compiled programs evaluate the arguments first, which suits forms
+        // given a list but not ones given an expression over a variable.
+        let program = Program::compile(&Expr::parse("total(1..4) + root2(16)").unwrap());
+        assert_eq!(Value::Float(14.0), program.run(&mut env).unwrap());
+
+        assert!(matches!(env.alias("sqrt", "ln"), Err(Error::Usage)));
+        env.alias("a", "b").unwrap();
+        assert!(matches!(env.alias("b", "a"), Err(Error::CircularReference(_))));
+        let e = env.eval("averag([1])").unwrap_err();
+        assert_eq!("unknown function 'averag', did you mean 'average'?", e.to_string());
+
+        // The names are those there were when the error was raised.
+        env.set("total", Value::Int(1));
+        let e = env.eval("totl + 1").unwrap_err();
+        env.unset("total");
+        env.set("tot", Value::Int(2));
+        assert_eq!("unknown variable 'totl', did you mean 'total'?", e.to_string());
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut env = Environment::new();
+        env.set("x", Value::Int(1));
+        let snap = env.snapshot();
+
+        env.set("x", Value::Int(2));
+        env.set("y", Value::Int(3));
+        env.store("m", Value::Int(4));
+        env.restore(snap);
+
+        assert_eq!(Some(&Value::Int(1)), env.get("x"));
+        assert_eq!(None, env.get("y"));
+        assert_eq!(Value::Int(0), env.recall("m"));
+    }
+
+    #[test]
+    fn test_child() {
+        let mut globals = Environment::new();
+        globals.set("g", Value::Float(9.81));
+        globals.set("x", Value::Int(1));
+        let globals = Arc::new(globals);
+
+        let mut a = Environment::child(&globals);
+        let mut b = Environment::child(&globals);
+        a.set("x", Value::Int(2));
+        assert_eq!("2", a.eval("x").unwrap().to_string());
+        assert_eq!("1", b.eval("x").unwrap().to_string());
+        assert_eq!("19.62", b.eval("2 * g").unwrap().to_string());
+
+        assert_eq!(None, b.unset("x"));
+        assert_eq!(Some(&Value::Int(1)), b.get("x"));
+        assert_eq!(Some(Value::Int(2)), a.unset("x"));
+        assert_eq!(Some(&Value::Int(1)), a.get("x"));
+    }
+
+    fn outcome(res: Result<Value>) -> String {
+        match res {
+            Ok(val) => val.to_string(),
+            Err(e) => format!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_float_policy() {
+        let exprs = ["1.0 / 0", "sqrt(-1)", "ln(0)", "2.0^2000", "1 / 0", "sqrt(4)"];
+        let lenient = ["inf", "NaN", "-inf", "inf", "DivisionByZero", "2"];
+        let strict = ["NotFinite", "NotFinite", "NotFinite", "NotFinite", "DivisionByZero", "2"];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, lenient) {
+            assert_eq!(ans, outcome(env.eval(expr)), "{}", expr);
+        }
+        env.set_options(EvalOptions { strict_float: true, ..EvalOptions::default() });
+        for (expr, ans) in std::iter::zip(exprs, strict) {
+            assert_eq!(ans, outcome(env.eval(expr)), "{}", expr);
+            let program = Program::compile(&Expr::parse(expr).unwrap());
+            assert_eq!(ans, outcome(program.run(&mut env)), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_overflow() {
+        let exprs = ["9223372036854775807 + 1", "-9223372036854775807 - 3", "2^64", "(-3)^41",
+                     "-(-9223372036854775807 - 1)", "4611686018427387904 * 2 // 2", "7 / 2"];
+        let modes = [Overflow::Float, Overflow::Wrap, Overflow::Saturate, Overflow::Error];
+        let answers = [
+            ["9223372036854776000", "-9223372036854775808", "9223372036854775807", "Overflow"],
+            ["-9223372036854776000", "9223372036854775806", "-9223372036854775808", "Overflow"],
+            ["18446744073709552000", "0", "9223372036854775807", "Overflow"],
+            ["-36472996377170790000", "420491770248316829", "-9223372036854775808", "Overflow"],
+            ["9223372036854776000", "-9223372036854775808", "9223372036854775807", "Overflow"],
+            ["4611686018427388000", "-4611686018427387904", "4611686018427387903", "Overflow"],
+            ["3.5", "3.5", "3.5", "3.5"],
+        ];
+
+        let mut env = Environment::new();
+        for (expr, answers) in std::iter::zip(exprs, answers) {
+            for (overflow, ans) in std::iter::zip(modes, answers) {
+                env.set_options(EvalOptions { overflow, ..EvalOptions::default() });
+                assert_eq!(ans, outcome(env.eval(expr)), "{} {:?}", expr, overflow);
+                let program = Program::compile(&Expr::parse(expr).unwrap());
+                assert_eq!(ans, outcome(program.run(&mut env)), "{} {:?}", expr, overflow);
+            }
+        }
+    }
+
+    #[test]
+    fn test_int_width() {
+        let exprs = ["0xFF + 1", "0 - 1", "0x7F + 1", "1 << 15", "0b1010 >> 1", "not(0)", "200 * 2"];
+        let widths = [IntWidth::U8, IntWidth::I8, IntWidth::I16, IntWidth::U63];
+        let answers = [
+            ["0", "0", "256", "256"],
+            ["255", "-1", "-1", "9223372036854775807"],
+            ["128", "-128", "128", "128"],
+            ["0", "0", "-32768", "32768"],
+            ["5", "5", "5", "5"],
+            ["255", "-1", "-1", "9223372036854775807"],
+            ["144", "-112", "400", "400"],
+        ];
+
+        let mut env = Environment::new();
+        for (expr, answers) in std::iter::zip(exprs, answers) {
+            for (width, ans) in std::iter::zip(widths, answers) {
+                env.set_options(EvalOptions { width, overflow: Overflow::Wrap, ..EvalOptions::default() });
+                assert_eq!(ans, outcome(env.eval(expr)), "{} {:?}", expr, width);
+            }
+        }
+
+        env.set_options(EvalOptions { width: IntWidth::U8, overflow: Overflow::Saturate, ..EvalOptions::default() });
+        assert_eq!("255", outcome(env.eval("0xF0 + 0xF0")));
+        assert_eq!("0", outcome(env.eval("1 - 2")));
+
+        assert_eq!(None, IntWidth::from_name("u64"));
+        assert_eq!(None, IntWidth::new(64, false));
+        assert_eq!(None, IntWidth::new(0, true));
+        assert_eq!(Some(IntWidth::I64), IntWidth::new(64, true));
+    }
+
+    #[test]
+    fn test_allow_only() {
+        let exprs = ["1 + 2 * 3", "-sqrt(25) / 2", "sum([3, 1, 2])", "price * 2"];
+        let answers = ["7", "-2.5", "6", "20"];
+
+        let mut env = Environment::new();
+        env.set("price", Value::Int(10));
+        env.set_options(EvalOptions::allow_only(&["+", "-", "*", "/", "sqrt", "sum"]));
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+
+        let exprs = ["2 ^ 3", "ln(4)", "prod(i, i, 1, 3)", "map(x -> x, [1])", "1 < 2", "sqrt(exp(1))"];
+        let names = ["^", "ln", "prod", "->", "<", "exp"];
+        for (expr, name) in std::iter::zip(exprs, names) {
+            match env.eval(expr) {
+                Err(Error::NotAllowed(n)) => assert_eq!(name, n, "{}", expr),
+                res => panic!("{}: {:?}", expr, res),
+            }
+        }
+        let program = Program::compile(&env.parse("ln(price)").unwrap());
+        assert!(matches!(program.run(&mut env), Err(Error::NotAllowed(_))));
+        let program = Program::compile(&env.parse("price ^ 2").unwrap());
+        assert!(matches!(program::eval_over(&program, "price", &[1.0], &mut env), Err(Error::NotAllowed(_))));
+    }
+
+    #[test]
+    fn test_dialect() {
+        let exprs = ["4 / 2", "7 // 2", "-7 // 2", "-7 % 3", "7 % -3", "7.5 % 2", "2 ** -1", "5 ^ 3", "2 ** 3 ^ 1", "1 | 6 ^ 3 & 5", "1.0 / 0", "0 ** -1"];
+        let kalkul = ["2", "3", "-4", "2", "-2", "1.5", "", "125", "", "1", "inf", ""];
+        let python = ["2", "3", "-4", "2", "-2", "1.5", "0.5", "6", "9", "7", "DivisionByZero", "DivisionByZero"];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, kalkul) {
+            if !ans.is_empty() {
+                assert_eq!(ans, outcome(env.eval(expr)), "{}", expr);
+            }
+        }
+        env.set_options(EvalOptions::dialect(Dialect::Python));
+        for (expr, ans) in std::iter::zip(exprs, python) {
+            assert_eq!(ans, outcome(env.eval(expr)), "{}", expr);
+        }
+        assert_eq!(Value::Float(2.0), env.eval("4 / 2").unwrap());
+    }
+
+    #[test]
+    fn test_c_dialect() {
+        let exprs = ["6 & 1 == 0", "1 | 2 ^ 3 & 1", "1 < 2 == 1", "7 / 2", "-7 / 2", "-7 % 2", "7.0 / 2", "7.5 % 2", "2147483647 + 1", "1 << 31", "5 ^ 1"];
+        let answers = ["0", "3", "1", "3", "-3", "-1", "3.5", "TypeError", "-2147483648", "-2147483648", "4"];
+        let mut env = Environment::new();
+        env.set_options(EvalOptions::dialect(Dialect::C));
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, outcome(env.eval(expr)), "{}", expr);
+        }
+        env.set_options(EvalOptions::default());
+        assert_eq!("1", outcome(env.eval("6 & 1 == 0")));
+    }
+
+    #[test]
+    fn test_excel_dialect() {
+        let mut env = Environment::new();
+        env.set_options(EvalOptions::dialect(Dialect::Excel));
+        for (cell, n) in [("A1", 1), ("A2", 2), ("A3", 3), ("B1", 10), ("B2", 20)] {
+            env.set(cell, Value::Int(n));
+        }
+        let exprs = ["=SUM(A1:A3)", " = Sum(a1:b2)", "=-2^2", "=2^-1", "=\"total: \" & A1 + A2", "=A1 = 1", "=A1 <> 1", "=A1<=1", "=SQRT(B1:A1)"];
+        let answers = ["6", "33", "4", "0.5", "total: 3", "1", "0", "1", "TypeError"];
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, outcome(env.eval(expr)), "{}", expr);
+        }
+        assert!(matches!(env.eval("=SUM(A1:XFD1048576)"), Err(Error::TooMany(_, 17179869184, _))));
+    }
+
+    #[test]
+    fn test_usage() {
+        let exprs = ["42", "1 + 2 * 3", "((1 + 2) + 3) + 4", "[1, [2, 3]]", "str(12)"];
+        let answers = [(1, 1, 0), (5, 3, 0), (7, 4, 0), (5, 3, 2), (2, 2, 1)];
+
+        let mut env = Environment::new();
+        for (expr, (operations, max_depth, allocations)) in std::iter::zip(exprs, answers) {
+            let usage = env.eval_output(expr).unwrap().usage;
+            assert_eq!(Usage { operations, max_depth, allocations }, usage, "{}", expr);
+        }
+        let usage = env.eval_output("sum(i, i, 1, 100)").unwrap().usage;
+        assert!(usage.operations > 100, "{:?}", usage);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let mut env = Environment::new();
+        let exprs = ["1 + 2", "7 / 2", "2^53 + 1.0", "(2^53 + 1) * 1.5", "2^62 * 4", "-(2^62 * 4)"];
+        let answers = [
+            "",
+            "",
+            "",
+            "9007199254740993 was rounded converting it to a float",
+            "integer result out of range, computed as a float",
+            "integer result out of range, computed as a float",
+        ];
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            let out = env.eval_output(expr).unwrap();
+            let warnings: Vec<String> = out.warnings.iter().map(Warning::to_string).collect();
+            assert_eq!(ans, warnings.join("; "), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_eval_detailed() {
+        let mut env = Environment::new();
+        env.set("x", Value::Int(3));
+        let details = env.eval_detailed("(1+2)*x^2").unwrap();
+        assert_eq!(Value::Int(27), details.value);
+        assert_eq!(9, details.tokens);
+        assert_eq!(7, details.steps);
+        assert_eq!("(1 + 2)*x^2", details.normalized);
+        assert!(details.timing().starts_with("parse "));
+
+        let details = kalkul::evaluate_detailed("sqrt(4)").unwrap();
+        assert_eq!(Value::Float(2.0), details.value);
+        assert_eq!(4, details.tokens);
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut env = Environment::new();
+        env.set_limits(Limits { max_steps: Some(1000), ..Limits::default() });
+        assert!(env.eval("1 + 2 * 3").is_ok());
+        assert!(matches!(env.eval("sum(i, i, 1, 10000)"), Err(Error::LimitExceeded)));
+
+        env.set_limits(Limits { deadline: Some(Instant::now()), ..Limits::default() });
+        assert!(matches!(env.eval("sum(i, i, 1, 10000)"), Err(Error::Timeout)));
+
+        env.set_limits(Limits::default());
+        assert!(env.eval("sum(i, i, 1, 10000)").is_ok());
+    }
+
+    #[test]
+    fn test_call_budgets() {
+        let mut env = Environment::new();
+        let twice = Function::parse(vec!["n".to_string()], "{ n <= 0: 1; twice(n - 1) + twice(n - 1) }").unwrap();
+        env.define("twice", Arc::new(twice)).unwrap();
+        let big = Function::parse(vec!["n".to_string()], "sum(1..n)").unwrap();
+        env.define("big", Arc::new(big)).unwrap();
+        env.set_limits(Limits { call_steps: Some(10_000), call_memory: Some(1 << 16), ..Limits::default() });
+
+        assert_eq!(Value::Int(256), env.eval("twice(8)").unwrap());
+        assert!(matches!(env.eval("twice(60)"), Err(Error::FunctionStepLimit)));
+        assert_eq!(Value::Int(5050), env.eval("big(100)").unwrap());
+        assert!(matches!(env.eval("big(5000)"), Err(Error::FunctionMemoryLimit)));
+        // Outside of functions, only the other limits apply.
+        assert!(env.eval("sum(i, i, 1, 20000) + sum(1..10^5)").is_ok());
+        assert_eq!(Value::Int(16), env.eval("twice(2) + twice(3) + twice(2)").unwrap());
+    }
+
+    #[test]
+    fn test_observer() {
+        #[derive(Debug, Default)]
+        struct Tracker {
+            reads: std::sync::Mutex<Vec<String>>,
+            writes: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl VarObserver for Tracker {
+            fn on_var_read(&self, name: &str, val: Option<&Value>) -> Option<Value> {
+                self.reads.lock().unwrap().push(name.to_string());
+                match (name, val) {
+                    ("price", None) => Some(Value::Int(40)),
+                    _ => None,
+                }
+            }
+
+            fn on_var_write(&self, name: &str, val: &Value) {
+                self.writes.lock().unwrap().push(format!("{} = {}", name, val));
+            }
+        }
+
+        let tracker = Arc::new(Tracker::default());
+        let mut env = Environment::new();
+        env.set("qty", Value::Int(3));
+        env.set_observer(Some(tracker.clone()));
+        assert_eq!(Value::Int(120), env.eval("price * qty").unwrap());
+        assert_eq!(Value::Int(40), env.eval("price").unwrap());
+        assert!(matches!(env.eval("tax"), Err(Error::UnknownVariable(_))));
+        env.set("qty", Value::Int(4));
+        let program = Program::compile(&Expr::parse("qty + 1").unwrap());
+        assert_eq!(Value::Int(5), program.run(&mut env).unwrap());
+
+        assert_eq!(["price", "qty", "price", "tax", "qty"].as_slice(), tracker.reads.lock().unwrap().as_slice());
+        assert_eq!(["qty = 4"].as_slice(), tracker.writes.lock().unwrap().as_slice());
+        env.set_observer(None);
+        assert_eq!(Value::Int(44), env.eval("price + qty").unwrap());
+        assert_eq!(5, tracker.reads.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_provider() {
+        #[derive(Debug)]
+        struct Sensors;
+
+        impl VariableProvider for Sensors {
+            fn fetch(&self, name: &str) -> Result<Option<Value>> {
+                match name.strip_prefix("tag") {
+                    Some("9") => Err(Error::FetchFailed(name.to_string(), "sensor offline".to_string())),
+                    Some(n) => Ok(n.parse().ok().map(Value::Int)),
+                    None => Ok(None),
+                }
+            }
+        }
+
+        let mut env = Environment::with_provider(Arc::new(Sensors));
+        env.set("tag1", Value::Int(100));
+        assert_eq!(Value::Int(142), env.eval("tag1 + tag42").unwrap());
+        assert_eq!(Value::Int(123456), env.eval("tag123456").unwrap());
+        assert!(env.get("tag42").is_none());
+        let program = Program::compile(&Expr::parse("tag7 * 2").unwrap());
+        assert_eq!(Value::Int(14), program.run(&mut env).unwrap());
+        assert_eq!("could not fetch tag9: sensor offline", env.eval("tag9 + 1").unwrap_err().to_string());
+        assert!(matches!(env.eval("depth"), Err(Error::UnknownVariable(_))));
+
+        let child = Environment::child(&Arc::new(env));
+        assert_eq!(Value::Int(5), Environment::child(&Arc::new(child)).eval("tag5").unwrap());
+    }
+}
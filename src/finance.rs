@@ -0,0 +1,132 @@
+use crate::kalkul::{Error, Result};
+
+const MAX_ITERATIONS: usize = 100;
+
+/// Growth of one unit over `nper` periods at `rate` per period.
+fn growth(rate: f64, nper: f64) -> f64 {
+    (1.0 + rate).powf(nper)
+}
+
+/// Percent change from `start` to `end`, so `growth(80, 100)` is 25.
+pub fn percent_change(start: f64, end: f64) -> Result<f64> {
+    if start == 0.0 {
+        return Err(Error::DivisionByZero);
+    }
+    Ok((end - start) / start.abs() * 100.0)
+}
+
+/// What `principal` grows to at `rate` per period, compounded over
+/// `periods` periods.
+pub fn compound(principal: f64, rate: f64, periods: f64) -> f64 {
+    principal * growth(rate, periods)
+}
+
+/// The payment each period that pays off a loan of `pv` over `nper`
+/// periods. Money paid out is negative, as on a financial calculator, so
+/// `pmt(0.01, 12, 1000)` is about -88.85.
+pub fn pmt(rate: f64, nper: f64, pv: f64, fv: f64) -> f64 {
+    if rate == 0.0 {
+        return -(pv + fv) / nper;
+    }
+    let g = growth(rate, nper);
+    -(pv * g + fv) * rate / (g - 1.0)
+}
+
+/// What an account holds after `nper` periods, starting from `pv` and
+/// with `pmt` paid in each period, both counted negative when paid in.
+pub fn fv(rate: f64, nper: f64, pmt: f64, pv: f64) -> f64 {
+    if rate == 0.0 {
+        return -(pv + pmt * nper);
+    }
+    let g = growth(rate, nper);
+    -(pv * g + pmt * (g - 1.0) / rate)
+}
+
+/// Present value of cash flows at the end of each period, the first
+/// discounted a whole period, as in spreadsheets.
+pub fn npv(rate: f64, flows: &[f64]) -> f64 {
+    flows.iter().rev().fold(0.0, |acc, flow| (acc + flow) / (1.0 + rate))
+}
+
+/// The rate at which cash flows, the first of them now, are worth
+/// nothing: `irr([-100, 60, 60])` is about 0.1307. Found with Newton's
+/// method, which needs the flows to change sign somewhere.
+pub fn irr(flows: &[f64]) -> Result<f64> {
+    if !flows.iter().any(|f| *f > 0.0) || !flows.iter().any(|f| *f < 0.0) {
+        return Err(Error::NoSolution);
+    }
+    let mut rate = 0.1;
+    for _ in 0..MAX_ITERATIONS {
+        // The value at time zero and its derivative with respect to the
+        // rate, by Horner's scheme in 1 / (1 + rate).
+        let v = 1.0 / (1.0 + rate);
+        let (value, slope) = flows.iter().rev().fold((0.0, 0.0), |(value, slope), flow| {
+            (value * v + flow, slope * v + value)
+        });
+        let slope = -slope * v * v;
+        let next = rate - value / slope;
+        if !next.is_finite() || next <= -1.0 {
+            break;
+        }
+        if (next - rate).abs() <= 1e-12 * rate.abs().max(1.0) {
+            return Ok(next);
+        }
+        rate = next;
+    }
+    Err(Error::NoSolution)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::Environment;
+    use crate::kalkul::Error;
+
+    #[test]
+    fn test_growth() {
+        let exprs = ["growth(80, 100)", "growth(100, 80)", "growth(-50, -25)", "compound(1000, 0.05, 10)", "compound(1000, 0, 3)"];
+        let answers = [25.0, -20.0, 50.0, 1628.894626777442, 1000.0];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            let x = env.eval(expr).unwrap().as_f64().unwrap();
+            assert!((x - ans).abs() < 1e-9, "{} = {}", expr, x);
+        }
+        assert!(matches!(env.eval("growth(0, 5)"), Err(Error::DivisionByZero)));
+    }
+
+    #[test]
+    fn test_finance() {
+        let exprs = [
+            "pmt(0.05 / 12, 360, 200000)",
+            "pmt(0, 10, 1000)",
+            "fv(0.05, 10, -100)",
+            "fv(0.05, 10, -100, -1000)",
+            "fv(0, 12, -50, -100)",
+            "npv(0.1, [100, 100, 100])",
+            "npv(0.08, [-1000, 300, 400, 500]) * 1.08",
+            "irr([-100, 60, 60])",
+            "irr([-1000, 300, 400, 500])",
+        ];
+        let answers = [
+            -1073.6432460242795,
+            -100.0,
+            1257.789253554884,
+            2886.683880332326,
+            700.0,
+            248.68519909842223,
+            -1000.0 + 300.0 / 1.08 + 400.0 / 1.08_f64.powi(2) + 500.0 / 1.08_f64.powi(3),
+            0.13066238629180742,
+            0.08896339469334992,
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            let x = env.eval(expr).unwrap().as_f64().unwrap();
+            assert!((x - ans).abs() < 1e-9 * ans.abs().max(1.0), "{} = {}", expr, x);
+        }
+        let rate = env.eval("irr([-500, 100, 200, 300])").unwrap().as_f64().unwrap();
+        assert!((super::npv(rate, &[100.0, 200.0, 300.0]) - 500.0).abs() < 1e-9);
+        assert!(matches!(env.eval("irr([100, 50])"), Err(Error::NoSolution)));
+        assert!(matches!(env.eval("npv(0.1, 100)"), Err(Error::TypeError)));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::kalkul::Value;
+
+/// How results are written out, mirroring the display modes found on
+/// scientific calculators.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Notation {
+    Normal,
+    /// One digit before the decimal point: `1.23e-5`.
+    Scientific,
+    /// Exponent is always a multiple of three: `12.3e-6`.
+    Engineering,
+    /// A fixed number of digits after the decimal point.
+    Fixed(usize),
+}
+
+/// Most digits after the decimal point `Notation::Fixed` writes, enough
+/// for every digit of the smallest float.
+pub const MAX_FIXED_DIGITS: usize = 1100;
+
+pub fn format_value(v: &Value, notation: Notation) -> String {
+    let x = match v.as_f64() {
+        Ok(x)  => x,
+        Err(_) => return v.to_string(),
+    };
+    match (v, notation) {
+        (Value::Int(n), Notation::Normal) => n.to_string(),
+        (_, Notation::Normal)       => v.to_string(),
+        (_, Notation::Scientific)   => scientific(x),
+        (_, Notation::Engineering)  => engineering(x),
+        (_, Notation::Fixed(digits)) => format!("{:.*}", digits.min(MAX_FIXED_DIGITS), x),
+    }
+}
+
+/// Writes an integer in base 2, 8, 10 or 16, with the prefix it would
+/// have in an expression: `with_radix(-31, 16)` is `-0x1F`.
+pub fn with_radix(n: i64, radix: u32) -> String {
+    let sign = if n < 0 { "-" } else { "" };
+    let n = n.unsigned_abs();
+    match radix {
+        16 => format!("{}0x{:X}", sign, n),
+        8  => format!("{}0o{:o}", sign, n),
+        2  => format!("{}0b{:b}", sign, n),
+        _  => format!("{}{}", sign, n),
+    }
+}
+
+/// Writes an integer in any base from 2 to 36, with lowercase letters
+/// for digits past 9 and no prefix: `to_radix(1261, 36)` is `z1`.
+pub fn to_radix(n: i64, radix: u32) -> String {
+    let mut m = n.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        digits.push(std::char::from_digit((m % radix as u64) as u32, radix).unwrap());
+        m /= radix as u64;
+        if m == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+/// Writes `x` with the fewest digits that read back as exactly `x`. Only
+/// moderate magnitudes are written out positionally; the rest get an
+/// exponent, as in `1e21` or `2.5e-8`. The digits come from the standard
+/// library's own shortest round-trip conversion rather than the platform's
+/// C library, so the same value prints the same everywhere.
+pub fn shortest(x: f64) -> String {
+    if !x.is_finite() || x == 0.0 {
+        return x.to_string();
+    }
+
+    let sci = format!("{:e}", x.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    let body = match exp {
+        0..=20 => {
+            let int_len = exp as usize + 1;
+            if digits.len() <= int_len {
+                format!("{:0<width$}", digits, width = int_len)
+            } else {
+                format!("{}.{}", &digits[..int_len], &digits[int_len..])
+            }
+        },
+        -6..=-1 => format!("0.{}{}", "0".repeat((-exp - 1) as usize), digits),
+        _ => format!("{}e{}", mantissa, exp),
+    };
+    format!("{}{}", sign, body)
+}
+
+fn scientific(x: f64) -> String {
+    if !x.is_finite() {
+        return x.to_string();
+    }
+    format!("{:e}", x)
+}
+
+fn engineering(x: f64) -> String {
+    if !x.is_finite() {
+        return x.to_string();
+    }
+
+    // Work on the shortest scientific representation so that shifting
+    // the decimal point cannot introduce rounding noise.
+    let sci = format!("{:e}", x.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+
+    let eng_exp = exp - exp.rem_euclid(3);
+    let int_len = (exp - eng_exp) as usize + 1;
+    let padded = format!("{:0<width$}", digits, width = int_len);
+    let (int_part, frac_part) = padded.split_at(int_len);
+
+    let sign = if x.is_sign_negative() { "-" } else { "" };
+    if frac_part.is_empty() {
+        format!("{}{}e{}", sign, int_part, eng_exp)
+    } else {
+        format!("{}{}.{}e{}", sign, int_part, frac_part, eng_exp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shortest() {
+        let xs = [0.1 + 0.2, 1e21, 1e20, 123456.789, -0.000001, 1.5e-7, 2f64.powi(70), -0.0, f64::NAN];
+        let answers = ["0.30000000000000004", "1e21", "100000000000000000000", "123456.789",
+                       "-0.000001", "1.5e-7", "1.1805916207174113e21", "-0", "NaN"];
+        for (x, ans) in std::iter::zip(xs, answers) {
+            assert_eq!(ans, shortest(x));
+            if x.is_finite() {
+                assert_eq!(x, shortest(x).parse::<f64>().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_notations() {
+        let x = Value::Float(0.0000123);
+        assert_eq!("0.0000123", format_value(&x, Notation::Normal));
+        assert_eq!("1.23e-5", format_value(&x, Notation::Scientific));
+        assert_eq!("12.3e-6", format_value(&x, Notation::Engineering));
+        assert_eq!("0.00", format_value(&x, Notation::Fixed(2)));
+
+        let n = Value::Int(-123400);
+        assert_eq!("-1.234e5", format_value(&n, Notation::Scientific));
+        assert_eq!("-123.4e3", format_value(&n, Notation::Engineering));
+        assert_eq!("-123400.00", format_value(&n, Notation::Fixed(2)));
+        let long = format_value(&Value::Float(1.5), Notation::Fixed(1_000_000_000));
+        assert_eq!(2 + MAX_FIXED_DIGITS, long.len());
+
+        assert_eq!("1e3", format_value(&Value::Int(1000), Notation::Engineering));
+        assert_eq!("100e0", format_value(&Value::Int(100), Notation::Engineering));
+        assert_eq!("0e0", format_value(&Value::Int(0), Notation::Engineering));
+    }
+
+    #[test]
+    fn test_with_radix() {
+        let ns = [(31, 16), (-31, 16), (8, 8), (5, 2), (0, 2), (i64::MIN, 16), (-7, 10)];
+        let answers = ["0x1F", "-0x1F", "0o10", "0b101", "0b0", "-0x8000000000000000", "-7"];
+        for ((n, radix), ans) in std::iter::zip(ns, answers) {
+            assert_eq!(ans, with_radix(n, radix));
+        }
+        assert_eq!("z1", to_radix(1261, 36));
+        assert_eq!("-1000000000000000000000000000000000000000000000000000000000000000", to_radix(i64::MIN, 2));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::env::Environment;
+use crate::kalkul::{Error, Expr, Result, Value};
+
+/// How deeply calls to user-defined functions may nest, so a function
+/// that recurses without end fails instead of overflowing the stack.
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/// One branch of a function, whose body applies when the guard, if any,
+/// evaluates to something other than zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
+/// A function defined in a session, such as `f(x) = x^2`, or piecewise
+/// as in `f(x) = { x < 0: -x; x >= 0: x }`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub params: Vec<String>,
+    pub cases: Vec<Case>,
+}
+
+impl Function {
+    /// Parses the right hand side of a definition. Braces hold cases
+    /// separated by `;`, each a guard and a body separated by `:`. A case
+    /// without a guard always applies.
+    pub fn parse(params: Vec<String>, src: &str) -> Result<Function> {
+        let src = src.trim();
+        let cases = match src.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            Some(inner) => inner.split(';')
+                .filter(|case| !case.trim().is_empty())
+                .map(|case| match case.split_once(':') {
+                    Some((guard, body)) => Ok(Case { guard: Some(Expr::parse(guard)?), body: Expr::parse(body)? }),
+                    None => Ok(Case { guard: None, body: Expr::parse(case)? }),
+                })
+                .collect::<Result<Vec<Case>>>()?,
+            None => vec![Case { guard: None, body: Expr::parse(src)? }],
+        };
+        if cases.is_empty() {
+            return Err(Error::ParseError);
+        }
+        Ok(Function { params, cases })
+    }
+
+    /// Evaluates the first case whose guard holds, with the parameters
+    /// bound to `args`. Variables of the same names are left as they were.
+    pub fn call(&self, args: &[Value], env: &mut Environment) -> Result<Value> {
+        if args.len() != self.params.len() {
+            return Err(Error::ArgumentCount);
+        }
+        env.enter_call()?;
+        let saved: Vec<Option<Value>> = self.params.iter().map(|p| env.unset(p)).collect();
+        for (param, arg) in std::iter::zip(&self.params, args) {
+            env.set(param, arg.clone());
+        }
+        let res = self.eval_cases(env);
+        for (param, val) in std::iter::zip(&self.params, saved) {
+            match val {
+                Some(val) => env.set(param, val),
+                None => {
+                    env.unset(param);
+                },
+            }
+        }
+        env.leave_call();
+        res
+    }
+
+    fn eval_cases(&self, env: &mut Environment) -> Result<Value> {
+        for case in &self.cases {
+            let holds = match &case.guard {
+                Some(guard) => guard.eval(env)?.as_f64()? != 0.0,
+                None => true,
+            };
+            if holds {
+                return case.body.eval(env);
+            }
+        }
+        Err(Error::NoMatchingCase)
+    }
+}
+
+/// Prints in lambda syntax, such as `(a, b) -> a + b`.
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.params.as_slice() {
+            [param] => write!(f, "{} -> ", param)?,
+            params => write!(f, "({}) -> ", params.join(", "))?,
+        }
+        match self.cases.as_slice() {
+            [Case { guard: None, body }] => write!(f, "{}", body),
+            cases => {
+                write!(f, "{{ ")?;
+                for (i, case) in cases.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    if let Some(guard) = &case.guard {
+                        write!(f, "{}: ", guard)?;
+                    }
+                    write!(f, "{}", case.body)?;
+                }
+                write!(f, " }}")
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_piecewise() {
+        let mut env = Environment::new();
+        let abs = Function::parse(vec!["x".to_string()], "{ x < 0: -x; x >= 0: x }").unwrap();
+        env.define("abs", Arc::new(abs)).unwrap();
+        let sign = Function::parse(vec!["x".to_string()], "{ x < 0: -1; x == 0: 0; 1 }").unwrap();
+        env.define("sign", Arc::new(sign)).unwrap();
+        let fact = Function::parse(vec!["n".to_string()], "{ n <= 1: 1; n * fact(n - 1) }").unwrap();
+        env.define("fact", Arc::new(fact)).unwrap();
+        let half = Function::parse(vec!["x".to_string()], "{ x > 0: x / 2 }").unwrap();
+        env.define("half", Arc::new(half)).unwrap();
+        env.set("x", Value::Int(7));
+
+        let exprs = ["abs(-3)", "abs(2.5)", "sign(-4) + sign(0) + sign(9)", "fact(10)", "half(x)", "x"];
+        let answers = ["3", "2.5", "0", "3628800", "3.5", "7"];
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+
+        assert!(matches!(env.eval("half(-1)"), Err(Error::NoMatchingCase)));
+        assert!(matches!(env.eval("abs(1, 2)"), Err(Error::ArgumentCount)));
+        let forever = Function::parse(vec!["n".to_string()], "forever(n + 1)").unwrap();
+        env.define("forever", Arc::new(forever)).unwrap();
+        assert!(matches!(env.eval("forever(0)"), Err(Error::RecursionLimit)));
+        assert_eq!("3", env.eval("abs(-3)").unwrap().to_string());
+    }
+}
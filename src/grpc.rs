@@ -0,0 +1,149 @@
+// Status is what every method of a tonic service returns.
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use tonic::{Request, Response, Status};
+
+use crate::env::Environment;
+use crate::kalkul::{Error, Expr, Result};
+use crate::program::Program;
+use crate::server::{client_env, Config};
+
+/// Messages and service traits generated from proto/kalkul.proto.
+pub mod proto {
+    tonic::include_proto!("kalkul");
+}
+
+use proto::kalkul_server::{Kalkul, KalkulServer};
+use proto::{CompileRequest, CompiledProgram, EvalRequest, RunRequest, Value};
+
+/// The evaluation service of `server`, over gRPC, with the same limits.
+#[derive(Debug, Clone, Default)]
+pub struct Service {
+    config: Arc<Config>,
+}
+
+impl Service {
+    pub fn new(config: Config) -> Self {
+        Service { config: Arc::new(config) }
+    }
+
+    /// Evaluates on a thread of its own, as evaluating can take up to
+    /// `config.eval_timeout` and the executor's threads must not wait.
+    async fn eval_with<F>(&self, vars: HashMap<String, f64>, f: F) -> std::result::Result<Response<Value>, Status>
+    where
+        F: FnOnce(&mut Environment) -> Result<crate::kalkul::Value> + Send + 'static,
+    {
+        let config = Arc::clone(&self.config);
+        let res = tokio::task::spawn_blocking(move || {
+            let mut env = client_env(vars.iter().map(|(name, x)| (name.as_str(), *x)), &config);
+            f(&mut env)
+        }).await.map_err(|e| Status::internal(e.to_string()))?;
+        match res {
+            Ok(val) => Ok(Response::new(Value { text: val.to_string(), number: val.as_f64().ok() })),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    fn check_len(&self, expr: &str) -> std::result::Result<(), Status> {
+        if expr.chars().count() > self.config.max_expr_len {
+            return Err(Status::resource_exhausted("expression too long"));
+        }
+        Ok(())
+    }
+}
+
+/// The status a client gets for `error`.
+fn status(error: Error) -> Status {
+    match error {
+        Error::LimitExceeded | Error::Timeout | Error::FunctionStepLimit | Error::FunctionMemoryLimit => Status::resource_exhausted(error.to_string()),
+        Error::IncompatibleVersion(_) => Status::failed_precondition(error.to_string()),
+        error => Status::invalid_argument(error.to_string()),
+    }
+}
+
+#[tonic::async_trait]
+impl Kalkul for Service {
+    async fn eval(&self, request: Request<EvalRequest>) -> std::result::Result<Response<Value>, Status> {
+        let EvalRequest { expr, vars } = request.into_inner();
+        self.check_len(&expr)?;
+        self.eval_with(vars, move |env| env.eval(&expr)).await
+    }
+
+    async fn compile(&self, request: Request<CompileRequest>) -> std::result::Result<Response<CompiledProgram>, Status> {
+        let CompileRequest { expr } = request.into_inner();
+        self.check_len(&expr)?;
+        let program = Program::compile(&Expr::parse(&expr).map_err(status)?);
+        Ok(Response::new(CompiledProgram { program: program.to_bytes().map_err(status)? }))
+    }
+
+    async fn run(&self, request: Request<RunRequest>) -> std::result::Result<Response<Value>, Status> {
+        let RunRequest { program, vars } = request.into_inner();
+        if program.len() > self.config.max_body {
+            return Err(Status::resource_exhausted("program too large"));
+        }
+        let program = Program::from_bytes(&program).map_err(|e| match e {
+            Error::IncompatibleVersion(_) => status(e),
+            _ => Status::invalid_argument("invalid program"),
+        })?;
+        self.eval_with(vars, move |env| program.run(env)).await
+    }
+}
+
+/// Listens on `config.addr` until the process ends.
+pub async fn serve(config: Config) -> io::Result<()> {
+    let addr = config.addr.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    tonic::transport::Server::builder()
+        .add_service(KalkulServer::new(Service::new(config)))
+        .serve(addr)
+        .await
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(fut)
+    }
+
+    #[test]
+    fn test_service() {
+        let service = Service::default();
+        let eval = |expr: &str, vars: &[(&str, f64)]| {
+            let vars = vars.iter().map(|(name, x)| (name.to_string(), *x)).collect();
+            block_on(service.eval(Request::new(EvalRequest { expr: expr.to_string(), vars })))
+        };
+        assert_eq!(Value { text: "7".to_string(), number: Some(7.0) }, eval("1 + 2 * 3", &[]).unwrap().into_inner());
+        assert_eq!(Some(5.0), eval("x + 2", &[("x", 3.0)]).unwrap().into_inner().number);
+        assert_eq!(None, eval("\"a\" & \"b\"", &[]).map(|r| r.into_inner().number).unwrap_or(None));
+        let err = eval("1 / 0", &[]).unwrap_err();
+        assert_eq!((tonic::Code::InvalidArgument, "division by zero"), (err.code(), err.message()));
+        let long = "1 + ".repeat(5000) + "1";
+        assert_eq!(tonic::Code::ResourceExhausted, eval(&long, &[]).unwrap_err().code());
+
+        let compiled = block_on(service.compile(Request::new(CompileRequest { expr: "x^2 + 1".to_string() })))
+            .unwrap()
+            .into_inner();
+        for (x, ans) in [(2.0, 5.0), (3.0, 10.0)] {
+            let request = RunRequest { program: compiled.program.clone(), vars: HashMap::from([("x".to_string(), x)]) };
+            assert_eq!(Some(ans), block_on(service.run(Request::new(request))).unwrap().into_inner().number);
+        }
+        let request = RunRequest { program: vec![0xff], vars: HashMap::new() };
+        assert_eq!(tonic::Code::InvalidArgument, block_on(service.run(Request::new(request))).unwrap_err().code());
+        assert!(block_on(service.compile(Request::new(CompileRequest { expr: "1 +".to_string() }))).is_err());
+        let request = RunRequest { program: b"KLKP\x09\x80".to_vec(), vars: HashMap::new() };
+        assert_eq!(tonic::Code::FailedPrecondition, block_on(service.run(Request::new(request))).unwrap_err().code());
+
+        let service = Service::new(Config { max_steps: u64::MAX, eval_timeout: Duration::ZERO, ..Config::default() });
+        let request = EvalRequest { expr: "sum(i, i, 1, 10^9)".to_string(), vars: HashMap::new() };
+        let err = block_on(service.eval(Request::new(request))).unwrap_err();
+        assert_eq!((tonic::Code::ResourceExhausted, "evaluation timed out"), (err.code(), err.message()));
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::Write;
+
+use crate::env::Environment;
+use crate::kalkul::{self, OpKind, OperatorInfo, Result};
+
+/// Signatures and descriptions of the builtin functions and forms, in
+/// the order of their tables. Tests check this covers both tables.
+const FUNCTIONS: &[(&str, &str)] = &[
+    ("store(x, name)",             "stores x in a memory register"),
+    ("recall(name)",               "reads a memory register"),
+    ("sqrt(x)",                    "square root"),
+    ("exp(x)",                     "e to the power of x"),
+    ("ln(x)",                      "natural logarithm"),
+    ("sin(x)",                     "sine, of x in radians"),
+    ("cos(x)",                     "cosine, of x in radians"),
+    ("tan(x)",                     "tangent, of x in radians"),
+    ("divmod(a, b)",               "floor quotient and remainder, as a tuple"),
+    ("xor(a, b)",                  "bitwise exclusive or"),
+    ("not(x)",                     "bitwise complement within the word size"),
+    ("tohex(x, bits)",             "two's complement hex string of x"),
+    ("tobin(x, bits)",             "two's complement binary string of x"),
+    ("signext(x, bits)",           "the low bits of x read as a signed number"),
+    ("bit(x, n)",                  "bit n of x, counting from 0"),
+    ("bits(x, hi, lo)",            "the field of x from bit hi down to bit lo"),
+    ("setbit(x, n)",               "x with bit n set"),
+    ("popcount(x)",                "number of bits set in x"),
+    ("bits(x)",                    "the IEEE-754 encoding of x"),
+    ("frombits(n)",                "the float encoded by n"),
+    ("ulp(x)",                     "gap to the next float further from zero"),
+    ("nextafter(a, b)",            "the float next to a towards b"),
+    ("frac(x)",                    "the simplest fraction near x, as (numerator, denominator)"),
+    ("approx(x, tol)",             "the simplest fraction within tol of x"),
+    ("scientific(x)",              "x as (mantissa, exponent) with the mantissa from 1 to 10"),
+    ("dms(angle)",                 "decimal degrees as (degrees, minutes, seconds)"),
+    ("factorize(n)",               "prime factors of n as a list"),
+    ("divisors(n)",                "positive divisors of n as a list"),
+    ("powmod(b, e, m)",            "b ^ e modulo m"),
+    ("invmod(a, m)",               "the inverse of a modulo m"),
+    ("map(list, f)",               "applies f to every item"),
+    ("filter(list, f)",            "the items for which f is nonzero"),
+    ("reduce(list, init, f)",      "combines the items from left to right"),
+    ("num(s)",                     "reads a number from a string"),
+    ("str(x)",                     "x as a string"),
+    ("parsehex(s)",                "reads a hexadecimal string"),
+    ("base(radix, s)",             "reads the digits s in a base up to 36"),
+    ("tobase(n, radix)",           "the digits of n in a base up to 36"),
+    ("roman(s)",                   "reads a Roman numeral"),
+    ("toroman(n)",                 "n as a Roman numeral"),
+    ("netmask(bits)",              "the netmask of a prefix length, as an address"),
+    ("contains(net, addr)",        "1 if addr lies in the network net"),
+    ("normcdf(x)",                 "probability a standard normal value is at most x"),
+    ("normcdf(x, mu, sigma)",      "probability a normal value is at most x"),
+    ("norminv(p)",                 "the x for which normcdf(x) is p"),
+    ("norminv(p, mu, sigma)",      "the x for which normcdf(x, mu, sigma) is p"),
+    ("binompdf(n, p, k)",          "probability of k successes in n trials"),
+    ("poissonpdf(lambda, k)",      "probability of k events when lambda are expected"),
+    ("growth(start, end)",         "percent change from start to end"),
+    ("compound(p, rate, periods)", "p compounded at rate per period"),
+    ("pmt(rate, nper, pv)",        "payment per period paying off pv, negative"),
+    ("pmt(rate, nper, pv, fv)",    "payment per period leaving fv at the end"),
+    ("fv(rate, nper, pmt)",        "future value of paying pmt each period"),
+    ("fv(rate, nper, pmt, pv)",    "future value starting from pv"),
+    ("npv(rate, flows)",           "present value of a list of cash flows"),
+    ("irr(flows)",                 "rate at which a list of cash flows is worth 0"),
+    ("now()",                      "the current time, in UTC"),
+    ("time(s)",                    "reads a time such as \"2024-03-10 14:30 PST\""),
+    ("diff(expr, var)",            "derivative of expr"),
+    ("simplify(expr)",             "expr in simpler form"),
+    ("solve(equation, var)",       "solves a linear or quadratic equation"),
+    ("invert(expr, var, y)",       "the value of var at which expr is y"),
+    ("findroot(expr, var, a, b)",  "a zero of expr between a and b"),
+    ("root(expr, a, b)",           "a zero of expr in x between a and b"),
+    ("integrate(expr, var, a, b)", "definite integral from a to b"),
+    ("sum(expr, var, from, to)",   "sum over a range, or sum(list)"),
+    ("prod(expr, var, from, to)",  "product over a range, or prod(list)"),
+    ("col(name)",                  "a variable named by a string"),
+    ("equiv(a, b)",                "1 if both are the same function"),
+    ("with(name = val, expr)",     "expr with name bound to val"),
+    ("assert(cond)",               "fails unless cond is nonzero"),
+    ("assert_eq(a, b)",            "fails unless a equals b"),
+    ("help(topic)",                "describes a function, an operator or a section"),
+    ("dims(expr)",                 "the dimensions of expr, such as kg·m/s²"),
+];
+
+fn describe(kind: OpKind) -> &'static str {
+    match kind {
+        OpKind::Plus         => "addition",
+        OpKind::Minus        => "subtraction",
+        OpKind::Divide       => "division",
+        OpKind::FloorDivide  => "division rounding down",
+        OpKind::Modulo       => "remainder of //, with the sign of the divisor",
+        OpKind::Multiply     => "multiplication",
+        OpKind::Power        => "exponentiation",
+        OpKind::Equals       => "equation, for solve and with",
+        OpKind::BitAnd       => "bitwise and",
+        OpKind::BitOr        => "bitwise or",
+        OpKind::BitXor       => "bitwise exclusive or",
+        OpKind::Concat       => "both values joined as text",
+        OpKind::ShiftLeft    => "shift left",
+        OpKind::ShiftRight   => "shift right",
+        OpKind::Arrow        => "function of the parameters on the left",
+        OpKind::Range        => "list of numbers from one bound to the other",
+        OpKind::Step         => "spacing of a range, as in 0..1 step 0.25",
+        OpKind::In           => "conversion to another unit, as in 1 GiB in MB",
+        OpKind::Less         => "less than, 1 or 0",
+        OpKind::LessEqual    => "less than or equal, 1 or 0",
+        OpKind::Greater      => "greater than, 1 or 0",
+        OpKind::GreaterEqual => "greater than or equal, 1 or 0",
+        OpKind::Equal        => "equal, 1 or 0",
+        OpKind::NotEqual     => "not equal, 1 or 0",
+        OpKind::Negate       => "negation",
+        OpKind::OpenParen | OpKind::CloseParen | OpKind::Unknown => "",
+    }
+}
+
+fn operator_line(out: &mut String, op: &OperatorInfo) {
+    let assoc = if op.right_assoc { "right" } else { "left" };
+    writeln!(out, "  {:<6} {:>2}  {:<5}  {}", op.symbol, op.prec, assoc, describe(op.kind)).unwrap();
+}
+
+fn function_line(out: &mut String, (sig, desc): (&str, &str)) {
+    writeln!(out, "  {:<28} {}", sig, desc).unwrap();
+}
+
+fn operators() -> String {
+    let mut out = String::from("Operators, tightest binding first, with their precedence:\n");
+    for op in kalkul::operators() {
+        operator_line(&mut out, &op);
+    }
+    out += "  A leading - negates, binding more tightly than everything but ^.\n";
+    out
+}
+
+fn functions() -> String {
+    let mut out = String::from("Functions:\n");
+    for &doc in FUNCTIONS {
+        function_line(&mut out, doc);
+    }
+    out
+}
+
+/// The full syntax reference, as printed by `kalkul --help-syntax`.
+pub fn syntax() -> String {
+    let mut out = String::new();
+    out += "Numbers:\n  42  1.5  2.5e-3  0x1F  0o17  0b101  36#z1\n";
+    out += "Addresses:\n  192.168.1.7  10.0.0.0/8\n";
+    out += "Quantities:\n  1.5 GiB  300 MB  2 KiB in B  90 min in h  32 F in C\n";
+    out += "Times:\n  time(\"14:30 UTC\") in PST  now() + 2 h\n";
+    out += "Strings:\n  \"text\"\n";
+    out += "Lists and tuples:\n  [1, 2, 3]  (1, 2)  1..10  0..1 step 0.25\n";
+    out += "Definitions, in the interactive session:\n";
+    out += "  x = 1 + 2                 assigns a variable\n";
+    out += "  q, r = divmod(7, 2)       unpacks a tuple\n";
+    out += "  f(x) = x^2                defines a function\n";
+    out += "  f(x) = { x < 0: -x; x }   uses the first case whose guard holds\n";
+    out += "  (a, b) -> a + b           a function without a name\n";
+    out += "  unit box = 24 widget      declares a unit, or a base unit without =\n";
+    out += &operators();
+    out += &functions();
+    out
+}
+
+/// Describes a builtin or user-defined function, an operator, or one of
+/// the sections `operators` and `functions`.
+pub fn topic(name: &str, env: &Environment) -> Result<String> {
+    let mut out = match name {
+        "operators" => operators(),
+        "functions" => functions(),
+        "syntax"    => syntax(),
+        _ => String::new(),
+    };
+    if out.is_empty() {
+        if let Some(op) = kalkul::operators().iter().find(|op| op.symbol == name) {
+            operator_line(&mut out, op);
+        } else if let Some(&doc) = FUNCTIONS.iter().find(|(sig, _)| sig.split('(').next() == Some(name)) {
+            function_line(&mut out, doc);
+        } else if let Some(target) = env.alias_of(name) {
+            writeln!(out, "  {} is an alias of {}", name, target).unwrap();
+        } else if let Some(f) = env.function(name) {
+            writeln!(out, "  {} = {}", name, f).unwrap();
+        } else {
+            return Err(env.unknown_function(name));
+        }
+    }
+    Ok(out.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kalkul::Error;
+    use crate::builtins::{BUILTINS, FORMS};
+    use crate::function::Function;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_covers_registries() {
+        let documented = |name: &str, arity: usize| FUNCTIONS.iter().any(|(sig, _)| {
+            let (sig_name, params) = sig.split_once('(').unwrap();
+            let count = if params == ")" { 0 } else { params.split(',').count() };
+            sig_name == name && count == arity
+        });
+        for (name, arity) in BUILTINS.iter().map(|b| (b.name, b.arity)).chain(FORMS.iter().map(|f| (f.name, f.arity))) {
+            assert!(documented(name, arity), "{}", name);
+        }
+        assert_eq!(BUILTINS.len() + FORMS.len(), FUNCTIONS.len());
+        for op in kalkul::operators() {
+            assert!(!describe(op.kind).is_empty(), "{}", op.symbol);
+        }
+    }
+
+    #[test]
+    fn test_topics() {
+        let mut env = Environment::new();
+        assert_eq!("  ^      11  right  exponentiation", topic("^", &env).unwrap());
+        assert_eq!("  //      9  left   division rounding down", topic("//", &env).unwrap());
+        assert_eq!("  sqrt(x)                      square root", topic("sqrt", &env).unwrap());
+        assert!(topic("operators", &env).unwrap().lines().nth(1).unwrap().starts_with("  ^ "));
+        assert!(syntax().contains("\n  solve(equation, var)         solves"));
+
+        let sq = Function::parse(vec!["x".to_string()], "x^2").unwrap();
+        env.define("sq", Arc::new(sq)).unwrap();
+        assert_eq!("  sq = x -> x^2", topic("sq", &env).unwrap());
+        let e = topic("sqtr", &env).unwrap_err();
+        assert_eq!("unknown function 'sqtr', did you mean 'sqrt'?", e.to_string());
+        assert!(matches!(topic("nope", &env), Err(Error::UnknownFunction(_))));
+    }
+}
@@ -0,0 +1,269 @@
+use crate::kalkul::{Error, OpKind, Result};
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    (a as u128 * b as u128 % m as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut acc = 1 % m;
+    base %= m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            acc = mul_mod(acc, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    acc
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Reads a modulus, which has to be positive.
+fn modulus_arg(m: i64) -> Result<u64> {
+    if m > 0 { Ok(m as u64) } else { Err(Error::TypeError) }
+}
+
+/// `b` to the power `e`, modulo `m`, without the power ever being
+/// written out. A negative `e` raises the inverse of `b`.
+pub fn powmod(b: i64, e: i64, m: i64) -> Result<i64> {
+    let m = modulus_arg(m)?;
+    let b = if e < 0 { invmod(b, m as i64)? } else { b };
+    Ok(pow_mod(b.rem_euclid(m as i64) as u64, e.unsigned_abs(), m) as i64)
+}
+
+/// The `x` between 0 and `m` for which `a * x` is 1 modulo `m`, by the
+/// extended Euclidean algorithm. There is none unless `a` and `m` are
+/// coprime.
+pub fn invmod(a: i64, m: i64) -> Result<i64> {
+    let m = modulus_arg(m)? as i128;
+    let (mut r, mut r_next) = (m, (a as i128).rem_euclid(m));
+    let (mut x, mut x_next) = (0i128, 1i128);
+    while r_next != 0 {
+        let q = r / r_next;
+        (r, r_next) = (r_next, r - q * r_next);
+        (x, x_next) = (x_next, x - q * x_next);
+    }
+    if r != 1 {
+        return Err(Error::NoSolution);
+    }
+    Ok(x.rem_euclid(m) as i64)
+}
+
+/// Applies an operator to integers modulo `m`, for `:mod`, or returns
+/// `None` for operators that ignore the modulus, such as comparisons.
+/// Division multiplies by the inverse of the divisor.
+pub fn apply_mod(kind: OpKind, a: i64, b: i64, m: i64) -> Option<Result<i64>> {
+    let reduce = |n: i128| n.rem_euclid(m as i128) as i64;
+    let (a, b) = (a as i128, b as i128);
+    let res = match kind {
+        OpKind::Plus     => Ok(reduce(a + b)),
+        OpKind::Minus    => Ok(reduce(a - b)),
+        OpKind::Multiply => Ok(reduce(reduce(a) as i128 * reduce(b) as i128)),
+        OpKind::Divide   => match invmod(b as i64, m) {
+            Ok(inverse) => Ok(reduce(reduce(a) as i128 * inverse as i128)),
+            Err(_) => Err(Error::DivisionByZero),
+        },
+        // As for division, there is no inverse to raise.
+        OpKind::Power    => match powmod(a as i64, b as i64, m) {
+            Err(Error::NoSolution) => Err(Error::DivisionByZero),
+            res => res,
+        },
+        _ => return None,
+    };
+    Some(res)
+}
+
+/// Miller-Rabin with the first twelve primes as witnesses, which is
+/// exact for every 64-bit number.
+pub fn is_prime(n: u64) -> bool {
+    const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    if let Some(p) = WITNESSES.iter().find(|p| n.is_multiple_of(**p)) {
+        return n == *p;
+    }
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    WITNESSES.iter().all(|&a| {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            return true;
+        }
+        for _ in 1..s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// A factor of the composite `n` other than 1 and `n`, by Pollard's rho
+/// method, trying another polynomial whenever one cycles without finding
+/// any.
+fn split(n: u64) -> u64 {
+    if n.is_multiple_of(2) {
+        return 2;
+    }
+    for c in 1.. {
+        let f = |x: u64| (mul_mod(x, x, n) + c) % n;
+        let (mut x, mut y, mut d) = (2, 2, 1);
+        while d == 1 {
+            x = f(x);
+            y = f(f(y));
+            d = gcd(x.abs_diff(y), n);
+        }
+        if d != n {
+            return d;
+        }
+    }
+    unreachable!()
+}
+
+fn prime_factors(n: u64, factors: &mut Vec<u64>) {
+    if n == 1 {
+        return;
+    }
+    if is_prime(n) {
+        factors.push(n);
+        return;
+    }
+    let d = split(n);
+    prime_factors(d, factors);
+    prime_factors(n / d, factors);
+}
+
+/// The prime factors of `n` from smallest to largest, repeated as often
+/// as they divide it, with -1 in front for a negative `n`: `factorize(360)`
+/// is `[2, 2, 2, 3, 3, 5]`.
+pub fn factorize(n: i64) -> Result<Vec<i64>> {
+    if n == 0 {
+        return Err(Error::TypeError);
+    }
+    let mut factors = Vec::new();
+    let mut m = n.unsigned_abs();
+    // Small factors are quicker found by trial division.
+    for p in 2..1000 {
+        while m.is_multiple_of(p) {
+            factors.push(p);
+            m /= p;
+        }
+    }
+    prime_factors(m, &mut factors);
+    factors.sort_unstable();
+    let sign = (n < 0).then_some(-1);
+    // Every prime factor fits in an i64, even those of -2^63.
+    Ok(sign.into_iter().chain(factors.into_iter().map(|p| p as i64)).collect())
+}
+
+/// Every positive divisor of `n`, in increasing order.
+pub fn divisors(n: i64) -> Result<Vec<i64>> {
+    let mut divisors = vec![1u64];
+    let factors = factorize(n)?;
+    let mut primes = factors.iter().filter(|p| **p > 0).peekable();
+    while let Some(&p) = primes.next() {
+        let mut count = 1;
+        while primes.next_if_eq(&&p).is_some() {
+            count += 1;
+        }
+        let known = divisors.len();
+        let mut power = 1u64;
+        for _ in 0..count {
+            power *= p as u64;
+            for i in 0..known {
+                divisors.push(divisors[i] * power);
+            }
+        }
+    }
+    divisors.sort_unstable();
+    divisors.into_iter().map(|d| i64::try_from(d).map_err(|_| Error::Overflow)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::{Environment, EvalOptions, Modulus};
+    use crate::kalkul::Error;
+
+    #[test]
+    fn test_modular() {
+        let exprs = [
+            "powmod(4, 13, 497)",
+            "powmod(2, 10000000000, 1000000007)",
+            "powmod(-2, 3, 5)",
+            "powmod(3, -1, 7)",
+            "powmod(5, 0, 1)",
+            "invmod(3, 7)",
+            "invmod(-3, 7)",
+            "invmod(65537, 3120)",
+        ];
+        let answers = ["445", "291251492", "2", "5", "0", "5", "2", "2753"];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert!(matches!(env.eval("invmod(6, 9)"), Err(Error::NoSolution)));
+        assert!(matches!(env.eval("powmod(2, 3, 0)"), Err(Error::TypeError)));
+
+        assert!(Modulus::new(0).is_none() && Modulus::new(-7).is_none() && Modulus::new(1).is_none());
+        env.set_options(EvalOptions { modulus: Modulus::new(17), ..env.options() });
+        let exprs = ["3 ^ 100", "10 + 9", "3 - 5", "-4 + 0", "5 / 3", "3 * (1 / 3)", "2 ^ -1", "12345678901 * 98765432109", "5 < 7"];
+        let answers = ["13", "2", "15", "13", "13", "1", "9", "8", "1"];
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        env.set_options(EvalOptions { modulus: Modulus::new(6), ..env.options() });
+        for expr in ["1 / 3", "3 / 0", "0 ^ -1", "2 ^ -1"] {
+            assert!(matches!(env.eval(expr), Err(Error::DivisionByZero)), "{}", expr);
+        }
+        env.set_options(EvalOptions { modulus: Modulus::new(7), ..env.options() });
+        for expr in ["sum(1..4)", "sum(i, i, 1, 4)", "prod(i, i, 3, 5) - 1"] {
+            assert_eq!("3", env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_factorize() {
+        let exprs = [
+            "factorize(360)",
+            "factorize(1)",
+            "factorize(-12)",
+            "factorize(97)",
+            "factorize(600851475143)",
+            "factorize(9223372036854775783)",
+            "factorize(4611686014132420609)",
+            "divisors(360)",
+            "divisors(-28)",
+            "divisors(1)",
+        ];
+        let answers = [
+            "[2, 2, 2, 3, 3, 5]",
+            "[]",
+            "[-1, 2, 2, 3]",
+            "[97]",
+            "[71, 839, 1471, 6857]",
+            "[9223372036854775783]",
+            "[2147483647, 2147483647]",
+            "[1, 2, 3, 4, 5, 6, 8, 9, 10, 12, 15, 18, 20, 24, 30, 36, 40, 45, 60, 72, 90, 120, 180, 360]",
+            "[1, 2, 4, 7, 14, 28]",
+            "[1]",
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert_eq!(63, env.eval("factorize(-9223372036854775807 - 1)").unwrap().to_string().matches('2').count());
+        for expr in ["factorize(0)", "divisors(0)", "factorize(2.5)"] {
+            assert!(matches!(env.eval(expr), Err(Error::TypeError)), "{}", expr);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::format;
+use crate::kalkul::{Error, Result};
+
+/// A parsed JSON document. Just enough of JSON for the request and
+/// response bodies used by the server and editor integrations.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+/// How deeply arrays and objects may be nested in parsed input.
+const MAX_DEPTH: usize = 256;
+
+impl Json {
+    pub fn parse(src: &str) -> Result<Json> {
+        let mut p = JsonParser { cs: src.chars().collect(), i: 0, depth: 0 };
+        let val = p.value()?;
+        p.skip_ws();
+        if p.i != p.cs.len() {
+            return Err(Error::ParseError);
+        }
+        Ok(val)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Num(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// Builds an object from key-value pairs.
+    pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    pub fn str(s: impl Into<String>) -> Json {
+        Json::Str(s.into())
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Num(x) if x.is_finite() => write!(f, "{}", format::shortest(*x)),
+            Json::Num(_) => write!(f, "null"),
+            Json::Str(s) => write_str(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"'  => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct JsonParser {
+    cs: Vec<char>,
+    i: usize,
+    depth: usize,
+}
+
+impl JsonParser {
+    fn skip_ws(&mut self) {
+        while self.i < self.cs.len() && self.cs[self.i].is_whitespace() {
+            self.i += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.cs.get(self.i).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        self.skip_ws();
+        if self.peek() != Some(c) {
+            return Err(Error::ParseError);
+        }
+        self.i += 1;
+        Ok(())
+    }
+
+    fn keyword(&mut self, word: &str, val: Json) -> Result<Json> {
+        for c in word.chars() {
+            if self.peek() != Some(c) {
+                return Err(Error::ParseError);
+            }
+            self.i += 1;
+        }
+        Ok(val)
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        self.skip_ws();
+        match self.peek().ok_or(Error::ParseError)? {
+            '[' | '{' if self.depth >= MAX_DEPTH => Err(Error::ParseError),
+            '[' | '{' => {
+                self.depth += 1;
+                let val = self.nested();
+                self.depth -= 1;
+                val
+            },
+            '"' => Ok(Json::Str(self.string()?)),
+            'n' => self.keyword("null", Json::Null),
+            't' => self.keyword("true", Json::Bool(true)),
+            'f' => self.keyword("false", Json::Bool(false)),
+            _ => self.number(),
+        }
+    }
+
+    fn nested(&mut self) -> Result<Json> {
+        match self.peek().ok_or(Error::ParseError)? {
+            '[' => {
+                self.i += 1;
+                let mut items = Vec::new();
+                self.skip_ws();
+                if self.peek() == Some(']') {
+                    self.i += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_ws();
+                    match self.peek() {
+                        Some(',') => self.i += 1,
+                        Some(']') => {
+                            self.i += 1;
+                            return Ok(Json::Array(items));
+                        },
+                        _ => return Err(Error::ParseError),
+                    }
+                }
+            },
+            '{' => {
+                self.i += 1;
+                let mut fields = BTreeMap::new();
+                self.skip_ws();
+                if self.peek() == Some('}') {
+                    self.i += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_ws();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    fields.insert(key, self.value()?);
+                    self.skip_ws();
+                    match self.peek() {
+                        Some(',') => self.i += 1,
+                        Some('}') => {
+                            self.i += 1;
+                            return Ok(Json::Object(fields));
+                        },
+                        _ => return Err(Error::ParseError),
+                    }
+                }
+            },
+            _ => Err(Error::ParseError),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.i;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || "+-.eE".contains(c) {
+                self.i += 1;
+            } else {
+                break;
+            }
+        }
+        let text: String = self.cs[start..self.i].iter().collect();
+        text.parse().map(Json::Num).map_err(|_| Error::ParseError)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        if self.peek() != Some('"') {
+            return Err(Error::ParseError);
+        }
+        self.i += 1;
+        let mut s = String::new();
+        loop {
+            let c = self.peek().ok_or(Error::ParseError)?;
+            self.i += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let esc = self.peek().ok_or(Error::ParseError)?;
+                    self.i += 1;
+                    match esc {
+                        '"' | '\\' | '/' => s.push(esc),
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => {
+                            let hex: String = self.cs.get(self.i..self.i + 4).ok_or(Error::ParseError)?.iter().collect();
+                            self.i += 4;
+                            let code = u32::from_str_radix(&hex, 16).map_err(|_| Error::ParseError)?;
+                            s.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                        },
+                        _ => return Err(Error::ParseError),
+                    }
+                },
+                c => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let src = r#" {"expr": "1 + \"2\"\n", "n": -1.5e2, "ok": [true, false, null], "empty": {}} "#;
+        let json = Json::parse(src).unwrap();
+        assert_eq!(Some("1 + \"2\"\n"), json.get("expr").and_then(Json::as_str));
+        assert_eq!(Some(-150.0), json.get("n").and_then(Json::as_f64));
+        assert_eq!(
+            r#"{"empty":{},"expr":"1 + \"2\"\n","n":-150,"ok":[true,false,null]}"#,
+            json.to_string(),
+        );
+
+        assert!(Json::parse("{\"a\": }").is_err());
+        assert!(Json::parse("[1, 2] 3").is_err());
+
+        let nested = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(Json::parse(&nested).is_ok());
+        let deep = "[".repeat(20000) + &"]".repeat(20000);
+        assert!(Json::parse(&deep).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use std::borrow::Cow;
+use std::char::ParseCharError;
+use std::ops::Range;
+use std::string::FromUtf8Error;
+use std::sync::Arc;
+
+use crate::env::EvalOptions;
+
+use super::value::Value;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    ReadError,
+    ParseError,
+    NotEnoughElements,
+    UnknownOperator,
+    UnexpectedToken,
+    /// A character no token starts with, and its offset in the input.
+    UnexpectedCharacter(char, usize),
+    UnbalancedParens,
+    DivisionByZero,
+    UnknownCommand,
+    Usage,
+    LimitExceeded,
+    Timeout,
+    UnknownFunction(Unknown),
+    UnknownVariable(Unknown),
+    ArgumentCount,
+    TypeError,
+    NoPreviousResult,
+    NothingToUndo,
+    NotDifferentiable,
+    NotSolvable,
+    NoSolution,
+    InfiniteSolutions,
+
+    StackUnderflow,
+    NotFinite,
+    Overflow,
+    /// Names that are defined in terms of each other, starting and ending
+    /// with the same one.
+    CircularReference(Vec<String>),
+    /// Unpacking assigned a different number of values than names.
+    UnpackMismatch,
+    /// None of the guards of a piecewise function held.
+    NoMatchingCase,
+    /// Calls to user-defined functions nested too deeply.
+    RecursionLimit,
+    /// A call to a user-defined function took more steps than
+    /// `Limits::call_steps`.
+    FunctionStepLimit,
+    /// A call to a user-defined function allocated more than
+    /// `Limits::call_memory`.
+    FunctionMemoryLimit,
+    /// An `assert` or `assert_eq` did not hold, with what was asserted.
+    AssertionFailed(String),
+    /// An error on a numbered line of a script.
+    AtLine(usize, Box<Error>),
+    /// No program for reaching the system clipboard was found.
+    NoClipboard,
+    /// A unit or time zone name that is not known.
+    UnknownUnit(String),
+    /// An operator or function left out of `EvalOptions::allowed`.
+    NotAllowed(&'static str),
+    /// An encoded program this version cannot read, with the version of
+    /// kalkul that wrote it, or of its encoding when that is not known.
+    IncompatibleVersion(String),
+    /// A `VariableProvider` could not fetch the named variable, and why.
+    FetchFailed(String, String),
+    /// Quantities of different dimensions, such as `kg` and `m/s`, that
+    /// cannot be added or compared.
+    DimensionMismatch(String, String),
+    /// More of something, such as the cells of a range, than can be
+    /// handled: what there are, how many, and the most allowed.
+    TooMany(&'static str, u64, u64),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            Error::ReadError         => "could not read input",
+            Error::ParseError        => "could not parse input",
+            Error::NotEnoughElements => "missing operand",
+            Error::UnknownOperator   => "unknown operator",
+            Error::UnexpectedToken   => "unexpected token",
+            Error::UnexpectedCharacter(c, at) => return write!(f, "unexpected character '{}' at column {}", c, at + 1),
+            Error::UnbalancedParens  => "unbalanced parentheses",
+            Error::DivisionByZero    => "division by zero",
+            Error::UnknownCommand    => "unknown command",
+            Error::Usage             => "invalid arguments",
+            Error::LimitExceeded     => "evaluation step limit exceeded",
+            Error::Timeout           => "evaluation timed out",
+            Error::UnknownFunction(u) => return write!(f, "unknown function {}", u),
+            Error::UnknownVariable(u) => return write!(f, "unknown variable {}", u),
+            Error::ArgumentCount     => "wrong number of arguments",
+            Error::TypeError         => "wrong type of argument",
+            Error::NoPreviousResult  => "no previous result",
+            Error::NothingToUndo     => "nothing to undo",
+            Error::NotDifferentiable => "expression cannot be differentiated",
+            Error::NotSolvable       => "equation cannot be solved",
+            Error::NoSolution        => "equation has no solution",
+            Error::InfiniteSolutions => "equation has infinitely many solutions",
+            Error::StackUnderflow    => "malformed expression",
+            Error::NotFinite         => "result is not a finite number",
+            Error::Overflow          => "integer overflow",
+            Error::UnpackMismatch    => "number of names and values differ",
+            Error::NoMatchingCase    => "no case of the function applies",
+            Error::RecursionLimit    => "function calls nested too deeply",
+            Error::FunctionStepLimit => "function call step limit exceeded",
+            Error::FunctionMemoryLimit => "function call memory limit exceeded",
+            Error::NoClipboard       => "no clipboard available",
+            Error::AssertionFailed(what) => return write!(f, "assertion failed: {}", what),
+            Error::AtLine(line, e) => return write!(f, "line {}: {}", line, e),
+            Error::UnknownUnit(name) => return write!(f, "unknown unit or time zone '{}'", name),
+            Error::CircularReference(path) => return write!(f, "circular reference {}", path.join(" -> ")),
+            Error::NotAllowed(name) => return write!(f, "'{}' is not allowed", name),
+            Error::IncompatibleVersion(by) => return write!(f, "program was compiled by an incompatible version of kalkul ({})", by),
+            Error::FetchFailed(name, why) => return write!(f, "could not fetch {}: {}", name, why),
+            Error::DimensionMismatch(a, b) => return write!(f, "mismatched dimensions {} and {}", a, b),
+            Error::TooMany(what, n, max) => return write!(f, "too many {}: {} where at most {} are allowed", what, n, max),
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl Error {
+    /// The status the command line tool exits with after this error: 2
+    /// when it was run the wrong way and 1 when evaluation failed.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage => 2,
+            Error::AtLine(_, e) => e.exit_code(),
+            _ => 1,
+        }
+    }
+}
+
+/// The names that exist where an `Unknown` was not found, only looked
+/// through once the error is shown.
+pub trait Known: Send + Sync {
+    fn names(&self) -> Vec<&str>;
+}
+
+impl Known for Vec<Cow<'static, str>> {
+    fn names(&self) -> Vec<&str> {
+        self.iter().map(AsRef::as_ref).collect()
+    }
+}
+
+/// A name that was not found, along with the names that exist, the
+/// closest of which is only worked out when the error is shown.
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct Unknown {
+    pub name: String,
+    known: Arc<dyn Known>,
+}
+
+impl Unknown {
+    pub fn new(name: &str, known: impl IntoIterator<Item = impl Into<Cow<'static, str>>>) -> Unknown {
+        let known: Vec<Cow<'static, str>> = known.into_iter().map(Into::into).collect();
+        Unknown::lazy(name, Arc::new(known))
+    }
+
+    /// Like `new`, for names that are costly to list, such as those of
+    /// an environment, and so are only listed if the error is shown.
+    pub fn lazy(name: &str, known: Arc<dyn Known>) -> Unknown {
+        Unknown { name: name.to_string(), known }
+    }
+
+    /// The existing name closest to the one not found, if any is close.
+    pub fn suggestion(&self) -> Option<&str> {
+        // Allow about one typo for every three characters.
+        let max = (self.name.chars().count() / 3).max(1);
+        self.known.names().into_iter()
+            .map(|k| (edit_distance(&self.name, k), k))
+            .filter(|(d, _)| *d <= max)
+            .min()
+            .map(|(_, k)| k)
+    }
+}
+
+impl PartialEq for Unknown {
+    fn eq(&self, other: &Unknown) -> bool {
+        self.name == other.name && self.suggestion() == other.suggestion()
+    }
+}
+
+impl std::fmt::Debug for Unknown {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Unknown").field("name", &self.name).field("suggestion", &self.suggestion()).finish()
+    }
+}
+
+impl std::fmt::Display for Unknown {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "'{}'", self.name)?;
+        match self.suggestion() {
+            Some(s) => write!(f, ", did you mean '{}'?", s),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Edit distance between two strings, counted in characters. Swapping
+/// two neighbouring characters counts as a single edit.
+pub(super) fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Something questionable that happened during an evaluation that still
+/// produced a value.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// An integer operand was rounded on its way to floating point.
+    PrecisionLoss(i64),
+    /// An integer result was out of range and computed in floating point.
+    FloatFallback,
+}
+
+impl Warning {
+    /// The warning, if any, for computing `result` from operands that
+    /// were the given integers, or `None` for those that were not.
+    pub(crate) fn check(operands: &[Option<i64>], result: &Value, options: &EvalOptions) -> Option<Warning> {
+        let x = match result {
+            Value::Float(x) => *x,
+            _ => return None,
+        };
+        if operands.iter().all(Option::is_some) {
+            let out_of_range = x.fract() == 0.0 && !options.width.contains(x as i128);
+            return out_of_range.then_some(Warning::FloatFallback);
+        }
+        let lossy = |n: &i64| (*n as f64) as i128 != *n as i128;
+        operands.iter().flatten().copied().find(lossy).map(Warning::PrecisionLoss)
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Warning::PrecisionLoss(n) => write!(f, "{} was rounded converting it to a float", n),
+            Warning::FloatFallback    => write!(f, "integer result out of range, computed as a float"),
+        }
+    }
+}
+
+/// An error along with the part of the input it is about. Only the
+/// offsets are kept while parsing; the text shown to people is put
+/// together by `render` when it is asked for.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Diagnostic {
+    pub error: Error,
+    /// Character offsets into the input.
+    pub span: Range<usize>,
+}
+
+impl Diagnostic {
+    /// What went wrong, leaving out where, which the span tells.
+    pub fn message(&self) -> String {
+        match &self.error {
+            Error::UnexpectedCharacter(c, _) => format!("unexpected character '{}'", c),
+            e => e.to_string(),
+        }
+    }
+
+    /// The line of `source` the error is on, marked underneath where it
+    /// is and followed by the message, prefixed with the line number if
+    /// `source` has more than one:
+    ///
+    /// ```text
+    /// 1 + (2 * 3
+    ///     ^^^^^^ unbalanced parentheses
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let lines: Vec<&str> = source.split('\n').collect();
+        let (mut n, mut start) = (0, 0);
+        // A span past the end of the input is put at the end of the
+        // last line.
+        while n + 1 < lines.len() && self.span.start > start + lines[n].chars().count() {
+            start += lines[n].chars().count() + 1;
+            n += 1;
+        }
+        let len = lines[n].chars().count();
+        let col = self.span.start.saturating_sub(start).min(len);
+        let width = self.span.end.min(start + len).saturating_sub(start + col).max(1);
+        let (prefix, margin) = match lines.len() {
+            1 => (String::new(), String::new()),
+            _ => (format!("{} | ", n + 1), format!("{} | ", " ".repeat((n + 1).to_string().len()))),
+        };
+        format!("{}{}\n{}{}{} {}", prefix, lines[n], margin, " ".repeat(col), "^".repeat(width), self.message())
+    }
+}
+
+impl From<ParseCharError> for Error {
+    fn from(_e: ParseCharError) -> Error {
+        Error::ParseError
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(_e: FromUtf8Error) -> Error {
+        Error::ParseError
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(_e: std::io::Error) -> Error {
+        Error::ReadError
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
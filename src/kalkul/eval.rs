@@ -0,0 +1,380 @@
+use std::io::BufRead;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::builtins;
+use crate::clock;
+use crate::env::Environment;
+use crate::function::{Case, Function};
+use crate::units;
+
+use super::error::{Error, Result, Warning};
+#[cfg(feature = "tokio")]
+use super::lexer::ChunkLexer;
+use super::lexer::{Op, OpKind, Token, TokenStream};
+use super::parser::Expr;
+#[cfg(feature = "tokio")]
+use super::parser::Syntax;
+use super::value::Value;
+
+/// A value along with the warnings raised while computing it.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct EvalOutput {
+    pub value: Value,
+    pub warnings: Vec<Warning>,
+    pub usage: Usage,
+}
+
+/// The resources one evaluation used, for metering expressions from
+/// many users.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Usage {
+    /// Evaluation steps taken, as counted against `Limits::max_steps`.
+    pub operations: u64,
+    /// Deepest nesting of subexpressions being evaluated at once, or of
+    /// values on the stack of a compiled program.
+    pub max_depth: usize,
+    /// Values produced that own memory on the heap, such as strings and
+    /// lists, which stands in for the allocations made without needing
+    /// a counting allocator.
+    pub allocations: u64,
+}
+
+/// A value along with what it took to compute it, for logging.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct EvalDetails {
+    pub value: Value,
+    pub warnings: Vec<Warning>,
+    /// Number of tokens in the input.
+    pub tokens: usize,
+    /// Evaluation steps taken, as counted against `Limits::max_steps`.
+    pub steps: u64,
+    /// Time taken to tokenize and parse the input.
+    pub parse_time: Duration,
+    /// Time taken to evaluate the parsed expression.
+    pub eval_time: Duration,
+    /// The expression as parsed, printed back in canonical form.
+    pub normalized: String,
+}
+
+impl EvalDetails {
+    /// The durations, as in `parse 12µs, eval 3.1ms`.
+    pub fn timing(&self) -> String {
+        format!("parse {:.1?}, eval {:.1?}", self.parse_time, self.eval_time)
+    }
+}
+
+struct Evaluator {
+    nums: Vec<i32>,
+    ops: Vec<Op>,
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
+        Evaluator {
+            nums: Vec::new(),
+            ops: Vec::new(),
+        }
+    }
+
+    pub fn evaluate(&mut self) -> Result<()> {
+        let res = match (self.pop_num(), self.pop_num()) {
+            (None, None) => Err(Error::NotEnoughElements),
+            (Some(_), None)    => Err(Error::NotEnoughElements),
+            (None, Some(_))    => Err(Error::NotEnoughElements),
+            (Some(lhs), Some(rhs)) => {
+                if let Some(op) = self.pop_op() {
+                    match op.kind {
+                        OpKind::Unknown | OpKind::Negate | OpKind::Power | OpKind::Equals | OpKind::FloorDivide |
+                        OpKind::Modulo | OpKind::BitAnd | OpKind::BitOr | OpKind::BitXor | OpKind::ShiftLeft |
+                        OpKind::ShiftRight | OpKind::Concat | OpKind::Arrow | OpKind::Range | OpKind::Step | OpKind::In |
+                        OpKind::Less | OpKind::LessEqual | OpKind::Greater | OpKind::GreaterEqual |
+                        OpKind::Equal | OpKind::NotEqual => {
+                            Err(Error::UnknownOperator)
+                        },
+
+                        OpKind::Plus        => rhs.checked_add(lhs).ok_or(Error::Overflow),
+                        OpKind::Minus       => rhs.checked_sub(lhs).ok_or(Error::Overflow),
+                        OpKind::Divide if lhs == 0 => Err(Error::DivisionByZero),
+                        OpKind::Divide      => rhs.checked_div(lhs).ok_or(Error::Overflow),
+                        OpKind::Multiply    => rhs.checked_mul(lhs).ok_or(Error::Overflow),
+                        // Parentheses are matched in `read` and `finish`.
+                        OpKind::OpenParen | OpKind::CloseParen => Err(Error::UnbalancedParens),
+                    }
+                } else {
+                    Err(Error::NotEnoughElements)
+                }
+            }
+        };
+
+        match res {
+            Ok(val) => {
+                self.push_num(val);
+                Ok(())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn push_op(&mut self, op: Op) {
+        self.ops.push(op)
+    }
+
+    pub fn pop_op(&mut self) -> Option<Op> {
+        self.ops.pop()
+    }
+
+    pub fn top_op(&self) -> Option<&Op> {
+        self.ops.last()
+    }
+
+    pub fn push_num(&mut self, n: i32) {
+        self.nums.push(n)
+    }
+
+    pub fn pop_num(&mut self) -> Option<i32> {
+        self.nums.pop()
+    }
+
+    pub fn top_num(&self) -> Option<&i32> {
+        self.nums.last()
+    }
+
+    /// Takes the next token of the input, with names read from the
+    /// integer variables of `env`.
+    pub fn read(&mut self, token: Token, env: &Environment) -> Result<()> {
+        let n = match token {
+            Token::Num(Value::Int(n)) => i32::try_from(n).map_err(|_| Error::Overflow)?,
+            Token::Num(_) => return Err(Error::ParseError),
+            Token::Ident(name) => match env.get(&name) {
+                Some(Value::Int(n)) => i32::try_from(*n).map_err(|_| Error::Overflow)?,
+                Some(_) => return Err(Error::TypeError),
+                None => return Err(env.unknown_variable(&name)),
+            },
+            Token::Op(OpKind::OpenParen) => {
+                self.push_op(Op::new(OpKind::OpenParen));
+                return Ok(());
+            },
+            Token::Op(OpKind::CloseParen) => {
+                loop {
+                    match self.top_op() {
+                        None => return Err(Error::UnbalancedParens),
+                        Some(top) if top.kind == OpKind::OpenParen => break,
+                        Some(_) => self.evaluate()?,
+                    }
+                }
+                self.pop_op();
+                return Ok(());
+            },
+            Token::Op(kind) => {
+                let op = Op::new(kind);
+                while let Some(top) = self.top_op() {
+                    if top.kind == OpKind::OpenParen || top.prec < op.prec {
+                        break;
+                    }
+                    self.evaluate()?;
+                }
+                self.push_op(op);
+                return Ok(());
+            },
+            _ => return Err(Error::UnexpectedToken),
+        };
+        self.push_num(n);
+        Ok(())
+    }
+
+    /// Evaluates the operators left once the input has ended.
+    pub fn finish(&mut self) -> Result<i32> {
+        while let Some(top) = self.top_op() {
+            if top.kind == OpKind::OpenParen {
+                return Err(Error::UnbalancedParens);
+            }
+            self.evaluate()?;
+        }
+
+        match self.top_num() {
+            Some(num) => Ok(*num),
+            None => Err(Error::StackUnderflow)
+        }
+    }
+}
+
+pub fn evaluate(src: impl BufRead) -> Result<i32> {
+    evaluate_in(src, &Environment::new())
+}
+
+/// Like `evaluate`, reading names such as `width` from the integer
+/// variables of `env`. Any other name is an error.
+pub fn evaluate_in(src: impl BufRead, env: &Environment) -> Result<i32> {
+    let mut ev = Evaluator::new();
+    for token in TokenStream::new(src) {
+        ev.read(token?.0, env)?;
+    }
+    ev.finish()
+}
+
+/// Like `evaluate`, reading from an asynchronous source such as a
+/// socket, so that waiting for the input does not block the thread.
+#[cfg(feature = "tokio")]
+pub async fn evaluate_async(src: impl AsyncBufRead + Unpin) -> Result<i32> {
+    evaluate_async_in(src, &Environment::new()).await
+}
+
+/// Like `evaluate_in`, reading from an asynchronous source.
+#[cfg(feature = "tokio")]
+pub async fn evaluate_async_in(mut src: impl AsyncBufRead + Unpin, env: &Environment) -> Result<i32> {
+    let mut ev = Evaluator::new();
+    let mut lexer = ChunkLexer::new(Syntax::default());
+    loop {
+        while let Some(token) = lexer.next_token() {
+            ev.read(token?.0, env)?;
+        }
+        if lexer.is_finished() {
+            return ev.finish();
+        }
+        let buf = src.fill_buf().await?;
+        let len = buf.len();
+        lexer.feed(buf);
+        src.consume(len);
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, env: &mut Environment) -> Result<Value> {
+        env.enter_eval();
+        let res = self.eval_node(env);
+        env.leave_eval();
+        res
+    }
+
+    fn eval_node(&self, env: &mut Environment) -> Result<Value> {
+        env.tick()?;
+        match self {
+            Expr::Neg(_) => env.check_allowed(OpKind::Negate.symbol())?,
+            Expr::Binary(kind, _, _) => env.check_allowed(kind.symbol())?,
+            _ => {},
+        }
+        let val = match self {
+            Expr::Lit(v) => Ok(v.clone()),
+            Expr::Var(name) => env.read(name),
+            Expr::Neg(e) => {
+                let val = e.eval(env)?;
+                env.negate(val)
+            },
+            Expr::Binary(OpKind::Arrow, params, body) => Ok(lambda(params, body)?),
+            Expr::Binary(OpKind::Step, range, step) => match &**range {
+                Expr::Binary(OpKind::Range, from, to) => {
+                    let (from, to, step) = (from.eval(env)?, to.eval(env)?, step.eval(env)?);
+                    range_list(from, to, step, env)
+                },
+                _ => Err(Error::TypeError),
+            },
+            Expr::Binary(OpKind::Range, from, to) => {
+                let (from, to) = (from.eval(env)?, to.eval(env)?);
+                range_list(from, to, Value::Int(1), env)
+            },
+            Expr::Binary(OpKind::In, val, unit) => {
+                // Names that are not identifiers, such as `"UTC+5:30"`,
+                // can be quoted.
+                let name = match &**unit {
+                    Expr::Var(name) | Expr::Lit(Value::Str(name)) => name,
+                    _ => return Err(Error::TypeError),
+                };
+                match val.eval(env)? {
+                    Value::Time(t) => clock::convert(&t, name),
+                    val => units::convert(&val, name, env),
+                }
+            },
+            Expr::Binary(kind, lhs, rhs) => {
+                let lhs = lhs.eval(env)?;
+                let rhs = rhs.eval(env)?;
+                env.apply(*kind, lhs, rhs)
+            },
+            Expr::List(items) => items.iter().map(|e| e.eval(env)).collect::<Result<_>>().map(Value::List),
+            Expr::Tuple(items) => items.iter().map(|e| e.eval(env)).collect::<Result<_>>().map(Value::Tuple),
+            Expr::Call(name, args) => {
+                let alias = env.alias_of(name);
+                let name = alias.as_deref().unwrap_or(name);
+                if let Some(form) = builtins::lookup_form(name) {
+                    env.check_allowed(form.name)?;
+                    return form.call(args, env);
+                }
+                let mut vals = Vec::with_capacity(args.len());
+                for arg in args {
+                    vals.push(arg.eval(env)?);
+                }
+                builtins::call(name, &vals, env)
+            },
+        };
+        env.check_value(val?)
+    }
+}
+
+/// Longest list a range may produce.
+const MAX_RANGE_LEN: usize = 10_000_000;
+
+/// The numbers from `from` up to and including `to`, `step` apart. The
+/// numbers go down when `step` is negative.
+fn range_list(from: Value, to: Value, step: Value, env: &mut Environment) -> Result<Value> {
+    let (a, b, d) = (from.as_f64()?, to.as_f64()?, step.as_f64()?);
+    if d == 0.0 || !d.is_finite() {
+        return Err(Error::TypeError);
+    }
+    let len = ((b - a) / d).floor() + 1.0;
+    if len > MAX_RANGE_LEN as f64 {
+        return Err(Error::LimitExceeded);
+    }
+    let len = len.max(0.0) as usize;
+    // Grown as the steps are taken, so that `Limits::max_steps` bounds
+    // the memory used as well.
+    let mut items = Vec::new();
+    for k in 0..len {
+        env.tick()?;
+        items.push(match (&from, &step) {
+            (Value::Int(a), Value::Int(d)) => {
+                let n = (k as i64).checked_mul(*d).and_then(|n| a.checked_add(n));
+                Value::Int(n.ok_or(Error::Overflow)?)
+            },
+            _ => Value::Float(a + k as f64 * d),
+        });
+    }
+    Ok(Value::List(items))
+}
+
+/// The function made by `params -> body`. Lambdas do not capture
+/// variables; the body sees the variables set where it is called.
+pub(super) fn lambda(params: &Expr, body: &Expr) -> Result<Value> {
+    let names = match params {
+        Expr::Tuple(items) => items.iter().collect(),
+        param => vec![param],
+    };
+    let params = names.into_iter()
+        .map(|p| match p {
+            Expr::Var(name) => Ok(name.clone()),
+            _ => Err(Error::TypeError),
+        })
+        .collect::<Result<_>>()?;
+    let cases = vec![Case { guard: None, body: body.clone() }];
+    Ok(Value::Func(Arc::new(Function { params, cases })))
+}
+
+/// Parses and evaluates a single expression in a fresh environment.
+pub fn eval(src: &str) -> Result<Value> {
+    Environment::new().eval(src)
+}
+
+/// Like `eval`, also returning the warnings raised along the way.
+pub fn eval_output(src: &str) -> Result<EvalOutput> {
+    Environment::new().eval_output(src)
+}
+
+/// Like `eval`, also returning how the evaluation went.
+pub fn evaluate_detailed(src: &str) -> Result<EvalDetails> {
+    Environment::new().eval_detailed(src)
+}
@@ -0,0 +1,728 @@
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::ops::Range;
+
+use crate::net::Addr;
+use crate::units::{self, Quantity};
+
+use super::error::{Diagnostic, Error, Result};
+use super::parser::{Dialect, Strictness, Syntax};
+use super::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum OpKind {
+    Plus,
+    Minus,
+    Divide,
+    /// `//`, division rounding towards negative infinity.
+    FloorDivide,
+    Multiply,
+    Power,
+    /// `%`, the remainder of `//`, with the sign of the divisor.
+    Modulo,
+    Equals,
+    BitAnd,
+    BitOr,
+    /// Exclusive or, written `^` in the Python dialect.
+    BitXor,
+    /// Joins two values as text, written `&` in the Excel dialect.
+    Concat,
+    ShiftLeft,
+    ShiftRight,
+    /// `->`, making a function of the parameters on its left.
+    Arrow,
+    /// `..`, the list of numbers from one bound to the other.
+    Range,
+    /// `step`, spelled as a word, the spacing of a range.
+    Step,
+    /// `in`, spelled as a word, converting to the unit on its right.
+    In,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    /// `==`, unlike `=` which states an equation.
+    Equal,
+    NotEqual,
+    Negate,
+
+    // Used only while parsing, never found in an `Expr`.
+    #[doc(hidden)]
+    OpenParen,
+    #[doc(hidden)]
+    CloseParen,
+    #[doc(hidden)]
+    Unknown,
+}
+
+impl OpKind {
+    /// How the operator is written, for `EvalOptions::allowed`.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            OpKind::Plus         => "+",
+            OpKind::Minus        => "-",
+            OpKind::Divide       => "/",
+            OpKind::FloorDivide  => "//",
+            OpKind::Multiply     => "*",
+            OpKind::Power        => "^",
+            OpKind::Modulo       => "%",
+            OpKind::Equals       => "=",
+            OpKind::BitAnd       => "&",
+            OpKind::BitOr        => "|",
+            OpKind::BitXor       => "xor",
+            OpKind::Concat       => "&",
+            OpKind::ShiftLeft    => "<<",
+            OpKind::ShiftRight   => ">>",
+            OpKind::Arrow        => "->",
+            OpKind::Range        => "..",
+            OpKind::Step         => "step",
+            OpKind::In           => "in",
+            OpKind::Less         => "<",
+            OpKind::LessEqual    => "<=",
+            OpKind::Greater      => ">",
+            OpKind::GreaterEqual => ">=",
+            OpKind::Equal        => "==",
+            OpKind::NotEqual     => "!=",
+            OpKind::OpenParen    => "(",
+            OpKind::CloseParen   => ")",
+            OpKind::Negate       => "-",
+            OpKind::Unknown      => "?",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Op {
+    pub(super) kind: OpKind,
+    pub(super) prec: u8,
+}
+
+impl Op {
+    pub(super) fn new(kind: OpKind) -> Self {
+        let prec = match kind {
+            OpKind::Plus         => 8,
+            OpKind::Minus        => 8,
+            OpKind::Divide       => 9,
+            OpKind::FloorDivide  => 9,
+            OpKind::Modulo       => 9,
+            OpKind::Multiply     => 9,
+            OpKind::Power        => 11,
+            OpKind::Equals       => 0,
+            OpKind::BitAnd       => 6,
+            OpKind::BitOr        => 4,
+            OpKind::BitXor       => 5,
+            OpKind::Concat       => 3,
+            OpKind::ShiftLeft    => 7,
+            OpKind::ShiftRight   => 7,
+            OpKind::Arrow        => 0,
+            OpKind::Range        => 3,
+            OpKind::Step         => 2,
+            OpKind::In           => 2,
+            OpKind::Less         => 1,
+            OpKind::LessEqual    => 1,
+            OpKind::Greater      => 1,
+            OpKind::GreaterEqual => 1,
+            OpKind::Equal        => 1,
+            OpKind::NotEqual     => 1,
+            OpKind::OpenParen    => 10,
+            OpKind::CloseParen   => 10,
+            OpKind::Negate       => 10,
+
+            OpKind::Unknown      => 0,
+        };
+
+        Op {
+            kind,
+            prec,
+        }
+    }
+
+    /// The operator as `dialect` reads it, where that differs in how
+    /// tightly it binds.
+    pub(super) fn in_dialect(kind: OpKind, dialect: Dialect) -> Self {
+        let mut op = Op::new(kind);
+        match (dialect, kind) {
+            // Excel negates before raising to a power, so -2^2 is 4.
+            (Dialect::Excel, OpKind::Negate) => op.prec = Op::new(OpKind::Power).prec + 1,
+            // C compares before it combines bits, so x & 1 == 0 is x & (1 == 0).
+            (Dialect::C, OpKind::Less | OpKind::LessEqual | OpKind::Greater | OpKind::GreaterEqual) => op.prec = 6,
+            (Dialect::C, OpKind::Equal | OpKind::NotEqual) => op.prec = 5,
+            (Dialect::C, OpKind::BitAnd) => op.prec = 4,
+            (Dialect::C, OpKind::BitXor) => op.prec = 3,
+            (Dialect::C, OpKind::BitOr)  => op.prec = 2,
+            _ => {},
+        }
+        op
+    }
+
+    pub(super) fn is_right_assoc(&self) -> bool {
+        matches!(self.kind, OpKind::Power | OpKind::Arrow)
+    }
+
+    pub(super) fn from_char(c: &char) -> Self {
+        let kind = match *c {
+            '+' => OpKind::Plus,
+            '-' => OpKind::Minus,
+            '/' => OpKind::Divide,
+            '%' => OpKind::Modulo,
+            '*' => OpKind::Multiply,
+            '^' => OpKind::Power,
+            '=' => OpKind::Equals,
+            '&' => OpKind::BitAnd,
+            '|' => OpKind::BitOr,
+            '<' => OpKind::Less,
+            '>' => OpKind::Greater,
+            '(' => OpKind::OpenParen,
+            ')' => OpKind::CloseParen,
+            _ => OpKind::Unknown
+        };
+        Op::new(kind)
+    }
+}
+
+const CHAR_OPS : [char; 13] = [
+    '+',
+    '-',
+    '/',
+    '%',
+    '*',
+    '^',
+    '=',
+    '&',
+    '|',
+    '<',
+    '>',
+    '(',
+    ')',
+];
+
+/// Operators spelled with two characters, which take priority over the
+/// single character ones they start with.
+const TWO_CHAR_OPS: [(&str, OpKind); 9] = [
+    ("//", OpKind::FloorDivide),
+    ("->", OpKind::Arrow),
+    ("..", OpKind::Range),
+    ("<<", OpKind::ShiftLeft),
+    (">>", OpKind::ShiftRight),
+    ("<=", OpKind::LessEqual),
+    (">=", OpKind::GreaterEqual),
+    ("==", OpKind::Equal),
+    ("!=", OpKind::NotEqual),
+];
+
+/// A binary operator as it is written, for the syntax reference.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct OperatorInfo {
+    pub symbol: String,
+    pub kind: OpKind,
+    /// Operators of higher precedence bind more tightly.
+    pub prec: u8,
+    pub right_assoc: bool,
+}
+
+/// Every binary operator the lexer knows, tightest binding first.
+pub fn operators() -> Vec<OperatorInfo> {
+    let info = |symbol: String, kind: OpKind| {
+        let op = Op::new(kind);
+        OperatorInfo { symbol, kind, prec: op.prec, right_assoc: op.is_right_assoc() }
+    };
+    let mut ops: Vec<OperatorInfo> = CHAR_OPS.iter()
+        .map(|c| (c.to_string(), Op::from_char(c).kind))
+        .filter(|(_, kind)| !matches!(kind, OpKind::OpenParen | OpKind::CloseParen))
+        .chain(TWO_CHAR_OPS.iter().map(|(s, kind)| (s.to_string(), *kind)))
+        .chain([("step".to_string(), OpKind::Step), ("in".to_string(), OpKind::In)])
+        .map(|(symbol, kind)| info(symbol, kind))
+        .collect();
+    ops.sort_by_key(|op| std::cmp::Reverse(op.prec));
+    ops
+}
+
+fn is_op(c: &char) -> bool {
+    for op in CHAR_OPS {
+        if *c == op {
+            return true;
+        }
+    }
+    false
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Token {
+    Num(Value),
+    Str(String),
+    Ident(String),
+    Op(OpKind),
+    Comma,
+    OpenBracket,
+    CloseBracket,
+}
+
+/// Splits an expression into tokens. Unlike `evaluate`, tokens do not
+/// need to be separated by whitespace.
+pub fn tokenize(src: &str) -> Result<Vec<Token>> {
+    let (tokens, _) = lex(src, false, Syntax::default())?;
+    Ok(tokens.into_iter().map(|(token, _)| token).collect())
+}
+
+/// A token with the character offsets it was read from.
+pub(super) type Spanned = (Token, Range<usize>);
+
+/// Splits `src` into tokens, keeping track of where each came from. When
+/// `recover` is set malformed tokens are reported and skipped instead of
+/// ending the scan.
+pub(super) fn lex(src: &str, recover: bool, syntax: Syntax) -> Result<(Vec<Spanned>, Vec<Diagnostic>)> {
+    // Read as a single chunk, so that both ways of lexing handle the
+    // dialects alike.
+    let mut lexer = ChunkLexer::new(syntax);
+    if recover {
+        lexer.diagnostics = Some(Vec::new());
+    }
+    lexer.feed(src.as_bytes());
+    lexer.feed(&[]);
+    let tokens = std::iter::from_fn(|| lexer.next_token()).collect::<Result<_>>()?;
+    Ok((tokens, lexer.diagnostics.unwrap_or_default()))
+}
+
+/// Tokens read from input handed over a chunk at a time, as it arrives.
+/// Only the text of the token being read is kept, and a token or
+/// character split between two chunks reads the same as if it were not.
+/// Spans count characters from the start of the input, as with `lex`.
+/// Reading stops at the first error.
+#[derive(Debug)]
+pub struct ChunkLexer {
+    syntax: Syntax,
+    /// Bytes of a character not yet completely read.
+    bytes: Vec<u8>,
+    chars: Vec<char>,
+    /// Position in `chars` of the next character to tokenize.
+    pos: usize,
+    /// Offset in the input of `chars[0]`.
+    offset: usize,
+    started: bool,
+    /// Whether the last chunk has been handed over.
+    eof: bool,
+    done: bool,
+    /// Tokens read but not yet returned. The last one is held back until
+    /// the next is known, as in the Excel dialect a `(` after a name
+    /// makes it a function name.
+    queue: VecDeque<Spanned>,
+    error: Option<Error>,
+    /// Malformed tokens skipped instead of ending the input, for `lex`
+    /// when recovering.
+    diagnostics: Option<Vec<Diagnostic>>,
+}
+
+impl ChunkLexer {
+    pub fn new(syntax: Syntax) -> Self {
+        ChunkLexer {
+            syntax,
+            bytes: Vec::new(),
+            chars: Vec::new(),
+            pos: 0,
+            offset: 0,
+            started: false,
+            eof: false,
+            done: false,
+            queue: VecDeque::new(),
+            error: None,
+            diagnostics: None,
+        }
+    }
+
+    /// Hands over the next chunk of input. An empty chunk marks the end.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.offset += self.pos;
+        self.chars.drain(..self.pos);
+        self.pos = 0;
+
+        if chunk.is_empty() {
+            self.eof = true;
+            if !self.bytes.is_empty() {
+                self.fail(Error::ParseError);
+            }
+            return;
+        }
+        self.bytes.extend_from_slice(chunk);
+        let valid = match std::str::from_utf8(&self.bytes) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => return self.fail(Error::ParseError),
+        };
+        if let Ok(text) = std::str::from_utf8(&self.bytes[..valid]) {
+            self.chars.extend(text.chars());
+        }
+        self.bytes.drain(..valid);
+    }
+
+    /// Ends the input with `error`, as when reading it failed.
+    pub fn fail(&mut self, error: Error) {
+        if !self.done {
+            self.error = Some(error);
+            self.done = true;
+        }
+    }
+
+    /// The next token, or `None` once more input is needed or the input
+    /// has been read to the end, as told by `is_finished`.
+    pub fn next_token(&mut self) -> Option<Result<(Token, Range<usize>)>> {
+        loop {
+            if self.queue.len() > 1 || (self.done && !self.queue.is_empty()) {
+                return self.queue.pop_front().map(Ok);
+            }
+            if self.done {
+                return self.error.take().map(Err);
+            }
+            match self.step() {
+                Ok(true) => {},
+                Ok(false) if self.eof => self.done = true,
+                Ok(false) => return None,
+                Err(error) => self.fail(error),
+            }
+        }
+    }
+
+    /// Whether every token and error has been returned.
+    pub fn is_finished(&self) -> bool {
+        self.done && self.queue.is_empty() && self.error.is_none()
+    }
+
+    /// Whether the token ending at `end` can no longer be changed by
+    /// what comes after the characters read so far. A token looks ahead
+    /// past at most some spaces and a word, as for the unit in `1.5 GiB`
+    /// or the exponent in `1e-5`, and two more characters after that.
+    fn settled(&self, end: usize) -> bool {
+        let cs = &self.chars[self.pos..];
+        let spaces = cs[end..].iter().take_while(|c| **c == ' ').count();
+        let word = cs[end + spaces..].iter()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | ':' | '#'))
+            .count();
+        self.eof || end + spaces + word + 2 <= cs.len()
+    }
+
+    /// Ends the input with `error` at `span`, or notes it and goes on
+    /// when recovering.
+    fn report(&mut self, error: Error, span: Range<usize>) -> Result<()> {
+        match &mut self.diagnostics {
+            Some(diagnostics) => {
+                diagnostics.push(Diagnostic { error, span });
+                Ok(())
+            },
+            None => Err(error),
+        }
+    }
+
+    fn push(&mut self, token: Token, span: Range<usize>) {
+        if self.syntax.dialect == Dialect::Excel && token == Token::Op(OpKind::OpenParen) {
+            // Function names are not case sensitive in spreadsheets.
+            if let Some((Token::Ident(name), _)) = self.queue.back_mut() {
+                *name = name.to_lowercase();
+            }
+        }
+        self.queue.push_back((token, span));
+    }
+
+    /// Tokenizes what starts at the next character, returning false when
+    /// more of the input is needed first.
+    fn step(&mut self) -> Result<bool> {
+        let cs = &self.chars[self.pos..];
+        let blank = cs.iter().take_while(|c| c.is_whitespace()).count();
+        self.pos += blank;
+        if blank == cs.len() {
+            return Ok(false);
+        }
+        let cs = &self.chars[self.pos..];
+        let start = self.offset + self.pos;
+        if !self.started {
+            self.started = true;
+            // Spreadsheet formulas start with `=`.
+            if self.syntax.dialect == Dialect::Excel && cs[0] == '=' {
+                self.pos += 1;
+                return Ok(true);
+            }
+        }
+        if let Some((cells, end)) = (self.syntax.dialect == Dialect::Excel).then(|| cell_range(cs, 0)).flatten() {
+            if !self.settled(end) {
+                return Ok(false);
+            }
+            let span = start..start + end;
+            self.pos += end;
+            let cells = match cells {
+                Ok(cells) => cells,
+                Err(error) => return self.report(error, span).map(|_| true),
+            };
+            // `A1:B2` reads as the list `[A1, A2, B1, B2]`.
+            self.push(Token::OpenBracket, span.clone());
+            for (n, cell) in cells.into_iter().enumerate() {
+                if n > 0 {
+                    self.push(Token::Comma, span.clone());
+                }
+                self.push(Token::Ident(cell), span.clone());
+            }
+            self.push(Token::CloseBracket, span);
+            return Ok(true);
+        }
+        let (token, end) = next_token(cs, 0, self.syntax);
+        let end = end.max(1);
+        if !self.settled(end) {
+            return Ok(false);
+        }
+        self.pos += end;
+        match token {
+            Ok(token) => self.push(token, start..start + end),
+            Err(Error::UnexpectedCharacter(..)) if self.syntax.strictness == Strictness::Permissive => {},
+            Err(Error::UnexpectedCharacter(c, _)) => self.report(Error::UnexpectedCharacter(c, start), start..start + end)?,
+            Err(error) => self.report(error, start..start + end)?,
+        }
+        Ok(true)
+    }
+}
+
+/// The tokens of a `BufRead`, read through a `ChunkLexer`, for inputs
+/// too large to hold in memory at once.
+pub struct TokenStream<R> {
+    src: R,
+    lexer: ChunkLexer,
+}
+
+impl<R: BufRead> TokenStream<R> {
+    pub fn new(src: R) -> Self {
+        TokenStream::with_syntax(src, Syntax::default())
+    }
+
+    pub fn with_syntax(src: R, syntax: Syntax) -> Self {
+        TokenStream { src, lexer: ChunkLexer::new(syntax) }
+    }
+}
+
+impl<R: BufRead> Iterator for TokenStream<R> {
+    type Item = Result<(Token, Range<usize>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(token) = self.lexer.next_token() {
+                return Some(token);
+            }
+            if self.lexer.is_finished() {
+                return None;
+            }
+            match self.src.fill_buf() {
+                Ok(buf) => {
+                    let len = buf.len();
+                    self.lexer.feed(buf);
+                    self.src.consume(len);
+                },
+                Err(error) => self.lexer.fail(error.into()),
+            }
+        }
+    }
+}
+
+/// Reads a cell reference such as `B12` at `cs[i]`, returning its column
+/// and row numbers, counting from 1, and the offset just past it.
+fn cell(cs: &[char], i: usize) -> Option<(u32, u32, usize)> {
+    let letters = cs[i..].iter().take_while(|c| c.is_ascii_alphabetic()).count();
+    let digits = cs[i + letters..].iter().take_while(|c| c.is_ascii_digit()).count();
+    let end = i + letters + digits;
+    if letters == 0 || digits == 0 || cs.get(end).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+        return None;
+    }
+    let col = cs[i..i + letters].iter()
+        .try_fold(0u32, |col, c| col.checked_mul(26)?.checked_add(c.to_ascii_uppercase() as u32 - 'A' as u32 + 1))?;
+    let row = cs[i + letters..end].iter().collect::<String>().parse().ok()?;
+    Some((col, row, end))
+}
+
+fn column_name(mut col: u32) -> String {
+    let mut name = Vec::new();
+    while col > 0 {
+        col -= 1;
+        name.push((b'A' + (col % 26) as u8) as char);
+        col /= 26;
+    }
+    name.iter().rev().collect()
+}
+
+/// Most cells a range such as `A1:B3` may cover.
+const MAX_RANGE_CELLS: u64 = 100_000;
+
+/// Reads a range of cells such as `A1:B3` at `cs[i]`, returning the
+/// names of the cells in it, row by row, and the offset just past it.
+/// Ranges of more than `MAX_RANGE_CELLS` cells are an error.
+fn cell_range(cs: &[char], i: usize) -> Option<(Result<Vec<String>>, usize)> {
+    let (col1, row1, colon) = cell(cs, i)?;
+    if cs.get(colon) != Some(&':') {
+        return None;
+    }
+    let (col2, row2, end) = cell(cs, colon + 1)?;
+    let count = (col1.abs_diff(col2) as u64 + 1) * (row1.abs_diff(row2) as u64 + 1);
+    if count > MAX_RANGE_CELLS {
+        return Some((Err(Error::TooMany("cells in range", count, MAX_RANGE_CELLS)), end));
+    }
+    let mut cells = Vec::new();
+    for row in row1.min(row2)..=row1.max(row2) {
+        for col in col1.min(col2)..=col1.max(col2) {
+            cells.push(format!("{}{}", column_name(col), row));
+        }
+    }
+    Some((Ok(cells), end))
+}
+
+/// Reads the token starting at `cs[i]`, returning it along with the
+/// offset just past it.
+fn next_token(cs: &[char], mut i: usize, syntax: Syntax) -> (Result<Token>, usize) {
+    let c = cs[i];
+    let python = syntax.dialect == Dialect::Python;
+    if (syntax.power_alias || python) && cs[i..].starts_with(&['*', '*']) {
+        return (Ok(Token::Op(OpKind::Power)), i + 2);
+    }
+    if (python || syntax.dialect == Dialect::C) && c == '^' {
+        return (Ok(Token::Op(OpKind::BitXor)), i + 1);
+    }
+    if syntax.dialect == Dialect::Excel {
+        match (c, cs.get(i + 1)) {
+            ('&', _)        => return (Ok(Token::Op(OpKind::Concat)), i + 1),
+            ('=', _)        => return (Ok(Token::Op(OpKind::Equal)), i + 1),
+            ('<', Some('>')) => return (Ok(Token::Op(OpKind::NotEqual)), i + 2),
+            _ => {},
+        }
+    }
+    if c == '0' {
+        let radix = match cs.get(i + 1) {
+            Some('x' | 'X') => 16,
+            Some('o' | 'O') => 8,
+            Some('b' | 'B') => 2,
+            _ => 0,
+        };
+        if radix != 0 && cs.get(i + 2).is_some_and(|d| d.is_digit(radix)) {
+            let start = i + 2;
+            i = start;
+            while i < cs.len() && cs[i].is_digit(radix) {
+                i += 1;
+            }
+            let text: String = cs[start..i].iter().collect();
+            let n = i64::from_str_radix(&text, radix).map_err(|_| Error::ParseError);
+            return (n.map(|n| Token::Num(Value::Int(n))), i);
+        }
+    }
+    // A number ends where a `..` starts.
+    let is_dot = |i: usize| cs.get(i) == Some(&'.') && cs.get(i + 1) != Some(&'.');
+    if c.is_ascii_digit() || is_dot(i) {
+        let start = i;
+        let mut is_float = false;
+        while i < cs.len() && (cs[i].is_ascii_digit() || is_dot(i)) {
+            is_float |= cs[i] == '.';
+            i += 1;
+        }
+        if i < cs.len() && (cs[i] == 'e' || cs[i] == 'E') {
+            let mut j = i + 1;
+            if j < cs.len() && (cs[j] == '+' || cs[j] == '-') {
+                j += 1;
+            }
+            if j < cs.len() && cs[j].is_ascii_digit() {
+                while j < cs.len() && cs[j].is_ascii_digit() {
+                    j += 1;
+                }
+                is_float = true;
+                i = j;
+            }
+        }
+        let text: String = cs[start..i].iter().collect();
+        // A radix before `#` gives the base of the digits after it, as
+        // in `36#z1`.
+        if let (false, Some('#'), Ok(radix @ 2..=36)) = (is_float, cs.get(i), text.parse::<u32>()) {
+            let end = i + 1 + cs[i + 1..].iter().take_while(|d| d.is_digit(radix)).count();
+            if end > i + 1 {
+                let digits: String = cs[i + 1..end].iter().collect();
+                let n = i64::from_str_radix(&digits, radix).map_err(|_| Error::ParseError);
+                return (n.map(|n| Token::Num(Value::Int(n))), end);
+            }
+        }
+        if text.matches('.').count() == 3 {
+            return (Addr::parse(&text).map(|addr| Token::Num(Value::Addr(addr))), i);
+        }
+        // With `Syntax::si_prefixes`, a prefix right after a number scales
+        // it, so `2.2u` is `2.2e-6`, while `2 m` is still two metres.
+        if let (true, false, Some(exp)) = (syntax.si_prefixes, text.contains(['e', 'E']), cs.get(i).and_then(|c| si_prefix(*c))) {
+            if !cs.get(i + 1).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+                let scaled = match (is_float, exp) {
+                    (false, 0..) => parse_number(&format!("{}{}", text, "0".repeat(exp as usize)), false),
+                    _ => parse_number(&format!("{}e{}", text, exp), true),
+                };
+                return (scaled.map(Token::Num), i + 1);
+            }
+        }
+        // A unit name after a number makes a quantity, as in `1.5 GiB`.
+        let start_unit = i + cs[i..].iter().take_while(|c| **c == ' ').count();
+        let end_unit = start_unit + cs[start_unit..].iter().take_while(|c| c.is_alphanumeric()).count();
+        if let Some(unit) = units::lookup(&cs[start_unit..end_unit].iter().collect::<String>()) {
+            let amount = parse_number(&text, is_float).and_then(|n| n.as_f64());
+            return (amount.map(|amount| Token::Num(Value::Quantity(Quantity { amount, unit }))), end_unit);
+        }
+        return (parse_number(&text, is_float).map(Token::Num), i);
+    }
+    if c.is_alphabetic() || c == '_' {
+        let start = i;
+        while i < cs.len() && (cs[i].is_alphanumeric() || cs[i] == '_') {
+            i += 1;
+        }
+        return (Ok(Token::Ident(cs[start..i].iter().collect())), i);
+    }
+    if c == '"' {
+        let start = i + 1;
+        i = start;
+        while i < cs.len() && cs[i] != '"' {
+            i += 1;
+        }
+        if i == cs.len() {
+            return (Err(Error::ParseError), i);
+        }
+        return (Ok(Token::Str(cs[start..i].iter().collect())), i + 1);
+    }
+    if c == ',' {
+        return (Ok(Token::Comma), i + 1);
+    }
+    if c == '[' {
+        return (Ok(Token::OpenBracket), i + 1);
+    }
+    if c == ']' {
+        return (Ok(Token::CloseBracket), i + 1);
+    }
+    if let Some((_, kind)) = TWO_CHAR_OPS.iter().find(|(op, _)| op.chars().eq(cs[i..].iter().take(2).copied())) {
+        return (Ok(Token::Op(*kind)), i + 2);
+    }
+    if is_op(&c) {
+        return (Ok(Token::Op(Op::from_char(&c).kind)), i + 1);
+    }
+    (Err(Error::UnexpectedCharacter(c, i)), i + 1)
+}
+
+/// The power of ten an SI prefix such as `k` stands for.
+fn si_prefix(c: char) -> Option<i32> {
+    match c {
+        'f' => Some(-15),
+        'p' => Some(-12),
+        'n' => Some(-9),
+        'u' | 'µ' => Some(-6),
+        'm' => Some(-3),
+        'k' => Some(3),
+        'M' => Some(6),
+        'G' => Some(9),
+        'T' => Some(12),
+        _ => None,
+    }
+}
+
+fn parse_number(text: &str, is_float: bool) -> Result<Value> {
+    if !is_float {
+        if let Ok(n) = text.parse() {
+            return Ok(Value::Int(n));
+        }
+    }
+    match text.parse() {
+        Ok(x)  => Ok(Value::Float(x)),
+        Err(_) => Err(Error::ParseError),
+    }
+}
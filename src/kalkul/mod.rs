@@ -0,0 +1,504 @@
+//! The language itself: `lexer` turns source into tokens, `parser`
+//! builds an `Expr` from them, and `eval` runs it against an
+//! `Environment`. Everything is re-exported here, so the submodules
+//! need only be named when that helps the reader.
+
+pub mod error;
+pub mod eval;
+pub mod lexer;
+pub mod parser;
+pub mod value;
+
+pub use error::{Diagnostic, Error, Known, Result, Unknown, Warning};
+pub use eval::{eval, eval_output, evaluate, evaluate_detailed, evaluate_in, EvalDetails, EvalOutput, Usage};
+#[cfg(feature = "tokio")]
+pub use eval::{evaluate_async, evaluate_async_in};
+pub use lexer::{operators, tokenize, ChunkLexer, OpKind, OperatorInfo, Token, TokenStream};
+pub use parser::{Dialect, Expr, Strictness, Syntax};
+pub use value::Value;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::error::edit_distance;
+    use super::lexer::lex;
+    use crate::env::Environment;
+    use std::io::{BufReader, Cursor};
+    use std::iter::zip;
+    use std::ops::Range;
+
+    #[test]
+    fn test_single_op() {
+        let exprs = [
+            "1 + 1",
+            "6 - 3",
+            "2 * 3",
+            "4 / 2",
+        ];
+        let answers = [
+            2, 3, 6, 2,
+        ];
+
+        for (expr, ans) in zip(exprs, answers) {
+            println!("-------------------------------");
+            println!("Testing {}", expr);
+            println!("-------------------------------");
+            let src = BufReader::new(Cursor::new(expr));
+            let fin = evaluate(src).unwrap();
+            println!("Final: {} {}", fin, if ans == fin {"PASS"} else {"FAIL"});
+            assert_eq!(ans, fin);
+        }
+    }
+
+    #[test]
+    fn test_multi_op_with_same_prec() {
+        let exprs = [
+            "1 + 1 - 1 + 1 - 1 + 1 - 1 + 1 - 1 + 1 - 1 + 1 - 1 + 1 - 1 + 1 - 1",
+            "2 * 3 / 6 * 2 * 3 / 6 * 2 * 3 / 1",
+        ];
+        let answers = [
+            1, 6,
+        ];
+
+        for (expr, ans) in zip(exprs, answers) {
+            println!("-------------------------------");
+            println!("Testing {}", expr);
+            println!("-------------------------------");
+            let src = BufReader::new(Cursor::new(expr));
+            let fin = evaluate(src).unwrap();
+            println!("Final: {} {}", fin, if ans == fin {"PASS"} else {"FAIL"});
+            assert_eq!(ans, fin);
+        }
+    }
+
+    #[test]
+    fn test_ops_with_diff_prec() {
+        let exprs = [
+            "2 + 2 * 2",
+            "4 * 3 + 2",
+            "8 + 4 / 2",
+            "3 - 2 * 4",
+            "3 * 2 - 4",
+        ];
+        let answers = [
+            6, 14, 10, -5, 2,
+        ];
+
+        for (expr, ans) in zip(exprs, answers) {
+            println!("-------------------------------");
+            println!("Testing {}", expr);
+            println!("-------------------------------");
+            let src = BufReader::new(Cursor::new(expr));
+            let fin = evaluate(src).unwrap();
+            println!("Final: {} {}", fin, if ans == fin {"PASS"} else {"FAIL"});
+            assert_eq!(ans, fin);
+        }
+    }
+
+    #[test]
+    fn test_unspaced_and_multi_char_ops() {
+        let exprs = ["2+2*2", "8 +4/2", "10-3 - 2"];
+        let answers = [6, 10, 5];
+        for (expr, ans) in zip(exprs, answers) {
+            assert_eq!(ans, evaluate(BufReader::new(Cursor::new(expr))).unwrap(), "{}", expr);
+        }
+
+        for expr in ["1 << 2", "1<=2", "7 // 2", "2 != 3"] {
+            let res = evaluate(BufReader::new(Cursor::new(expr)));
+            assert!(matches!(res, Err(Error::UnknownOperator)), "{}", expr);
+        }
+        assert!(matches!(evaluate(BufReader::new(Cursor::new("1 $ 2"))), Err(Error::UnexpectedCharacter('$', 2))));
+
+        let exprs = ["2 * (3)", "1 + (2 + 3)", "(1 + 2) * (3 - 4)", "((2))*3"];
+        let answers = [6, 6, -3, 6];
+        for (expr, ans) in zip(exprs, answers) {
+            assert_eq!(ans, evaluate(expr.as_bytes()).unwrap(), "{}", expr);
+        }
+        assert!(matches!(evaluate("1 / 0".as_bytes()), Err(Error::DivisionByZero)));
+        assert!(matches!(evaluate("2147483647 + 1".as_bytes()), Err(Error::Overflow)));
+        assert!(matches!(evaluate("65536 * 65536".as_bytes()), Err(Error::Overflow)));
+        for expr in ["(1 + 2", "1 + 2)", "(1))"] {
+            assert!(matches!(evaluate(expr.as_bytes()), Err(Error::UnbalancedParens)), "{}", expr);
+        }
+        assert!(matches!(evaluate(BufReader::new(Cursor::new("10 +  2#"))), Err(Error::UnexpectedCharacter('#', 7))));
+
+        let mut env = Environment::new();
+        env.set("width", Value::Int(3));
+        env.set("rate", Value::Float(0.5));
+        assert_eq!(7, evaluate_in(BufReader::new(Cursor::new("width*2+1")), &env).unwrap());
+        match evaluate_in(BufReader::new(Cursor::new("widht * 2")), &env) {
+            Err(Error::UnknownVariable(unknown)) => assert_eq!(Some("width"), unknown.suggestion()),
+            res => panic!("{:?}", res),
+        }
+        assert!(matches!(evaluate_in(BufReader::new(Cursor::new("rate + 1")), &env), Err(Error::TypeError)));
+        env.set("zero", Value::Int(0));
+        env.set("big", Value::Int(i32::MAX as i64));
+        assert_eq!(8, evaluate_in("(width + 1) * 2".as_bytes(), &env).unwrap());
+        assert!(matches!(evaluate_in("width / (zero)".as_bytes(), &env), Err(Error::DivisionByZero)));
+        assert!(matches!(evaluate_in("big * width".as_bytes(), &env), Err(Error::Overflow)));
+        assert!(matches!(evaluate_in("(width".as_bytes(), &env), Err(Error::UnbalancedParens)));
+        for expr in ["()", ")(", "-1", "1 -", "(", "*", "-(-2147483647 - 1)"] {
+            assert!(evaluate_in(expr.as_bytes(), &env).is_err(), "{}", expr);
+        }
+        let tokens: Vec<Token> = TokenStream::new("<<3".as_bytes()).map(|t| t.unwrap().0).collect();
+        assert_eq!(vec![Token::Op(OpKind::ShiftLeft), Token::Num(Value::Int(3))], tokens);
+    }
+
+    #[test]
+    fn test_eval_expr() {
+        let exprs = [
+            "2*(3+4)",
+            "-2 * -3",
+            "(1 + 2) * (3 - 4) / 3",
+            "7 / 2",
+            "1.5e3 + .5",
+            "2^3^2",
+            "-2^2",
+            "4^-1",
+            "7 // 2",
+            "-7 // 2",
+            "7.5 // 2",
+            "1 + 9 // 2 * 2",
+            "0x1F + 0o17 + 0b11",
+            "36#z1 + 3#12 - 16#FF",
+            "1 << 2 + 1",
+            "6 & 3 | 8",
+            "-16 >> 2",
+            "1 + 1 == 2",
+            "2 < 1.5",
+            "3 >= 3 != 0",
+            "6 & 3 < 8",
+        ];
+        let answers = [
+            Value::Int(14),
+            Value::Int(6),
+            Value::Int(-1),
+            Value::Float(3.5),
+            Value::Float(1500.5),
+            Value::Int(512),
+            Value::Int(-4),
+            Value::Float(0.25),
+            Value::Int(3),
+            Value::Int(-4),
+            Value::Float(3.0),
+            Value::Int(9),
+            Value::Int(49),
+            Value::Int(1011),
+            Value::Int(8),
+            Value::Int(10),
+            Value::Int(-4),
+            Value::Int(1),
+            Value::Int(0),
+            Value::Int(1),
+            Value::Int(1),
+        ];
+
+        for (expr, ans) in zip(exprs, answers) {
+            assert_eq!(ans, eval(expr).unwrap(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_display_expr() {
+        let exprs = [
+            "1 - (2 - 3)",
+            "(1 - 2) - 3",
+            "(2^3)^2",
+            "2^3^2",
+            "-(x + 1) * f(y, \"s\")",
+            "a // (b // c)",
+            "(a | b) & c << 1",
+            "(a < b) == (c >= d | e)",
+            "(a, b) -> [a, (b, 1), []]",
+        ];
+        let answers = [
+            "1 - (2 - 3)",
+            "1 - 2 - 3",
+            "(2^3)^2",
+            "2^3^2",
+            "-(x + 1)*f(y, \"s\")",
+            "a//(b//c)",
+            "(a | b) & c << 1",
+            "a < b == (c >= d | e)",
+            "(a, b) -> [a, (b, 1), []]",
+        ];
+
+        for (expr, ans) in zip(exprs, answers) {
+            assert_eq!(ans, Expr::parse(expr).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        let errors = |src: &str| -> Vec<(String, Range<usize>)> {
+            match Expr::parse_recovering(src) {
+                Ok(_) => Vec::new(),
+                Err(ds) => ds.into_iter().map(|d| (format!("{:?}", d.error), d.span)).collect(),
+            }
+        };
+
+        assert_eq!(
+            vec![
+                ("UnexpectedToken".to_string(), 4..5),
+                ("UnbalancedParens".to_string(), 8..9),
+                ("UnbalancedParens".to_string(), 12..13),
+            ],
+            errors("1 + * 2 ) + (3"),
+        );
+        assert_eq!(
+            vec![
+                ("UnbalancedParens".to_string(), 0..5),
+                ("UnexpectedCharacter('$', 7)".to_string(), 7..8),
+                ("UnexpectedToken".to_string(), 9..10),
+            ],
+            errors("sqrt(2 $ 3"),
+        );
+        assert_eq!(
+            vec![
+                ("UnexpectedToken".to_string(), 2..3),
+                ("NotEnoughElements".to_string(), 7..7),
+            ],
+            errors("x = 1 +"),
+        );
+        assert_eq!(
+            vec![("UnexpectedToken".to_string(), 4..5)],
+            errors("(1 +) * 3"),
+        );
+        assert!(Expr::parse_recovering("f(1, 2) * -3").is_ok());
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let mut env = Environment::new();
+        env.set("radius", Value::Int(2));
+        let errors = ["sqtr(4)", "sq rt(4)", "raduis * 2", "frobnicate(1)", "y"];
+        let answers = [
+            "unknown function 'sqtr', did you mean 'sqrt'?",
+            "unexpected token",
+            "unknown variable 'raduis', did you mean 'radius'?",
+            "unknown function 'frobnicate'",
+            "unknown variable 'y'",
+        ];
+        for (expr, ans) in zip(errors, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap_err().to_string(), "{}", expr);
+        }
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+        assert_eq!(1, edit_distance("sqtr", "sqrt"));
+    }
+
+    #[test]
+    fn test_substitute() {
+        let mut env = Environment::new();
+        let formula = Expr::parse("x^2 + y*x").unwrap();
+
+        let e = formula.substitute("x", 3);
+        assert_eq!("3^2 + y*3", e.to_string());
+        assert_eq!(Value::Int(15), e.substitute("y", 2).eval(&mut env).unwrap());
+
+        let e = formula.substitute("x", Expr::parse("a + 1").unwrap());
+        assert_eq!("(a + 1)^2 + y*(a + 1)", e.to_string());
+        assert_eq!(formula, formula.substitute("z", 1.5));
+    }
+
+    #[test]
+    fn test_render_diagnostic() {
+        let src = "1 + * 2 ) + (3";
+        let rendered: Vec<String> = Expr::parse_recovering(src).unwrap_err().iter().map(|d| d.render(src)).collect();
+        assert_eq!(vec![
+            "1 + * 2 ) + (3\n    ^ unexpected token",
+            "1 + * 2 ) + (3\n        ^ unbalanced parentheses",
+            "1 + * 2 ) + (3\n            ^ unbalanced parentheses",
+        ], rendered);
+
+        let d = Diagnostic { error: Error::UnexpectedCharacter('$', 7), span: 7..8 };
+        assert_eq!("sqrt(2 $ 3\n       ^ unexpected character '$'", d.render("sqrt(2 $ 3"));
+        let d = Diagnostic { error: Error::UnbalancedParens, span: 10..20 };
+        assert_eq!("2 | y = (2\n  |     ^^ unbalanced parentheses", d.render("x = 1\ny = (2"));
+        let d = Diagnostic { error: Error::NotEnoughElements, span: 3..3 };
+        assert_eq!("1 +\n   ^ missing operand", d.render("1 +"));
+    }
+
+    #[test]
+    fn test_permissive_parsing() {
+        let permissive = Syntax { strictness: Strictness::Permissive, ..Syntax::default() };
+        let exprs = ["(1 + 2", "2 * (3 + 4", "1 +", "2 * (3 +", "-", "1 + (", "[1, 2", "sqrt(16", "((2)"];
+        let answers = ["1 + 2", "2*(3 + 4)", "1", "2*3", "", "1", "[1, 2]", "sqrt(16)", "2"];
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            match Expr::parse_with(expr, permissive) {
+                Ok(e) => assert_eq!(ans, e.to_string(), "{}", expr),
+                Err(e) => assert!(ans.is_empty() && matches!(e, Error::NotEnoughElements), "{}", expr),
+            }
+            assert!(Expr::parse(expr).is_err(), "{}", expr);
+        }
+        for expr in ["1 + 2)", "1 2", "f(1,", "(1, "] {
+            assert!(Expr::parse_with(expr, permissive).is_err(), "{}", expr);
+        }
+        assert_eq!("2*3", Expr::parse_with("2 *$ 3 @", permissive).unwrap().to_string());
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        let src = vec!["1"; 100].join(" + ");
+        let mut reports = Vec::new();
+        let e = Expr::parse_with_progress(&src, Syntax::default(), 50, |done, total| reports.push((done, total))).unwrap();
+        assert_eq!(Value::Int(100), e.eval(&mut Environment::new()).unwrap());
+        assert_eq!(vec![(0, 199), (50, 199), (100, 199), (150, 199), (199, 199)], reports);
+        assert!(Expr::parse_with_progress("1 +", Syntax::default(), 1, |_, _| {}).is_err());
+    }
+
+    #[test]
+    fn test_canonical_hash() {
+        let pairs = [
+            ("a + b", "b + a"),
+            ("x * (y + 1)", "(1 + y) * x"),
+            ("2 + 3 + x", "x + 5"),
+            ("f(b * a, [c == d])", "f(a * b, [d == c])"),
+            ("-(2 * 3) * x", "x * -6"),
+        ];
+        for (a, b) in pairs {
+            let (a, b) = (Expr::parse(a).unwrap(), Expr::parse(b).unwrap());
+            assert_eq!(a.canonical_hash(), b.canonical_hash(), "{} {}", a, b);
+        }
+
+        let pairs = [
+            ("a - b", "b - a"),
+            ("a / b", "b / a"),
+            ("(a + b) + c", "a + (b + c)"),
+            ("1", "1.5"),
+            ("[a, b]", "(a, b)"),
+            ("x", "\"x\""),
+            ("x / x", "1"),
+            ("0 / x", "0"),
+            ("x - x", "0"),
+            ("x * 0", "0"),
+            ("x * 1", "x"),
+        ];
+        for (a, b) in pairs {
+            let (a, b) = (Expr::parse(a).unwrap(), Expr::parse(b).unwrap());
+            assert_ne!(a.canonical_hash(), b.canonical_hash(), "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_power_alias() {
+        let python = Syntax { power_alias: true, ..Syntax::default() };
+        let exprs = ["2 ** 10", "-2**2", "2**3**2", "2 * *3"];
+        let answers = ["2^10", "-2^2", "2^3^2", ""];
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            match Expr::parse_with(expr, python) {
+                Ok(e) => assert_eq!(ans, e.to_string(), "{}", expr),
+                Err(_) => assert!(ans.is_empty(), "{}", expr),
+            }
+            assert!(Expr::parse(expr).is_err(), "{}", expr);
+        }
+        assert_eq!(Value::Int(-4), Expr::parse_with("-2**2", python).unwrap().eval(&mut env).unwrap());
+    }
+
+    #[test]
+    fn test_si_prefixes() {
+        let si = Syntax { si_prefixes: true, ..Syntax::default() };
+        let exprs = ["3k", "5M", "2.2u", "2k * 5u", "100n + 1µ", "4.7k", "2m", "2 m", "1 kB", "9T * 2000000000"];
+        let answers = ["3000", "5000000", "0.0000022", "0.01", "0.0000011", "4700", "0.002", "2 m", "1 kB", "1.8e22"];
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            let e = Expr::parse_with(expr, si).unwrap();
+            match e.eval(&mut env) {
+                Ok(val) => assert_eq!(ans, val.to_string(), "{}", expr),
+                Err(_) => assert_eq!(ans, e.to_string(), "{}", expr),
+            }
+        }
+        assert_eq!("2 m", eval("2m").unwrap().to_string());
+        assert!(Expr::parse_with("3kg", si).is_ok());
+        assert!(Expr::parse_with("1e3k", si).is_err());
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        assert!(matches!(eval("(1 + 2"), Err(Error::UnbalancedParens)));
+        assert!(matches!(eval("1 + 2)"), Err(Error::UnbalancedParens)));
+        assert!(matches!(eval("1 2"), Err(Error::UnexpectedToken)));
+        assert!(matches!(eval("1 +"), Err(Error::NotEnoughElements)));
+        assert!(matches!(eval("1 / 0"), Err(Error::DivisionByZero)));
+        assert!(matches!(eval("1 $ 2"), Err(Error::UnexpectedCharacter('$', 2))));
+        assert_eq!("unexpected character '?' at column 5", eval("2 * ?3").unwrap_err().to_string());
+        assert!(matches!(eval("1 = 2"), Err(Error::UnexpectedToken)));
+        assert!(matches!(eval("diff(x = 2, x)"), Err(Error::UnexpectedToken)));
+        assert!(matches!(eval("[1, 2)"), Err(Error::UnbalancedParens)));
+        assert!(matches!(eval("(1, 2]"), Err(Error::UnbalancedParens)));
+        assert!(matches!(eval("1, 2"), Err(Error::UnbalancedParens)));
+    }
+
+    #[test]
+    fn test_token_stream() {
+        let kalkul = Syntax::default();
+        let excel = Syntax { dialect: Dialect::Excel, ..Syntax::default() };
+        let srcs = [
+            ("sqrt(2.5e-3) + 0x1f * 36#z1", kalkul),
+            ("1.5  GiB in MB + π * 2", kalkul),
+            ("[1..10 step 2]  <<  3 // \"a b ü\"", kalkul),
+            ("10.0.0.1 + 2", kalkul),
+            ("= SUM(A1:B2) & \"x\" <> 3", excel),
+        ];
+
+        for (src, syntax) in srcs {
+            let expected = lex(src, false, syntax).map(|(tokens, _)| tokens);
+            assert!(expected.is_ok(), "{}", src);
+            // Chunks of every small size split tokens and characters alike.
+            for capacity in 1..8 {
+                let reader = BufReader::with_capacity(capacity, src.as_bytes());
+                let tokens: Result<Vec<_>> = TokenStream::with_syntax(reader, syntax).collect();
+                assert_eq!(expected.as_ref().ok(), tokens.as_ref().ok(), "{} {}", src, capacity);
+            }
+        }
+
+        let mut stream = TokenStream::new(BufReader::with_capacity(1, "1 $ 2".as_bytes()));
+        assert_eq!(Token::Num(Value::Int(1)), stream.next().unwrap().unwrap().0);
+        assert!(matches!(stream.next(), Some(Err(Error::UnexpectedCharacter('$', 2)))));
+        assert!(stream.next().is_none());
+
+        let bytes = [b'1', b'+', 0xff];
+        assert!(matches!(TokenStream::new(&bytes[..]).last(), Some(Err(Error::ParseError))));
+        let long = "1 + ".repeat(100_000) + "1";
+        assert_eq!(100_001, evaluate(BufReader::with_capacity(16, long.as_bytes())).unwrap());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_evaluate_async() {
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        // Reading from memory never waits, so one poll is enough.
+        fn block_on<F: Future>(fut: F) -> F::Output {
+            match std::pin::pin!(fut).poll(&mut Context::from_waker(Waker::noop())) {
+                Poll::Ready(res) => res,
+                Poll::Pending => panic!("pending"),
+            }
+        }
+
+        let exprs = ["2 + 2 * 2", "10-3 - 2", "1 $ 2", "2 * (3)", "1 + (2 + 3)", "1 / 0", "(1"];
+        let answers = [Some(6), Some(5), None, Some(6), Some(6), None, None];
+        for (expr, ans) in zip(exprs, answers) {
+            for capacity in 1..4 {
+                let src = tokio::io::BufReader::with_capacity(capacity, expr.as_bytes());
+                assert_eq!(ans, block_on(evaluate_async(src)).ok(), "{} {}", expr, capacity);
+            }
+        }
+
+        let mut env = Environment::new();
+        env.set("width", Value::Int(3));
+        assert_eq!(7, block_on(evaluate_async_in("width*2+1".as_bytes(), &env)).unwrap());
+    }
+
+    #[test]
+    fn test_prelude() {
+        use crate::prelude::*;
+
+        let mut env = Environment::new();
+        env.set("x", Value::Int(4));
+        assert_eq!(Value::Float(2.0), Expr::parse("sqrt(x)").unwrap().eval(&mut env).unwrap());
+        assert_eq!(7, evaluate("1 + 2 * 3\n".as_bytes()).unwrap());
+        assert_eq!(Value::Int(7), crate::eval::eval("1 + 2 * 3").unwrap());
+    }
+}
@@ -0,0 +1,725 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Range;
+
+use crate::builtins;
+use crate::symbolic;
+use crate::units::{Quantity, Unit};
+
+use super::error::{Diagnostic, Error, Result};
+use super::lexer::{lex, Op, OpKind, Spanned, Token};
+use super::value::{write_list, Value};
+
+/// Parsed form of an expression.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Expr {
+    Lit(Value),
+    Neg(Box<Expr>),
+    Binary(OpKind, Box<Expr>, Box<Expr>),
+    Var(String),
+    Call(String, Vec<Expr>),
+    /// `[a, b, c]`.
+    List(Vec<Expr>),
+    /// `(a, b)`, also the parameters of a lambda such as `(a, b) -> a + b`.
+    Tuple(Vec<Expr>),
+}
+
+/// What an open parenthesis or bracket on the operator stack started,
+/// with where its elements start on the expression stack.
+#[derive(Debug)]
+enum Group {
+    /// Grouping, or a tuple once a comma is seen.
+    Paren(usize, bool),
+    Call(String, usize),
+    List(usize),
+}
+
+/// How forgiving parsing is of incomplete input.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[non_exhaustive]
+pub enum Strictness {
+    /// Rejects anything malformed.
+    #[default]
+    Strict,
+    /// Closes parentheses left open and drops operators left dangling at
+    /// the end, so `2 * (3 + 4` reads as `2 * (3 + 4)` and `1 +` as `1`.
+    /// Characters that start no token are skipped.
+    Permissive,
+}
+
+/// The language expressions are written in, for formulas copied from
+/// elsewhere to give the answers they give there.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[non_exhaustive]
+pub enum Dialect {
+    #[default]
+    Kalkul,
+    /// `**` is exponentiation and `^` exclusive or, `/` always gives a
+    /// float, and dividing by zero is an error for floats too.
+    Python,
+    /// Spreadsheet formulas: an optional leading `=`, `&` joining text,
+    /// `=` and `<>` comparing, `A1:B3` standing for a list of cells,
+    /// function names in any case and negation before `^`.
+    Excel,
+    /// C's precedence, with comparisons binding more tightly than the
+    /// bitwise operators and `^` as exclusive or, and arithmetic on `int`:
+    /// 32 bits wrapping around, `/` and `%` truncating towards zero.
+    C,
+}
+
+/// Settings for how source text is read.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Syntax {
+    pub strictness: Strictness,
+    /// Whether `**` is read as `^`, for formulas written for Python.
+    pub power_alias: bool,
+    pub dialect: Dialect,
+    /// Whether an SI prefix right after a number scales it, so that
+    /// `5k * 2.2u` is `5000 * 2.2e-6`, for electronics.
+    pub si_prefixes: bool,
+}
+
+/// Built up from `Syntax::default()`, as in
+/// `Syntax::default().with_dialect(Dialect::Python)`.
+impl Syntax {
+    pub fn with_strictness(self, strictness: Strictness) -> Self {
+        Syntax { strictness, ..self }
+    }
+
+    pub fn with_power_alias(self, power_alias: bool) -> Self {
+        Syntax { power_alias, ..self }
+    }
+
+    pub fn with_dialect(self, dialect: Dialect) -> Self {
+        Syntax { dialect, ..self }
+    }
+
+    pub fn with_si_prefixes(self, si_prefixes: bool) -> Self {
+        Syntax { si_prefixes, ..self }
+    }
+}
+
+/// Finds a unit by name, see `Expr::parse_with_units`.
+pub type UnitLookup<'a> = dyn Fn(&str) -> Option<Unit> + 'a;
+
+/// Makes the nodes of what is parsed, an `Expr` tree or the nodes of an
+/// `ast::Ast`, out of those already made for the operands.
+pub(crate) trait Build {
+    type Node;
+    fn lit(&mut self, val: Value) -> Self::Node;
+    fn var(&mut self, name: String) -> Self::Node;
+    fn neg(&mut self, a: Self::Node) -> Self::Node;
+    fn binary(&mut self, kind: OpKind, a: Self::Node, b: Self::Node) -> Self::Node;
+    fn call(&mut self, name: String, args: Vec<Self::Node>) -> Self::Node;
+    fn list(&mut self, items: Vec<Self::Node>) -> Self::Node;
+    fn tuple(&mut self, items: Vec<Self::Node>) -> Self::Node;
+    /// The value of `node` if it is a literal, for reading a unit after
+    /// a number.
+    fn lit_mut<'n>(&'n mut self, node: &'n mut Self::Node) -> Option<&'n mut Value>;
+}
+
+/// Builds `Expr` trees.
+struct Exprs;
+
+impl Build for Exprs {
+    type Node = Expr;
+
+    fn lit(&mut self, val: Value) -> Expr {
+        Expr::Lit(val)
+    }
+
+    fn var(&mut self, name: String) -> Expr {
+        Expr::Var(name)
+    }
+
+    fn neg(&mut self, a: Expr) -> Expr {
+        Expr::Neg(Box::new(a))
+    }
+
+    fn binary(&mut self, kind: OpKind, a: Expr, b: Expr) -> Expr {
+        Expr::Binary(kind, Box::new(a), Box::new(b))
+    }
+
+    fn call(&mut self, name: String, args: Vec<Expr>) -> Expr {
+        Expr::Call(name, args)
+    }
+
+    fn list(&mut self, items: Vec<Expr>) -> Expr {
+        Expr::List(items)
+    }
+
+    fn tuple(&mut self, items: Vec<Expr>) -> Expr {
+        Expr::Tuple(items)
+    }
+
+    fn lit_mut<'n>(&'n mut self, node: &'n mut Expr) -> Option<&'n mut Value> {
+        match node {
+            Expr::Lit(val) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+/// Parses `src` into the nodes `build` makes, returning the root.
+pub(crate) fn parse_into<B: Build>(src: &str, syntax: Syntax, build: &mut B) -> Result<B::Node> {
+    let (tokens, _) = lex(src, false, syntax)?;
+    let mut parser = Parser::new(build);
+    parser.strictness = syntax.strictness;
+    parser.dialect = syntax.dialect;
+    parser.parse(tokens, src.chars().count())
+}
+
+/// Builds an `Expr`, or other nodes with `Build`, using the same
+/// two-stack algorithm as `evaluate`, except that reducing an operator
+/// produces a tree node instead of a number.
+struct Parser<'a, B: Build> {
+    build: &'a mut B,
+    exprs: Vec<B::Node>,
+    ops: Vec<Op>,
+    // One entry per open parenthesis or bracket on `ops`.
+    parens: Vec<Group>,
+    // Where each open parenthesis is, for reporting unclosed ones.
+    paren_spans: Vec<Range<usize>>,
+    // Collects errors instead of stopping at the first one when set.
+    diagnostics: Option<Vec<Diagnostic>>,
+    strictness: Strictness,
+    dialect: Dialect,
+    // Called with the tokens parsed so far and the total every
+    // `progress_every` tokens, see `Expr::parse_with_progress`.
+    progress: Option<&'a mut dyn FnMut(usize, usize)>,
+    progress_every: usize,
+    // Finds units declared at runtime, see `Expr::parse_with_units`.
+    units: Option<&'a UnitLookup<'a>>,
+}
+
+impl<'a, B: Build> Parser<'a, B> {
+    fn new(build: &'a mut B) -> Self {
+        Parser {
+            build,
+            exprs: Vec::new(),
+            ops: Vec::new(),
+            parens: Vec::new(),
+            paren_spans: Vec::new(),
+            diagnostics: None,
+            strictness: Strictness::Strict,
+            dialect: Dialect::Kalkul,
+            progress: None,
+            progress_every: 1,
+            units: None,
+        }
+    }
+
+    fn reduce(&mut self) -> Result<()> {
+        let op = match self.ops.pop() {
+            Some(op) => op,
+            None => return Err(Error::NotEnoughElements),
+        };
+        let rhs = self.exprs.pop().ok_or(Error::NotEnoughElements)?;
+        let node = match op.kind {
+            OpKind::Negate => self.build.neg(rhs),
+            OpKind::OpenParen | OpKind::CloseParen => return Err(Error::UnbalancedParens),
+            kind => {
+                let lhs = self.exprs.pop().ok_or(Error::NotEnoughElements)?;
+                self.build.binary(kind, lhs, rhs)
+            }
+        };
+        self.exprs.push(node);
+        Ok(())
+    }
+
+    fn top_is_open_paren(&self) -> bool {
+        matches!(self.ops.last(), Some(Op { kind: OpKind::OpenParen, .. }))
+    }
+
+    /// Whether the innermost parenthesis holds the arguments of a form
+    /// that takes an equation, the only place where `=` may appear.
+    fn in_equation_form(&self) -> bool {
+        match self.parens.last() {
+            Some(Group::Call(name, _)) => builtins::lookup_form(name).is_some_and(|f| f.equation),
+            _ => false,
+        }
+    }
+
+    /// Evaluates operators until the innermost open parenthesis.
+    fn reduce_to_paren(&mut self) -> Result<()> {
+        while !self.top_is_open_paren() {
+            if self.ops.is_empty() {
+                return Err(Error::UnbalancedParens);
+            }
+            self.reduce()?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the elements of the innermost group with the call, list
+    /// or tuple they make up.
+    fn close_group(&mut self) {
+        let node = match self.parens.pop() {
+            Some(Group::Call(name, start)) => self.build.call(name, self.exprs.split_off(start)),
+            Some(Group::List(start)) => self.build.list(self.exprs.split_off(start)),
+            Some(Group::Paren(start, true)) => self.build.tuple(self.exprs.split_off(start)),
+            _ => return,
+        };
+        self.exprs.push(node);
+    }
+
+    /// Reports a syntax error: fails right away, or notes it and lets
+    /// parsing go on when recovering.
+    fn error(&mut self, error: Error, span: Range<usize>) -> Result<()> {
+        match &mut self.diagnostics {
+            Some(diagnostics) => {
+                diagnostics.push(Diagnostic { error, span });
+                Ok(())
+            },
+            None => Err(error),
+        }
+    }
+
+    /// Parses a token stream ending at character offset `end`.
+    fn parse(&mut self, tokens: Vec<Spanned>, end: usize) -> Result<B::Node> {
+        // Whether the next token has to start an operand. Used to tell a
+        // unary minus apart from subtraction and to reject `1 2` or `1 +`.
+        let mut expect_operand = true;
+        let total = tokens.len();
+        let mut tokens = tokens.into_iter().peekable();
+
+        while let Some((token, span)) = tokens.next() {
+            if let Some(report) = &mut self.progress {
+                let done = total - tokens.len() - 1;
+                if done.is_multiple_of(self.progress_every) {
+                    report(done, total);
+                }
+            }
+            // `step` and `in` are operators where one is expected, so
+            // they can still name variables elsewhere.
+            let token = match token {
+                Token::Ident(name) if name == "step" && !expect_operand => Token::Op(OpKind::Step),
+                Token::Ident(name) if name == "in" && !expect_operand => Token::Op(OpKind::In),
+                token => token,
+            };
+            match token {
+                // When recovering, the offending token is skipped.
+                Token::Num(_) | Token::Str(_) if !expect_operand => {
+                    self.error(Error::UnexpectedToken, span)?;
+                },
+                // The lexer only makes quantities of the built-in units,
+                // so those declared at runtime are looked up here.
+                Token::Ident(name) if !expect_operand => {
+                    let unit = self.units.and_then(|units| units(&name));
+                    match (unit, self.exprs.last_mut().and_then(|e| self.build.lit_mut(e))) {
+                        (Some(unit), Some(n @ (Value::Int(_) | Value::Float(_)))) => {
+                            *n = Value::Quantity(Quantity { amount: n.as_f64()?, unit });
+                        },
+                        _ => self.error(Error::UnexpectedToken, span)?,
+                    }
+                },
+                Token::Num(n) => {
+                    self.exprs.push(self.build.lit(n));
+                    expect_operand = false;
+                },
+                Token::Str(s) => {
+                    self.exprs.push(self.build.lit(Value::Str(s)));
+                    expect_operand = false;
+                },
+                Token::Ident(name) => {
+                    if tokens.peek().map(|(t, _)| t) != Some(&Token::Op(OpKind::OpenParen)) {
+                        self.exprs.push(self.build.var(name));
+                        expect_operand = false;
+                        continue;
+                    }
+                    let (_, paren) = tokens.next().unwrap();
+                    self.ops.push(Op::new(OpKind::OpenParen));
+                    self.parens.push(Group::Call(name, self.exprs.len()));
+                    self.paren_spans.push(span.start..paren.end);
+                },
+                Token::Op(OpKind::OpenParen) => {
+                    if !expect_operand {
+                        self.error(Error::UnexpectedToken, span)?;
+                        continue;
+                    }
+                    self.ops.push(Op::new(OpKind::OpenParen));
+                    self.parens.push(Group::Paren(self.exprs.len(), false));
+                    self.paren_spans.push(span);
+                },
+                Token::OpenBracket => {
+                    if !expect_operand {
+                        self.error(Error::UnexpectedToken, span)?;
+                        continue;
+                    }
+                    self.ops.push(Op::new(OpKind::OpenParen));
+                    self.parens.push(Group::List(self.exprs.len()));
+                    self.paren_spans.push(span);
+                },
+                Token::Comma => {
+                    if expect_operand {
+                        self.error(Error::UnexpectedToken, span)?;
+                        continue;
+                    }
+                    if let Err(e) = self.reduce_to_paren() {
+                        self.error(e, span)?;
+                        continue;
+                    }
+                    match self.parens.last_mut() {
+                        Some(Group::Paren(_, tuple)) => *tuple = true,
+                        Some(_) => {},
+                        None => {
+                            self.error(Error::UnexpectedToken, span)?;
+                            continue;
+                        },
+                    }
+                    expect_operand = true;
+                },
+                Token::Op(OpKind::CloseParen) | Token::CloseBracket => {
+                    let bracket = token == Token::CloseBracket;
+                    if bracket != matches!(self.parens.last(), Some(Group::List(_))) {
+                        self.error(Error::UnbalancedParens, span)?;
+                        continue;
+                    }
+                    let may_be_empty = match self.parens.last() {
+                        Some(Group::Call(_, start) | Group::List(start)) => *start == self.exprs.len(),
+                        _ => false,
+                    };
+                    if expect_operand && !may_be_empty {
+                        // Stand in for the missing operand so the
+                        // parenthesis can still be matched.
+                        self.error(Error::UnexpectedToken, span.clone())?;
+                        self.exprs.push(self.build.lit(Value::Int(0)));
+                    }
+                    if let Err(e) = self.reduce_to_paren() {
+                        self.error(e, span)?;
+                        continue;
+                    }
+                    self.ops.pop();
+                    self.paren_spans.pop();
+                    self.close_group();
+                    expect_operand = false;
+                },
+                Token::Op(OpKind::Minus) if expect_operand => {
+                    self.ops.push(Op::in_dialect(OpKind::Negate, self.dialect));
+                },
+                Token::Op(kind) => {
+                    if expect_operand {
+                        self.error(Error::UnexpectedToken, span)?;
+                        continue;
+                    }
+                    if kind == OpKind::Equals && !self.in_equation_form() {
+                        self.error(Error::UnexpectedToken, span)?;
+                    }
+                    let op = Op::in_dialect(kind, self.dialect);
+                    while let Some(top) = self.ops.last() {
+                        if top.kind == OpKind::OpenParen || top.prec < op.prec {
+                            break;
+                        }
+                        if top.prec == op.prec && op.is_right_assoc() {
+                            break;
+                        }
+                        self.reduce()?;
+                    }
+                    self.ops.push(op);
+                    expect_operand = true;
+                },
+            }
+        }
+
+        let permissive = self.strictness == Strictness::Permissive;
+        if permissive {
+            expect_operand = self.drop_dangling(expect_operand);
+        }
+        if expect_operand {
+            self.error(Error::NotEnoughElements, end..end)?;
+            self.exprs.push(self.build.lit(Value::Int(0)));
+        }
+        while !self.ops.is_empty() {
+            if self.top_is_open_paren() {
+                let span = self.paren_spans.pop().unwrap_or(end..end);
+                if !permissive {
+                    self.error(Error::UnbalancedParens, span)?;
+                }
+                self.ops.pop();
+                self.close_group();
+                continue;
+            }
+            self.reduce()?;
+        }
+
+        match (self.exprs.pop(), self.exprs.is_empty()) {
+            (Some(expr), true) => Ok(expr),
+            _ => Err(Error::StackUnderflow),
+        }
+    }
+
+    /// Drops the operators and empty parentheses at the end of input that
+    /// still wait for an operand, returning whether one is still needed.
+    fn drop_dangling(&mut self, mut expect_operand: bool) -> bool {
+        while expect_operand {
+            match (self.ops.last(), self.parens.last()) {
+                (Some(Op { kind: OpKind::Negate, .. }), _) => {
+                    self.ops.pop();
+                },
+                (Some(op), _) if op.kind != OpKind::OpenParen => {
+                    self.ops.pop();
+                    expect_operand = false;
+                },
+                (Some(_), Some(Group::Paren(start, false))) if *start == self.exprs.len() => {
+                    self.ops.pop();
+                    self.parens.pop();
+                    self.paren_spans.pop();
+                },
+                _ => break,
+            }
+        }
+        expect_operand
+    }
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Expr> {
+        Expr::parse_with(src, Syntax::default())
+    }
+
+    pub fn parse_with(src: &str, syntax: Syntax) -> Result<Expr> {
+        parse_into(src, syntax, &mut Exprs)
+    }
+
+    /// Like `parse_with`, also reading a number followed by the name of
+    /// a unit `units` finds as a quantity, such as `2 widget`.
+    pub fn parse_with_units(src: &str, syntax: Syntax, units: &UnitLookup<'_>) -> Result<Expr> {
+        let (tokens, _) = lex(src, false, syntax)?;
+        let mut exprs = Exprs;
+        let mut parser = Parser::new(&mut exprs);
+        parser.strictness = syntax.strictness;
+        parser.dialect = syntax.dialect;
+        parser.units = Some(units);
+        parser.parse(tokens, src.chars().count())
+    }
+
+    /// Like `parse_with`, calling `progress` with the number of tokens
+    /// parsed so far and the total every `every` tokens, and once more at
+    /// the end, so that an interface can show how parsing a generated
+    /// formula of megabytes is going.
+    pub fn parse_with_progress(src: &str, syntax: Syntax, every: usize, mut progress: impl FnMut(usize, usize)) -> Result<Expr> {
+        let (tokens, _) = lex(src, false, syntax)?;
+        let total = tokens.len();
+        let mut exprs = Exprs;
+        let mut parser = Parser::new(&mut exprs);
+        parser.strictness = syntax.strictness;
+        parser.dialect = syntax.dialect;
+        parser.progress = Some(&mut progress);
+        parser.progress_every = every.max(1);
+        let expr = parser.parse(tokens, src.chars().count())?;
+        progress(total, total);
+        Ok(expr)
+    }
+
+    /// Like `parse`, but carries on past syntax errors so that all of
+    /// them are reported, not just the first.
+    pub fn parse_recovering(src: &str) -> std::result::Result<Expr, Vec<Diagnostic>> {
+        let end = src.chars().count();
+        let (tokens, mut diagnostics) = match lex(src, true, Syntax::default()) {
+            Ok(lexed) => lexed,
+            Err(error) => return Err(vec![Diagnostic { error, span: 0..end }]),
+        };
+        let mut exprs = Exprs;
+        let mut parser = Parser::new(&mut exprs);
+        parser.diagnostics = Some(Vec::new());
+        let res = parser.parse(tokens, end);
+        diagnostics.extend(parser.diagnostics.take().unwrap_or_default());
+        diagnostics.sort_by_key(|d| d.span.start);
+        match res {
+            Ok(expr) if diagnostics.is_empty() => Ok(expr),
+            Ok(_) => Err(diagnostics),
+            Err(error) => {
+                diagnostics.push(Diagnostic { error, span: 0..end });
+                Err(diagnostics)
+            },
+        }
+    }
+}
+
+impl Expr {
+    /// Returns a copy of the expression with every occurrence of the
+    /// variable `name` replaced, e.g. by a number or another expression.
+    pub fn substitute(&self, name: &str, with: impl Into<Expr>) -> Expr {
+        self.substitute_expr(name, &with.into())
+    }
+
+    fn substitute_expr(&self, name: &str, with: &Expr) -> Expr {
+        match self {
+            Expr::Var(var) if var == name => with.clone(),
+            Expr::Lit(_) | Expr::Var(_) => self.clone(),
+            Expr::Neg(e) => Expr::Neg(Box::new(e.substitute_expr(name, with))),
+            Expr::Binary(kind, lhs, rhs) => Expr::Binary(
+                *kind,
+                Box::new(lhs.substitute_expr(name, with)),
+                Box::new(rhs.substitute_expr(name, with)),
+            ),
+            Expr::Call(f, args) => {
+                Expr::Call(f.clone(), args.iter().map(|arg| arg.substitute_expr(name, with)).collect())
+            },
+            Expr::List(items) => Expr::List(items.iter().map(|e| e.substitute_expr(name, with)).collect()),
+            Expr::Tuple(items) => Expr::Tuple(items.iter().map(|e| e.substitute_expr(name, with)).collect()),
+        }
+    }
+
+    /// How deeply subexpressions are nested, 1 for a lone number or
+    /// variable. Found without recursing, so any parsed input can be
+    /// measured before it is handed to code that does recurse.
+    pub fn depth(&self) -> usize {
+        let mut deepest = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((expr, depth)) = stack.pop() {
+            deepest = deepest.max(depth);
+            match expr {
+                Expr::Lit(_) | Expr::Var(_) => {},
+                Expr::Neg(e) => stack.push((e, depth + 1)),
+                Expr::Binary(_, lhs, rhs) => stack.extend([(&**lhs, depth + 1), (&**rhs, depth + 1)]),
+                Expr::Call(_, items) | Expr::List(items) | Expr::Tuple(items) => {
+                    stack.extend(items.iter().map(|e| (e, depth + 1)))
+                },
+            }
+        }
+        deepest
+    }
+
+    /// A hash of `symbolic::canonical(self)`, the same for expressions
+    /// such as `a + b` and `b + a` that differ only in the order of
+    /// commutative operands or in constants left unfolded, for caches of
+    /// results. It stays the same from run to run, though not
+    /// necessarily across versions of Rust.
+    pub fn canonical_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        symbolic::canonical(self).hash_into(&mut hasher);
+        hasher.finish()
+    }
+
+    fn hash_into(&self, state: &mut impl Hasher) {
+        std::mem::discriminant(self).hash(state);
+        let items = match self {
+            Expr::Lit(v) => {
+                // Values have no hash of their own, floats being among them.
+                std::mem::discriminant(v).hash(state);
+                v.to_string().hash(state);
+                return;
+            },
+            Expr::Var(name) => {
+                name.hash(state);
+                return;
+            },
+            Expr::Neg(e) => std::slice::from_ref(&**e),
+            Expr::Binary(kind, lhs, rhs) => {
+                std::mem::discriminant(kind).hash(state);
+                lhs.hash_into(state);
+                rhs.hash_into(state);
+                return;
+            },
+            Expr::Call(name, args) => {
+                name.hash(state);
+                args
+            },
+            Expr::List(items) | Expr::Tuple(items) => items,
+        };
+        items.len().hash(state);
+        for item in items {
+            item.hash_into(state);
+        }
+    }
+
+    /// Binding strength used to decide where parentheses are needed
+    /// when printing.
+    fn prec(&self) -> u8 {
+        match self {
+            Expr::Binary(OpKind::BitXor, _, _) => u8::MAX,
+            Expr::Binary(kind, _, _) => Op::new(*kind).prec,
+            Expr::Neg(_) => Op::new(OpKind::Negate).prec,
+            Expr::Lit(Value::Int(n)) if *n < 0 => Op::new(OpKind::Negate).prec,
+            Expr::Lit(Value::Float(x)) if x.is_sign_negative() => Op::new(OpKind::Negate).prec,
+            _ => u8::MAX,
+        }
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let paren = |f: &mut std::fmt::Formatter, e: &Expr, needed: bool| {
+            if needed { write!(f, "({})", e) } else { write!(f, "{}", e) }
+        };
+
+        match self {
+            Expr::Lit(Value::Str(s)) => write!(f, "\"{}\"", s),
+            Expr::Lit(v) => write!(f, "{}", v),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::Neg(e) => {
+                write!(f, "-")?;
+                paren(f, e, e.prec() <= self.prec())
+            },
+            // Only the Python dialect has an operator for it.
+            Expr::Binary(OpKind::BitXor, lhs, rhs) => write!(f, "xor({}, {})", lhs, rhs),
+            Expr::Binary(kind, lhs, rhs) => {
+                let op = Op::new(*kind);
+                let right_assoc = op.is_right_assoc();
+                paren(f, lhs, lhs.prec() < op.prec || (right_assoc && lhs.prec() == op.prec))?;
+                match kind {
+                    OpKind::Plus         => write!(f, " + ")?,
+                    OpKind::Minus        => write!(f, " - ")?,
+                    OpKind::Multiply     => write!(f, "*")?,
+                    OpKind::Divide       => write!(f, "/")?,
+                    OpKind::FloorDivide  => write!(f, "//")?,
+                    OpKind::Modulo       => write!(f, "%")?,
+                    OpKind::Power        => write!(f, "^")?,
+                    OpKind::Equals       => write!(f, " = ")?,
+                    OpKind::BitAnd       => write!(f, " & ")?,
+                    OpKind::BitOr        => write!(f, " | ")?,
+                    OpKind::Concat       => write!(f, " & ")?,
+                    OpKind::ShiftLeft    => write!(f, " << ")?,
+                    OpKind::ShiftRight   => write!(f, " >> ")?,
+                    OpKind::Arrow        => write!(f, " -> ")?,
+                    OpKind::Range        => write!(f, "..")?,
+                    OpKind::Step         => write!(f, " step ")?,
+                    OpKind::In           => write!(f, " in ")?,
+                    OpKind::Less         => write!(f, " < ")?,
+                    OpKind::LessEqual    => write!(f, " <= ")?,
+                    OpKind::Greater      => write!(f, " > ")?,
+                    OpKind::GreaterEqual => write!(f, " >= ")?,
+                    OpKind::Equal        => write!(f, " == ")?,
+                    OpKind::NotEqual     => write!(f, " != ")?,
+                    _ => write!(f, " ? ")?,
+                }
+                paren(f, rhs, rhs.prec() < op.prec || (!right_assoc && rhs.prec() == op.prec))
+            },
+            Expr::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            },
+            Expr::List(items) => {
+                write!(f, "[")?;
+                write_list(f, items)?;
+                write!(f, "]")
+            },
+            Expr::Tuple(items) => {
+                write!(f, "(")?;
+                write_list(f, items)?;
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+impl From<Value> for Expr {
+    fn from(v: Value) -> Expr {
+        Expr::Lit(v)
+    }
+}
+
+impl From<i64> for Expr {
+    fn from(n: i64) -> Expr {
+        Expr::Lit(Value::Int(n))
+    }
+}
+
+impl From<f64> for Expr {
+    fn from(x: f64) -> Expr {
+        Expr::Lit(Value::Float(x))
+    }
+}
@@ -0,0 +1,247 @@
+use std::sync::Arc;
+
+use crate::clock::{self, Time};
+use crate::env::{EvalOptions, Overflow};
+use crate::format;
+use crate::function::Function;
+use crate::integer;
+use crate::net::{self, Addr};
+use crate::units::{self, Quantity};
+
+use super::error::{Error, Result};
+use super::lexer::OpKind;
+use super::parser::{Dialect, Expr};
+
+/// A value produced by the expression evaluator. Integer arithmetic
+/// stays exact for as long as it can and falls back to floating point
+/// on overflow or inexact division.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    /// A symbolic result, such as the one returned by `diff`.
+    Expr(Box<Expr>),
+    List(Vec<Value>),
+    /// Several results of one call, such as the quotient and remainder
+    /// returned by `divmod`. Can be unpacked with `q, r = divmod(17, 5)`.
+    Tuple(Vec<Value>),
+    /// A function made by a lambda such as `x -> x^2`.
+    Func(Arc<Function>),
+    /// An IPv4 address or network, such as `192.168.1.0/24`.
+    Addr(Addr),
+    /// An amount of a unit, such as `1.5 GiB`.
+    Quantity(Quantity),
+    /// An instant, shown in a time zone.
+    Time(Time),
+}
+
+impl Value {
+    pub fn as_f64(&self) -> Result<f64> {
+        match self {
+            Value::Int(n)   => Ok(*n as f64),
+            Value::Float(x) => Ok(*x),
+            _ => Err(Error::TypeError),
+        }
+    }
+
+    pub fn as_int(&self) -> Result<i64> {
+        match self {
+            Value::Int(n) => Ok(*n),
+            _ => Err(Error::TypeError),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str> {
+        match self {
+            Value::Str(s) => Ok(s),
+            _ => Err(Error::TypeError),
+        }
+    }
+
+    pub fn negate(self) -> Result<Value> {
+        self.negate_with(&EvalOptions::default())
+    }
+
+    /// Like `negate`, with overflow handled as `options` say.
+    pub fn negate_with(self, options: &EvalOptions) -> Result<Value> {
+        match self {
+            Value::Int(n) => match options.overflow.fit(-(n as i128), options.width)? {
+                Some(n) => Ok(Value::Int(n)),
+                None    => Ok(Value::Float(-(n as f64))),
+            },
+            Value::Float(x) => Ok(Value::Float(-x)),
+            Value::Quantity(q) => Ok(Value::Quantity(Quantity { amount: -q.amount, ..q })),
+            _ => Err(Error::TypeError),
+        }
+    }
+
+    /// Applies a binary arithmetic operator.
+    pub fn apply(kind: OpKind, lhs: Value, rhs: Value) -> Result<Value> {
+        Value::apply_with(kind, lhs, rhs, &EvalOptions::default())
+    }
+
+    /// Like `apply`, with integer overflow handled as `options` say.
+    pub fn apply_with(kind: OpKind, lhs: Value, rhs: Value, options: &EvalOptions) -> Result<Value> {
+        if kind == OpKind::Concat {
+            return Ok(Value::Str(format!("{}{}", lhs, rhs)));
+        }
+        if let Some(res) = net::apply(kind, &lhs, &rhs) {
+            return res;
+        }
+        if let Some(res) = clock::apply(kind, &lhs, &rhs) {
+            return res;
+        }
+        if let Some(res) = units::apply(kind, &lhs, &rhs) {
+            return res;
+        }
+        let python = options.syntax.dialect == Dialect::Python;
+        let c = options.syntax.dialect == Dialect::C;
+        if let (Value::Int(a), Value::Int(b), Some(m)) = (&lhs, &rhs, options.modulus) {
+            if let Some(res) = integer::apply_mod(kind, *a, *b, m.get()) {
+                return res.map(Value::Int);
+            }
+        }
+        if let (Value::Int(a), Value::Int(b)) = (&lhs, &rhs) {
+            // No operation on two i64s except `^` can overflow an i128.
+            let (a, b) = (*a as i128, *b as i128);
+            let exact = match kind {
+                OpKind::Plus        => Some(a + b),
+                OpKind::Minus       => Some(a - b),
+                OpKind::Multiply    => Some(a * b),
+                OpKind::Divide      => {
+                    if b == 0 {
+                        return Err(Error::DivisionByZero);
+                    }
+                    // Python's `/` gives a float even when it divides exactly,
+                    // and C's drops the fraction.
+                    if c || (a % b == 0 && !python) { Some(a / b) } else { None }
+                },
+                OpKind::FloorDivide => {
+                    if b == 0 {
+                        return Err(Error::DivisionByZero);
+                    }
+                    Some(if a % b != 0 && (a < 0) != (b < 0) { a / b - 1 } else { a / b })
+                },
+                OpKind::Modulo      => {
+                    if b == 0 {
+                        return Err(Error::DivisionByZero);
+                    }
+                    let r = a % b;
+                    Some(if r != 0 && (r < 0) != (b < 0) && !c { r + b } else { r })
+                },
+                OpKind::Power       => match u32::try_from(b) {
+                    Ok(e)  => match a.checked_pow(e) {
+                        Some(n) => Some(n),
+                        None if options.overflow == Overflow::Wrap => Some((a as i64).wrapping_pow(e) as i128),
+                        // Out of range either way; only the sign matters.
+                        None => Some(if a < 0 && e % 2 == 1 { i128::MIN } else { i128::MAX }),
+                    },
+                    Err(_) => None,
+                },
+                OpKind::BitAnd      => Some(a & b),
+                OpKind::BitOr       => Some(a | b),
+                OpKind::BitXor      => Some(a ^ b),
+                OpKind::ShiftLeft   => {
+                    let b = u32::try_from(b).map_err(|_| Error::TypeError)?;
+                    match (b < 127).then(|| a.checked_mul(1 << b)).flatten() {
+                        Some(n) => Some(n),
+                        None if a == 0 => Some(0),
+                        None if options.overflow == Overflow::Wrap => Some((a as u128).checked_shl(b).unwrap_or(0) as i128),
+                        None => Some(if a < 0 { i128::MIN } else { i128::MAX }),
+                    }
+                },
+                OpKind::ShiftRight  => {
+                    let b = u32::try_from(b).map_err(|_| Error::TypeError)?;
+                    Some(a >> b.min(127))
+                },
+                OpKind::Less | OpKind::LessEqual | OpKind::Greater | OpKind::GreaterEqual |
+                OpKind::Equal | OpKind::NotEqual => Some(compare(kind, a.cmp(&b)) as i128),
+                _ => return Err(Error::UnknownOperator),
+            };
+            if let Some(n) = exact {
+                if let Some(n) = options.overflow.fit(n, options.width)? {
+                    return Ok(Value::Int(n));
+                }
+            }
+        }
+
+        let (a, b) = (lhs.as_f64()?, rhs.as_f64()?);
+        // Python raises where IEEE-754 would give an infinity or NaN.
+        let by_zero = match kind {
+            OpKind::Divide | OpKind::FloorDivide | OpKind::Modulo => b == 0.0,
+            OpKind::Power => a == 0.0 && b < 0.0,
+            _ => false,
+        };
+        if python && by_zero {
+            return Err(Error::DivisionByZero);
+        }
+        match kind {
+            OpKind::Plus        => Ok(Value::Float(a + b)),
+            OpKind::Minus       => Ok(Value::Float(a - b)),
+            OpKind::Multiply    => Ok(Value::Float(a * b)),
+            OpKind::Divide      => Ok(Value::Float(a / b)),
+            OpKind::FloorDivide => Ok(Value::Float((a / b).floor())),
+            OpKind::Modulo if c => Err(Error::TypeError),
+            OpKind::Modulo      => Ok(Value::Float(a - b * (a / b).floor())),
+            OpKind::Power       => Ok(Value::Float(a.powf(b))),
+            OpKind::BitAnd | OpKind::BitOr | OpKind::BitXor | OpKind::ShiftLeft | OpKind::ShiftRight => Err(Error::TypeError),
+            OpKind::Less | OpKind::LessEqual | OpKind::Greater | OpKind::GreaterEqual |
+            OpKind::Equal | OpKind::NotEqual => {
+                // NaN compares unequal to everything, itself included.
+                let res = match a.partial_cmp(&b) {
+                    Some(ord) => compare(kind, ord),
+                    None => kind == OpKind::NotEqual,
+                };
+                Ok(Value::Int(res as i64))
+            },
+            _ => Err(Error::UnknownOperator),
+        }
+    }
+}
+
+/// Whether two numbers ordered as `ord` satisfy a comparison operator.
+fn compare(kind: OpKind, ord: std::cmp::Ordering) -> bool {
+    match kind {
+        OpKind::Less         => ord.is_lt(),
+        OpKind::LessEqual    => ord.is_le(),
+        OpKind::Greater      => ord.is_gt(),
+        OpKind::GreaterEqual => ord.is_ge(),
+        OpKind::Equal        => ord.is_eq(),
+        OpKind::NotEqual     => ord.is_ne(),
+        _ => false,
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Int(n)   => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", format::shortest(*x)),
+            Value::Str(s)   => write!(f, "{}", s),
+            Value::Expr(e)  => write!(f, "{}", e),
+            Value::Func(func) => write!(f, "{}", func),
+            Value::Addr(addr) => write!(f, "{}", addr),
+            Value::Quantity(q) => write!(f, "{}", q),
+            Value::Time(t) => write!(f, "{}", t),
+            Value::List(vs) | Value::Tuple(vs) => {
+                let (open, close) = if matches!(self, Value::List(_)) { ("[", "]") } else { ("(", ")") };
+                write!(f, "{}", open)?;
+                write_list(f, vs)?;
+                write!(f, "{}", close)
+            },
+        }
+    }
+}
+
+/// Writes items separated by commas.
+pub(super) fn write_list<T: std::fmt::Display>(f: &mut std::fmt::Formatter, items: &[T]) -> std::fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
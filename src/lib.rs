@@ -1 +1,58 @@
 pub mod kalkul;
+pub mod ast;
+pub mod audit;
+pub mod builtins;
+pub mod cbor;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod clock;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+#[cfg(feature = "cli")]
+pub mod command;
+pub mod csv;
+pub mod diff;
+pub mod env;
+pub mod finance;
+pub mod format;
+pub mod function;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod help;
+pub mod integer;
+pub mod json;
+#[cfg(feature = "cli")]
+pub mod lsp;
+pub mod net;
+pub mod numeric;
+pub mod plot;
+#[cfg(feature = "precise")]
+pub mod precise;
+pub mod program;
+#[cfg(feature = "python")]
+mod python;
+pub mod reactive;
+#[cfg(feature = "cli")]
+pub mod repl;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod solve;
+pub mod stats;
+pub mod symbol;
+pub mod symbolic;
+pub mod template;
+#[cfg(feature = "tzdata")]
+mod tzif;
+pub mod units;
+
+pub use kalkul::{error, eval, lexer, parser, value};
+pub use kalkul::{evaluate, Error, Expr, Result, Value};
+
+/// What most programs using kalkul as a library need, for
+/// `use kalkul::prelude::*`.
+pub mod prelude {
+    pub use crate::env::{Environment, EvalOptions};
+    pub use crate::kalkul::{eval, evaluate, Dialect, Error, EvalOutput, Expr, Result, Syntax, Value};
+    #[cfg(feature = "tokio")]
+    pub use crate::kalkul::evaluate_async;
+}
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Read, Write};
+use std::time::{Duration, Instant};
+
+use crate::env::Limits;
+use crate::json::Json;
+use crate::kalkul::{Diagnostic, Error, Expr, Result};
+use crate::repl::{self, Repl};
+
+/// Bounds on evaluating a whole document, so a runaway expression cannot
+/// freeze the editor.
+const MAX_STEPS: u64 = 1_000_000;
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+const PARSE_ERROR: f64 = -32700.0;
+const METHOD_NOT_FOUND: f64 = -32601.0;
+
+/// Largest message body accepted, in bytes. Editors send whole documents,
+/// so this is generous, but a `Content-Length` is not trusted beyond it.
+const MAX_MESSAGE: usize = 16 * 1024 * 1024;
+
+/// The outcome of each line of a document, evaluated top to bottom like a
+/// REPL session so later lines can use variables assigned earlier.
+fn analyze(text: &str) -> Vec<Option<Result<String>>> {
+    // Looking at a document must not write files or the clipboard.
+    let mut repl = Repl::sandboxed();
+    repl.set_limits(Limits {
+        max_steps: Some(MAX_STEPS),
+        deadline: Some(Instant::now() + TIMEOUT),
+        ..Limits::default()
+    });
+    text.lines()
+        .map(|line| match repl.handle(line) {
+            Ok(None) => None,
+            Ok(Some(out)) => Some(Ok(out)),
+            Err(e) => Some(Err(e)),
+        })
+        .collect()
+}
+
+fn position(line: usize, character: usize) -> Json {
+    Json::object([
+        ("line", Json::Num(line as f64)),
+        ("character", Json::Num(character as f64)),
+    ])
+}
+
+fn line_range(line: usize, text: &str) -> Json {
+    Json::object([
+        ("start", position(line, 0)),
+        ("end", position(line, text.chars().count())),
+    ])
+}
+
+fn diagnostic(range: Json, message: String) -> Json {
+    Json::object([
+        ("range", range),
+        // Error.
+        ("severity", Json::Num(1.0)),
+        ("source", Json::str("kalkul")),
+        ("message", Json::str(message)),
+    ])
+}
+
+/// Every syntax error in the expression on a line, or `None` if the line
+/// is not an expression or has none.
+fn syntax_errors(line: usize, text: &str) -> Option<Vec<Json>> {
+    if text.trim_start().starts_with(':') {
+        return None;
+    }
+    let (offset, expr) = match repl::split_assignment(text) {
+        Some((_, expr)) => (text.len() - expr.len(), expr),
+        None => (0, text),
+    };
+    let offset = text[..offset].chars().count();
+    let found: Vec<Diagnostic> = Expr::parse_recovering(expr).err()?;
+    Some(found.into_iter()
+        .map(|d| {
+            let range = Json::object([
+                ("start", position(line, offset + d.span.start)),
+                ("end", position(line, offset + d.span.end)),
+            ]);
+            // The range already says where.
+            diagnostic(range, d.message())
+        })
+        .collect())
+}
+
+/// Editor integration over the Language Server Protocol. Each line of a
+/// document is a calculator input; errors are published as diagnostics
+/// and hovering a line shows its value.
+#[derive(Default)]
+pub struct Server {
+    docs: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles one incoming message, returning the messages to send back.
+    pub fn handle(&mut self, msg: &Json) -> Vec<Json> {
+        let method = msg.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = msg.get("params").unwrap_or(&Json::Null);
+        let doc = params.get("textDocument");
+        let uri = doc.and_then(|d| d.get("uri")).and_then(Json::as_str).unwrap_or("");
+
+        let result = match method {
+            "initialize" => Json::object([
+                ("capabilities", Json::object([
+                    // Full document sync.
+                    ("textDocumentSync", Json::Num(1.0)),
+                    ("hoverProvider", Json::Bool(true)),
+                ])),
+                ("serverInfo", Json::object([("name", Json::str("kalkul"))])),
+            ]),
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            },
+            "textDocument/didOpen" => {
+                let text = doc.and_then(|d| d.get("text")).and_then(Json::as_str).unwrap_or("");
+                return self.update(uri, text.to_string());
+            },
+            "textDocument/didChange" => {
+                let change = match params.get("contentChanges") {
+                    Some(Json::Array(changes)) => changes.last(),
+                    _ => None,
+                };
+                let text = change.and_then(|c| c.get("text")).and_then(Json::as_str).unwrap_or("");
+                return self.update(uri, text.to_string());
+            },
+            "textDocument/didClose" => {
+                self.docs.remove(uri);
+                return vec![diagnostics(uri, Vec::new())];
+            },
+            "textDocument/hover" => self.hover(uri, params.get("position")),
+            _ => {
+                // Unknown notifications are ignored, unknown requests are not.
+                return match msg.get("id") {
+                    Some(id) => vec![error_reply(id.clone(), METHOD_NOT_FOUND, format!("unknown method {}", method))],
+                    None => Vec::new(),
+                };
+            },
+        };
+
+        match msg.get("id") {
+            Some(id) => vec![Json::object([
+                ("jsonrpc", Json::str("2.0")),
+                ("id", id.clone()),
+                ("result", result),
+            ])],
+            None => Vec::new(),
+        }
+    }
+
+    fn update(&mut self, uri: &str, text: String) -> Vec<Json> {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut found = Vec::new();
+        for (i, res) in analyze(&text).into_iter().enumerate() {
+            if let Some(Err(e)) = res {
+                match syntax_errors(i, lines[i]) {
+                    Some(errors) => found.extend(errors),
+                    None => found.push(diagnostic(line_range(i, lines[i]), e.to_string())),
+                }
+            }
+        }
+        self.docs.insert(uri.to_string(), text);
+        vec![diagnostics(uri, found)]
+    }
+
+    fn hover(&self, uri: &str, pos: Option<&Json>) -> Json {
+        let line = pos.and_then(|p| p.get("line")).and_then(Json::as_f64).unwrap_or(-1.0);
+        let text = match self.docs.get(uri) {
+            Some(text) if line >= 0.0 => text,
+            _ => return Json::Null,
+        };
+        let line = line as usize;
+        match analyze(text).into_iter().nth(line) {
+            Some(Some(Ok(out))) => Json::object([
+                ("contents", Json::object([
+                    ("kind", Json::str("plaintext")),
+                    ("value", Json::str(format!("= {}", out))),
+                ])),
+                ("range", line_range(line, text.lines().nth(line).unwrap_or(""))),
+            ]),
+            _ => Json::Null,
+        }
+    }
+}
+
+fn diagnostics(uri: &str, found: Vec<Json>) -> Json {
+    Json::object([
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("textDocument/publishDiagnostics")),
+        ("params", Json::object([
+            ("uri", Json::str(uri)),
+            ("diagnostics", Json::Array(found)),
+        ])),
+    ])
+}
+
+fn error_reply(id: Json, code: f64, message: String) -> Json {
+    Json::object([
+        ("jsonrpc", Json::str("2.0")),
+        ("id", id),
+        ("error", Json::object([
+            ("code", Json::Num(code)),
+            ("message", Json::str(message)),
+        ])),
+    ])
+}
+
+/// Reads the body of one `Content-Length` framed message, or `None` at
+/// end of input.
+fn read_message(input: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, val)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                len = Some(val.trim().parse().map_err(|_| Error::ParseError)?);
+            }
+        }
+    }
+
+    let len: usize = len.ok_or(Error::ParseError)?;
+    if len > MAX_MESSAGE {
+        return Err(Error::LimitExceeded);
+    }
+    let mut body = Vec::new();
+    input.take(len as u64).read_to_end(&mut body)?;
+    if body.len() < len {
+        return Err(Error::ReadError);
+    }
+    Ok(Some(body))
+}
+
+fn write_message(out: &mut impl Write, msg: &Json) -> Result<()> {
+    let body = msg.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Serves a single editor over `input` and `out` until it sends `exit`.
+pub fn run(input: &mut impl BufRead, out: &mut impl Write) -> Result<()> {
+    let mut server = Server::new();
+    while let Some(body) = read_message(input)? {
+        // The framing was sound, so the next message can still be read.
+        let Some(msg) = String::from_utf8(body).ok().and_then(|body| Json::parse(&body).ok()) else {
+            write_message(out, &error_reply(Json::Null, PARSE_ERROR, "malformed JSON".to_string()))?;
+            continue;
+        };
+        if msg.get("method").and_then(Json::as_str) == Some("exit") {
+            return if server.shutdown { Ok(()) } else { Err(Error::Usage) };
+        }
+        for reply in server.handle(&msg) {
+            write_message(out, &reply)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(msgs: &[&str]) -> String {
+        msgs.iter().map(|m| format!("Content-Length: {}\r\n\r\n{}", m.len(), m)).collect()
+    }
+
+    #[test]
+    fn test_diagnostics_and_hover() {
+        let mut server = Server::new();
+        let open = Json::parse(r#"{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params":
+            {"textDocument": {"uri": "file:///a.calc", "text": "x = 3\n\nx * 2 +\nx^2\ny = (1 + ) * $"}}}"#).unwrap();
+        let replies = server.handle(&open);
+        assert_eq!(Some("textDocument/publishDiagnostics"), replies[0].get("method").and_then(Json::as_str));
+        let found = match replies[0].get("params").and_then(|p| p.get("diagnostics")) {
+            Some(Json::Array(found)) => found.clone(),
+            _ => Vec::new(),
+        };
+        let summary: Vec<String> = found.iter()
+            .map(|d| {
+                let range = d.get("range").unwrap();
+                let at = |end: &str, key: &str| range.get(end).and_then(|p| p.get(key)).and_then(Json::as_f64).unwrap();
+                format!("{}:{}-{} {}", at("start", "line"), at("start", "character"), at("end", "character"),
+                        d.get("message").and_then(Json::as_str).unwrap())
+            })
+            .collect();
+        // The missing operand of `x * 2 +`, then every error on the last line.
+        let expected = [
+            "2:7-7 missing operand",
+            "4:9-10 unexpected token",
+            "4:13-14 unexpected character '$'",
+            "4:14-14 missing operand",
+        ];
+        assert_eq!(expected.to_vec(), summary);
+
+        let hover = Json::parse(r#"{"jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params":
+            {"textDocument": {"uri": "file:///a.calc"}, "position": {"line": 3, "character": 1}}}"#).unwrap();
+        let replies = server.handle(&hover);
+        let contents = replies[0].get("result").and_then(|r| r.get("contents"));
+        assert_eq!(Some("= 9"), contents.and_then(|c| c.get("value")).and_then(Json::as_str));
+
+        let unknown = Json::parse(r#"{"jsonrpc": "2.0", "id": 3, "method": "frobnicate"}"#).unwrap();
+        assert!(server.handle(&unknown)[0].get("error").is_some());
+    }
+
+    #[test]
+    fn test_no_side_effects() {
+        let path = std::env::temp_dir().join(format!("kalkul-lsp-{}.csv", std::process::id()));
+        let text = format!("1 + 1\n:export {}\n:copy", path.display());
+        let open = Json::object([
+            ("jsonrpc", Json::str("2.0")),
+            ("method", Json::str("textDocument/didOpen")),
+            ("params", Json::object([("textDocument", Json::object([
+                ("uri", Json::str("file:///b.calc")),
+                ("text", Json::str(text)),
+            ]))])),
+        ]);
+        Server::new().handle(&open);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_run() {
+        let input = frame(&[
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#,
+            r#"{"jsonrpc": "2.0", "method": "initialized", "params": {}}"#,
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "shutdown"}"#,
+            r#"{"jsonrpc": "2.0", "method": "exit"}"#,
+        ]);
+        let mut out = Vec::new();
+        run(&mut input.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Content-Length: "));
+        assert!(out.contains(r#""hoverProvider":true"#));
+        assert!(out.ends_with(r#"{"id":2,"jsonrpc":"2.0","result":null}"#));
+
+        let input = frame(&[
+            r#"{"jsonrpc": "2.0", "id": 1,"#,
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "shutdown"}"#,
+            r#"{"jsonrpc": "2.0", "method": "exit"}"#,
+        ]);
+        let mut out = Vec::new();
+        run(&mut input.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(r#""code":-32700"#));
+        assert!(out.ends_with(r#"{"id":2,"jsonrpc":"2.0","result":null}"#));
+
+        let input = format!("Content-Length: {}\r\n\r\n{{}}", u64::MAX);
+        assert!(matches!(run(&mut input.as_bytes(), &mut Vec::new()), Err(Error::LimitExceeded)));
+        assert!(matches!(run(&mut "Content-Length: 10\r\n\r\n{}".as_bytes(), &mut Vec::new()), Err(Error::ReadError)));
+    }
+}
@@ -0,0 +1,39 @@
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use kalkul::cli;
+use kalkul::repl::Repl;
+
+fn repl() -> io::Result<()> {
+    let mut repl = Repl::new();
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    loop {
+        write!(stdout, "> ")?;
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        match repl.handle(&line) {
+            Ok(Some(out)) => writeln!(stdout, "{}", out)?,
+            Ok(None) => {},
+            Err(e) => writeln!(stdout, "error: {}", e)?,
+        }
+    }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        return repl();
+    }
+    if let Err(e) = cli::run(&args, &mut io::stdin().lock(), &mut io::stdout()) {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::net::Ipv4Addr;
+
+use crate::kalkul::{Error, OpKind, Result, Value};
+
+/// An IPv4 address, or a network when it has a prefix length, as in
+/// `192.168.1.0/24`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Addr {
+    pub ip: u32,
+    pub prefix: Option<u8>,
+}
+
+impl Addr {
+    /// Reads an address in dotted form, such as `10.0.0.1`.
+    pub fn parse(text: &str) -> Result<Addr> {
+        let ip: Ipv4Addr = text.parse().map_err(|_| Error::ParseError)?;
+        Ok(Addr { ip: ip.into(), prefix: None })
+    }
+
+    /// The mask with the top `prefix` bits set, 0 to 32.
+    pub fn netmask(prefix: i64) -> Result<Addr> {
+        let prefix = prefix_arg(prefix)?;
+        Ok(Addr { ip: mask(prefix), prefix: None })
+    }
+
+    /// Whether `other` lies in this network, all of it if `other` is a
+    /// network itself. An address without a prefix only contains itself.
+    pub fn contains(self, other: Addr) -> bool {
+        let prefix = self.prefix.unwrap_or(32);
+        other.prefix.unwrap_or(32) >= prefix && (self.ip ^ other.ip) & mask(prefix) == 0
+    }
+
+    fn offset(self, n: i64) -> Result<Addr> {
+        let ip = (self.ip as i64).checked_add(n).and_then(|ip| u32::try_from(ip).ok()).ok_or(Error::Overflow)?;
+        Ok(Addr { ip, ..self })
+    }
+}
+
+impl std::fmt::Display for Addr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", Ipv4Addr::from(self.ip))?;
+        match self.prefix {
+            Some(prefix) => write!(f, "/{}", prefix),
+            None => Ok(()),
+        }
+    }
+}
+
+fn prefix_arg(prefix: i64) -> Result<u8> {
+    match prefix {
+        0..=32 => Ok(prefix as u8),
+        _ => Err(Error::TypeError),
+    }
+}
+
+fn mask(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+/// Applies an operator to operands of which at least one is an address,
+/// or returns `None` if neither is. Dividing by a number gives the prefix
+/// length, adding or subtracting one moves through the addresses, and
+/// subtracting two addresses gives how far apart they are.
+pub fn apply(kind: OpKind, lhs: &Value, rhs: &Value) -> Option<Result<Value>> {
+    let res = match (kind, lhs, rhs) {
+        (OpKind::Divide, Value::Addr(a), Value::Int(n)) => prefix_arg(*n).map(|prefix| Addr { prefix: Some(prefix), ..*a }),
+        (OpKind::Plus, Value::Addr(a), Value::Int(n)) | (OpKind::Plus, Value::Int(n), Value::Addr(a)) => a.offset(*n),
+        (OpKind::Minus, Value::Addr(a), Value::Int(n)) => n.checked_neg().ok_or(Error::Overflow).and_then(|n| a.offset(n)),
+        (OpKind::Minus, Value::Addr(a), Value::Addr(b)) => return Some(Ok(Value::Int(a.ip as i64 - b.ip as i64))),
+        (OpKind::Equal, Value::Addr(a), Value::Addr(b)) => return Some(Ok(Value::Int((a == b) as i64))),
+        (OpKind::NotEqual, Value::Addr(a), Value::Addr(b)) => return Some(Ok(Value::Int((a != b) as i64))),
+        (OpKind::Less | OpKind::LessEqual | OpKind::Greater | OpKind::GreaterEqual, Value::Addr(a), Value::Addr(b)) => {
+            let holds = match kind {
+                OpKind::Less      => a.ip < b.ip,
+                OpKind::LessEqual => a.ip <= b.ip,
+                OpKind::Greater   => a.ip > b.ip,
+                _                 => a.ip >= b.ip,
+            };
+            return Some(Ok(Value::Int(holds as i64)));
+        },
+        (_, Value::Addr(_), _) | (_, _, Value::Addr(_)) => Err(Error::TypeError),
+        _ => return None,
+    };
+    Some(res.map(Value::Addr))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::Environment;
+    use crate::kalkul::Error;
+
+    #[test]
+    fn test_addresses() {
+        let exprs = [
+            "192.168.1.0/24 + 5",
+            "10.0.0.255 + 1",
+            "10.0.1.0 - 10.0.0.0",
+            "10.0.0.1 < 10.0.0.2",
+            "netmask(24)",
+            "netmask(0)",
+            "contains(192.168.1.0/24, 192.168.1.77)",
+            "contains(192.168.1.0/24, 192.168.2.1)",
+            "contains(10.0.0.0/8, 10.1.0.0/16)",
+            "contains(10.1.0.0/16, 10.0.0.0/8)",
+        ];
+        let answers = ["192.168.1.5/24", "10.0.1.0", "256", "1", "255.255.255.0", "0.0.0.0", "1", "0", "1", "0"];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        for expr in ["255.255.255.255 + 1", "1.2.3.4/33", "1.2.3.4 * 2", "netmask(40)", "1.2.3.256"] {
+            assert!(env.eval(expr).is_err(), "{}", expr);
+        }
+        for expr in ["10.0.0.1 + 9223372036854775807", "10.0.0.1 - (-9223372036854775807 - 1)"] {
+            assert!(matches!(env.eval(expr), Err(Error::Overflow)), "{}", expr);
+        }
+    }
+}
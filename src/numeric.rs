@@ -0,0 +1,227 @@
+use crate::env::Environment;
+use crate::kalkul::{Error, Expr, OpKind, Result, Value};
+use crate::program::Program;
+use crate::symbolic::{derivative, simplify};
+
+const MAX_ITERATIONS: usize = 200;
+
+const INTEGRATION_TOLERANCE: f64 = 1e-10;
+const MAX_INTEGRATION_DEPTH: u32 = 50;
+
+/// Runs `f` with the variable `x` free to be overwritten, restoring its
+/// previous value, if any, afterwards.
+pub fn with_var<T>(env: &mut Environment, x: &str, f: impl FnOnce(&mut Environment) -> Result<T>) -> Result<T> {
+    let saved = env.unset(x);
+    let res = f(env);
+    match saved {
+        Some(val) => env.set(x, val),
+        None => {
+            env.unset(x);
+        },
+    }
+    res
+}
+
+/// Finds a root of `expr`, as a function of `x`, between `a` and `b`.
+///
+/// When the function changes sign over the interval the root is
+/// bracketed and found by bisection. Otherwise Newton's method is tried
+/// starting from the middle of the interval.
+pub fn findroot(expr: &Expr, x: &str, a: f64, b: f64, env: &mut Environment) -> Result<f64> {
+    let f = Program::compile(expr);
+    with_var(env, x, |env| {
+        let (fa, fb) = (f.call(env, x, a)?, f.call(env, x, b)?);
+        if fa == 0.0 {
+            return Ok(a);
+        }
+        if fb == 0.0 {
+            return Ok(b);
+        }
+        if fa.signum() != fb.signum() {
+            return bisect(&f, x, (a, fa), (b, fb), env);
+        }
+
+        let df = match derivative(expr, x) {
+            Ok(d) => Some(Program::compile(&simplify(&d, env)?)),
+            Err(_) => None,
+        };
+        newton(&f, df.as_ref(), x, (a + b) / 2.0, env)
+    })
+}
+
+fn bisect(f: &Program, x: &str, lo: (f64, f64), hi: (f64, f64), env: &mut Environment) -> Result<f64> {
+    let ((mut lo, flo), (mut hi, _)) = (lo, hi);
+    for _ in 0..MAX_ITERATIONS {
+        let mid = lo + (hi - lo) / 2.0;
+        if mid == lo || mid == hi {
+            return Ok(mid);
+        }
+        let fmid = f.call(env, x, mid)?;
+        if fmid == 0.0 {
+            return Ok(mid);
+        }
+        if fmid.signum() == flo.signum() {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    Ok(lo + (hi - lo) / 2.0)
+}
+
+fn newton(f: &Program, df: Option<&Program>, x: &str, start: f64, env: &mut Environment) -> Result<f64> {
+    let mut xn = start;
+    for _ in 0..MAX_ITERATIONS {
+        let fx = f.call(env, x, xn)?;
+        let dfx = match df {
+            Some(df) => df.call(env, x, xn)?,
+            None => {
+                let h = 1e-7 * xn.abs().max(1.0);
+                (f.call(env, x, xn + h)? - f.call(env, x, xn - h)?) / (2.0 * h)
+            },
+        };
+        if dfx == 0.0 || !dfx.is_finite() {
+            break;
+        }
+        let step = fx / dfx;
+        xn -= step;
+        if !xn.is_finite() {
+            break;
+        }
+        if step.abs() <= 1e-14 * xn.abs().max(1.0) {
+            return Ok(xn);
+        }
+    }
+    Err(Error::NoSolution)
+}
+
+/// Integrates `expr` over `x` from `a` to `b` using adaptive Simpson
+/// quadrature.
+pub fn integrate(expr: &Expr, x: &str, a: f64, b: f64, env: &mut Environment) -> Result<f64> {
+    let f = Program::compile(expr);
+    with_var(env, x, |env| {
+        let (fa, fb) = (f.call(env, x, a)?, f.call(env, x, b)?);
+        let m = (a + b) / 2.0;
+        let fm = f.call(env, x, m)?;
+        let whole = simpson(a, b, fa, fm, fb);
+        let mut quad = Quadrature { f: &f, x, env };
+        quad.adapt((a, fa), (m, fm), (b, fb), whole, INTEGRATION_TOLERANCE, MAX_INTEGRATION_DEPTH)
+    })
+}
+
+fn simpson(a: f64, b: f64, fa: f64, fm: f64, fb: f64) -> f64 {
+    (b - a) / 6.0 * (fa + 4.0 * fm + fb)
+}
+
+struct Quadrature<'a> {
+    f: &'a Program,
+    x: &'a str,
+    env: &'a mut Environment,
+}
+
+impl Quadrature<'_> {
+    /// Refines the Simpson estimate `whole` over `[a, b]` until halving
+    /// the interval no longer changes the result by more than `tol`.
+    fn adapt(&mut self, a: (f64, f64), m: (f64, f64), b: (f64, f64), whole: f64, tol: f64, depth: u32) -> Result<f64> {
+        let lm = (a.0 + m.0) / 2.0;
+        let rm = (m.0 + b.0) / 2.0;
+        let flm = self.f.call(self.env, self.x, lm)?;
+        let frm = self.f.call(self.env, self.x, rm)?;
+        let left = simpson(a.0, m.0, a.1, flm, m.1);
+        let right = simpson(m.0, b.0, m.1, frm, b.1);
+        let delta = left + right - whole;
+
+        if depth == 0 || delta.abs() <= 15.0 * tol || !delta.is_finite() {
+            return Ok(left + right + delta / 15.0);
+        }
+        let left = self.adapt(a, (lm, flm), m, left, tol / 2.0, depth - 1)?;
+        let right = self.adapt(m, (rm, frm), b, right, tol / 2.0, depth - 1)?;
+        Ok(left + right)
+    }
+}
+
+/// Adds up (`OpKind::Plus`) or multiplies (`OpKind::Multiply`) the values
+/// of `expr` for every integer `i` from `from` to `to` inclusive.
+pub fn fold_range(expr: &Expr, i: &str, from: i64, to: i64, kind: OpKind, env: &mut Environment) -> Result<Value> {
+    let f = Program::compile(expr);
+    let mut acc = match kind {
+        OpKind::Multiply => Value::Int(1),
+        _ => Value::Int(0),
+    };
+    with_var(env, i, |env| {
+        for k in from..=to {
+            env.set(i, Value::Int(k));
+            let val = f.run(env)?;
+            acc = env.apply(kind, acc, val)?;
+        }
+        Ok(acc)
+    })
+}
+
+/// Evaluates a form argument that must produce an integer.
+pub fn integer_arg(arg: &Expr, env: &mut Environment) -> Result<i64> {
+    match arg.eval(env)? {
+        Value::Int(n) => Ok(n),
+        Value::Float(x) if x.fract() == 0.0 && x.abs() < i64::MAX as f64 => Ok(x as i64),
+        _ => Err(Error::TypeError),
+    }
+}
+
+/// Evaluates a form argument that must produce a number.
+pub fn number_arg(arg: &Expr, env: &mut Environment) -> Result<f64> {
+    match arg.eval(env)? {
+        Value::Int(n)   => Ok(n as f64),
+        Value::Float(x) => Ok(x),
+        _ => Err(Error::TypeError),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_findroot() {
+        let mut env = Environment::new();
+        let cbrt2 = env.eval("findroot(x^3 - 2, x, 1, 2)").unwrap().as_f64().unwrap();
+        assert!((cbrt2 - 2f64.cbrt()).abs() < 1e-12);
+
+        // No sign change over the interval, so this goes through Newton.
+        let r = env.eval("findroot(x^2 - 2, x, 1, 1.2)").unwrap().as_f64().unwrap();
+        assert!((r - 2f64.sqrt()).abs() < 1e-12);
+
+        let pi = env.eval("root(sin(x), 3, 4)").unwrap().as_f64().unwrap();
+        assert!((pi - std::f64::consts::PI).abs() < 1e-12);
+
+        env.set("x", Value::Int(7));
+        env.eval("root(x - 1, 0, 2)").unwrap();
+        assert_eq!(Some(&Value::Int(7)), env.get("x"));
+
+        assert!(matches!(env.eval("root(x^2 + 1, 0, 2)"), Err(Error::NoSolution)));
+    }
+
+    #[test]
+    fn test_integrate() {
+        let mut env = Environment::new();
+        let exprs = [
+            ("integrate(x^2, x, 0, 3)", 9.0),
+            ("integrate(sin(t), t, 0, 3.141592653589793)", 2.0),
+            ("integrate(1/x, x, 1, exp(1))", 1.0),
+            ("integrate(x, x, 2, 0)", -2.0),
+        ];
+        for (expr, ans) in exprs {
+            let val = env.eval(expr).unwrap().as_f64().unwrap();
+            assert!((val - ans).abs() < 1e-9, "{} = {}", expr, val);
+        }
+    }
+
+    #[test]
+    fn test_sum_prod() {
+        let mut env = Environment::new();
+        assert_eq!(Value::Int(5050), env.eval("sum(i, i, 1, 100)").unwrap());
+        assert_eq!(Value::Int(120), env.eval("prod(k, k, 1, 5)").unwrap());
+        assert_eq!(Value::Int(0), env.eval("sum(i, i, 1, 0)").unwrap());
+        assert_eq!(Value::Float(1.5), env.eval("sum(1/2^n, n, 0, 1)").unwrap());
+        assert!(matches!(env.eval("sum(i, i, 1, 2.5)"), Err(Error::TypeError)));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::env::Environment;
+use crate::kalkul::{Error, Expr, Result};
+use crate::numeric::with_var;
+use crate::program::{eval_over, Program};
+
+/// Renders an expression as a function of `x` over `[from, to]`.
+pub struct Plot {
+    pub from: f64,
+    pub to: f64,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Default for Plot {
+    fn default() -> Self {
+        Plot {
+            from: -10.0,
+            to: 10.0,
+            width: 72,
+            height: 20,
+        }
+    }
+}
+
+/// Most characters `ascii` draws, so that a mistyped `--width` cannot
+/// take all the memory there is.
+pub const MAX_CELLS: usize = 1_000_000;
+
+/// Number of points sampled for SVG output.
+const SVG_SAMPLES: usize = 500;
+const SVG_WIDTH: f64 = 640.0;
+const SVG_HEIGHT: f64 = 400.0;
+
+impl Plot {
+    /// Evaluates `expr` at `n` evenly spaced points. Points where the
+    /// function is undefined are NaN and show up as gaps.
+    pub fn sample(&self, expr: &Expr, x: &str, n: usize, env: &mut Environment) -> Result<Vec<(f64, f64)>> {
+        let f = Program::compile(expr);
+        let xs: Vec<f64> = (0..n).map(|i| {
+            if n > 1 {
+                self.from + (self.to - self.from) * i as f64 / (n - 1) as f64
+            } else {
+                self.from
+            }
+        }).collect();
+        // Most functions can be run over every point at once; failing
+        // that, each point is tried on its own so that one undefined
+        // point does not lose the rest.
+        if let Ok(ys) = eval_over(&f, x, &xs, env) {
+            return Ok(std::iter::zip(xs, ys).collect());
+        }
+        with_var(env, x, |env| {
+            let mut points = Vec::with_capacity(n);
+            let mut first_err = None;
+            for xv in xs {
+                let yv = match f.call(env, x, xv) {
+                    Ok(y) => y,
+                    Err(e) => {
+                        first_err.get_or_insert(e);
+                        f64::NAN
+                    },
+                };
+                points.push((xv, yv));
+            }
+            // A function that fails everywhere is most likely a mistake,
+            // such as an unknown variable, rather than a domain issue.
+            match first_err {
+                Some(e) if points.iter().all(|(_, y)| y.is_nan()) => Err(e),
+                _ => Ok(points),
+            }
+        })
+    }
+
+    fn y_range(points: &[(f64, f64)]) -> (f64, f64) {
+        let ys = points.iter().map(|(_, y)| *y).filter(|y| y.is_finite());
+        let (lo, hi) = ys.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), y| (lo.min(y), hi.max(y)));
+        if lo > hi {
+            (-1.0, 1.0)
+        } else if lo == hi {
+            (lo - 1.0, hi + 1.0)
+        } else {
+            (lo, hi)
+        }
+    }
+
+    /// Draws the function as a character grid with axes where they fall
+    /// inside the plotted area.
+    pub fn ascii(&self, expr: &Expr, x: &str, env: &mut Environment) -> Result<String> {
+        let cells = self.width.saturating_mul(self.height);
+        if cells > MAX_CELLS {
+            return Err(Error::TooMany("plot cells", cells as u64, MAX_CELLS as u64));
+        }
+        let points = self.sample(expr, x, self.width, env)?;
+        let (ylo, yhi) = Plot::y_range(&points);
+        let row_of = |y: f64| {
+            let t = (yhi - y) / (yhi - ylo);
+            (t * (self.height - 1) as f64).round() as usize
+        };
+
+        let mut grid = vec![vec![' '; self.width]; self.height];
+        if ylo <= 0.0 && 0.0 <= yhi {
+            let row = row_of(0.0);
+            for c in grid[row].iter_mut() {
+                *c = '-';
+            }
+        }
+        if self.from <= 0.0 && 0.0 <= self.to {
+            let col = ((0.0 - self.from) / (self.to - self.from) * (self.width - 1) as f64).round() as usize;
+            for row in grid.iter_mut() {
+                row[col] = if row[col] == '-' { '+' } else { '|' };
+            }
+        }
+        for (col, (_, y)) in points.iter().enumerate() {
+            if y.is_finite() {
+                grid[row_of(*y)][col] = '*';
+            }
+        }
+
+        let top = axis_label(yhi);
+        let bottom = axis_label(ylo);
+        let label_width = top.len().max(bottom.len());
+        let mut out = String::new();
+        for (i, row) in grid.iter().enumerate() {
+            let label = match i {
+                0 => &top,
+                _ if i == self.height - 1 => &bottom,
+                _ => "",
+            };
+            let line: String = row.iter().collect();
+            out.push_str(&format!("{:>w$} |{}\n", label, line.trim_end(), w = label_width));
+        }
+        out.push_str(&format!("{:>w$}  {}{:>r$}\n", "", self.from, self.to,
+            w = label_width, r = self.width.saturating_sub(self.from.to_string().len())));
+        Ok(out)
+    }
+
+    /// Renders the function as an SVG polyline, broken up where the
+    /// function is undefined.
+    pub fn svg(&self, expr: &Expr, x: &str, env: &mut Environment) -> Result<String> {
+        let points = self.sample(expr, x, SVG_SAMPLES, env)?;
+        let (ylo, yhi) = Plot::y_range(&points);
+        let sx = |x: f64| (x - self.from) / (self.to - self.from) * SVG_WIDTH;
+        let sy = |y: f64| (yhi - y) / (yhi - ylo) * SVG_HEIGHT;
+
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+            w = SVG_WIDTH, h = SVG_HEIGHT,
+        );
+        if ylo <= 0.0 && 0.0 <= yhi {
+            out.push_str(&format!("  <line x1=\"0\" y1=\"{y}\" x2=\"{w}\" y2=\"{y}\" stroke=\"gray\"/>\n", y = sy(0.0), w = SVG_WIDTH));
+        }
+        if self.from <= 0.0 && 0.0 <= self.to {
+            out.push_str(&format!("  <line x1=\"{x}\" y1=\"0\" x2=\"{x}\" y2=\"{h}\" stroke=\"gray\"/>\n", x = sx(0.0), h = SVG_HEIGHT));
+        }
+        for segment in points.split(|(_, y)| !y.is_finite()) {
+            if segment.len() < 2 {
+                continue;
+            }
+            let coords: Vec<String> = segment.iter()
+                .map(|(x, y)| format!("{:.2},{:.2}", sx(*x), sy(*y)))
+                .collect();
+            out.push_str(&format!("  <polyline fill=\"none\" stroke=\"black\" points=\"{}\"/>\n", coords.join(" ")));
+        }
+        out.push_str("</svg>\n");
+        Ok(out)
+    }
+}
+
+/// Formats an axis bound with at most three decimals.
+fn axis_label(y: f64) -> String {
+    let s = format!("{:.3}", y);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ascii_plot() {
+        let mut env = Environment::new();
+        let plot = Plot { from: -2.0, to: 2.0, width: 5, height: 5 };
+        let out = plot.ascii(&Expr::parse("x^2").unwrap(), "x", &mut env).unwrap();
+        let expected = [
+            "4 |* | *",
+            "  |  |",
+            "  |  |",
+            "  | *|*",
+            "0 |--*--",
+            "   -2  2",
+            "",
+        ];
+        assert_eq!(expected.join("\n"), out);
+
+        let plot = Plot { width: usize::MAX, ..Plot::default() };
+        let res = plot.ascii(&Expr::parse("x").unwrap(), "x", &mut env);
+        assert!(matches!(res, Err(Error::TooMany("plot cells", _, _))));
+    }
+
+    #[test]
+    fn test_svg_plot() {
+        let mut env = Environment::new();
+        let plot = Plot { from: -1.0, to: 1.0, ..Plot::default() };
+        // Undefined between -0.5 and 0.5, so drawn in two pieces.
+        let out = plot.svg(&Expr::parse("sqrt(x^2 - 0.25)").unwrap(), "x", &mut env).unwrap();
+        assert!(out.starts_with("<svg"));
+        assert_eq!(2, out.matches("<polyline").count());
+
+        assert!(plot.svg(&Expr::parse("y").unwrap(), "x", &mut env).is_err());
+    }
+}
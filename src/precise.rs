@@ -0,0 +1,589 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::env::Environment;
+use crate::format;
+use crate::kalkul::{Expr, OpKind, Value};
+
+/// Each limb holds nine decimal digits, so numbers print without any
+/// base conversion.
+const BASE: u64 = 1_000_000_000;
+
+/// Extra limbs carried beyond those needed for the requested digits, to
+/// absorb the rounding of intermediate steps.
+const GUARD_LIMBS: usize = 3;
+
+/// Largest number of digits `:digits` accepts.
+pub const MAX_DIGITS: usize = 1000;
+
+/// Largest power of `BASE` a number may be scaled by, either way. Past
+/// it ordinary evaluation takes over.
+const MAX_EXP: i64 = 1_000_000_000;
+
+/// A floating decimal: `limbs` is the mantissa in base 10^9, least
+/// significant limb first, and the number is the mantissa times
+/// `BASE ^ exp`. Tiny and huge numbers alike keep every digit asked for.
+#[derive(Debug, Clone)]
+struct Decimal {
+    neg: bool,
+    limbs: Vec<u32>,
+    exp: i64,
+}
+
+/// The number of limbs every mantissa in a calculation is rounded to.
+#[derive(Debug, Clone, Copy)]
+struct Ctx {
+    limbs: usize,
+}
+
+fn trim(mut v: Vec<u32>) -> Vec<u32> {
+    while v.last() == Some(&0) {
+        v.pop();
+    }
+    v
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    let (a, b) = (&a[..len(a)], &b[..len(b)]);
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+/// Length without leading zero limbs.
+fn len(v: &[u32]) -> usize {
+    v.len() - v.iter().rev().take_while(|l| **l == 0).count()
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let sum = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        out.push((sum % BASE) as u32);
+        carry = sum / BASE;
+    }
+    out.push(carry as u32);
+    trim(out)
+}
+
+/// `a - b`, for `a` at least `b`.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, x) in a.iter().enumerate() {
+        let mut diff = *x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = (diff < 0) as i64;
+        if diff < 0 {
+            diff += BASE as i64;
+        }
+        out.push(diff as u32);
+    }
+    trim(out)
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = vec![0u64; a.len() + b.len() + 1];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0;
+        for (j, y) in b.iter().enumerate() {
+            let cur = out[i + j] + *x as u64 * *y as u64 + carry;
+            out[i + j] = cur % BASE;
+            carry = cur / BASE;
+        }
+        out[i + b.len()] += carry;
+    }
+    trim(out.into_iter().map(|l| l as u32).collect())
+}
+
+fn div_small(a: &[u32], d: u32) -> Vec<u32> {
+    let mut out = vec![0; a.len()];
+    let mut rem = 0u64;
+    for i in (0..a.len()).rev() {
+        let cur = rem * BASE + a[i] as u64;
+        out[i] = (cur / d as u64) as u32;
+        rem = cur % d as u64;
+    }
+    trim(out)
+}
+
+impl Decimal {
+    fn zero() -> Decimal {
+        Decimal { neg: false, limbs: Vec::new(), exp: 0 }
+    }
+
+    fn is_zero(&self) -> bool {
+        len(&self.limbs) == 0
+    }
+
+    /// One past the power of `BASE` of the leading limb.
+    fn top(&self) -> i64 {
+        self.exp + len(&self.limbs) as i64
+    }
+
+    fn in_range(&self) -> bool {
+        self.exp.abs() <= MAX_EXP
+    }
+
+    /// Drops the zero limbs at either end, and the limbs past the
+    /// precision of `ctx` at the low end.
+    fn normalized(mut self, ctx: Ctx) -> Decimal {
+        self.limbs = trim(self.limbs);
+        let zeros = self.limbs.iter().take_while(|l| **l == 0).count();
+        let drop = zeros.max(self.limbs.len().saturating_sub(ctx.limbs));
+        self.limbs.drain(..drop);
+        self.exp += drop as i64;
+        if self.limbs.is_empty() {
+            return Decimal::zero();
+        }
+        self
+    }
+
+    /// The number times `BASE ^ k`.
+    fn scaled(mut self, k: i64) -> Decimal {
+        if !self.is_zero() {
+            self.exp += k;
+        }
+        self
+    }
+
+    fn from_int(n: i64, ctx: Ctx) -> Decimal {
+        let mut limbs = Vec::new();
+        let mut m = n.unsigned_abs();
+        while m > 0 {
+            limbs.push((m % BASE) as u32);
+            m /= BASE;
+        }
+        Decimal { neg: n < 0, limbs, exp: 0 }.normalized(ctx)
+    }
+
+    /// Reads a decimal such as `-1.5e-7`.
+    fn parse(text: &str, ctx: Ctx) -> Option<Decimal> {
+        let (neg, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (mantissa, exp) = match text.split_once('e') {
+            Some((m, e)) => (m, e.parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let mut digits: Vec<u8> = int.bytes().chain(frac.bytes()).map(|b| b.wrapping_sub(b'0')).collect();
+        if digits.iter().any(|d| *d > 9) {
+            return None;
+        }
+        // The digits as an integer times a power of ten, made a multiple
+        // of nine by moving digits into the integer.
+        let exp10 = exp.checked_sub(frac.len() as i64)?;
+        let shift = exp10.rem_euclid(9);
+        digits.extend(std::iter::repeat_n(0, shift as usize));
+        let limbs = digits.rchunks(9).map(|chunk| chunk.iter().fold(0, |acc, d| acc * 10 + *d as u32)).collect();
+        let x = Decimal { neg, limbs, exp: (exp10 - shift) / 9 }.normalized(ctx);
+        x.in_range().then_some(x)
+    }
+
+    /// The number as `m * BASE ^ k`, with `m` the leading limbs as a
+    /// float, for first guesses at numbers a float cannot hold.
+    fn split(&self) -> (f64, i64) {
+        let low = self.limbs.len().saturating_sub(3);
+        let m = self.limbs[low..].iter().rev().fold(0.0, |acc, l| acc * BASE as f64 + *l as f64);
+        (if self.neg { -m } else { m }, self.exp + low as i64)
+    }
+
+    fn to_f64(&self) -> f64 {
+        let (m, k) = self.split();
+        m * (BASE as f64).powi(k.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+
+    fn from_f64(x: f64, ctx: Ctx) -> Option<Decimal> {
+        if !x.is_finite() {
+            return None;
+        }
+        Decimal::parse(&format!("{:e}", x), ctx)
+    }
+
+    fn neg(mut self) -> Decimal {
+        self.neg = !self.neg && !self.is_zero();
+        self
+    }
+
+    /// The limbs of the mantissa scaled to `BASE ^ exp`, for `exp` no
+    /// more than that of the number.
+    fn limbs_at(&self, exp: i64) -> Vec<u32> {
+        let mut limbs = vec![0; (self.exp - exp) as usize];
+        limbs.extend_from_slice(&self.limbs);
+        limbs
+    }
+
+    fn add(&self, other: &Decimal, ctx: Ctx) -> Decimal {
+        if self.is_zero() || other.is_zero() {
+            return if self.is_zero() { other.clone() } else { self.clone() };
+        }
+        // A number that far below the other only changes digits that are
+        // rounded away, and lining the two up would take as many limbs.
+        let apart = self.top() - other.top();
+        if apart > ctx.limbs as i64 + 1 {
+            return self.clone();
+        }
+        if apart < -(ctx.limbs as i64) - 1 {
+            return other.clone();
+        }
+        let exp = self.exp.min(other.exp);
+        let (a, b) = (self.limbs_at(exp), other.limbs_at(exp));
+        let (neg, limbs) = if self.neg == other.neg {
+            (self.neg, add_mag(&a, &b))
+        } else {
+            match cmp_mag(&a, &b) {
+                Ordering::Less => (other.neg, sub_mag(&b, &a)),
+                Ordering::Equal => return Decimal::zero(),
+                Ordering::Greater => (self.neg, sub_mag(&a, &b)),
+            }
+        };
+        Decimal { neg, limbs, exp }.normalized(ctx)
+    }
+
+    fn sub(&self, other: &Decimal, ctx: Ctx) -> Decimal {
+        self.add(&other.clone().neg(), ctx)
+    }
+
+    fn mul(&self, other: &Decimal, ctx: Ctx) -> Decimal {
+        let limbs = mul_mag(&self.limbs, &other.limbs);
+        Decimal { neg: self.neg != other.neg, limbs, exp: self.exp + other.exp }.normalized(ctx)
+    }
+
+    fn div_small(&self, d: u32, ctx: Ctx) -> Decimal {
+        // Room below the mantissa for the digits the division brings.
+        let pad = (ctx.limbs + 1).saturating_sub(self.limbs.len());
+        let limbs = div_small(&self.limbs_at(self.exp - pad as i64), d);
+        Decimal { neg: self.neg, limbs, exp: self.exp - pad as i64 }.normalized(ctx)
+    }
+
+    /// Iterations of Newton's method enough to take a double's 15 good
+    /// digits to all of them, each doubling the digits that are right.
+    fn newton_steps(ctx: Ctx) -> usize {
+        let digits = ctx.limbs * 9;
+        (usize::BITS - (digits / 15).leading_zeros()) as usize + 2
+    }
+
+    fn recip(&self, ctx: Ctx) -> Option<Decimal> {
+        if self.is_zero() {
+            return None;
+        }
+        let (m, k) = self.split();
+        let mut x = Decimal::from_f64(1.0 / m, ctx)?.scaled(-k);
+        let two = Decimal::from_int(2, ctx);
+        for _ in 0..Decimal::newton_steps(ctx) {
+            x = x.mul(&two.sub(&self.mul(&x, ctx), ctx), ctx);
+        }
+        Some(x)
+    }
+
+    fn div(&self, other: &Decimal, ctx: Ctx) -> Option<Decimal> {
+        Some(self.mul(&other.recip(ctx)?, ctx))
+    }
+
+    /// The square root, as `a` times Newton's approximation of
+    /// `1 / sqrt(a)`, which needs no division.
+    fn sqrt(&self, ctx: Ctx) -> Option<Decimal> {
+        if self.neg {
+            return None;
+        }
+        if self.is_zero() {
+            return Some(Decimal::zero());
+        }
+        // An even power of `BASE`, whose root is exact.
+        let (mut m, mut k) = self.split();
+        if k % 2 != 0 {
+            m *= BASE as f64;
+            k -= 1;
+        }
+        let mut y = Decimal::from_f64(1.0 / m.sqrt(), ctx)?.scaled(-k / 2);
+        let three = Decimal::from_int(3, ctx);
+        for _ in 0..Decimal::newton_steps(ctx) {
+            let ayy = self.mul(&y, ctx).mul(&y, ctx);
+            y = y.mul(&three.sub(&ayy, ctx), ctx).div_small(2, ctx);
+        }
+        Some(self.mul(&y, ctx))
+    }
+
+    /// Whether adding `term` would leave the number as it is.
+    fn absorbs(&self, term: &Decimal, ctx: Ctx) -> bool {
+        term.is_zero() || term.top() + (ctx.limbs as i64) < self.top()
+    }
+
+    /// `e ^ self` by its Taylor series, after halving the argument until
+    /// the series converges quickly and squaring as often afterwards.
+    fn exp(&self, ctx: Ctx) -> Option<Decimal> {
+        let x = self.to_f64();
+        if x.abs() > 10000.0 {
+            return None;
+        }
+        let halvings = (x.abs().log2().max(0.0) as u32) + 8;
+        let mut r = self.clone();
+        for _ in 0..halvings {
+            r = r.div_small(2, ctx);
+        }
+        let mut sum = Decimal::from_int(1, ctx);
+        let mut term = Decimal::from_int(1, ctx);
+        for k in 1.. {
+            term = term.mul(&r, ctx).div_small(k, ctx);
+            if sum.absorbs(&term, ctx) {
+                break;
+            }
+            sum = sum.add(&term, ctx);
+        }
+        for _ in 0..halvings {
+            sum = sum.mul(&sum, ctx);
+        }
+        Some(sum)
+    }
+
+    /// The natural logarithm, as that of the number scaled to between 1
+    /// and `BASE` plus as many times `ln(BASE)`, so that `exp` is only
+    /// ever asked for small powers.
+    fn ln(&self, ctx: Ctx) -> Option<Decimal> {
+        if self.neg || self.is_zero() {
+            return None;
+        }
+        let k = self.top() - 1;
+        let y = self.clone().scaled(-k).ln_newton(ctx)?;
+        if k == 0 {
+            return Some(y);
+        }
+        let ln_base = Decimal::from_int(BASE as i64, ctx).ln_newton(ctx)?;
+        Some(y.add(&ln_base.mul(&Decimal::from_int(k, ctx), ctx), ctx))
+    }
+
+    /// The natural logarithm by Newton's method on `exp`.
+    fn ln_newton(&self, ctx: Ctx) -> Option<Decimal> {
+        let mut y = Decimal::from_f64(self.to_f64().ln(), ctx)?;
+        for _ in 0..Decimal::newton_steps(ctx) {
+            let ey = y.exp(ctx)?;
+            let step = self.sub(&ey, ctx).div(&self.add(&ey, ctx), ctx)?;
+            y = y.add(&step.add(&step, ctx), ctx);
+        }
+        Some(y)
+    }
+
+    /// `atan(1 / n)` by its series.
+    fn atan_inv(n: u32, ctx: Ctx) -> Decimal {
+        let mut power = Decimal::from_int(1, ctx).div_small(n, ctx);
+        let mut sum = power.clone();
+        for k in 1.. {
+            power = power.div_small(n, ctx).div_small(n, ctx);
+            let term = power.div_small(2 * k + 1, ctx);
+            if sum.absorbs(&term, ctx) {
+                break;
+            }
+            sum = if k % 2 == 1 { sum.sub(&term, ctx) } else { sum.add(&term, ctx) };
+        }
+        sum
+    }
+
+    /// Machin's formula, `pi = 16 atan(1/5) - 4 atan(1/239)`.
+    fn pi(ctx: Ctx) -> Decimal {
+        let a = Decimal::atan_inv(5, ctx);
+        let b = Decimal::atan_inv(239, ctx);
+        let sixteen = Decimal::from_int(16, ctx);
+        let four = Decimal::from_int(4, ctx);
+        sixteen.mul(&a, ctx).sub(&four.mul(&b, ctx), ctx)
+    }
+
+    fn powi(&self, mut e: u64, ctx: Ctx) -> Option<Decimal> {
+        let mut base = self.clone();
+        let mut acc = Decimal::from_int(1, ctx);
+        while e > 0 {
+            if e & 1 == 1 {
+                acc = acc.mul(&base, ctx);
+            }
+            e >>= 1;
+            if e > 0 {
+                base = base.mul(&base, ctx);
+            }
+            if !acc.in_range() || !base.in_range() {
+                return None;
+            }
+        }
+        Some(acc)
+    }
+
+    fn pow(&self, exp: &Decimal, ctx: Ctx) -> Option<Decimal> {
+        let e = exp.to_f64();
+        if e.fract() == 0.0 && e.abs() <= 1e6 {
+            let p = self.powi(e.abs() as u64, ctx)?;
+            return if e < 0.0 { Decimal::from_int(1, ctx).div(&p, ctx) } else { Some(p) };
+        }
+        exp.mul(&self.ln(ctx)?, ctx).exp(ctx)
+    }
+
+    /// Writes the number rounded to `digits` significant digits, without
+    /// trailing zeros after the point. Numbers with more digits than that
+    /// before the point, or more zeros after it, get an exponent.
+    fn to_string(&self, digits: usize) -> String {
+        if self.is_zero() {
+            return "0".to_string();
+        }
+        let mut all: Vec<u8> = Vec::new();
+        for limb in self.limbs.iter().rev() {
+            all.extend(format!("{:09}", limb).bytes().map(|b| b - b'0'));
+        }
+        // Digits before the point, counting from the first of `all`.
+        let mut point = all.len() as i64 + self.exp * 9;
+        let first = all.iter().position(|d| *d != 0).unwrap_or(0);
+        let keep = first + digits;
+        let round_up = all.get(keep).is_some_and(|d| *d >= 5);
+        all.truncate(keep);
+        if round_up {
+            let mut i = all.len();
+            loop {
+                if i == 0 {
+                    all.insert(0, 1);
+                    point += 1;
+                    break;
+                }
+                i -= 1;
+                if all[i] == 9 {
+                    all[i] = 0;
+                } else {
+                    all[i] += 1;
+                    break;
+                }
+            }
+        }
+        let zeros = all.iter().take_while(|d| **d == 0).count();
+        all.drain(..zeros);
+        point -= zeros as i64;
+        while all.last() == Some(&0) {
+            all.pop();
+        }
+
+        let text = |ds: &[u8]| ds.iter().map(|d| (d + b'0') as char).collect::<String>();
+        let sign = if self.neg { "-" } else { "" };
+        if point > digits as i64 || point < -(digits as i64) {
+            let mantissa = match all.split_first() {
+                Some((d, [])) => text(&[*d]),
+                Some((d, rest)) => format!("{}.{}", d, text(rest)),
+                None => "0".to_string(),
+            };
+            return format!("{}{}e{}", sign, mantissa, point - 1);
+        }
+        if point <= 0 {
+            return format!("{}0.{}{}", sign, "0".repeat(-point as usize), text(&all));
+        }
+        let point = point as usize;
+        if all.len() <= point {
+            all.resize(point, 0);
+            return format!("{}{}", sign, text(&all));
+        }
+        format!("{}{}.{}", sign, text(&all[..point]), text(&all[point..]))
+    }
+}
+
+fn eval(e: &Expr, env: &Environment, exact: &HashMap<String, String>, ctx: Ctx) -> Option<Decimal> {
+    let value = |val: &Value| match val {
+        Value::Int(n) => Some(Decimal::from_int(*n, ctx)),
+        // The shortest digits that read back as the float, so `0.1` is
+        // exactly one tenth.
+        Value::Float(x) if x.is_finite() => Decimal::parse(&format::shortest(*x), ctx),
+        _ => None,
+    };
+    match e {
+        Expr::Lit(val) => value(val),
+        Expr::Neg(a) => Some(eval(a, env, exact, ctx)?.neg()),
+        Expr::Binary(kind, a, b) => {
+            let (a, b) = (eval(a, env, exact, ctx)?, eval(b, env, exact, ctx)?);
+            let x = match kind {
+                OpKind::Plus     => a.add(&b, ctx),
+                OpKind::Minus    => a.sub(&b, ctx),
+                OpKind::Multiply => a.mul(&b, ctx),
+                OpKind::Divide   => a.div(&b, ctx)?,
+                OpKind::Power    => a.pow(&b, ctx)?,
+                _ => return None,
+            };
+            x.in_range().then_some(x)
+        },
+        Expr::Var(name) => match (env.get(name), name.as_str()) {
+            // Still holding the float a result with more digits was
+            // rounded to.
+            (Some(Value::Float(x)), _) if exact.get(name).and_then(|t| t.parse().ok()) == Some(*x) => {
+                Decimal::parse(&exact[name], ctx)
+            },
+            (Some(val), _) => value(val),
+            (None, "pi") => Some(Decimal::pi(ctx)),
+            (None, "e")  => Decimal::from_int(1, ctx).exp(ctx),
+            _ => None,
+        },
+        Expr::Call(name, args) if args.len() == 1 && env.function(name).is_none() => {
+            let x = eval(&args[0], env, exact, ctx)?;
+            match name.as_str() {
+                "sqrt" => x.sqrt(ctx),
+                "exp"  => x.exp(ctx),
+                "ln"   => x.ln(ctx),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Evaluates `e` to `digits` significant digits, for `:digits`. Only
+/// arithmetic, `pi`, `e`, `sqrt`, `exp` and `ln` of numbers are
+/// understood; anything else, and anything without a real result such
+/// as `sqrt(-1)` or `1 / 0`, gives `None` for ordinary evaluation to
+/// handle. `exact` has the digits of earlier results by the names of the
+/// variables holding them as floats.
+pub fn eval_digits(e: &Expr, digits: usize, env: &Environment, exact: &HashMap<String, String>) -> Option<String> {
+    // A limb more than the digits take, for those lost to leading zeros
+    // in the first limb.
+    let ctx = Ctx { limbs: digits.div_ceil(9) + 1 + GUARD_LIMBS };
+    Some(eval(e, env, exact, ctx)?.to_string(digits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_digits() {
+        let exprs = ["pi", "sqrt(2)", "exp(1)", "ln(10)", "1 / 3", "0.1 + 0.2", "2 ^ 0.5", "-pi * 10 ^ 60", "sqrt(4)", "2 ^ 100", "e ^ -10"];
+        let answers = [
+            "3.1415926535897932384626433832795028841971693993751",
+            "1.4142135623730950488016887242096980785696718753769",
+            "2.7182818284590452353602874713526624977572470937",
+            "2.3025850929940456840179914546843642076011014886288",
+            "0.33333333333333333333333333333333333333333333333333",
+            "0.3",
+            "1.4142135623730950488016887242096980785696718753769",
+            "-3.1415926535897932384626433832795028841971693993751e60",
+            "2",
+            "1267650600228229401496703205376",
+            "0.000045399929762484851535591515560550610237918088866565",
+        ];
+        let env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            let e = env.parse(expr).unwrap();
+            assert_eq!(Some(ans.to_string()), eval_digits(&e, 50, &env, &HashMap::new()), "{}", expr);
+        }
+        for expr in ["sqrt(-1)", "1 / 0", "ln(0)", "x + 1", "sin(1)", "1 < 2"] {
+            assert_eq!(None, eval_digits(&env.parse(expr).unwrap(), 50, &env, &HashMap::new()), "{}", expr);
+        }
+
+        let exprs = ["sqrt(1e200)", "sqrt(1e-300)", "2 ^ -200", "1e-60 / 3", "sqrt(2e100)", "ln(1e300)", "exp(-1000)"];
+        let answers = [
+            "1e100",
+            "1e-150",
+            "6.2230152778611417071440640537801242405902521687212e-61",
+            "3.3333333333333333333333333333333333333333333333333e-61",
+            "1.4142135623730950488016887242096980785696718753769e50",
+            "690.77552789821370520539743640530926228033044658863",
+            "5.0759588975494567652918094795743369193055992828928e-435",
+        ];
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            let e = env.parse(expr).unwrap();
+            assert_eq!(Some(ans.to_string()), eval_digits(&e, 50, &env, &HashMap::new()), "{}", expr);
+        }
+
+        let e = env.parse("pi").unwrap();
+        assert_eq!(Some("3.14".to_string()), eval_digits(&e, 3, &env, &HashMap::new()));
+        let pi = eval_digits(&e, 1000, &env, &HashMap::new()).unwrap();
+        assert!(pi.len() == 1001 && pi.ends_with("9216420199"));
+    }
+}
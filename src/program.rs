@@ -0,0 +1,871 @@
+use crate::builtins;
+use crate::cbor::Cbor;
+use crate::env::Environment;
+use crate::kalkul::{Dialect, Error, Expr, OpKind, Result, Unknown, Value};
+use crate::net::Addr;
+use crate::numeric::with_var;
+use crate::symbol::SymbolId;
+use crate::symbolic;
+use crate::units::{self, Quantity};
+
+/// Builtins whose results depend on more than their arguments and the
+/// variables in them, so they are never cached.
+const IMPURE: &[&str] = &["store", "recall", "now"];
+
+/// A single step of a compiled program, operating on a value stack.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Instr {
+    Push(Value),
+    Load(String),
+    /// Loads a variable by the id it has in the environment the program
+    /// was linked to, see `Program::link`.
+    LoadSymbol(SymbolId),
+    Neg,
+    Binary(OpKind),
+    /// Calls a builtin with the given number of arguments from the stack.
+    Call(String, usize),
+    /// Forms need their arguments unevaluated and are left to the tree
+    /// walking evaluator.
+    Eval(Expr),
+}
+
+/// An expression compiled to postfix order, for evaluating the same
+/// expression many times without walking the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    code: Vec<Instr>,
+}
+
+impl Program {
+    pub fn compile(expr: &Expr) -> Program {
+        let mut code = Vec::new();
+        emit(expr, &mut code);
+        Program { code }
+    }
+
+    pub fn code(&self) -> &[Instr] {
+        &self.code
+    }
+
+    /// The program with its variables looked up by their ids in `env`
+    /// instead of by name, for running it many times in that
+    /// environment, one with thousands of variables say. Running it in
+    /// another environment reads the wrong variables.
+    pub fn link(&self, env: &mut Environment) -> Program {
+        let code = self.code.iter().map(|instr| match instr {
+            Instr::Load(name) => Instr::LoadSymbol(env.intern(name)),
+            instr => instr.clone(),
+        }).collect();
+        Program { code }
+    }
+
+    pub fn run(&self, env: &mut Environment) -> Result<Value> {
+        let mut stack: Vec<Value> = Vec::new();
+        for instr in &self.code {
+            env.tick()?;
+            let val = match instr {
+                Instr::Push(v) => v.clone(),
+                Instr::Load(name) => env.read(name)?,
+                Instr::LoadSymbol(id) => env.read_symbol(*id)?,
+                Instr::Neg => {
+                    env.check_allowed(OpKind::Negate.symbol())?;
+                    let val = stack.pop().ok_or(Error::StackUnderflow)?;
+                    env.negate(val)?
+                },
+                Instr::Binary(kind) => {
+                    env.check_allowed(kind.symbol())?;
+                    let rhs = stack.pop().ok_or(Error::StackUnderflow)?;
+                    let lhs = stack.pop().ok_or(Error::StackUnderflow)?;
+                    env.apply(*kind, lhs, rhs)?
+                },
+                Instr::Call(name, argc) => {
+                    if stack.len() < *argc {
+                        return Err(Error::StackUnderflow);
+                    }
+                    let args = stack.split_off(stack.len() - argc);
+                    builtins::call(name, &args, env)?
+                },
+                Instr::Eval(e) => e.eval(env)?,
+            };
+            stack.push(env.check_value(val)?);
+            env.note_depth(stack.len());
+        }
+
+        match (stack.pop(), stack.is_empty()) {
+            (Some(val), true) => Ok(val),
+            _ => Err(Error::StackUnderflow),
+        }
+    }
+
+    /// Runs the program with the variable `x` set to `val`, for use by
+    /// numeric methods that treat the program as a function of `x`.
+    pub fn call(&self, env: &mut Environment, x: &str, val: f64) -> Result<f64> {
+        env.set(x, Value::Float(val));
+        self.run(env)?.as_f64()
+    }
+
+    /// The program in a compact binary form, for keeping compiled
+    /// formulas in a cache: `MAGIC`, the version of the encoding as a
+    /// byte, then as CBOR the version of kalkul writing it and the
+    /// instructions. Linked programs, and those holding functions or
+    /// times as constants, cannot be encoded and give `Error::TypeError`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let code = self.code.iter().map(encode_instr).collect::<Result<_>>()?;
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION);
+        Cbor::Array(vec![Cbor::text(env!("CARGO_PKG_VERSION")), Cbor::Array(code)]).encode(&mut bytes);
+        Ok(bytes)
+    }
+
+    /// Reads a program written by `to_bytes` of this or an earlier
+    /// version of kalkul. Programs from a later version, whose encoding
+    /// this one does not know, give `Error::IncompatibleVersion`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program> {
+        let (&format, body) = bytes.strip_prefix(MAGIC).and_then(<[u8]>::split_first).ok_or(Error::ParseError)?;
+        let body = Cbor::decode(body)?;
+        if !(1..=FORMAT_VERSION).contains(&format) {
+            // Every later encoding starts with the version of kalkul too.
+            let by = match body.as_array().ok().and_then(|items| items.first()) {
+                Some(Cbor::Text(version)) => version.clone(),
+                _ => format!("format {}", format),
+            };
+            return Err(Error::IncompatibleVersion(by));
+        }
+        let body = (format..FORMAT_VERSION).try_fold(body, upgrade)?;
+        let [_, code] = body.as_array()? else {
+            return Err(Error::ParseError);
+        };
+        let code = code.as_array()?.iter().map(decode_instr).collect::<Result<_>>()?;
+        Ok(Program { code })
+    }
+}
+
+type Unary = fn(f64) -> f64;
+
+/// Builtins of one float, which `eval_over` and `compile_fn` call directly.
+const ELEMENTWISE: &[(&str, Unary)] = &[
+    ("sqrt", f64::sqrt),
+    ("exp",  f64::exp),
+    ("ln",   f64::ln),
+    ("sin",  f64::sin),
+    ("cos",  f64::cos),
+    ("tan",  f64::tan),
+];
+
+/// A value on the stack of `run_columns`: either the same for every `x`,
+/// or one float per `x`.
+enum Lane {
+    Scalar(Value),
+    Column(Vec<f64>),
+}
+
+/// Runs `program` as a function of `x` for every value in `xs`, giving
+/// the same results as calling it on each in turn, leaving `x` as it was.
+///
+/// Programs of arithmetic and the elementwise builtins are run one
+/// instruction at a time over all of `xs` at once, in tight loops over
+/// floats that the compiler can vectorize, counting a step per
+/// instruction. The parts that do not depend on `x` are computed just
+/// once. Anything else is run for each value separately.
+pub fn eval_over(program: &Program, x: &str, xs: &[f64], env: &mut Environment) -> Result<Vec<f64>> {
+    with_var(env, x, |env| {
+        if let Some(ys) = run_columns(program, x, xs, env) {
+            return ys;
+        }
+        xs.iter().map(|val| program.call(env, x, *val)).collect()
+    })
+}
+
+/// The columnar half of `eval_over`, or `None` for programs it cannot
+/// run, found out before anything with side effects could happen.
+fn run_columns(program: &Program, x: &str, xs: &[f64], env: &mut Environment) -> Option<Result<Vec<f64>>> {
+    // Python raises on division by zero, which a column cannot, and
+    // audits and restrictions want every operation on its own.
+    let options = env.options();
+    if options.syntax.dialect == Dialect::Python || options.allowed.is_some() || env.auditing() {
+        return None;
+    }
+    let columnar = program.code.iter().all(|instr| match instr {
+        Instr::Push(_) | Instr::Load(_) | Instr::LoadSymbol(_) | Instr::Neg => true,
+        Instr::Binary(kind) => matches!(kind, OpKind::Plus | OpKind::Minus | OpKind::Multiply | OpKind::Divide | OpKind::Power),
+        Instr::Call(name, 1) => ELEMENTWISE.iter().any(|(f, _)| f == name),
+        Instr::Call(..) | Instr::Eval(_) => false,
+    });
+    if !columnar {
+        return None;
+    }
+
+    let strict = env.options().strict_float;
+    let check = |ys: Vec<f64>| {
+        if strict && !ys.iter().all(|y| y.is_finite()) { Err(Error::NotFinite) } else { Ok(Lane::Column(ys)) }
+    };
+    let mut stack: Vec<Lane> = Vec::new();
+    for instr in &program.code {
+        if let Err(e) = env.tick() {
+            return Some(Err(e));
+        }
+        let lane = match instr {
+            Instr::Push(v) => Ok(Lane::Scalar(v.clone())),
+            Instr::Load(name) if name == x => Ok(Lane::Column(xs.to_vec())),
+            Instr::Load(name) => env.read(name).map(Lane::Scalar),
+            Instr::LoadSymbol(id) if env.symbol_name(*id) == x => Ok(Lane::Column(xs.to_vec())),
+            Instr::LoadSymbol(id) => env.read_symbol(*id).map(Lane::Scalar),
+            Instr::Neg => match stack.pop()? {
+                Lane::Scalar(v)  => env.negate(v).map(Lane::Scalar),
+                Lane::Column(ys) => check(ys.into_iter().map(|y| -y).collect()),
+            },
+            Instr::Binary(kind) => {
+                let (rhs, lhs) = (stack.pop()?, stack.pop()?);
+                let op: fn(f64, f64) -> f64 = match kind {
+                    OpKind::Plus     => |a, b| a + b,
+                    OpKind::Minus    => |a, b| a - b,
+                    OpKind::Multiply => |a, b| a * b,
+                    OpKind::Divide   => |a, b| a / b,
+                    _                => f64::powf,
+                };
+                // Only a number can meet a column; anything else, such as
+                // a quantity, is left to the general evaluator.
+                match (lhs, rhs) {
+                    (Lane::Scalar(a), Lane::Scalar(b)) => env.apply(*kind, a, b).map(Lane::Scalar),
+                    (Lane::Column(a), Lane::Column(b)) => check(std::iter::zip(a, b).map(|(a, b)| op(a, b)).collect()),
+                    (Lane::Column(a), Lane::Scalar(b)) => {
+                        let b = scalar_f64(&b)?;
+                        check(a.into_iter().map(|a| op(a, b)).collect())
+                    },
+                    (Lane::Scalar(a), Lane::Column(b)) => {
+                        let a = scalar_f64(&a)?;
+                        check(b.into_iter().map(|b| op(a, b)).collect())
+                    },
+                }
+            },
+            Instr::Call(name, _) => {
+                let (_, f) = ELEMENTWISE.iter().find(|(f, _)| f == name)?;
+                match stack.pop()? {
+                    Lane::Scalar(v)  => builtins::call(name, &[v], env).map(Lane::Scalar),
+                    Lane::Column(ys) => check(ys.into_iter().map(f).collect()),
+                }
+            },
+            Instr::Eval(_) => return None,
+        };
+        match lane.and_then(|lane| match lane {
+            Lane::Scalar(v) => env.check_value(v).map(Lane::Scalar),
+            column => Ok(column),
+        }) {
+            Ok(lane) => stack.push(lane),
+            Err(e) => return Some(Err(e)),
+        }
+    }
+
+    let res = match (stack.pop(), stack.is_empty()) {
+        (Some(Lane::Column(ys)), true) => Ok(ys),
+        // An expression without `x` has the same value everywhere.
+        (Some(Lane::Scalar(v)), true) => v.as_f64().map(|y| vec![y; xs.len()]),
+        _ => Err(Error::StackUnderflow),
+    };
+    Some(res)
+}
+
+fn scalar_f64(val: &Value) -> Option<f64> {
+    match val {
+        Value::Int(n)   => Some(*n as f64),
+        Value::Float(x) => Some(*x),
+        _ => None,
+    }
+}
+
+/// A compiled piece of a program, reading the variables by position.
+type Closure = Box<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+/// Compiles an expression of `vars` into a plain function of their
+/// values, for running it far more often than `Program::run` could:
+/// `compile_fn(&expr, &["x", "y"])?([1.0, 2.0])`.
+///
+/// The program is turned into nested closures, one per instruction,
+/// with the constant parts folded and small whole powers multiplied
+/// out. Everything is done in floats, so there are no integer results,
+/// options or errors along the way; expressions with anything other
+/// than arithmetic and the elementwise builtins are a `TypeError`.
+pub fn compile_fn<const N: usize>(expr: &Expr, vars: &[&str; N]) -> Result<impl Fn([f64; N]) -> f64 + Send + Sync + use<N>> {
+    let f = Program::compile(expr).specialize(vars)?;
+    Ok(move |args: [f64; N]| f(&args))
+}
+
+impl Program {
+    fn specialize(&self, vars: &[&str]) -> Result<Closure> {
+        // Each entry is the closure with its value if that is constant.
+        let mut stack: Vec<(Closure, Option<f64>)> = Vec::new();
+        for instr in &self.code {
+            let entry: (Closure, Option<f64>) = match instr {
+                Instr::Push(v) => {
+                    let c = scalar_f64(v).ok_or(Error::TypeError)?;
+                    (Box::new(move |_| c), Some(c))
+                },
+                Instr::Load(name) => match vars.iter().position(|v| v == name) {
+                    Some(i) => (Box::new(move |args| args[i]), None),
+                    None => return Err(Error::UnknownVariable(Unknown::new(name, vars.iter().copied().map(String::from)))),
+                },
+                Instr::Neg => {
+                    let (f, c) = stack.pop().ok_or(Error::StackUnderflow)?;
+                    match c {
+                        Some(c) => (Box::new(move |_| -c), Some(-c)),
+                        None => (Box::new(move |args| -f(args)), None),
+                    }
+                },
+                Instr::Binary(kind) => {
+                    let (g, b) = stack.pop().ok_or(Error::StackUnderflow)?;
+                    let (f, a) = stack.pop().ok_or(Error::StackUnderflow)?;
+                    let op: fn(f64, f64) -> f64 = match kind {
+                        OpKind::Plus     => |a, b| a + b,
+                        OpKind::Minus    => |a, b| a - b,
+                        OpKind::Multiply => |a, b| a * b,
+                        OpKind::Divide   => |a, b| a / b,
+                        OpKind::Power    => f64::powf,
+                        _ => return Err(Error::TypeError),
+                    };
+                    match (a, b) {
+                        (Some(a), Some(b)) => {
+                            let c = op(a, b);
+                            (Box::new(move |_| c), Some(c))
+                        },
+                        (_, Some(2.0)) if *kind == OpKind::Power => (Box::new(move |args| {
+                            let a = f(args);
+                            a * a
+                        }), None),
+                        (_, Some(3.0)) if *kind == OpKind::Power => (Box::new(move |args| {
+                            let a = f(args);
+                            a * a * a
+                        }), None),
+                        (None, Some(b)) => (Box::new(move |args| op(f(args), b)), None),
+                        (Some(a), None) => (Box::new(move |args| op(a, g(args))), None),
+                        (None, None) => (Box::new(move |args| op(f(args), g(args))), None),
+                    }
+                },
+                Instr::Call(name, 1) => {
+                    let &(_, func) = ELEMENTWISE.iter().find(|(f, _)| f == name).ok_or(Error::TypeError)?;
+                    let (f, c) = stack.pop().ok_or(Error::StackUnderflow)?;
+                    match c {
+                        Some(c) => {
+                            let c = func(c);
+                            (Box::new(move |_| c), Some(c))
+                        },
+                        None => (Box::new(move |args| func(f(args))), None),
+                    }
+                },
+                // Linked programs only make sense in their environment.
+                Instr::LoadSymbol(_) | Instr::Call(..) | Instr::Eval(_) => return Err(Error::TypeError),
+            };
+            stack.push(entry);
+        }
+        match (stack.pop(), stack.is_empty()) {
+            (Some((f, _)), true) => Ok(f),
+            _ => Err(Error::StackUnderflow),
+        }
+    }
+}
+
+/// An expression that remembers the values of its subexpressions, so
+/// that rerunning it after some variables change only recomputes the
+/// parts that use them, like a spreadsheet recalculation.
+///
+/// Cached values are keyed by the variables they depend on; changing the
+/// evaluation options calls for `clear`.
+#[derive(Debug, Clone)]
+pub struct Memoized {
+    root: Node,
+    hits: u64,
+}
+
+#[derive(Debug, Clone)]
+enum NodeKind {
+    Lit(Value),
+    Var(String),
+    Neg,
+    Binary(OpKind),
+    Call(String),
+    /// Left to the tree walking evaluator, like `Instr::Eval`.
+    Form(Expr),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    kind: NodeKind,
+    children: Vec<Node>,
+    /// Variables the value depends on, or `None` if it is not cached.
+    deps: Option<Vec<String>>,
+    /// The values `deps` had when `value` was computed.
+    cache: Option<(Vec<Option<Value>>, Value)>,
+}
+
+impl Memoized {
+    pub fn new(expr: &Expr) -> Memoized {
+        Memoized { root: Node::new(expr), hits: 0 }
+    }
+
+    pub fn run(&mut self, env: &mut Environment) -> Result<Value> {
+        self.root.run(env, &mut self.hits)
+    }
+
+    /// Number of times a cached value was used instead of recomputing it.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Forgets every cached value.
+    pub fn clear(&mut self) {
+        self.root.clear();
+    }
+}
+
+impl Node {
+    fn new(expr: &Expr) -> Node {
+        let (kind, children) = match expr {
+            Expr::Lit(v) => (NodeKind::Lit(v.clone()), Vec::new()),
+            Expr::Var(name) => (NodeKind::Var(name.clone()), Vec::new()),
+            Expr::Neg(e) => (NodeKind::Neg, vec![Node::new(e)]),
+            Expr::Binary(OpKind::Arrow | OpKind::Range | OpKind::Step | OpKind::In, _, _) | Expr::List(_) | Expr::Tuple(_) => {
+                (NodeKind::Form(expr.clone()), Vec::new())
+            },
+            Expr::Binary(kind, lhs, rhs) => (NodeKind::Binary(*kind), vec![Node::new(lhs), Node::new(rhs)]),
+            Expr::Call(name, _) if builtins::lookup_form(name).is_some() => (NodeKind::Form(expr.clone()), Vec::new()),
+            Expr::Call(name, args) => (NodeKind::Call(name.clone()), args.iter().map(Node::new).collect()),
+        };
+        // Looking up a literal or a variable is as cheap as checking a cache.
+        let deps = match expr {
+            Expr::Lit(_) | Expr::Var(_) => None,
+            _ if !is_pure(expr) => None,
+            _ => {
+                let mut deps = Vec::new();
+                symbolic::variables(expr, &mut deps);
+                Some(deps)
+            },
+        };
+        Node { kind, children, deps, cache: None }
+    }
+
+    fn run(&mut self, env: &mut Environment, hits: &mut u64) -> Result<Value> {
+        let key: Option<Vec<Option<Value>>> = self.deps.as_ref()
+            .map(|deps| deps.iter().map(|name| env.get(name).cloned()).collect());
+        if let (Some(key), Some((cached, val))) = (&key, &self.cache) {
+            if key == cached {
+                *hits += 1;
+                return Ok(val.clone());
+            }
+        }
+
+        let val = match &self.kind {
+            NodeKind::Form(e) => e.eval(env)?,
+            kind => {
+                env.tick()?;
+                let mut args = Vec::with_capacity(self.children.len());
+                for child in &mut self.children {
+                    args.push(child.run(env, hits)?);
+                }
+                let val = match kind {
+                    NodeKind::Lit(v) => v.clone(),
+                    NodeKind::Var(name) => env.read(name)?,
+                    NodeKind::Neg => {
+                        env.check_allowed(OpKind::Negate.symbol())?;
+                        env.negate(args.remove(0))?
+                    },
+                    NodeKind::Binary(kind) => {
+                        env.check_allowed(kind.symbol())?;
+                        let rhs = args.pop().ok_or(Error::StackUnderflow)?;
+                        let lhs = args.pop().ok_or(Error::StackUnderflow)?;
+                        env.apply(*kind, lhs, rhs)?
+                    },
+                    NodeKind::Call(name) => builtins::call(name, &args, env)?,
+                    NodeKind::Form(_) => unreachable!(),
+                };
+                env.check_value(val)?
+            },
+        };
+        if let Some(key) = key {
+            self.cache = Some((key, val.clone()));
+        }
+        Ok(val)
+    }
+
+    fn clear(&mut self) {
+        self.cache = None;
+        for child in &mut self.children {
+            child.clear();
+        }
+    }
+}
+
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(_) | Expr::Var(_) => true,
+        Expr::Neg(e) => is_pure(e),
+        Expr::Binary(_, lhs, rhs) => is_pure(lhs) && is_pure(rhs),
+        Expr::List(items) | Expr::Tuple(items) => items.iter().all(is_pure),
+        // User-defined functions may read variables other than their arguments.
+        Expr::Call(name, args) => {
+            let builtin = builtins::lookup(name).is_some() || builtins::lookup_form(name).is_some();
+            builtin && !IMPURE.contains(&name.as_str()) && args.iter().all(is_pure)
+        },
+    }
+}
+
+fn emit(expr: &Expr, code: &mut Vec<Instr>) {
+    match expr {
+        Expr::Lit(v) => code.push(Instr::Push(v.clone())),
+        Expr::Var(name) => code.push(Instr::Load(name.clone())),
+        Expr::Neg(e) => {
+            emit(e, code);
+            code.push(Instr::Neg);
+        },
+        // Lambdas, ranges, lists and tuples are left to the tree walking
+        // evaluator.
+        Expr::Binary(OpKind::Arrow | OpKind::Range | OpKind::Step | OpKind::In, _, _) | Expr::List(_) | Expr::Tuple(_) => {
+            code.push(Instr::Eval(expr.clone()))
+        },
+        Expr::Binary(kind, lhs, rhs) => {
+            emit(lhs, code);
+            emit(rhs, code);
+            code.push(Instr::Binary(*kind));
+        },
+        Expr::Call(name, _) if builtins::lookup_form(name).is_some() => {
+            code.push(Instr::Eval(expr.clone()));
+        },
+        Expr::Call(name, args) => {
+            for arg in args {
+                emit(arg, code);
+            }
+            code.push(Instr::Call(name.clone(), args.len()));
+        },
+    }
+}
+
+/// Starts every program encoded by `Program::to_bytes`.
+pub const MAGIC: &[u8] = b"KLKP";
+
+/// The version of the encoding `Program::to_bytes` writes. Programs in
+/// an earlier one are brought up to date by `upgrade` as they are read.
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Brings a program encoded in the format `from` up to the next one.
+fn upgrade(body: Cbor, from: u8) -> Result<Cbor> {
+    match from {
+        // Version 1 had just the instructions.
+        1 => Ok(Cbor::Array(vec![Cbor::text("0.1.0"), body])),
+        _ => Err(Error::IncompatibleVersion(format!("format {}", from))),
+    }
+}
+
+/// Binary operators, which are encoded by name.
+const BINARY_OPS: &[OpKind] = &[
+    OpKind::Plus, OpKind::Minus, OpKind::Divide, OpKind::FloorDivide, OpKind::Multiply, OpKind::Power,
+    OpKind::Modulo, OpKind::Equals, OpKind::BitAnd, OpKind::BitOr, OpKind::BitXor, OpKind::Concat,
+    OpKind::ShiftLeft, OpKind::ShiftRight, OpKind::Arrow, OpKind::Range, OpKind::Step, OpKind::In,
+    OpKind::Less, OpKind::LessEqual, OpKind::Greater, OpKind::GreaterEqual, OpKind::Equal, OpKind::NotEqual,
+];
+
+// Each instruction and value is an array starting with one of these.
+const PUSH: i64 = 0;
+const LOAD: i64 = 1;
+const NEG: i64 = 2;
+const BINARY: i64 = 3;
+const CALL: i64 = 4;
+const EVAL: i64 = 5;
+
+const INT: i64 = 0;
+const FLOAT: i64 = 1;
+const STR: i64 = 2;
+const EXPR: i64 = 3;
+const LIST: i64 = 4;
+const TUPLE: i64 = 5;
+const ADDR: i64 = 6;
+const QUANTITY: i64 = 7;
+
+fn encode_instr(instr: &Instr) -> Result<Cbor> {
+    let items = match instr {
+        Instr::Push(val)         => vec![Cbor::Int(PUSH), encode_value(val)?],
+        Instr::Load(name)        => vec![Cbor::Int(LOAD), Cbor::text(name)],
+        Instr::LoadSymbol(_)     => return Err(Error::TypeError),
+        Instr::Neg               => vec![Cbor::Int(NEG)],
+        Instr::Binary(kind)      => vec![Cbor::Int(BINARY), Cbor::text(format!("{:?}", kind))],
+        Instr::Call(name, argc)  => vec![Cbor::Int(CALL), Cbor::text(name), Cbor::Int(*argc as i64)],
+        // Expressions are kept as text, which reads back the same.
+        Instr::Eval(expr)        => vec![Cbor::Int(EVAL), Cbor::text(expr.to_string())],
+    };
+    Ok(Cbor::Array(items))
+}
+
+fn decode_instr(item: &Cbor) -> Result<Instr> {
+    let instr = match item.as_array()? {
+        [Cbor::Int(PUSH), val] => Instr::Push(decode_value(val)?),
+        [Cbor::Int(LOAD), name] => Instr::Load(name.as_str()?.to_string()),
+        [Cbor::Int(NEG)] => Instr::Neg,
+        [Cbor::Int(BINARY), name] => {
+            let name = name.as_str()?;
+            let kind = BINARY_OPS.iter().find(|kind| format!("{:?}", kind) == name).ok_or(Error::UnknownOperator)?;
+            Instr::Binary(*kind)
+        },
+        [Cbor::Int(CALL), name, argc] => {
+            let argc = usize::try_from(argc.as_int()?).map_err(|_| Error::ParseError)?;
+            Instr::Call(name.as_str()?.to_string(), argc)
+        },
+        [Cbor::Int(EVAL), src] => Instr::Eval(Expr::parse(src.as_str()?)?),
+        _ => return Err(Error::ParseError),
+    };
+    Ok(instr)
+}
+
+fn encode_value(val: &Value) -> Result<Cbor> {
+    let items = match val {
+        Value::Int(n)      => vec![Cbor::Int(INT), Cbor::Int(*n)],
+        Value::Float(x)    => vec![Cbor::Int(FLOAT), Cbor::Float(*x)],
+        Value::Str(s)      => vec![Cbor::Int(STR), Cbor::text(s)],
+        Value::Expr(e)     => vec![Cbor::Int(EXPR), Cbor::text(e.to_string())],
+        Value::List(vs)    => vec![Cbor::Int(LIST), Cbor::Array(vs.iter().map(encode_value).collect::<Result<_>>()?)],
+        Value::Tuple(vs)   => vec![Cbor::Int(TUPLE), Cbor::Array(vs.iter().map(encode_value).collect::<Result<_>>()?)],
+        Value::Addr(addr)  => {
+            let prefix = addr.prefix.map_or(Cbor::Null, |p| Cbor::Int(p as i64));
+            vec![Cbor::Int(ADDR), Cbor::Int(addr.ip as i64), prefix]
+        },
+        Value::Quantity(q) => vec![Cbor::Int(QUANTITY), Cbor::Float(q.amount), Cbor::text(&q.unit.name)],
+        Value::Func(_) | Value::Time(_) => return Err(Error::TypeError),
+    };
+    Ok(Cbor::Array(items))
+}
+
+fn decode_value(item: &Cbor) -> Result<Value> {
+    let val = match item.as_array()? {
+        [Cbor::Int(INT), n] => Value::Int(n.as_int()?),
+        [Cbor::Int(FLOAT), x] => Value::Float(x.as_f64()?),
+        [Cbor::Int(STR), s] => Value::Str(s.as_str()?.to_string()),
+        [Cbor::Int(EXPR), src] => Value::Expr(Box::new(Expr::parse(src.as_str()?)?)),
+        [Cbor::Int(LIST), vs] => Value::List(vs.as_array()?.iter().map(decode_value).collect::<Result<_>>()?),
+        [Cbor::Int(TUPLE), vs] => Value::Tuple(vs.as_array()?.iter().map(decode_value).collect::<Result<_>>()?),
+        [Cbor::Int(ADDR), ip, prefix] => {
+            let ip = u32::try_from(ip.as_int()?).map_err(|_| Error::ParseError)?;
+            let prefix = match prefix {
+                Cbor::Null => None,
+                Cbor::Int(p @ 0..=32) => Some(*p as u8),
+                _ => return Err(Error::ParseError),
+            };
+            Value::Addr(Addr { ip, prefix })
+        },
+        [Cbor::Int(QUANTITY), amount, unit] => {
+            let name = unit.as_str()?;
+            let unit = units::lookup(name).ok_or_else(|| Error::UnknownUnit(name.to_string()))?;
+            Value::Quantity(Quantity { amount: amount.as_f64()?, unit })
+        },
+        _ => return Err(Error::ParseError),
+    };
+    Ok(val)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::EvalOptions;
+
+    #[test]
+    fn test_compile_and_run() {
+        let exprs = [
+            "1 + 2 * 3",
+            "-(2^3) / 4",
+            "sqrt(16) + a",
+            "diff(x^2, x)",
+        ];
+        let answers = [
+            "7",
+            "-2",
+            "14",
+            "2*x",
+        ];
+
+        let mut env = Environment::new();
+        env.set("a", Value::Int(10));
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            let program = Program::compile(&Expr::parse(expr).unwrap());
+            assert_eq!(ans, program.run(&mut env).unwrap().to_string(), "{}", expr);
+        }
+
+        let program = Program::compile(&Expr::parse("1 + 2").unwrap());
+        assert_eq!(&[
+            Instr::Push(Value::Int(1)),
+            Instr::Push(Value::Int(2)),
+            Instr::Binary(OpKind::Plus),
+        ], program.code());
+    }
+
+    #[test]
+    fn test_link() {
+        let mut env = Environment::new();
+        for i in 0..5000 {
+            env.set(&format!("v{}", i), Value::Int(i));
+        }
+        let program = Program::compile(&Expr::parse("v10 * v4999 + rate").unwrap()).link(&mut env);
+        assert!(matches!(program.code()[0], Instr::LoadSymbol(_)));
+        match program.run(&mut env) {
+            Err(Error::UnknownVariable(unknown)) => assert_eq!("rate", unknown.name),
+            res => panic!("{:?}", res),
+        }
+
+        let rate = env.intern("rate");
+        for r in 1..4 {
+            env.set_symbol(rate, Value::Int(r));
+            assert_eq!(Value::Int(49990 + r), program.run(&mut env).unwrap());
+        }
+        assert_eq!(Some(&Value::Int(3)), env.get("rate"));
+        assert_eq!(Some(Value::Int(3)), env.unset("rate"));
+        assert!(!env.names().contains(&"rate"));
+        assert_eq!(5000, env.names().len());
+
+        let parent = std::sync::Arc::new(env);
+        let mut child = Environment::child(&parent);
+        let program = Program::compile(&Expr::parse("v7 + 1").unwrap()).link(&mut child);
+        assert_eq!(Value::Int(8), program.run(&mut child).unwrap());
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let exprs = [
+            "x^2 + 1",
+            "-sqrt(a) // 3 % b",
+            "\"tax\" & 10.0.0.0/8 & 1.5 GiB",
+            "[1, 2.5] .. 3",
+            "diff(x^3, x)",
+            "map(x -> x * 2, [1, 2])",
+        ];
+
+        let mut env = Environment::new();
+        env.set("x", Value::Int(3));
+        env.set("a", Value::Int(16));
+        env.set("b", Value::Int(5));
+        for expr in exprs {
+            let program = Program::compile(&Expr::parse(expr).unwrap());
+            let bytes = program.to_bytes().unwrap();
+            assert!(bytes.starts_with(b"KLKP\x02"));
+            let decoded = Program::from_bytes(&bytes).unwrap();
+            assert_eq!(program, decoded, "{}", expr);
+            assert_eq!(program.run(&mut env).ok(), decoded.run(&mut env).ok(), "{}", expr);
+        }
+        assert_eq!(42, Program::compile(&Expr::parse("x^2 + 1").unwrap()).to_bytes().unwrap().len());
+
+        let linked = Program::compile(&Expr::parse("x + 1").unwrap()).link(&mut env);
+        assert!(matches!(linked.to_bytes(), Err(Error::TypeError)));
+        let bytes = Program::compile(&Expr::parse("x + 1").unwrap()).to_bytes().unwrap();
+        for bad in [&bytes[..4], &bytes[..bytes.len() - 1], &bytes[1..], b"KLKP\x01\x81\x82\x03\x63Foo"] {
+            assert!(Program::from_bytes(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_format_versions() {
+        // `x + 1` as the first version wrote it, without the version of kalkul.
+        let v1 = b"KLKP\x01\x83\x82\x01\x61x\x82\x00\x82\x00\x01\x82\x03\x64Plus";
+        let program = Program::from_bytes(v1).unwrap();
+        assert_eq!(Program::compile(&Expr::parse("x + 1").unwrap()), program);
+        assert_eq!(Program::from_bytes(&program.to_bytes().unwrap()).unwrap(), program);
+
+        let future = b"KLKP\x09\x83\x652.0.0\xf6\x80";
+        let unknown = b"KLKP\x00\x80";
+        for (bytes, by) in [(&future[..], "2.0.0"), (unknown, "format 0")] {
+            match Program::from_bytes(bytes) {
+                Err(Error::IncompatibleVersion(v)) => assert_eq!(by, v),
+                res => panic!("{:?}", res),
+            }
+        }
+        assert_eq!(
+            "program was compiled by an incompatible version of kalkul (2.0.0)",
+            Program::from_bytes(future).unwrap_err().to_string(),
+        );
+    }
+
+    #[test]
+    fn test_eval_over() {
+        let exprs = [
+            "x^2 - 3*x + a",
+            "sin(x) / (1 + exp(-x))",
+            "-sqrt(x) * 2^a",
+            "a * 7",
+            "normcdf(x - 2) + (x < a)",
+        ];
+
+        let mut env = Environment::new();
+        env.set("a", Value::Int(3));
+        env.set("x", Value::Str("kept".to_string()));
+        let xs: Vec<f64> = (0..50).map(|i| i as f64 / 7.0 - 1.0).collect();
+        for expr in exprs {
+            let program = Program::compile(&Expr::parse(expr).unwrap());
+            let ys = eval_over(&program, "x", &xs, &mut env).unwrap();
+            for (x, y) in std::iter::zip(&xs, ys) {
+                let expected = program.call(&mut env, "x", *x).unwrap();
+                assert!(y == expected || y.is_nan() && expected.is_nan(), "{} at {}: {} != {}", expr, x, y, expected);
+            }
+            env.set("x", Value::Str("kept".to_string()));
+        }
+        assert_eq!(Some(&Value::Str("kept".to_string())), env.get("x"));
+
+        let program = Program::compile(&Expr::parse("x + b").unwrap());
+        assert!(matches!(eval_over(&program, "x", &xs, &mut env), Err(Error::UnknownVariable(_))));
+        env.set_options(EvalOptions { strict_float: true, ..env.options() });
+        let program = Program::compile(&Expr::parse("1 / x").unwrap());
+        assert!(matches!(eval_over(&program, "x", &[1.0, 0.0], &mut env), Err(Error::NotFinite)));
+    }
+
+    #[test]
+    fn test_compile_fn() {
+        let exprs = [
+            "x^2 + 3*x*y - 1",
+            "sin(x) * exp(-y / 2)",
+            "(1 + 2) * x ^ 3 / -y",
+            "sqrt(x^2 + y^2)",
+            "7 / 2 + x - x",
+        ];
+
+        let mut env = Environment::new();
+        for expr in exprs {
+            let parsed = Expr::parse(expr).unwrap();
+            let f = compile_fn(&parsed, &["x", "y"]).unwrap();
+            for (x, y) in [(1.0, 2.0), (-0.5, 3.25), (10.0, -7.0)] {
+                env.set("x", Value::Float(x));
+                env.set("y", Value::Float(y));
+                let expected = parsed.eval(&mut env).unwrap().as_f64().unwrap();
+                assert!((f([x, y]) - expected).abs() <= 1e-12 * expected.abs(), "{} at ({}, {})", expr, x, y);
+            }
+        }
+        let f = compile_fn(&Expr::parse("t * 2").unwrap(), &["t"]).unwrap();
+        assert_eq!(5.0, f([2.5]));
+
+        let parsed = Expr::parse("x + z").unwrap();
+        assert!(matches!(compile_fn(&parsed, &["x", "y"]), Err(Error::UnknownVariable(_))));
+        for expr in ["factorize(x)", "x < 1", "\"a\" + x", "sum(i, i, 1, x)"] {
+            assert!(matches!(compile_fn(&Expr::parse(expr).unwrap(), &["x"]), Err(Error::TypeError)), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_memoized() {
+        let mut env = Environment::new();
+        env.set("n", Value::Int(1000));
+        env.set("b", Value::Int(1));
+        let mut memo = Memoized::new(&Expr::parse("sum(i, i, 1, n) * 2 + b").unwrap());
+
+        assert_eq!(Value::Int(1001001), memo.run(&mut env).unwrap());
+        let steps = env.steps();
+        env.set("b", Value::Int(2));
+        assert_eq!(Value::Int(1001002), memo.run(&mut env).unwrap());
+        assert_eq!(1, memo.hits());
+        assert!(env.steps() - steps < 10);
+
+        env.set("n", Value::Int(10));
+        assert_eq!(Value::Int(112), memo.run(&mut env).unwrap());
+        assert_eq!(1, memo.hits());
+
+        let mut memo = Memoized::new(&Expr::parse("store(recall(\"m\") + 1, \"m\")").unwrap());
+        memo.run(&mut env).unwrap();
+        assert_eq!(Value::Int(2), memo.run(&mut env).unwrap());
+        assert_eq!(0, memo.hits());
+
+        let mut memo = Memoized::new(&Expr::parse("now() + 1 s").unwrap());
+        memo.run(&mut env).unwrap();
+        memo.run(&mut env).unwrap();
+        assert_eq!(0, memo.hits());
+    }
+}
@@ -0,0 +1,104 @@
+// The conversions clippy flags are generated by the pyo3 macros.
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyList, PyTuple};
+
+use crate::env;
+use crate::kalkul::{self, Error, Value};
+
+fn to_py_err(e: Error) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+fn to_py(py: Python<'_>, val: &Value) -> PyObject {
+    match val {
+        Value::Int(n) => n.into_py(py),
+        Value::Float(x) => x.into_py(py),
+        Value::Str(s) => s.into_py(py),
+        // Symbolic results and functions come back in the same syntax
+        // they are written in.
+        Value::Expr(e) => e.to_string().into_py(py),
+        Value::Func(f) => f.to_string().into_py(py),
+        Value::Addr(a) => a.to_string().into_py(py),
+        Value::Quantity(q) => q.to_string().into_py(py),
+        Value::Time(t) => t.to_string().into_py(py),
+        Value::List(items) => PyList::new_bound(py, items.iter().map(|v| to_py(py, v))).into_py(py),
+        Value::Tuple(items) => PyTuple::new_bound(py, items.iter().map(|v| to_py(py, v))).into_py(py),
+    }
+}
+
+fn from_py(obj: &Bound<'_, PyAny>) -> PyResult<Value> {
+    if let Ok(n) = obj.extract::<i64>() {
+        Ok(Value::Int(n))
+    } else if let Ok(x) = obj.extract::<f64>() {
+        Ok(Value::Float(x))
+    } else if let Ok(s) = obj.extract::<String>() {
+        Ok(Value::Str(s))
+    } else {
+        Err(to_py_err(Error::TypeError))
+    }
+}
+
+/// Evaluates an expression in a fresh environment.
+#[pyfunction]
+fn evaluate(py: Python<'_>, src: &str) -> PyResult<PyObject> {
+    let val = kalkul::eval(src).map_err(to_py_err)?;
+    Ok(to_py(py, &val))
+}
+
+/// Variables and memory registers shared by the expressions evaluated in it.
+#[pyclass(name = "Environment")]
+#[derive(Default)]
+struct PyEnvironment {
+    env: env::Environment,
+}
+
+#[pymethods]
+impl PyEnvironment {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn eval(&mut self, py: Python<'_>, src: &str) -> PyResult<PyObject> {
+        let val = self.env.eval(src).map_err(to_py_err)?;
+        Ok(to_py(py, &val))
+    }
+
+    fn get(&self, py: Python<'_>, name: &str) -> Option<PyObject> {
+        self.env.get(name).map(|v| to_py(py, v))
+    }
+
+    fn set(&mut self, name: &str, val: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.env.set(name, from_py(val)?);
+        Ok(())
+    }
+
+    fn unset(&mut self, py: Python<'_>, name: &str) -> Option<PyObject> {
+        self.env.unset(name).map(|v| to_py(py, &v))
+    }
+
+    fn store(&mut self, name: &str, val: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.env.store(name, from_py(val)?);
+        Ok(())
+    }
+
+    fn recall(&self, py: Python<'_>, name: &str) -> PyObject {
+        to_py(py, &self.env.recall(name))
+    }
+
+    fn alias(&mut self, name: &str, target: &str) -> PyResult<()> {
+        self.env.alias(name, target).map_err(to_py_err)
+    }
+}
+
+/// The `kalkul` Python module.
+#[pymodule]
+#[pyo3(name = "kalkul")]
+fn py_kalkul(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(evaluate, m)?)?;
+    m.add_class::<PyEnvironment>()?;
+    Ok(())
+}
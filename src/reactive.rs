@@ -0,0 +1,205 @@
+use crate::env::Environment;
+use crate::kalkul::{Error, Expr, Result, Value};
+use crate::program::Memoized;
+use crate::symbolic;
+
+/// A named expression whose value is kept up to date.
+#[derive(Debug, Clone)]
+struct Cell {
+    name: String,
+    expr: Memoized,
+    deps: Vec<String>,
+}
+
+/// A recalculation engine, like the cells of a spreadsheet. Each defined
+/// expression is bound to a name other expressions can use, and changing
+/// a variable reevaluates only the expressions that depend on it.
+///
+/// Expressions are reevaluated after the ones they use, whatever order
+/// they were defined in, so each is evaluated at most once per change.
+#[derive(Debug, Clone, Default)]
+pub struct Reactive {
+    env: Environment,
+    cells: Vec<Cell>,
+    /// Indices into `cells`, each after the cells it depends on.
+    order: Vec<usize>,
+}
+
+/// The new outcome of an expression whose value changed.
+pub type Change = (String, Result<Value>);
+
+impl Reactive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.env.get(name)
+    }
+
+    /// Binds `name` to the expression `src`, replacing any previous
+    /// definition, and returns the outcomes that changed as a result,
+    /// starting with its own. A definition that would make expressions
+    /// depend on themselves fails with `Error::CircularReference`.
+    pub fn define(&mut self, name: &str, src: &str) -> Result<Vec<Change>> {
+        let expr = Expr::parse(src)?;
+        let mut deps = Vec::new();
+        symbolic::variables(&expr, &mut deps);
+        let cell = Cell { name: name.to_string(), expr: Memoized::new(&expr), deps };
+
+        let i = match self.cells.iter().position(|c| c.name == name) {
+            Some(i) => {
+                let old = std::mem::replace(&mut self.cells[i], cell);
+                if let Err(e) = self.sort(i) {
+                    self.cells[i] = old;
+                    return Err(e);
+                }
+                i
+            },
+            None => {
+                self.cells.push(cell);
+                if let Err(e) = self.sort(self.cells.len() - 1) {
+                    self.cells.pop();
+                    return Err(e);
+                }
+                self.cells.len() - 1
+            },
+        };
+        let mut changes = Vec::new();
+        let res = self.cells[i].expr.run(&mut self.env);
+        let changed = self.update(name, res, &mut changes);
+        if changed {
+            changes.extend(self.recalc(name));
+        }
+        Ok(changes)
+    }
+
+    /// Sets a variable, returning the outcomes that changed as a result.
+    pub fn set(&mut self, name: &str, val: Value) -> Vec<Change> {
+        if self.env.get(name) == Some(&val) {
+            return Vec::new();
+        }
+        self.env.set(name, val);
+        self.recalc(name)
+    }
+
+    /// Reevaluates the expressions depending on `name`, directly or
+    /// through other expressions.
+    fn recalc(&mut self, name: &str) -> Vec<Change> {
+        let mut dirty = vec![name.to_string()];
+        let mut changes = Vec::new();
+        for i in self.order.clone() {
+            if !self.cells[i].deps.iter().any(|d| dirty.contains(d)) {
+                continue;
+            }
+            let name = self.cells[i].name.clone();
+            let res = self.cells[i].expr.run(&mut self.env);
+            if self.update(&name, res, &mut changes) {
+                dirty.push(name);
+            }
+        }
+        changes
+    }
+
+    /// Puts the cells in dependency order, failing if some depend on
+    /// themselves. Cycles are reported starting from cell `first`.
+    fn sort(&mut self, first: usize) -> Result<()> {
+        // Depth first, with `path` holding the cells being visited.
+        fn visit(cells: &[Cell], i: usize, done: &mut Vec<bool>, path: &mut Vec<usize>, order: &mut Vec<usize>) -> Result<()> {
+            if done[i] {
+                return Ok(());
+            }
+            if let Some(start) = path.iter().position(|&j| j == i) {
+                let mut cycle: Vec<String> = path[start..].iter().map(|&j| cells[j].name.clone()).collect();
+                cycle.push(cells[i].name.clone());
+                return Err(Error::CircularReference(cycle));
+            }
+            path.push(i);
+            for dep in &cells[i].deps {
+                if let Some(j) = cells.iter().position(|c| &c.name == dep) {
+                    visit(cells, j, done, path, order)?;
+                }
+            }
+            path.pop();
+            done[i] = true;
+            order.push(i);
+            Ok(())
+        }
+
+        let mut done = vec![false; self.cells.len()];
+        let mut order = Vec::with_capacity(self.cells.len());
+        for i in std::iter::once(first).chain(0..self.cells.len()) {
+            visit(&self.cells, i, &mut done, &mut Vec::new(), &mut order)?;
+        }
+        self.order = order;
+        Ok(())
+    }
+
+    /// Binds the outcome of an expression to its name, recording it in
+    /// `changes` if it differs from before. A failed expression leaves
+    /// its name unbound, so the expressions using it fail too.
+    fn update(&mut self, name: &str, res: Result<Value>, changes: &mut Vec<Change>) -> bool {
+        match &res {
+            Ok(val) if self.env.get(name) == Some(val) => return false,
+            Ok(val) => self.env.set(name, val.clone()),
+            Err(_) => {
+                self.env.unset(name);
+            },
+        }
+        changes.push((name.to_string(), res));
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn summary(changes: &[Change]) -> Vec<String> {
+        changes.iter()
+            .map(|(name, res)| match res {
+                Ok(val) => format!("{} = {}", name, val),
+                Err(e) => format!("{}: {}", name, e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reactive() {
+        let mut sheet = Reactive::new();
+        sheet.set("price", Value::Int(4));
+        sheet.set("qty", Value::Int(3));
+        sheet.set("rate", Value::Float(0.5));
+        assert_eq!(vec!["total = 12"], summary(&sheet.define("total", "price * qty").unwrap()));
+        assert_eq!(vec!["tax = 6"], summary(&sheet.define("tax", "total * rate").unwrap()));
+        assert_eq!(vec!["shipping = 5"], summary(&sheet.define("shipping", "5").unwrap()));
+
+        assert_eq!(vec!["total = 20", "tax = 10"], summary(&sheet.set("qty", Value::Int(5))));
+        assert_eq!(vec!["tax = 2"], summary(&sheet.set("rate", Value::Float(0.1))));
+        assert!(sheet.set("rate", Value::Float(0.1)).is_empty());
+
+        let changes = sheet.set("price", Value::Str("x".to_string()));
+        assert_eq!(vec!["total: wrong type of argument", "tax: unknown variable 'total'"], summary(&changes));
+        assert_eq!(None, sheet.get("tax"));
+        assert_eq!(Some(&Value::Int(5)), sheet.get("shipping"));
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        let mut sheet = Reactive::new();
+        sheet.define("c", "b * 2").unwrap();
+        sheet.define("b", "a + 1").unwrap();
+        assert_eq!(vec!["a = 1", "b = 2", "c = 4"], summary(&sheet.define("a", "1").unwrap()));
+        assert_eq!(vec!["a = 2", "b = 3", "c = 6"], summary(&sheet.define("a", "2").unwrap()));
+
+        let e = sheet.define("a", "c - 1").unwrap_err();
+        assert_eq!("circular reference a -> c -> b -> a", e.to_string());
+        assert_eq!("circular reference d -> d", sheet.define("d", "d + 1").unwrap_err().to_string());
+        assert_eq!(Some(&Value::Int(2)), sheet.get("a"));
+        assert_eq!(vec!["a = 3", "b = 4", "c = 8"], summary(&sheet.define("a", "3").unwrap()));
+    }
+}
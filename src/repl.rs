@@ -0,0 +1,777 @@
+#[cfg(feature = "precise")]
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::command::Command;
+use crate::csv;
+use crate::env::{Environment, EvalOptions, IntWidth, Limits, Overflow, Snapshot};
+use crate::function::Function;
+use crate::format::{format_value, with_radix, Notation};
+use crate::json::Json;
+use crate::kalkul::{Error, Expr, OpKind, Result, Syntax, Value};
+use crate::symbolic;
+use crate::units;
+
+/// Interactive session state. Lines starting with `:` are commands that
+/// change how the session behaves, `name = expr` assigns a variable and
+/// anything else is evaluated.
+pub struct Repl {
+    env: Environment,
+    notation: Notation,
+    /// Radix integer results are shown in.
+    radix: u32,
+    /// Whether results are followed by how long they took.
+    time: bool,
+    /// Whether integer results are also shown in hex and binary while
+    /// integers wrap around like machine words.
+    multibase: bool,
+    /// Significant digits results are computed to, see `:digits`.
+    #[cfg(feature = "precise")]
+    digits: Option<usize>,
+    /// The digits of results computed that way, by the variables that
+    /// hold them rounded to floats.
+    #[cfg(feature = "precise")]
+    exact: HashMap<String, String>,
+    last: Option<Value>,
+    // Number of results bound so far as `_1`, `_2`, ...
+    history: usize,
+    /// Every line evaluated in the session with its result, for `:export`.
+    /// Unlike the history variables it survives `:clear`.
+    log: Vec<(String, Value)>,
+    /// States to go back to, the last `MAX_UNDO` of them.
+    undo: VecDeque<State>,
+    /// Whether commands reaching outside the session, writing files or
+    /// the clipboard, are refused, see `sandboxed`.
+    sandboxed: bool,
+}
+
+/// Most changes `:undo` can go back through.
+const MAX_UNDO: usize = 100;
+
+/// A saved session state, with the history count, last result and length
+/// of the log then.
+type State = (Snapshot, usize, Option<Value>, usize);
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl {
+            env: Environment::new(),
+            notation: Notation::Normal,
+            radix: 10,
+            time: false,
+            multibase: true,
+            #[cfg(feature = "precise")]
+            digits: None,
+            #[cfg(feature = "precise")]
+            exact: HashMap::new(),
+            last: None,
+            history: 0,
+            log: Vec::new(),
+            undo: VecDeque::new(),
+            sandboxed: false,
+        }
+    }
+
+    /// A session whose commands only ever change the session itself, for
+    /// going through documents that were not typed in, as the language
+    /// server does. `:export` and `:copy` fail with `Error::NotAllowed`.
+    pub fn sandboxed() -> Self {
+        Repl { sandboxed: true, ..Repl::new() }
+    }
+
+    /// Bounds the work done by the rest of the session.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.env.set_limits(limits);
+    }
+
+    /// Handles a single line of input, returning the text to print, if any.
+    pub fn handle(&mut self, line: &str) -> Result<Option<String>> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        if let Some(cmd) = line.strip_prefix(':') {
+            return self.command(cmd);
+        }
+
+        if let Some((name, size)) = split_unit(line) {
+            let size = match size.map(|expr| self.env.eval(expr)).transpose()? {
+                Some(Value::Quantity(q)) => Some(q),
+                Some(_) => return Err(Error::TypeError),
+                None => None,
+            };
+            self.undoable(|repl| units::define(&mut repl.env, name, size.as_ref()))?;
+            return Ok(None);
+        }
+
+        if let Some((name, params, body)) = split_definition(line) {
+            let f = Function::parse(params.into_iter().map(String::from).collect(), body)?;
+            self.undoable(|repl| repl.env.define(name, Arc::new(f)))?;
+            return Ok(None);
+        }
+
+        #[cfg(feature = "precise")]
+        if let Some(out) = self.eval_precise(line) {
+            return out;
+        }
+
+        let state = self.state();
+        let assignment = split_assignment(line);
+        let assigns = assignment.is_some();
+        let res = match assignment {
+            Some((names, expr)) => self.env.eval_detailed(expr).and_then(|details| {
+                self.assign(&names, &details.value)?;
+                Ok(details)
+            }),
+            None => self.env.eval_detailed(line),
+        };
+        match res {
+            Ok(details) => {
+                // Only assignments are steps of their own for `:undo`, the
+                // results of plain evaluations going with the step before.
+                if assigns {
+                    self.checkpoint(state);
+                }
+                let mut out = self.echo(&details.value);
+                for warning in &details.warnings {
+                    out += &format!("\nwarning: {}", warning);
+                }
+                if self.time {
+                    out += &format!("\ntime: {}", details.timing());
+                }
+                self.log.push((line.to_string(), details.value.clone()));
+                self.record(details.value);
+                Ok(Some(out))
+            },
+            Err(e) => {
+                // Builtins such as `store` may have run before the error.
+                self.return_to(state);
+                Err(e)
+            },
+        }
+    }
+
+    /// Evaluates a line to the digits `:digits` asks for, or returns
+    /// `None` if it is off or the line needs more than the arithmetic
+    /// `precise` knows. Variables are given the nearest float, with the
+    /// digits kept aside for later lines.
+    #[cfg(feature = "precise")]
+    fn eval_precise(&mut self, line: &str) -> Option<Result<Option<String>>> {
+        let digits = self.digits?;
+        let (names, src) = match split_assignment(line) {
+            Some((names, src)) => (Some(names), src),
+            None => (None, line),
+        };
+        let text = crate::precise::eval_digits(&self.env.parse(src).ok()?, digits, &self.env, &self.exact)?;
+        let val = Value::Float(text.parse().ok()?);
+        if let Some(names) = names {
+            if let Err(e) = self.undoable(|repl| repl.assign(&names, &val)) {
+                return Some(Err(e));
+            }
+            for name in names {
+                self.exact.insert(name.to_string(), text.clone());
+            }
+        }
+        self.log.push((line.to_string(), val.clone()));
+        self.record(val);
+        for name in [format!("_{}", self.history), "_".to_string()] {
+            self.exact.insert(name, text.clone());
+        }
+        Some(Ok(Some(text)))
+    }
+
+    fn state(&self) -> State {
+        (self.env.snapshot(), self.history, self.last.clone(), self.log.len())
+    }
+
+    fn return_to(&mut self, (snapshot, history, last, logged): State) {
+        self.env.restore(snapshot);
+        self.history = history;
+        self.last = last;
+        self.log.truncate(logged);
+    }
+
+    /// Saves the state from before a change so the change can be undone,
+    /// forgetting the oldest once there are `MAX_UNDO`.
+    fn checkpoint(&mut self, state: State) {
+        if self.undo.len() == MAX_UNDO {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(state);
+    }
+
+    /// Makes a change that can be undone, leaving the session as it was
+    /// if the change fails.
+    fn undoable<T>(&mut self, change: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let state = self.state();
+        match change(self) {
+            Ok(res) => {
+                self.checkpoint(state);
+                Ok(res)
+            },
+            Err(e) => {
+                self.return_to(state);
+                Err(e)
+            },
+        }
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        let state = self.undo.pop_back().ok_or(Error::NothingToUndo)?;
+        self.return_to(state);
+        Ok(())
+    }
+
+    /// Binds a result to the next history variable and to `_`.
+    fn record(&mut self, val: Value) {
+        self.history += 1;
+        self.env.set(&format!("_{}", self.history), val.clone());
+        self.env.set("_", val.clone());
+        self.last = Some(val);
+    }
+
+    /// Formats a result, in programmer mode as `42 | 0x2A | 0b101010`
+    /// with negative numbers in two's complement.
+    fn echo(&self, val: &Value) -> String {
+        let options = self.env.options();
+        match val {
+            Value::Int(n) if self.multibase && options.overflow == Overflow::Wrap => {
+                let pattern = *n as i128 & ((1 << options.width.bits()) - 1);
+                format!("{} | 0x{:X} | 0b{:b}", n, pattern, pattern)
+            },
+            _ => self.format(val),
+        }
+    }
+
+    fn format(&self, val: &Value) -> String {
+        match val {
+            Value::Int(n) if self.radix != 10 => with_radix(*n, self.radix),
+            _ => format_value(val, self.notation),
+        }
+    }
+
+    fn command(&mut self, cmd: &str) -> Result<Option<String>> {
+        match Command::parse(cmd)? {
+            Command::Notation(notation) => self.notation = notation,
+            Command::Base(radix) => self.radix = radix,
+            Command::Width(None) => self.env.set_options(EvalOptions {
+                overflow: Overflow::default(),
+                width: IntWidth::default(),
+                ..self.env.options()
+            }),
+            // Programmer mode: integers wrap around like machine words.
+            Command::Width(Some(width)) => self.env.set_options(EvalOptions {
+                overflow: Overflow::Wrap,
+                width,
+                ..self.env.options()
+            }),
+            Command::Modulus(modulus) => self.env.set_options(EvalOptions {
+                modulus,
+                ..self.env.options()
+            }),
+            Command::StrictFloat(strict_float) => self.env.set_options(EvalOptions {
+                strict_float,
+                ..self.env.options()
+            }),
+            Command::Syntax(strictness) => {
+                let options = self.env.options();
+                self.env.set_options(EvalOptions {
+                    syntax: Syntax { strictness, ..options.syntax },
+                    ..options
+                });
+            },
+            // A dialect brings its own integer arithmetic, like C's `int`.
+            Command::Dialect(dialect) => {
+                let options = self.env.options();
+                let dialect = EvalOptions::dialect(dialect);
+                self.env.set_options(EvalOptions {
+                    strict_float: options.strict_float,
+                    syntax: Syntax {
+                        strictness: options.syntax.strictness,
+                        si_prefixes: options.syntax.si_prefixes,
+                        ..dialect.syntax
+                    },
+                    ..dialect
+                });
+            },
+            Command::Time(time) => self.time = time,
+            Command::SiPrefixes(si_prefixes) => {
+                let options = self.env.options();
+                self.env.set_options(EvalOptions {
+                    syntax: Syntax { si_prefixes, ..options.syntax },
+                    ..options
+                });
+            },
+            Command::Multibase => self.multibase = !self.multibase,
+            Command::Vars => {
+                let mut names: Vec<&str> = self.env.names().into_iter()
+                    .filter(|name| !is_history(name))
+                    .collect();
+                names.sort();
+                let lines: Vec<String> = names.iter()
+                    .map(|name| format!("{} = {}", name, self.format(self.env.get(name).unwrap())))
+                    .collect();
+                return Ok(Some(lines.join("\n")));
+            },
+            Command::Funcs => {
+                let mut names = self.env.function_names();
+                names.sort();
+                let lines: Vec<String> = names.iter()
+                    .map(|name| format!("{} = {}", name, self.env.function(name).unwrap()))
+                    .collect();
+                return Ok(Some(lines.join("\n")));
+            },
+            Command::Clear => self.undoable(|repl| {
+                repl.env.clear();
+                repl.history = 0;
+                repl.last = None;
+                Ok(())
+            })?,
+            Command::Undo => self.rollback()?,
+            Command::MemoryAdd(register) => self.accumulate(register, OpKind::Plus)?,
+            Command::MemorySubtract(register) => self.accumulate(register, OpKind::Minus)?,
+            Command::MemoryClear(register) => self.undoable(|repl| {
+                repl.env.clear_register(register);
+                Ok(())
+            })?,
+            Command::MemoryRecall(register) => {
+                let val = self.env.recall(register);
+                let out = self.format(&val);
+                self.last = Some(val);
+                return Ok(Some(out));
+            },
+            #[cfg(feature = "precise")]
+            Command::Digits(digits) => self.digits = digits,
+            #[cfg(feature = "clipboard")]
+            Command::Copy => {
+                if self.sandboxed {
+                    return Err(Error::NotAllowed(":copy"));
+                }
+                let last = self.last.as_ref().ok_or(Error::NoPreviousResult)?;
+                crate::clipboard::copy(&self.format(last))?;
+            },
+            Command::Alias(Some((name, target))) => {
+                self.undoable(|repl| repl.env.alias(name, target))?;
+            },
+            Command::Alias(None) => {
+                let lines: Vec<String> = self.env.aliases().iter()
+                    .map(|(name, target)| format!("{} = {}", name, target))
+                    .collect();
+                return Ok(Some(lines.join("\n")));
+            },
+            Command::Export(_) if self.sandboxed => return Err(Error::NotAllowed(":export")),
+            Command::Export(path) => self.export(path)?,
+            Command::Simplify(expr) => {
+                let expr = symbolic::resolve(&Expr::parse(expr)?, &mut self.env)?;
+                return Ok(Some(symbolic::simplify(&expr, &mut self.env)?.to_string()));
+            },
+        }
+        Ok(None)
+    }
+
+    /// Writes every line evaluated so far and its result to a file, as
+    /// CSV or JSON depending on its extension.
+    fn export(&self, path: &str) -> Result<()> {
+        let mut out = Vec::new();
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => {
+                csv::write_record(&mut out, &["expr", "result"])?;
+                for (line, val) in &self.log {
+                    csv::write_record(&mut out, &[line.clone(), val.to_string()])?;
+                }
+            },
+            Some("json") => {
+                let entries = self.log.iter()
+                    .map(|(line, val)| {
+                        let result = match val {
+                            Value::Int(_) | Value::Float(_) => Json::Num(val.as_f64()?),
+                            _ => Json::str(val.to_string()),
+                        };
+                        Ok(Json::object([("expr", Json::str(line.as_str())), ("result", result)]))
+                    })
+                    .collect::<Result<Vec<Json>>>()?;
+                writeln!(out, "{}", Json::Array(entries))?;
+            },
+            _ => return Err(Error::Usage),
+        }
+        std::fs::write(path, out)?;
+        Ok(())
+    }
+
+    /// Binds a value to a name, or unpacks a tuple into several.
+    fn assign(&mut self, names: &[&str], val: &Value) -> Result<()> {
+        match (names, val) {
+            ([name], _) => self.env.set(name, val.clone()),
+            (_, Value::Tuple(vals)) if vals.len() == names.len() => {
+                for (name, val) in std::iter::zip(names, vals) {
+                    self.env.set(name, val.clone());
+                }
+            },
+            _ => return Err(Error::UnpackMismatch),
+        }
+        Ok(())
+    }
+
+    /// Adds the last result to, or subtracts it from, a memory register.
+    fn accumulate(&mut self, register: &str, kind: OpKind) -> Result<()> {
+        let last = self.last.clone().ok_or(Error::NoPreviousResult)?;
+        let val = self.env.apply(kind, self.env.recall(register), last)?;
+        self.undoable(|repl| {
+            repl.env.store(register, val);
+            Ok(())
+        })
+    }
+}
+
+/// Splits `name = expr`, or `a, b = expr` for unpacking, into its parts.
+pub(crate) fn split_assignment(line: &str) -> Option<(Vec<&str>, &str)> {
+    let (lhs, expr) = line.split_once('=')?;
+    // `x == 1` is a comparison.
+    if expr.starts_with('=') {
+        return None;
+    }
+    let names: Vec<&str> = lhs.split(',').map(str::trim).collect();
+    if !names.iter().all(|name| is_name(name)) {
+        return None;
+    }
+    Some((names, expr))
+}
+
+/// Splits a function definition `f(x, y) = body` into the name, the
+/// parameters and the body.
+pub(crate) fn split_definition(line: &str) -> Option<(&str, Vec<&str>, &str)> {
+    let (lhs, body) = line.split_once('=')?;
+    if body.starts_with('=') {
+        return None;
+    }
+    let (name, params) = lhs.trim().strip_suffix(')')?.split_once('(')?;
+    let name = name.trim();
+    let params: Vec<&str> = params.split(',').map(str::trim).collect();
+    if !is_name(name) || !params.iter().all(|p| is_name(p)) {
+        return None;
+    }
+    Some((name, params, body))
+}
+
+/// Splits a unit declaration, `unit name = size` or `unit name` for a
+/// new base unit, into the name and the size.
+fn split_unit(line: &str) -> Option<(&str, Option<&str>)> {
+    let rest = line.strip_prefix("unit ")?.trim();
+    let (name, size) = match rest.split_once('=') {
+        Some((name, size)) => (name.trim(), Some(size.trim())),
+        None => (rest, None),
+    };
+    is_name(name).then_some((name, size))
+}
+
+/// Whether a variable is `_` or one of `_1`, `_2`, ... holding results.
+fn is_history(name: &str) -> bool {
+    name.strip_prefix('_').is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn is_name(s: &str) -> bool {
+    let mut cs = s.chars();
+    match cs.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => cs.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn out(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test]
+    fn test_notation_commands() {
+        let mut repl = Repl::new();
+        let expr = "123 / 10000000";
+        assert_eq!(out("0.0000123"), repl.handle(expr).unwrap());
+
+        repl.handle(":sci").unwrap();
+        assert_eq!(out("1.23e-5"), repl.handle(expr).unwrap());
+        repl.handle(":eng").unwrap();
+        assert_eq!(out("12.3e-6"), repl.handle(expr).unwrap());
+        repl.handle(":fix 7").unwrap();
+        assert_eq!(out("0.0000123"), repl.handle(expr).unwrap());
+
+        assert!(repl.handle(":fix").is_err());
+        assert!(repl.handle(":nope").is_err());
+    }
+
+    #[cfg(feature = "clipboard")]
+    #[test]
+    fn test_copy_command() {
+        let mut repl = Repl::new();
+        assert!(matches!(repl.handle(":copy"), Err(Error::NoPreviousResult)));
+        assert!(matches!(repl.handle(":copy now"), Err(Error::UnknownCommand)));
+    }
+
+    #[cfg(feature = "precise")]
+    #[test]
+    fn test_digits_command() {
+        let mut repl = Repl::new();
+        repl.handle(":digits 30").unwrap();
+        assert_eq!(out("1.41421356237309504880168872421"), repl.handle("r = sqrt(2)").unwrap());
+        assert_eq!(out("2"), repl.handle("r * r").unwrap());
+        // Anything else is evaluated as usual.
+        assert_eq!(out("0.8414709848078965"), repl.handle("sin(1)").unwrap());
+        repl.handle(":digits off").unwrap();
+        assert_eq!(out("1.4142135623730951"), repl.handle("r").unwrap());
+        assert!(matches!(repl.handle(":digits 0"), Err(Error::ParseError)));
+    }
+
+    #[test]
+    fn test_session_commands() {
+        let mut repl = Repl::new();
+        repl.handle("rate = 0.5").unwrap();
+        repl.handle("n = 255").unwrap();
+        repl.handle("sq(x) = x^2").unwrap();
+        repl.handle("cube(x) = x^3").unwrap();
+        assert_eq!(out("n = 255\nrate = 0.5"), repl.handle(":vars").unwrap());
+        assert_eq!(out("cube = x -> x^3\nsq = x -> x^2"), repl.handle(":funcs").unwrap());
+
+        repl.handle(":base hex").unwrap();
+        assert_eq!(out("0xFF"), repl.handle("n").unwrap());
+        assert_eq!(out("-0x10"), repl.handle("-16").unwrap());
+        assert_eq!(out("0.5"), repl.handle("rate").unwrap());
+        repl.handle(":base dec").unwrap();
+
+        assert_eq!(out("inf"), repl.handle("1 / 0.0").unwrap());
+        repl.handle(":mode strict").unwrap();
+        assert!(matches!(repl.handle("1 / 0.0"), Err(Error::NotFinite)));
+        repl.handle(":mode float").unwrap();
+
+        assert!(repl.handle("2 * (n + 1").is_err());
+        repl.handle(":syntax permissive").unwrap();
+        assert_eq!(out("512"), repl.handle("2 * (n + 1").unwrap());
+        repl.handle(":syntax strict").unwrap();
+
+        repl.handle(":time on").unwrap();
+        let timed = repl.handle("1 + 1").unwrap().unwrap();
+        assert!(timed.starts_with("2\ntime: parse "), "{}", timed);
+        assert!(timed.contains(", eval "), "{}", timed);
+        repl.handle(":time off").unwrap();
+
+        repl.handle(":clear").unwrap();
+        assert_eq!(out(""), repl.handle(":vars").unwrap());
+        assert!(repl.handle("sq(2)").is_err());
+        repl.handle(":undo").unwrap();
+        assert_eq!(out("4"), repl.handle("sq(2)").unwrap());
+    }
+
+    #[test]
+    fn test_export() {
+        let dir = std::env::temp_dir();
+        let csv_path = dir.join(format!("kalkul-history-{}.csv", std::process::id()));
+        let json_path = dir.join(format!("kalkul-history-{}.json", std::process::id()));
+
+        let mut repl = Repl::new();
+        repl.handle("x = 1.5").unwrap();
+        repl.handle("divmod(7, 2)").unwrap();
+        repl.handle("x * 2").unwrap();
+        repl.handle("oops = 1").unwrap();
+        repl.handle(":undo").unwrap();
+        assert!(repl.handle("1 / 0").is_err());
+        repl.handle(&format!(":export {}", csv_path.display())).unwrap();
+        repl.handle(&format!(":export {}", json_path.display())).unwrap();
+
+        let csv = std::fs::read_to_string(&csv_path).unwrap();
+        assert_eq!("expr,result\nx = 1.5,1.5\n\"divmod(7, 2)\",\"(3, 1)\"\nx * 2,3\n", csv);
+        let json = std::fs::read_to_string(&json_path).unwrap();
+        assert_eq!(
+            "[{\"expr\":\"x = 1.5\",\"result\":1.5},{\"expr\":\"divmod(7, 2)\",\"result\":\"(3, 1)\"},{\"expr\":\"x * 2\",\"result\":3}]\n",
+            json,
+        );
+        assert!(matches!(repl.handle(":export history.txt"), Err(Error::Usage)));
+
+        std::fs::remove_file(csv_path).unwrap();
+        std::fs::remove_file(json_path).unwrap();
+    }
+
+    #[test]
+    fn test_width_command() {
+        let mut repl = Repl::new();
+        repl.handle(":width u8").unwrap();
+        assert_eq!(out("0 | 0x0 | 0b0"), repl.handle("0xFF + 1").unwrap());
+        assert_eq!(out("254 | 0xFE | 0b11111110"), repl.handle("0xFF << 1").unwrap());
+        assert_eq!(out("240 | 0xF0 | 0b11110000"), repl.handle("not(0x0F)").unwrap());
+        repl.handle(":width i8").unwrap();
+        assert_eq!(out("-128 | 0x80 | 0b10000000"), repl.handle("127 + 1").unwrap());
+        assert_eq!(out("0.5"), repl.handle("1 / 2").unwrap());
+        repl.handle(":multibase").unwrap();
+        assert_eq!(out("-1"), repl.handle("127 + 128").unwrap());
+        repl.handle(":multibase").unwrap();
+        assert_eq!(out("42 | 0x2A | 0b101010"), repl.handle("42").unwrap());
+        repl.handle(":width off").unwrap();
+        assert_eq!(out("256"), repl.handle("0xFF + 1").unwrap());
+
+        assert!(repl.handle(":width u7").is_err());
+    }
+
+    #[test]
+    fn test_dialect_command() {
+        let mut repl = Repl::new();
+        repl.handle(":dialect c").unwrap();
+        assert_eq!(out("-2147483648 | 0x80000000 | 0b10000000000000000000000000000000"), repl.handle("2147483647 + 1").unwrap());
+        assert_eq!(out("0 | 0x0 | 0b0"), repl.handle("6 & 1 == 0").unwrap());
+        repl.handle(":dialect python").unwrap();
+        assert_eq!(out("2147483648"), repl.handle("2147483647 + 1").unwrap());
+        assert_eq!(out("1024"), repl.handle("2 ** 10").unwrap());
+        assert!(repl.handle(":dialect basic").is_err());
+    }
+
+    #[test]
+    fn test_si_command() {
+        let mut repl = Repl::new();
+        assert!(repl.handle("4.7k").is_err());
+        repl.handle(":si on").unwrap();
+        assert_eq!(out("4700"), repl.handle("4.7k").unwrap());
+        repl.handle(":dialect python").unwrap();
+        assert_eq!(out("0.01"), repl.handle("2k * 5u").unwrap());
+        repl.handle(":si off").unwrap();
+        assert_eq!(out("2 m"), repl.handle("2m").unwrap());
+    }
+
+    #[test]
+    fn test_memory_commands() {
+        let mut repl = Repl::new();
+        assert!(matches!(repl.handle(":m+"), Err(Error::NoPreviousResult)));
+
+        repl.handle("2 * 3").unwrap();
+        repl.handle(":m+").unwrap();
+        repl.handle(":m+").unwrap();
+        repl.handle("2").unwrap();
+        repl.handle(":m-").unwrap();
+        assert_eq!(out("10"), repl.handle(":mr").unwrap());
+
+        repl.handle(":m+ m1").unwrap();
+        assert_eq!(out("10"), repl.handle("recall(\"m1\")").unwrap());
+        repl.handle(":mc").unwrap();
+        assert_eq!(out("0"), repl.handle(":mr").unwrap());
+        assert_eq!(out("10"), repl.handle(":mr m1").unwrap());
+
+        repl.handle(":mod 7").unwrap();
+        repl.handle("5").unwrap();
+        repl.handle(":m+ m1").unwrap();
+        assert_eq!(out("1"), repl.handle(":mr m1").unwrap());
+    }
+
+    #[test]
+    fn test_history_variables() {
+        let mut repl = Repl::new();
+        assert!(matches!(repl.handle("_"), Err(Error::UnknownVariable(_))));
+
+        repl.handle("1 + 1").unwrap();
+        repl.handle("_1 * 10").unwrap();
+        assert_eq!(out("22"), repl.handle("_ + _1").unwrap());
+        assert_eq!(out("20"), repl.handle("_2").unwrap());
+        assert_eq!(out("20"), repl.handle("_4").unwrap());
+        assert!(repl.handle("_9").is_err());
+    }
+
+    #[test]
+    fn test_unpacking() {
+        let mut repl = Repl::new();
+        assert_eq!(out("(3, 2)"), repl.handle("q, r = divmod(17, 5)").unwrap());
+        assert_eq!(out("17"), repl.handle("q * 5 + r").unwrap());
+        assert_eq!(out("(3, 2)"), repl.handle("t = divmod(17, 5)").unwrap());
+        assert!(matches!(repl.handle("a, b, c = divmod(17, 5)"), Err(Error::UnpackMismatch)));
+        assert!(matches!(repl.handle("a, b = 1"), Err(Error::UnpackMismatch)));
+        assert!(repl.handle("a").is_err());
+    }
+
+    #[test]
+    fn test_alias_command() {
+        let mut repl = Repl::new();
+        repl.handle(":alias log ln").unwrap();
+        repl.handle(":alias root sqrt").unwrap_err();
+        assert_eq!(out("0"), repl.handle("log(1)").unwrap());
+        assert_eq!(out("log = ln"), repl.handle(":alias").unwrap());
+        repl.handle(":undo").unwrap();
+        assert!(repl.handle("log(1)").is_err());
+    }
+
+    #[test]
+    fn test_function_definitions() {
+        let mut repl = Repl::new();
+        assert_eq!(None, repl.handle("f(x) = { x < 0: -x; x >= 0: x }").unwrap());
+        assert_eq!(None, repl.handle("hyp(a, b) = sqrt(a^2 + b^2)").unwrap());
+        assert_eq!(out("5"), repl.handle("f(-3) + f(2)").unwrap());
+        assert_eq!(out("5"), repl.handle("hyp(3, 4)").unwrap());
+        assert_eq!(out("1"), repl.handle("f(-1) == 1").unwrap());
+        assert!(matches!(repl.handle("sqrt(x) = x"), Err(Error::Usage)));
+
+        repl.handle(":undo").unwrap();
+        assert!(matches!(repl.handle("hyp(3, 4)"), Err(Error::UnknownFunction(_))));
+        assert_eq!(out("3"), repl.handle("f(-3)").unwrap());
+    }
+
+    #[test]
+    fn test_unit_definitions() {
+        let mut repl = Repl::new();
+        assert_eq!(None, repl.handle("unit widget").unwrap());
+        assert_eq!(None, repl.handle("unit widgetbox = 24 widget").unwrap());
+        assert_eq!(out("48 widget"), repl.handle("2 widgetbox in widget").unwrap());
+        assert_eq!(out("widget/s"), repl.handle("dims(1 widgetbox / 1 h)").unwrap());
+        assert!(matches!(repl.handle("unit kg = 2 widget"), Err(Error::Usage)));
+        assert!(matches!(repl.handle("unit crate = 12"), Err(Error::TypeError)));
+        assert_eq!(None, repl.handle("unit gadget").unwrap());
+        assert_eq!(None, repl.handle(":undo").unwrap());
+        assert!(matches!(repl.handle("1 widget in gadget"), Err(Error::UnknownUnit(_))));
+        // Still a name for a variable.
+        assert_eq!(out("3"), repl.handle("unit = 3").unwrap());
+        assert_eq!(out("4"), repl.handle("unit + 1").unwrap());
+    }
+
+    #[test]
+    fn test_simplify_command() {
+        let mut repl = Repl::new();
+        assert_eq!(out("3*x"), repl.handle(":simplify x + 2*x").unwrap());
+        repl.handle("f = diff(x^3, x)").unwrap();
+        assert_eq!(out("3*x^2 + x"), repl.handle(":simplify f + x").unwrap());
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut repl = Repl::new();
+        assert!(matches!(repl.handle(":undo"), Err(Error::NothingToUndo)));
+
+        repl.handle("rate = 5").unwrap();
+        repl.handle("rate = 7").unwrap();
+        repl.handle(":undo").unwrap();
+        assert_eq!(out("5"), repl.handle("rate").unwrap());
+        assert_eq!(out("5"), repl.handle("_2").unwrap());
+
+        // Plain evaluations are not steps of their own.
+        repl.handle(":undo").unwrap();
+        assert!(repl.handle("rate").is_err());
+        assert!(matches!(repl.handle(":undo"), Err(Error::NothingToUndo)));
+
+        // A failing line leaves nothing behind, not even its `store`.
+        assert!(repl.handle("store(1, \"m\") + nope").is_err());
+        assert_eq!(out("0"), repl.handle(":mr").unwrap());
+
+        // Only the last hundred changes are kept.
+        for n in 0..150 {
+            repl.handle(&format!("n = {}", n)).unwrap();
+        }
+        for _ in 0..MAX_UNDO {
+            repl.handle(":undo").unwrap();
+        }
+        assert_eq!(out("49"), repl.handle("n").unwrap());
+        assert!(matches!(repl.handle(":undo"), Err(Error::NothingToUndo)));
+    }
+}
@@ -0,0 +1,339 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::env::{Environment, Limits};
+use crate::json::Json;
+use crate::kalkul::{Expr, Value};
+
+/// Settings for the evaluation service. The defaults are meant for
+/// expressions submitted by untrusted clients.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub addr: String,
+    /// Largest request body accepted, in bytes.
+    pub max_body: usize,
+    /// Longest expression accepted, in characters.
+    pub max_expr_len: usize,
+    /// Deepest nesting of subexpressions accepted, as evaluating and
+    /// simplifying recurse through them.
+    pub max_depth: usize,
+    pub max_steps: u64,
+    /// Time allowed for evaluating a single expression.
+    pub eval_timeout: Duration,
+    /// Time allowed for a client to send its request.
+    pub read_timeout: Duration,
+    /// Longest request line accepted, in bytes.
+    pub max_request_line: usize,
+    /// Most bytes of headers accepted, all lines together.
+    pub max_headers: usize,
+    /// Most connections handled at once; any more are turned away.
+    pub max_connections: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            addr: "127.0.0.1:8080".to_string(),
+            max_body: 64 * 1024,
+            max_expr_len: 4096,
+            max_depth: 256,
+            max_steps: 1_000_000,
+            eval_timeout: Duration::from_secs(1),
+            read_timeout: Duration::from_secs(5),
+            max_request_line: 8 * 1024,
+            max_headers: 16 * 1024,
+            max_connections: 64,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: Json) -> Response {
+        Response { status, body: body.to_string() }
+    }
+
+    fn error(status: u16, msg: &str) -> Response {
+        Response::json(status, Json::object([("error", Json::str(msg))]))
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _   => "Error",
+    }
+}
+
+/// An environment for evaluating a client's expression, with `vars` set
+/// and the limits of `config` starting now.
+pub fn client_env<'a>(vars: impl IntoIterator<Item = (&'a str, f64)>, config: &Config) -> Environment {
+    let mut env = Environment::new();
+    for (name, x) in vars {
+        env.set(name, Value::Float(x));
+    }
+    env.set_limits(Limits {
+        max_steps: Some(config.max_steps),
+        deadline: Some(Instant::now() + config.eval_timeout),
+        ..Limits::default()
+    });
+    env
+}
+
+/// Handles a single request.
+///
+/// `POST /eval` takes `{"expr": "...", "vars": {"x": 1}}`, where `vars`
+/// is optional, and answers with `{"result": "..."}`, plus `"value"` when
+/// the result is a number.
+pub fn respond(method: &str, path: &str, body: &[u8], config: &Config) -> Response {
+    if path != "/eval" {
+        return Response::error(404, "not found");
+    }
+    if method != "POST" {
+        return Response::error(405, "method not allowed");
+    }
+    if body.len() > config.max_body {
+        return Response::error(413, "request too large");
+    }
+
+    let req = match std::str::from_utf8(body).ok().and_then(|b| Json::parse(b).ok()) {
+        Some(req) => req,
+        None => return Response::error(400, "invalid JSON"),
+    };
+    let expr = match req.get("expr").and_then(Json::as_str) {
+        Some(expr) => expr,
+        None => return Response::error(400, "missing \"expr\""),
+    };
+    if expr.chars().count() > config.max_expr_len {
+        return Response::error(413, "expression too long");
+    }
+    // Anything that does not parse fails the same way in `eval` below.
+    if Expr::parse(expr).is_ok_and(|e| e.depth() > config.max_depth) {
+        return Response::error(413, "expression nested too deeply");
+    }
+
+    let mut vars = Vec::new();
+    if let Some(Json::Object(fields)) = req.get("vars") {
+        for (name, val) in fields {
+            match val.as_f64() {
+                Some(x) => vars.push((name.as_str(), x)),
+                None => return Response::error(400, "variables must be numbers"),
+            }
+        }
+    }
+    let mut env = client_env(vars, config);
+
+    match env.eval(expr) {
+        Ok(val) => {
+            let mut fields = vec![("result", Json::str(val.to_string()))];
+            if let Ok(x) = val.as_f64() {
+                fields.push(("value", Json::Num(x)));
+            }
+            Response::json(200, Json::object(fields))
+        },
+        Err(e) => Response::error(422, &e.to_string()),
+    }
+}
+
+/// Reads a line of at most `limit` bytes into `buf`, returning how many
+/// bytes were read, or `None` if the line goes on past `limit`.
+fn read_line_within(reader: &mut impl BufRead, limit: usize, buf: &mut String) -> io::Result<Option<usize>> {
+    let n = reader.take(limit as u64).read_line(buf)?;
+    if n == limit && !buf.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(Some(n))
+}
+
+fn write_response(stream: &mut TcpStream, resp: &Response) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        resp.status, reason(resp.status), resp.body.len(), resp.body,
+    )?;
+    stream.flush()
+}
+
+fn read_request(reader: &mut impl BufRead, config: &Config) -> io::Result<Response> {
+    let mut request_line = String::new();
+    if read_line_within(reader, config.max_request_line, &mut request_line)?.is_none() {
+        return Ok(Response::error(414, "request line too long"));
+    }
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    let mut content_length = 0;
+    let mut header_budget = config.max_headers;
+    loop {
+        let mut header = String::new();
+        let n = match read_line_within(reader, header_budget, &mut header)? {
+            Some(n) => n,
+            None => return Ok(Response::error(431, "headers too large")),
+        };
+        if n == 0 || header.trim().is_empty() {
+            break;
+        }
+        header_budget -= n;
+        if let Some((name, val)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = val.trim().parse().unwrap_or(usize::MAX);
+            }
+        }
+    }
+
+    if content_length > config.max_body {
+        return Ok(Response::error(413, "request too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(respond(method, path, &body, config))
+}
+
+fn handle_connection(stream: TcpStream, config: &Config) -> io::Result<()> {
+    stream.set_read_timeout(Some(config.read_timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let resp = read_request(&mut reader, config)?;
+    let mut stream = stream;
+    write_response(&mut stream, &resp)
+}
+
+/// Holds one of the `Config::max_connections` slots until dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(active: &Arc<AtomicUsize>, max: usize) -> Option<Slot> {
+        if active.fetch_add(1, Ordering::SeqCst) < max {
+            Some(Slot(Arc::clone(active)))
+        } else {
+            active.fetch_sub(1, Ordering::SeqCst);
+            None
+        }
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Listens on `config.addr`, handling each connection on its own thread,
+/// up to `config.max_connections` at once. Connections past that are
+/// answered with 503 straight away.
+pub fn serve(config: Config) -> io::Result<()> {
+    let listener = TcpListener::bind(&config.addr)?;
+    let config = Arc::new(config);
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        let slot = match Slot::take(&active, config.max_connections) {
+            Some(slot) => slot,
+            None => {
+                let _ = write_response(&mut stream, &Response::error(503, "too many connections"));
+                continue;
+            },
+        };
+        let config = Arc::clone(&config);
+        thread::spawn(move || {
+            let _slot = slot;
+            // A client going away mid-request is not the server's problem.
+            let _ = handle_connection(stream, &config);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn post(body: &str) -> Response {
+        respond("POST", "/eval", body.as_bytes(), &Config::default())
+    }
+
+    #[test]
+    fn test_eval_endpoint() {
+        assert_eq!(Response { status: 200, body: r#"{"result":"7","value":7}"#.to_string() },
+                   post(r#"{"expr": "1 + 2 * 3"}"#));
+        assert_eq!(r#"{"result":"5","value":5}"#, post(r#"{"expr": "x + 2", "vars": {"x": 3}}"#).body);
+        assert_eq!(r#"{"error":"division by zero"}"#, post(r#"{"expr": "1 / 0"}"#).body);
+
+        assert_eq!(400, post("not json").status);
+        assert_eq!(400, post(r#"{"expression": "1"}"#).status);
+        assert_eq!(404, respond("POST", "/", b"", &Config::default()).status);
+        assert_eq!(405, respond("GET", "/eval", b"", &Config::default()).status);
+    }
+
+    #[test]
+    fn test_guards() {
+        let long = format!(r#"{{"expr": "{}1"}}"#, "1 + ".repeat(5000));
+        assert_eq!(413, post(&long).status);
+        let deep = format!(r#"{{"expr": "simplify({}x)"}}"#, "-".repeat(4000));
+        assert_eq!(r#"{"error":"expression nested too deeply"}"#, post(&deep).body);
+        let nested = format!(r#"{{"expr": "{}1{}"}}"#, "(".repeat(200), ")".repeat(200));
+        assert_eq!(200, post(&nested).status);
+        // A deadline far enough away that only the step limit can be hit.
+        let config = Config { eval_timeout: Duration::from_secs(60), ..Config::default() };
+        let resp = respond("POST", "/eval", br#"{"expr": "sum(i, i, 1, 10^9)"}"#, &config);
+        assert_eq!(r#"{"error":"evaluation step limit exceeded"}"#, resp.body);
+
+        let config = Config { max_steps: u64::MAX, eval_timeout: Duration::ZERO, ..Config::default() };
+        let resp = respond("POST", "/eval", br#"{"expr": "sum(i, i, 1, 10^9)"}"#, &config);
+        assert_eq!(r#"{"error":"evaluation timed out"}"#, resp.body);
+    }
+
+    #[test]
+    fn test_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &Config::default()).unwrap();
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let body = r#"{"expr": "2^10"}"#;
+        write!(stream, "POST /eval HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.ends_with(r#"{"result":"1024","value":1024}"#));
+    }
+
+    #[test]
+    fn test_oversized_requests() {
+        let read = |req: String| read_request(&mut req.as_bytes(), &Config::default()).unwrap().status;
+        assert_eq!(414, read(format!("POST /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000))));
+        assert_eq!(431, read(format!("POST /eval HTTP/1.1\r\nX-Pad: {}\r\n\r\n", "a".repeat(20_000))));
+        assert_eq!(431, read(format!("POST /eval HTTP/1.1\r\n{}\r\n", "X-Pad: a\r\n".repeat(2000))));
+        assert_eq!(200, read("POST /eval HTTP/1.1\r\nContent-Length: 13\r\n\r\n{\"expr\": \"1\"}".to_string()));
+    }
+
+    #[test]
+    fn test_connection_slots() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let first = Slot::take(&active, 2).unwrap();
+        let _second = Slot::take(&active, 2).unwrap();
+        assert!(Slot::take(&active, 2).is_none());
+        drop(first);
+        assert!(Slot::take(&active, 2).is_some());
+        assert_eq!(1, active.load(Ordering::SeqCst));
+    }
+}
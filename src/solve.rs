@@ -0,0 +1,209 @@
+use crate::env::Environment;
+use crate::kalkul::{Error, Expr, OpKind, Result, Value};
+use crate::numeric;
+use crate::symbolic::{depends_on, derivative, simplify};
+
+/// Solves `equation` for `x`. Plain expressions are treated as being
+/// equal to zero.
+///
+/// Only equations that are linear or quadratic in `x` are supported.
+/// Other variables are looked up in `env`. A quadratic with two real
+/// roots gives a list of both in ascending order.
+pub fn solve(equation: &Expr, x: &str, env: &mut Environment) -> Result<Value> {
+    let f = match equation {
+        Expr::Binary(OpKind::Equals, lhs, rhs) => {
+            Expr::Binary(OpKind::Minus, lhs.clone(), rhs.clone())
+        },
+        e => e.clone(),
+    };
+
+    // Recover a*x^2 + b*x + c from the derivatives at zero, which also
+    // rejects anything that is not a polynomial of degree two or less.
+    let d1 = derivative(&f, x)?;
+    let d2 = derivative(&d1, x)?;
+    if simplify(&derivative(&d2, x)?, env)? != Expr::Lit(Value::Int(0)) {
+        return Err(Error::NotSolvable);
+    }
+    let at_zero = |e: &Expr, env: &mut Environment| simplify(&e.substitute(x, 0), env)?.eval(env);
+    let a = Value::apply(OpKind::Divide, at_zero(&d2, env)?, Value::Int(2))?;
+    let b = at_zero(&d1, env)?;
+    let c = at_zero(&f, env)?;
+
+    if a.as_f64()? == 0.0 {
+        if b.as_f64()? == 0.0 {
+            return match c.as_f64()? == 0.0 {
+                true  => Err(Error::InfiniteSolutions),
+                false => Err(Error::NoSolution),
+            };
+        }
+        let minus_c = Value::apply(OpKind::Minus, Value::Int(0), c)?;
+        return Value::apply(OpKind::Divide, minus_c, b);
+    }
+
+    let (a, b, c) = (a.as_f64()?, b.as_f64()?, c.as_f64()?);
+    let disc = b * b - 4.0 * a * c;
+    if disc < 0.0 {
+        return Err(Error::NoSolution);
+    }
+    let lo = (-b - disc.sqrt()) / (2.0 * a);
+    let hi = (-b + disc.sqrt()) / (2.0 * a);
+    let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+    if disc == 0.0 {
+        return Ok(root(lo));
+    }
+    Ok(Value::List(vec![root(lo), root(hi)]))
+}
+
+/// Finds the value of `x` for which `expr` comes to `target`, such as the
+/// rate at which a loan has a given payment.
+///
+/// When `x` appears only once, under operators and functions that can be
+/// undone, they are undone one by one, so `2*x + 3` gives back 4 for 11.
+/// Anything else is solved numerically, starting from the value `x`
+/// has in `env`, if any, and a few guesses. Other variables are looked up
+/// in `env`.
+pub fn invert(expr: &Expr, x: &str, target: Value, env: &mut Environment) -> Result<Value> {
+    if let Some(inverse) = undo(expr, x, Expr::Lit(target.clone())) {
+        return match inverse.eval(env)? {
+            Value::Float(y) if !y.is_finite() => Err(Error::NoSolution),
+            val => Ok(val),
+        };
+    }
+
+    let f = Expr::Binary(OpKind::Minus, Box::new(expr.clone()), Box::new(Expr::Lit(target)));
+    let current = env.get(x).and_then(|v| v.as_f64().ok());
+    for start in current.into_iter().chain(GUESSES) {
+        match numeric::findroot(&f, x, start, start, env) {
+            Ok(root) => return Ok(Value::Float(root)),
+            Err(Error::NoSolution | Error::DivisionByZero | Error::TypeError) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(Error::NoSolution)
+}
+
+/// Where `invert` starts looking for a solution it cannot find exactly.
+const GUESSES: [f64; 6] = [0.1, 1.0, 10.0, -1.0, 0.001, 1000.0];
+
+/// The expression for `x` that makes `e` equal `target`, if `e` can be
+/// undone step by step down to `x`.
+fn undo(e: &Expr, x: &str, target: Expr) -> Option<Expr> {
+    let bin = |kind, lhs, rhs| Expr::Binary(kind, Box::new(lhs), Box::new(rhs));
+    let call = |name: &str, arg| Expr::Call(name.to_string(), vec![arg]);
+    match e {
+        Expr::Var(name) if name == x => Some(target),
+        Expr::Neg(u) => undo(u, x, Expr::Neg(Box::new(target))),
+        Expr::Binary(kind, u, v) => {
+            let (in_u, in_v) = (depends_on(u, x), depends_on(v, x));
+            if in_u == in_v {
+                return None;
+            }
+            let (u, v) = ((**u).clone(), (**v).clone());
+            let (inner, target) = match (kind, in_u) {
+                (OpKind::Plus, true)      => (u, bin(OpKind::Minus, target, v)),
+                (OpKind::Plus, false)     => (v, bin(OpKind::Minus, target, u)),
+                (OpKind::Minus, true)     => (u, bin(OpKind::Plus, target, v)),
+                (OpKind::Minus, false)    => (v, bin(OpKind::Minus, u, target)),
+                (OpKind::Multiply, true)  => (u, bin(OpKind::Divide, target, v)),
+                (OpKind::Multiply, false) => (v, bin(OpKind::Divide, target, u)),
+                (OpKind::Divide, true)    => (u, bin(OpKind::Multiply, target, v)),
+                (OpKind::Divide, false)   => (v, bin(OpKind::Divide, u, target)),
+                // Only the positive root of an even power.
+                (OpKind::Power, true)     => (u, bin(OpKind::Power, target, bin(OpKind::Divide, Expr::Lit(Value::Float(1.0)), v))),
+                (OpKind::Power, false)    => (v, bin(OpKind::Divide, call("ln", target), call("ln", u))),
+                _ => return None,
+            };
+            undo(&inner, x, target)
+        },
+        Expr::Call(name, args) if args.len() == 1 => {
+            let target = match name.as_str() {
+                "exp"  => call("ln", target),
+                "ln"   => call("exp", target),
+                "sqrt" => bin(OpKind::Power, target, Expr::Lit(Value::Int(2))),
+                _ => return None,
+            };
+            undo(&args[0], x, target)
+        },
+        _ => None,
+    }
+}
+
+fn root(x: f64) -> Value {
+    if x.fract() == 0.0 && x.abs() < i64::MAX as f64 {
+        Value::Int(x as i64)
+    } else {
+        Value::Float(x)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_solve() {
+        let exprs = [
+            "solve(2*x + 3 = 11, x)",
+            "solve(x / 4 = 1 - x, x)",
+            "solve(x^2 = 4, x)",
+            "solve(x^2 - 2*x + 1, x)",
+            "solve(2*y = a, y)",
+            "solve(x^2 + x = 1, x)",
+        ];
+        let answers = [
+            "4",
+            "0.8",
+            "[-2, 2]",
+            "1",
+            "5",
+            "[-1.618033988749895, 0.6180339887498949]",
+        ];
+
+        let mut env = Environment::new();
+        env.set("a", Value::Int(10));
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+
+        assert!(matches!(env.eval("solve(x^3 = 8, x)"), Err(Error::NotSolvable)));
+        assert!(matches!(env.eval("solve(sin(x) = 0, x)"), Err(Error::NotSolvable)));
+        assert!(matches!(env.eval("solve(x^2 = -1, x)"), Err(Error::NoSolution)));
+        assert!(matches!(env.eval("solve(x + 1 = x, x)"), Err(Error::NoSolution)));
+        assert!(matches!(env.eval("solve(x = x, x)"), Err(Error::InfiniteSolutions)));
+    }
+
+    #[test]
+    fn test_invert() {
+        let exprs = [
+            "invert(2*x + 3, x, 11)",
+            "invert(10 - x, x, 4)",
+            "invert(12 / x, x, 3)",
+            "invert(-sqrt(x), x, -3)",
+            "invert(2^x, x, 1024)",
+            "invert(exp(x / 2), x, 1)",
+            "invert(x^3 + x, x, 10)",
+        ];
+        let answers = [
+            "4",
+            "6",
+            "4",
+            "9",
+            "10",
+            "0",
+            "2",
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+
+        // The rate at which paying 88.85 a month pays off 1000 in a year.
+        let pmt = Expr::parse("pmt(rate, 12, principal)").unwrap();
+        env.set("principal", Value::Int(1000));
+        let rate = invert(&pmt, "rate", Value::Float(-88.85), &mut env).unwrap().as_f64().unwrap();
+        assert!((rate - 0.01).abs() < 1e-4, "{}", rate);
+        assert!(env.get("rate").is_none());
+        assert!(matches!(env.eval("invert(x^2 + 1, x, 0)"), Err(Error::NoSolution)));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::kalkul::{Error, Result};
+
+/// Probability that a standard normal variable is at most `x`, after
+/// Graeme West's implementation of Hart's algorithm 5666, accurate to
+/// double precision.
+pub fn normcdf(x: f64) -> f64 {
+    let z = x.abs();
+    let tail = if z > 37.0 {
+        0.0
+    } else if z < 7.07106781186547 {
+        let n = (((((0.0352624965998911 * z + 0.700383064443688) * z + 6.37396220353165) * z
+            + 33.912866078383) * z + 112.079291497871) * z + 221.213596169931) * z + 220.206867912376;
+        let d = ((((((0.0883883476483184 * z + 1.75566716318264) * z + 16.064177579207) * z
+            + 86.7807322029461) * z + 296.564248779674) * z + 637.333633378831) * z + 793.826512519948) * z
+            + 440.413735824752;
+        (-z * z / 2.0).exp() * n / d
+    } else {
+        let f = z + 1.0 / (z + 2.0 / (z + 3.0 / (z + 4.0 / (z + 0.65))));
+        (-z * z / 2.0).exp() / f / 2.506628274631
+    };
+    if x <= 0.0 { tail } else { 1.0 - tail }
+}
+
+/// The `x` for which `normcdf(x)` is `p`: Peter Acklam's approximation,
+/// refined by a step of Halley's method.
+pub fn norminv(p: f64) -> Result<f64> {
+    const A: [f64; 6] = [-39.69683028665376, 220.9460984245205, -275.9285104469687, 138.357751867269, -30.66479806614716, 2.506628277459239];
+    const B: [f64; 5] = [-54.47609879822406, 161.5858368580409, -155.6989798598866, 66.80131188771972, -13.28068155288572];
+    const C: [f64; 6] = [-0.007784894002430293, -0.3223964580411365, -2.400758277161838, -2.549732539343734, 4.374664141464968, 2.938163982698783];
+    const D: [f64; 4] = [0.007784695709041462, 0.3224671290700398, 2.445134137142996, 3.754408661907416];
+    const LOW: f64 = 0.02425;
+
+    if !(0.0..=1.0).contains(&p) {
+        return Err(Error::TypeError);
+    }
+    if p == 0.0 || p == 1.0 {
+        return Ok(if p == 0.0 { f64::NEG_INFINITY } else { f64::INFINITY });
+    }
+    let poly = |coeffs: &[f64], x: f64| coeffs.iter().fold(0.0, |acc, c| acc * x + c);
+    let x = if !(LOW..=1.0 - LOW).contains(&p) {
+        let q = (-2.0 * p.min(1.0 - p).ln()).sqrt();
+        let x = poly(&C, q) / (poly(&D, q) * q + 1.0);
+        if p < LOW { x } else { -x }
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        poly(&A, r) * q / (poly(&B, r) * r + 1.0)
+    };
+    let e = normcdf(x) - p;
+    let u = e * (2.0 * std::f64::consts::PI).sqrt() * (x * x / 2.0).exp();
+    Ok(x - u / (1.0 + x * u / 2.0))
+}
+
+/// `ln(n!)`, summed directly since arguments stay small enough.
+fn ln_factorial(n: u64) -> f64 {
+    (2..=n).map(|i| (i as f64).ln()).sum()
+}
+
+/// Probability of exactly `k` successes in `n` trials of probability `p`.
+pub fn binompdf(n: u64, p: f64, k: u64) -> Result<f64> {
+    if !(0.0..=1.0).contains(&p) {
+        return Err(Error::TypeError);
+    }
+    if k > n {
+        return Ok(0.0);
+    }
+    // 0^0 is 1 here, for p of 0 or 1.
+    let pow = |x: f64, e: u64| if e == 0 { 0.0 } else { e as f64 * x.ln() };
+    let ln_choose = ln_factorial(n) - ln_factorial(k) - ln_factorial(n - k);
+    Ok((ln_choose + pow(p, k) + pow(1.0 - p, n - k)).exp())
+}
+
+/// Probability of exactly `k` events when `lambda` are expected.
+pub fn poissonpdf(lambda: f64, k: u64) -> Result<f64> {
+    if lambda < 0.0 {
+        return Err(Error::TypeError);
+    }
+    if lambda == 0.0 {
+        return Ok(if k == 0 { 1.0 } else { 0.0 });
+    }
+    Ok((k as f64 * lambda.ln() - lambda - ln_factorial(k)).exp())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::Environment;
+
+    #[test]
+    fn test_distributions() {
+        let exprs = [
+            "normcdf(0)",
+            "normcdf(1.96)",
+            "normcdf(-3)",
+            "normcdf(110, 100, 15)",
+            "norminv(0.975)",
+            "norminv(0.001)",
+            "norminv(0.5, 100, 15)",
+            "binompdf(10, 0.5, 3)",
+            "binompdf(5, 1, 5)",
+            "binompdf(5, 0.3, 6)",
+            "poissonpdf(2, 3)",
+            "poissonpdf(0, 0)",
+        ];
+        let answers = [
+            0.5,
+            0.9750021048517795,
+            0.0013498980316301,
+            0.7475074624530771,
+            1.959963984540054,
+            -3.090232306167813,
+            100.0,
+            0.1171875,
+            1.0,
+            0.0,
+            0.1804470443154836,
+            1.0,
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            let x = env.eval(expr).unwrap().as_f64().unwrap();
+            assert!((x - ans).abs() < 1e-12, "{} = {}", expr, x);
+        }
+        for p in [1e-10, 0.01, 0.3, 0.77, 0.999] {
+            assert!((normcdf(norminv(p).unwrap()) - p).abs() < 1e-14 * p.max(0.1), "{}", p);
+        }
+        for expr in ["norminv(1.5)", "normcdf(1, 0, 0)", "binompdf(10, 2, 3)", "binompdf(-1, 0.5, 0)", "poissonpdf(-1, 2)"] {
+            assert!(matches!(env.eval(expr), Err(Error::TypeError)), "{}", expr);
+        }
+    }
+}
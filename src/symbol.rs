@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+/// Refers to a name interned in a `SymbolTable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SymbolId(u32);
+
+impl SymbolId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Names numbered in the order they were first seen, so that what is
+/// kept for each name can live in a vector indexed by its `SymbolId`
+/// rather than in a map hashing the name on every access. Names are
+/// never removed, so an id stays valid for the life of the table.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    ids: HashMap<String, SymbolId>,
+    names: Vec<String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    /// The id of `name`, adding it if it is new.
+    pub fn intern(&mut self, name: &str) -> SymbolId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = SymbolId(self.names.len() as u32);
+        self.ids.insert(name.to_string(), id);
+        self.names.push(name.to_string());
+        id
+    }
+
+    /// The id of `name`, if it was ever interned.
+    pub fn lookup(&self, name: &str) -> Option<SymbolId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: SymbolId) -> &str {
+        &self.names[id.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Every id with its name, in the order they were interned.
+    pub fn iter(&self) -> impl Iterator<Item = (SymbolId, &str)> {
+        self.names.iter().enumerate().map(|(i, name)| (SymbolId(i as u32), name.as_str()))
+    }
+}
@@ -0,0 +1,814 @@
+use crate::builtins;
+use crate::env::Environment;
+use crate::kalkul::{Error, Expr, OpKind, Result, Value};
+
+fn lit(n: i64) -> Expr {
+    Expr::Lit(Value::Int(n))
+}
+
+fn bin(kind: OpKind, lhs: Expr, rhs: Expr) -> Expr {
+    Expr::Binary(kind, Box::new(lhs), Box::new(rhs))
+}
+
+fn call(name: &str, arg: Expr) -> Expr {
+    Expr::Call(name.to_string(), vec![arg])
+}
+
+fn is_num(e: &Expr) -> bool {
+    matches!(e, Expr::Lit(Value::Int(_)) | Expr::Lit(Value::Float(_)))
+}
+
+fn is_lit(e: &Expr, n: i64) -> bool {
+    match e {
+        Expr::Lit(Value::Int(m))   => *m == n,
+        Expr::Lit(Value::Float(x)) => *x == n as f64,
+        _ => false,
+    }
+}
+
+fn is_negative(e: &Expr) -> bool {
+    match e {
+        Expr::Lit(Value::Int(n))   => *n < 0,
+        Expr::Lit(Value::Float(x)) => *x < 0.0,
+        _ => false,
+    }
+}
+
+/// Whether `e` mentions the variable `x` anywhere.
+pub fn depends_on(e: &Expr, x: &str) -> bool {
+    match e {
+        Expr::Lit(_) => false,
+        Expr::Var(name) => name == x,
+        Expr::Neg(u) => depends_on(u, x),
+        Expr::Binary(_, u, v) => depends_on(u, x) || depends_on(v, x),
+        Expr::Call(_, args) | Expr::List(args) | Expr::Tuple(args) => args.iter().any(|arg| depends_on(arg, x)),
+    }
+}
+
+/// Adds the variables mentioned in `e` to `vars`, in order of first
+/// appearance.
+pub fn variables(e: &Expr, vars: &mut Vec<String>) {
+    match e {
+        Expr::Lit(_) => {},
+        Expr::Var(name) => {
+            if !vars.contains(name) {
+                vars.push(name.clone());
+            }
+        },
+        Expr::Neg(u) => variables(u, vars),
+        Expr::Binary(_, u, v) => {
+            variables(u, vars);
+            variables(v, vars);
+        },
+        Expr::Call(_, args) | Expr::List(args) | Expr::Tuple(args) => {
+            for arg in args {
+                variables(arg, vars);
+            }
+        },
+    }
+}
+
+/// Prepares a form argument for symbolic manipulation: variables holding
+/// symbolic results are replaced by those results and nested forms, as
+/// in `diff(diff(f, x), x)`, are evaluated.
+pub fn resolve(e: &Expr, env: &mut Environment) -> Result<Expr> {
+    match e {
+        Expr::Lit(_) => Ok(e.clone()),
+        Expr::Var(name) => match env.get(name) {
+            Some(Value::Expr(sym)) => Ok((**sym).clone()),
+            _ => Ok(e.clone()),
+        },
+        Expr::Neg(u) => Ok(Expr::Neg(Box::new(resolve(u, env)?))),
+        Expr::Binary(kind, u, v) => Ok(bin(*kind, resolve(u, env)?, resolve(v, env)?)),
+        Expr::Call(name, args) => {
+            if builtins::lookup_form(name).is_some() {
+                return match e.eval(env)? {
+                    Value::Expr(sym) => Ok(*sym),
+                    val => Ok(Expr::Lit(val)),
+                };
+            }
+            let mut resolved = Vec::with_capacity(args.len());
+            for arg in args {
+                resolved.push(resolve(arg, env)?);
+            }
+            Ok(Expr::Call(name.clone(), resolved))
+        },
+        Expr::List(items) => Ok(Expr::List(items.iter().map(|u| resolve(u, env)).collect::<Result<_>>()?)),
+        Expr::Tuple(items) => Ok(Expr::Tuple(items.iter().map(|u| resolve(u, env)).collect::<Result<_>>()?)),
+    }
+}
+
+/// `e` with everything that can be worked out from the variables of
+/// `env` evaluated, leaving an expression in the variables that are not
+/// set, such as `2 * rate + 1` for `principal * rate + 1` when
+/// `principal = 2`. Lambdas are left as they are, and errors other than
+/// unknown variables are returned.
+pub fn partial_eval(e: &Expr, env: &mut Environment) -> Result<Expr> {
+    let residual = match e {
+        Expr::Lit(_) => return Ok(e.clone()),
+        Expr::Var(name) => match env.read(name) {
+            Ok(Value::Expr(sym)) => return Ok(*sym),
+            Ok(val) => return Ok(Expr::Lit(val)),
+            Err(Error::UnknownVariable(_)) => return Ok(e.clone()),
+            Err(e) => return Err(e),
+        },
+        Expr::Binary(OpKind::Arrow, _, _) => return Ok(e.clone()),
+        // Forms bind variables of their own, so theirs are not replaced.
+        Expr::Call(name, _) if builtins::lookup_form(name).is_some() => e.clone(),
+        Expr::Neg(u) => Expr::Neg(Box::new(partial_eval(u, env)?)),
+        Expr::Binary(kind, u, v) => bin(*kind, partial_eval(u, env)?, partial_eval(v, env)?),
+        Expr::Call(name, args) => Expr::Call(name.clone(), args.iter().map(|u| partial_eval(u, env)).collect::<Result<_>>()?),
+        Expr::List(items) => Expr::List(items.iter().map(|u| partial_eval(u, env)).collect::<Result<_>>()?),
+        Expr::Tuple(items) => Expr::Tuple(items.iter().map(|u| partial_eval(u, env)).collect::<Result<_>>()?),
+    };
+    let mut vars = Vec::new();
+    variables(&residual, &mut vars);
+    if !vars.is_empty() && !matches!(residual, Expr::Call(..)) {
+        return Ok(residual);
+    }
+    // A call can read variables other than its arguments, or bind them.
+    match residual.eval(env) {
+        Ok(Value::Expr(sym)) => Ok(*sym),
+        Ok(val) => Ok(Expr::Lit(val)),
+        Err(Error::UnknownVariable(_)) => Ok(residual),
+        Err(e) => Err(e),
+    }
+}
+
+/// Differentiates `e` with respect to `x`. The result is not simplified.
+pub fn derivative(e: &Expr, x: &str) -> Result<Expr> {
+    if !depends_on(e, x) {
+        return Ok(lit(0));
+    }
+
+    match e {
+        Expr::Lit(_) => Ok(lit(0)),
+        Expr::Var(_) => Ok(lit(1)),
+        Expr::Neg(u) => Ok(Expr::Neg(Box::new(derivative(u, x)?))),
+        Expr::Binary(kind, u, v) => {
+            let (u, v) = (&**u, &**v);
+            let du = derivative(u, x)?;
+            let dv = derivative(v, x)?;
+            match kind {
+                OpKind::Plus | OpKind::Minus => Ok(bin(*kind, du, dv)),
+                OpKind::Multiply => Ok(bin(
+                    OpKind::Plus,
+                    bin(OpKind::Multiply, du, v.clone()),
+                    bin(OpKind::Multiply, u.clone(), dv),
+                )),
+                OpKind::Divide => Ok(bin(
+                    OpKind::Divide,
+                    bin(
+                        OpKind::Minus,
+                        bin(OpKind::Multiply, du, v.clone()),
+                        bin(OpKind::Multiply, u.clone(), dv),
+                    ),
+                    bin(OpKind::Power, v.clone(), lit(2)),
+                )),
+                OpKind::Power if !depends_on(v, x) => {
+                    // d(u^n) = n * u^(n - 1) * u'
+                    let lowered = bin(OpKind::Power, u.clone(), bin(OpKind::Minus, v.clone(), lit(1)));
+                    Ok(bin(OpKind::Multiply, bin(OpKind::Multiply, v.clone(), lowered), du))
+                },
+                OpKind::Power => {
+                    // d(u^v) = u^v * (v' * ln(u) + v * u' / u)
+                    let inner = bin(
+                        OpKind::Plus,
+                        bin(OpKind::Multiply, dv, call("ln", u.clone())),
+                        bin(OpKind::Divide, bin(OpKind::Multiply, v.clone(), du), u.clone()),
+                    );
+                    Ok(bin(OpKind::Multiply, e.clone(), inner))
+                },
+                _ => Err(Error::NotDifferentiable),
+            }
+        },
+        Expr::List(_) | Expr::Tuple(_) => Err(Error::NotDifferentiable),
+        Expr::Call(name, args) => {
+            if args.len() != 1 {
+                return Err(Error::NotDifferentiable);
+            }
+            let u = &args[0];
+            let du = derivative(u, x)?;
+            let outer = match name.as_str() {
+                "sin"  => call("cos", u.clone()),
+                "cos"  => Expr::Neg(Box::new(call("sin", u.clone()))),
+                "exp"  => call("exp", u.clone()),
+                "tan"  => return Ok(bin(OpKind::Divide, du, bin(OpKind::Power, call("cos", u.clone()), lit(2)))),
+                "ln"   => return Ok(bin(OpKind::Divide, du, u.clone())),
+                "sqrt" => return Ok(bin(OpKind::Divide, du, bin(OpKind::Multiply, lit(2), e.clone()))),
+                _ => return Err(Error::NotDifferentiable),
+            };
+            Ok(bin(OpKind::Multiply, outer, du))
+        },
+    }
+}
+
+fn neg(e: Expr) -> Expr {
+    match e {
+        Expr::Lit(Value::Int(n)) if n != i64::MIN => lit(-n),
+        Expr::Lit(Value::Float(x)) => Expr::Lit(Value::Float(-x)),
+        Expr::Neg(u) => *u,
+        e => Expr::Neg(Box::new(e)),
+    }
+}
+
+/// Rewrites `e` using algebraic identities: constants are folded, like
+/// terms are combined and common factors cancelled. Every subexpression
+/// visited is a step counted against the limits of `env`, and dividing
+/// by a literal zero fails with `Error::DivisionByZero`.
+pub fn simplify(e: &Expr, env: &mut Environment) -> Result<Expr> {
+    let tick = &mut || env.tick();
+    normalize(&fold(e, tick)?, tick)
+}
+
+/// `e` with its constants folded and the operands of commutative
+/// operators in a fixed order, so that `b + a` and `1 + 1 + a` both come
+/// out as `2 + a`. Unlike `simplify` it only rewrites what evaluates to
+/// the same value: sums are not regrouped, since that is not exact in
+/// floating point, and identities such as `x/x = 1` are not applied,
+/// since they fail for some `x`.
+pub fn canonical(e: &Expr) -> Expr {
+    order(&fold_constants(e))
+}
+
+/// Folds the operators whose operands are all literals.
+fn fold_constants(e: &Expr) -> Expr {
+    match e {
+        Expr::Lit(_) | Expr::Var(_) => e.clone(),
+        Expr::Neg(u) => match fold_constants(u) {
+            u @ Expr::Lit(_) => neg(u),
+            u => Expr::Neg(Box::new(u)),
+        },
+        Expr::Call(name, args) => Expr::Call(name.clone(), args.iter().map(fold_constants).collect()),
+        Expr::List(items) => Expr::List(items.iter().map(fold_constants).collect()),
+        Expr::Tuple(items) => Expr::Tuple(items.iter().map(fold_constants).collect()),
+        Expr::Binary(OpKind::Arrow, u, v) => bin(OpKind::Arrow, (**u).clone(), fold_constants(v)),
+        Expr::Binary(kind, u, v) => {
+            let (u, v) = (fold_constants(u), fold_constants(v));
+            match (&u, &v) {
+                (Expr::Lit(a), Expr::Lit(b)) => fold_literals(*kind, a, b).unwrap_or_else(|| bin(*kind, u, v)),
+                _ => bin(*kind, u, v),
+            }
+        },
+    }
+}
+
+/// `a kind b` worked out, unless that fails or would lose information.
+fn fold_literals(kind: OpKind, a: &Value, b: &Value) -> Option<Expr> {
+    let float_input = matches!(a, Value::Float(_)) || matches!(b, Value::Float(_));
+    match Value::apply(kind, a.clone(), b.clone()) {
+        // Folding 1/3 into 0.333... would lose information.
+        Ok(val @ Value::Int(_)) => Some(Expr::Lit(val)),
+        Ok(val) if float_input => Some(Expr::Lit(val)),
+        _ => None,
+    }
+}
+
+fn order(e: &Expr) -> Expr {
+    match e {
+        Expr::Lit(_) | Expr::Var(_) => e.clone(),
+        Expr::Neg(u) => Expr::Neg(Box::new(order(u))),
+        Expr::Call(name, args) => Expr::Call(name.clone(), args.iter().map(order).collect()),
+        Expr::List(items) => Expr::List(items.iter().map(order).collect()),
+        Expr::Tuple(items) => Expr::Tuple(items.iter().map(order).collect()),
+        Expr::Binary(kind, u, v) => {
+            let (u, v) = (order(u), order(v));
+            let commutative = matches!(kind,
+                OpKind::Plus | OpKind::Multiply | OpKind::BitAnd | OpKind::BitOr | OpKind::BitXor |
+                OpKind::Equal | OpKind::NotEqual);
+            if commutative && u.to_string() > v.to_string() { bin(*kind, v, u) } else { bin(*kind, u, v) }
+        },
+    }
+}
+
+/// Points at which `equiv` compares expressions it cannot prove equal.
+const EQUIV_SAMPLES: usize = 32;
+
+/// Whether `a` and `b` are the same function of their free variables.
+///
+/// Expressions are equivalent if they simplify to the same form or their
+/// difference simplifies to zero. Failing that, both are evaluated at
+/// random points and compared numerically, which catches identities the
+/// simplifier does not know, such as `sin(x)^2 + cos(x)^2 = 1`. Points
+/// where neither is defined are skipped; points where only one is defined
+/// show they differ.
+pub fn equiv(a: &Expr, b: &Expr, env: &mut Environment) -> Result<bool> {
+    let difference = simplify(&bin(OpKind::Minus, a.clone(), b.clone()), env);
+    if simplify(a, env)? == simplify(b, env)? || difference.is_ok_and(|d| is_lit(&d, 0)) {
+        return Ok(true);
+    }
+
+    let mut vars = Vec::new();
+    variables(a, &mut vars);
+    variables(b, &mut vars);
+    vars.retain(|v| env.get(v).is_none());
+
+    // A fixed seed keeps the answer the same from run to run.
+    let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut random = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed >> 11) as f64 / (1u64 << 53) as f64 * 20.0 - 10.0
+    };
+
+    let mut compared = 0;
+    for _ in 0..EQUIV_SAMPLES {
+        let (mut ea, mut eb) = (a.clone(), b.clone());
+        for v in &vars {
+            let x = random();
+            ea = ea.substitute(v, x);
+            eb = eb.substitute(v, x);
+        }
+        let ya = ea.eval(env).and_then(|v| v.as_f64()).ok().filter(|y| y.is_finite());
+        let yb = eb.eval(env).and_then(|v| v.as_f64()).ok().filter(|y| y.is_finite());
+        match (ya, yb) {
+            (None, None) => continue,
+            (Some(ya), Some(yb)) if (ya - yb).abs() <= 1e-9 * (1.0 + ya.abs().max(yb.abs())) => {
+                compared += 1;
+            },
+            _ => return Ok(false),
+        }
+    }
+    Ok(compared > 0)
+}
+
+/// Applies local identities such as `x*1 => x` and folds constants,
+/// calling `tick` for every subexpression.
+fn fold(e: &Expr, tick: &mut dyn FnMut() -> Result<()>) -> Result<Expr> {
+    tick()?;
+    Ok(match e {
+        Expr::Lit(_) | Expr::Var(_) => e.clone(),
+        Expr::Neg(u) => neg(fold(u, tick)?),
+        Expr::Call(name, args) => Expr::Call(name.clone(), args.iter().map(|u| fold(u, tick)).collect::<Result<_>>()?),
+        Expr::List(items) => Expr::List(items.iter().map(|u| fold(u, tick)).collect::<Result<_>>()?),
+        Expr::Tuple(items) => Expr::Tuple(items.iter().map(|u| fold(u, tick)).collect::<Result<_>>()?),
+        Expr::Binary(OpKind::Arrow, u, v) => bin(OpKind::Arrow, (**u).clone(), fold(v, tick)?),
+        Expr::Binary(kind, u, v) => simplify_binary(*kind, fold(u, tick)?, fold(v, tick)?),
+    })
+}
+
+fn simplify_binary(kind: OpKind, u: Expr, v: Expr) -> Expr {
+    if let (Expr::Lit(a), Expr::Lit(b)) = (&u, &v) {
+        if let Some(folded) = fold_literals(kind, a, b) {
+            return folded;
+        }
+    }
+
+    match kind {
+        OpKind::Plus if is_lit(&u, 0) => v,
+        OpKind::Plus if is_lit(&v, 0) => u,
+        OpKind::Plus if is_negative(&v) => simplify_binary(OpKind::Minus, u, neg(v)),
+        OpKind::Plus => match v {
+            Expr::Neg(v) => simplify_binary(OpKind::Minus, u, *v),
+            v => bin(kind, u, v),
+        },
+
+        OpKind::Minus if is_lit(&v, 0) => u,
+        OpKind::Minus if is_lit(&u, 0) => neg(v),
+        OpKind::Minus if u == v => lit(0),
+        OpKind::Minus if is_negative(&v) => simplify_binary(OpKind::Plus, u, neg(v)),
+        OpKind::Minus => match v {
+            Expr::Neg(v) => simplify_binary(OpKind::Plus, u, *v),
+            v => bin(kind, u, v),
+        },
+
+        OpKind::Multiply if is_lit(&u, 0) || is_lit(&v, 0) => lit(0),
+        OpKind::Multiply if is_lit(&u, 1) => v,
+        OpKind::Multiply if is_lit(&v, 1) => u,
+        OpKind::Multiply if is_lit(&u, -1) => neg(v),
+        OpKind::Multiply if is_num(&v) && !is_num(&u) => simplify_binary(kind, v, u),
+        OpKind::Multiply => match (u, v) {
+            // Gather constant factors on the left: 2*(3*x) => 6*x.
+            (Expr::Lit(a), Expr::Binary(OpKind::Multiply, b, rest)) if is_num(&b) => {
+                let factor = simplify_binary(kind, Expr::Lit(a), *b);
+                simplify_binary(kind, factor, *rest)
+            },
+            (u, v) => bin(kind, u, v),
+        },
+
+        OpKind::Divide if is_lit(&v, 1) => u,
+        OpKind::Divide if is_lit(&u, 0) && !is_lit(&v, 0) => lit(0),
+        OpKind::Divide if u == v && !is_lit(&v, 0) => lit(1),
+
+        OpKind::Power if is_lit(&v, 1) => u,
+        OpKind::Power if is_lit(&v, 0) => lit(1),
+        OpKind::Power if is_lit(&u, 1) => lit(1),
+
+        _ => bin(kind, u, v),
+    }
+}
+
+/// A numeric coefficient, kept as an exact fraction for as long as the
+/// arithmetic allows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Coeff {
+    Ratio(i64, i64),
+    Float(f64),
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 { a.abs() } else { gcd(b, a % b) }
+}
+
+impl Coeff {
+    fn from_value(v: &Value) -> Option<Coeff> {
+        match v {
+            Value::Int(n)   => Some(Coeff::Ratio(*n, 1)),
+            Value::Float(x) => Some(Coeff::Float(*x)),
+            _ => None,
+        }
+    }
+
+    /// `n/d` in lowest terms, where `d` is not zero.
+    fn ratio(n: i128, d: i128) -> Coeff {
+        let g = gcd(n, d) * d.signum();
+        match (i64::try_from(n / g), i64::try_from(d / g)) {
+            (Ok(n), Ok(d)) => Coeff::Ratio(n, d),
+            _ => Coeff::Float(n as f64 / d as f64),
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Coeff::Ratio(n, d) => n as f64 / d as f64,
+            Coeff::Float(x) => x,
+        }
+    }
+
+    fn add(self, other: Coeff) -> Coeff {
+        match (self, other) {
+            (Coeff::Ratio(a, b), Coeff::Ratio(c, d)) => {
+                let (a, b, c, d) = (a as i128, b as i128, c as i128, d as i128);
+                Coeff::ratio(a * d + c * b, b * d)
+            },
+            _ => Coeff::Float(self.as_f64() + other.as_f64()),
+        }
+    }
+
+    fn mul(self, other: Coeff) -> Coeff {
+        match (self, other) {
+            (Coeff::Ratio(a, b), Coeff::Ratio(c, d)) => {
+                Coeff::ratio(a as i128 * c as i128, b as i128 * d as i128)
+            },
+            _ => Coeff::Float(self.as_f64() * other.as_f64()),
+        }
+    }
+
+    fn recip(self) -> Result<Coeff> {
+        match self {
+            _ if self.is_zero() => Err(Error::DivisionByZero),
+            Coeff::Ratio(n, d) => Ok(Coeff::ratio(d as i128, n as i128)),
+            Coeff::Float(x) => Ok(Coeff::Float(1.0 / x)),
+        }
+    }
+
+    fn neg(self) -> Coeff {
+        self.mul(Coeff::Ratio(-1, 1))
+    }
+
+    fn is_zero(self) -> bool {
+        self.as_f64() == 0.0
+    }
+
+    fn is_one(self) -> bool {
+        self == Coeff::Ratio(1, 1) || self == Coeff::Float(1.0)
+    }
+
+    fn is_negative(self) -> bool {
+        self.as_f64() < 0.0
+    }
+
+    fn as_integer(self) -> Option<i64> {
+        match self {
+            Coeff::Ratio(n, 1) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Raises the coefficient to `n` by repeated squaring, carrying on
+    /// in floating point once a fraction no longer fits.
+    fn powi(self, n: i64) -> Result<Coeff> {
+        let mut base = if n < 0 { self.recip()? } else { self };
+        let mut acc = Coeff::Ratio(1, 1);
+        let mut k = n.unsigned_abs();
+        while k > 0 {
+            if k & 1 == 1 {
+                acc = acc.mul(base);
+            }
+            k >>= 1;
+            if k > 0 {
+                base = base.mul(base);
+            }
+        }
+        Ok(acc)
+    }
+
+    /// Splits the coefficient into the numeric numerator and denominator
+    /// to write out.
+    fn split(self) -> (Value, Option<Value>) {
+        match self {
+            Coeff::Ratio(n, 1) => (Value::Int(n), None),
+            Coeff::Ratio(n, d) => (Value::Int(n), Some(Value::Int(d))),
+            Coeff::Float(x)    => (Value::Float(x), None),
+        }
+    }
+}
+
+/// A product of a coefficient and factors raised to numeric powers.
+#[derive(Debug, Clone)]
+struct Term {
+    coeff: Coeff,
+    factors: Vec<(Expr, Coeff)>,
+}
+
+impl Term {
+    fn new() -> Self {
+        Term {
+            coeff: Coeff::Ratio(1, 1),
+            factors: Vec::new(),
+        }
+    }
+
+    fn push_factor(&mut self, base: Expr, exp: Coeff) -> Result<()> {
+        if let Expr::Lit(v) = &base {
+            if let (Some(c), Some(n)) = (Coeff::from_value(v), exp.as_integer()) {
+                self.coeff = self.coeff.mul(c.powi(n)?);
+                return Ok(());
+            }
+        }
+        match self.factors.iter_mut().find(|(b, _)| *b == base) {
+            Some((_, e)) => *e = e.add(exp),
+            None => self.factors.push((base, exp)),
+        }
+        self.factors.retain(|(_, e)| !e.is_zero());
+        Ok(())
+    }
+
+    /// Collects the factors of `e`, raised to `exp`.
+    fn collect(&mut self, e: &Expr, exp: Coeff, tick: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        tick()?;
+        match e {
+            Expr::Neg(u) => {
+                if exp.as_integer().map(|n| n % 2 != 0).unwrap_or(false) {
+                    self.coeff = self.coeff.neg();
+                }
+                self.collect(u, exp, tick)
+            },
+            Expr::Binary(OpKind::Multiply, u, v) => {
+                self.collect(u, exp, tick)?;
+                self.collect(v, exp, tick)
+            },
+            Expr::Binary(OpKind::Divide, u, v) => {
+                self.collect(u, exp, tick)?;
+                self.collect(v, exp.neg(), tick)
+            },
+            Expr::Binary(OpKind::Power, u, v) => match &**v {
+                Expr::Lit(n) if Coeff::from_value(n).is_some() => {
+                    self.collect(u, exp.mul(Coeff::from_value(n).unwrap()), tick)
+                },
+                _ => self.push_factor(normalize(e, tick)?, exp),
+            },
+            _ => self.push_factor(normalize(e, tick)?, exp),
+        }
+    }
+
+    fn degree(&self) -> f64 {
+        self.factors.iter().map(|(_, e)| e.as_f64()).sum()
+    }
+
+    fn to_expr(&self) -> Expr {
+        let power = |base: &Expr, exp: Coeff| {
+            if exp.is_one() {
+                base.clone()
+            } else {
+                let (n, d) = exp.split();
+                let exp = match d {
+                    Some(d) => bin(OpKind::Divide, Expr::Lit(n), Expr::Lit(d)),
+                    None => Expr::Lit(n),
+                };
+                bin(OpKind::Power, base.clone(), exp)
+            }
+        };
+        let product = |factors: Vec<Expr>| {
+            factors.into_iter().reduce(|acc, f| bin(OpKind::Multiply, acc, f))
+        };
+
+        let (n, d) = self.coeff.split();
+        let mut num = Vec::new();
+        let mut den = Vec::new();
+        let has_num = self.factors.iter().any(|(_, e)| !e.is_negative());
+        let n = Expr::Lit(n);
+        let minus_one = is_lit(&n, -1);
+        if !has_num || !(is_lit(&n, 1) || minus_one) {
+            num.push(n);
+        }
+        if let Some(d) = d {
+            den.push(Expr::Lit(d));
+        }
+        for (base, exp) in &self.factors {
+            if exp.is_negative() {
+                den.push(power(base, exp.neg()));
+            } else {
+                num.push(power(base, *exp));
+            }
+        }
+
+        let mut num = product(num).unwrap();
+        if has_num && minus_one {
+            num = Expr::Neg(Box::new(num));
+        }
+        match product(den) {
+            Some(den) => bin(OpKind::Divide, num, den),
+            None => num,
+        }
+    }
+}
+
+fn collect_sum(e: &Expr, negate: bool, terms: &mut Vec<Term>, tick: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+    tick()?;
+    match e {
+        Expr::Binary(OpKind::Plus, u, v) => {
+            collect_sum(u, negate, terms, tick)?;
+            collect_sum(v, negate, terms, tick)?;
+        },
+        Expr::Binary(OpKind::Minus, u, v) => {
+            collect_sum(u, negate, terms, tick)?;
+            collect_sum(v, !negate, terms, tick)?;
+        },
+        Expr::Neg(u) => collect_sum(u, !negate, terms, tick)?,
+        _ => {
+            let mut term = Term::new();
+            term.collect(e, Coeff::Ratio(1, 1), tick)?;
+            if negate {
+                term.coeff = term.coeff.neg();
+            }
+            let mut key = term.factors.clone();
+            key.sort_by_key(|(b, _)| b.to_string());
+            match terms.iter_mut().find(|t| {
+                let mut other = t.factors.clone();
+                other.sort_by_key(|(b, _)| b.to_string());
+                other == key
+            }) {
+                Some(t) => t.coeff = t.coeff.add(term.coeff),
+                None => terms.push(term),
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Rebuilds `e` as a sum of terms in a canonical order.
+fn normalize(e: &Expr, tick: &mut dyn FnMut() -> Result<()>) -> Result<Expr> {
+    let e = match e {
+        Expr::Lit(_) | Expr::Var(_) => return Ok(e.clone()),
+        Expr::Call(name, args) => {
+            let args = args.iter().map(|u| normalize(u, tick)).collect::<Result<_>>()?;
+            return Ok(Expr::Call(name.clone(), args));
+        },
+        Expr::Binary(OpKind::Power, u, v) if !is_num(v) => {
+            return Ok(bin(OpKind::Power, normalize(u, tick)?, normalize(v, tick)?));
+        },
+        Expr::Binary(OpKind::Equals, u, v) => {
+            return Ok(bin(OpKind::Equals, normalize(u, tick)?, normalize(v, tick)?));
+        },
+        e => e,
+    };
+
+    let mut terms = Vec::new();
+    collect_sum(e, false, &mut terms, tick)?;
+    terms.retain(|t| !t.coeff.is_zero());
+    for t in terms.iter_mut() {
+        t.factors.sort_by_key(|(b, _)| b.to_string());
+    }
+    // Highest degree first, constants last: 2*x^2 + x + 1. Among terms
+    // of the same degree positive ones go first, giving `y - x`.
+    terms.sort_by(|a, b| {
+        b.degree().partial_cmp(&a.degree())
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.coeff.is_negative().cmp(&b.coeff.is_negative()))
+    });
+
+    let mut sum: Option<Expr> = None;
+    for t in terms {
+        sum = Some(match sum {
+            None => t.to_expr(),
+            Some(acc) if t.coeff.is_negative() => {
+                let positive = Term { coeff: t.coeff.neg(), factors: t.factors };
+                bin(OpKind::Minus, acc, positive.to_expr())
+            },
+            Some(acc) => bin(OpKind::Plus, acc, t.to_expr()),
+        });
+    }
+    Ok(sum.unwrap_or(lit(0)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_partial_eval() {
+        let mut env = Environment::new();
+        env.set("principal", Value::Int(2));
+        env.set("n", Value::Int(12));
+        let f = crate::function::Function::parse(vec!["x".to_string()], "x^2").unwrap();
+        env.define("f", std::sync::Arc::new(f)).unwrap();
+        let exprs = [
+            "principal * rate + 1",
+            "principal * n / 4 + rate ^ (n / 6)",
+            "sqrt(principal + 2) * x",
+            "f(3) + f(x)",
+            "sum(i * x, i, 1, n)",
+            "sum(i, i, 1, n)",
+            "[n, n + x]",
+            "diff(x^3, x)",
+            "x -> x * n",
+        ];
+        let answers = [
+            "2*rate + 1",
+            "6 + rate^2",
+            "2*x",
+            "9 + f(x)",
+            "sum(i*x, i, 1, n)",
+            "78",
+            "[12, 12 + x]",
+            "3*x^2",
+            "x -> x*n",
+        ];
+
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            let e = Expr::parse(expr).unwrap();
+            assert_eq!(ans, partial_eval(&e, &mut env).unwrap().to_string(), "{}", expr);
+        }
+        assert!(matches!(partial_eval(&Expr::parse("x + 1 / (n - 12)").unwrap(), &mut env), Err(Error::DivisionByZero)));
+    }
+
+    #[test]
+    fn test_equiv() {
+        let pairs = [
+            ("2*x + x", "3*x"),
+            ("(x + 1)^2", "x^2 + 2*x + 1"),
+            ("sin(x)^2 + cos(x)^2", "1"),
+            ("exp(x + y)", "exp(x)*exp(y)"),
+            ("x/y", "y/x"),
+            ("sqrt(x^2)", "x"),
+            ("x + 1", "x + 1.000001"),
+            ("ln(x)", "ln(x)"),
+        ];
+        let answers = [true, true, true, true, false, false, false, true];
+
+        let mut env = Environment::new();
+        for ((a, b), ans) in std::iter::zip(pairs, answers) {
+            let (a, b) = (Expr::parse(a).unwrap(), Expr::parse(b).unwrap());
+            assert_eq!(ans, equiv(&a, &b, &mut env).unwrap(), "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_simplify() {
+        let exprs = [
+            "x + x + x",
+            "3*x - x*3",
+            "x*y + 2*y*x - 1",
+            "x*x*x / x",
+            "(x + 1)*(x + 1)",
+            "x / 3 + x / 6",
+            "2*x/x",
+            "1 + x + 1",
+            "-(x - y)",
+            "x^2 / x^5",
+            "sin(x + x) - sin(2*x)",
+        ];
+        let answers = [
+            "3*x",
+            "0",
+            "3*x*y - 1",
+            "x^2",
+            "(x + 1)^2",
+            "x/2",
+            "2",
+            "x + 2",
+            "y - x",
+            "1/x^3",
+            "0",
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            let e = Expr::parse(expr).unwrap();
+            assert_eq!(ans, simplify(&e, &mut env).unwrap().to_string(), "{}", expr);
+        }
+
+        assert_eq!("1/4611686018427387904", simplify(&Expr::parse("2^-62").unwrap(), &mut env).unwrap().to_string());
+        let e = Expr::parse("x^2 * 3^9007199254740993").unwrap();
+        assert!(simplify(&e, &mut env).is_ok());
+        for expr in ["x/0", "x*0^-2", "3 + x/(2 - 2)"] {
+            let e = Expr::parse(expr).unwrap();
+            assert!(matches!(simplify(&e, &mut env), Err(Error::DivisionByZero)), "{}", expr);
+        }
+        env.set_limits(crate::env::Limits { max_steps: Some(env.steps() + 100), ..Default::default() });
+        let long = Expr::parse(&vec!["x"; 1000].join(" + ")).unwrap();
+        assert!(matches!(simplify(&long, &mut env), Err(Error::LimitExceeded)));
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::kalkul::{Error, Expr, Result, Syntax, Unknown};
+
+/// An expression with named holes, written `{{rate}} * principal`, to be
+/// filled in later with `bind`, as for formulas kept in a database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    /// The expression with each hole as a variable no expression could
+    /// name, see `hole_var`.
+    expr: Expr,
+    holes: Vec<String>,
+}
+
+/// The variable standing in for the hole `name`, spelt as the hole is
+/// so that it displays as one, and which the lexer never reads as an
+/// identifier.
+fn hole_var(name: &str) -> String {
+    format!("{{{{{}}}}}", name)
+}
+
+impl Template {
+    pub fn parse(src: &str) -> Result<Template> {
+        Template::parse_with(src, Syntax::default())
+    }
+
+    pub fn parse_with(src: &str, syntax: Syntax) -> Result<Template> {
+        // Holes are first written as identifiers starting with a prefix
+        // found nowhere in `src`, then swapped for `hole_var`s once parsed.
+        let mut prefix = "__hole".to_string();
+        while src.contains(&prefix) {
+            prefix.push('_');
+        }
+        let mut holes: Vec<String> = Vec::new();
+        let mut text = String::new();
+        let mut rest = src;
+        loop {
+            let quote = rest.find('"');
+            let Some(start) = rest.find("{{").filter(|start| quote.is_none_or(|quote| start < &quote)) else {
+                let Some(quote) = quote else { break };
+                // String literals are copied as they are, braces and all.
+                let close = rest[quote + 1..].find('"').map_or(rest.len(), |close| quote + close + 2);
+                if rest[..quote].contains("}}") {
+                    return Err(Error::UnbalancedParens);
+                }
+                text.push_str(&rest[..close]);
+                rest = &rest[close..];
+                continue;
+            };
+            if rest[..start].contains("}}") {
+                return Err(Error::UnbalancedParens);
+            }
+            let end = rest[start..].find("}}").ok_or(Error::UnbalancedParens)? + start;
+            let name = rest[start + 2..end].trim();
+            let valid = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+            if !valid {
+                return Err(Error::ParseError);
+            }
+            let i = match holes.iter().position(|h| h == name) {
+                Some(i) => i,
+                None => {
+                    holes.push(name.to_string());
+                    holes.len() - 1
+                },
+            };
+            // Spaced so that the hole is a token of its own.
+            text.push_str(&rest[..start]);
+            text.push_str(&format!(" {}{} ", prefix, i));
+            rest = &rest[end + 2..];
+        }
+        if rest.contains("}}") {
+            return Err(Error::UnbalancedParens);
+        }
+        text.push_str(rest);
+        let mut expr = Expr::parse_with(&text, syntax)?;
+        for (i, name) in holes.iter().enumerate() {
+            expr = expr.substitute(&format!("{}{}", prefix, i), Expr::Var(hole_var(name)));
+        }
+        Ok(Template { expr, holes })
+    }
+
+    /// Names of the holes, in the order they first appear.
+    pub fn holes(&self) -> &[String] {
+        &self.holes
+    }
+
+    /// The expression with every hole filled from `values`, failing with
+    /// `Error::UnknownVariable` for a hole it has nothing for.
+    pub fn bind<V: Clone + Into<Expr>>(&self, values: &HashMap<String, V>) -> Result<Expr> {
+        let mut expr = self.expr.clone();
+        for name in &self.holes {
+            let Some(val) = values.get(name) else {
+                return Err(Error::UnknownVariable(Unknown::new(name, values.keys().cloned())));
+            };
+            expr = expr.substitute(&hole_var(name), val.clone());
+        }
+        Ok(expr)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::Environment;
+    use crate::kalkul::Value;
+
+    #[test]
+    fn test_template() {
+        let template = Template::parse("{{rate}} * principal + {{ fee }} - {{rate}}").unwrap();
+        assert_eq!(["rate", "fee"], template.holes());
+        assert_eq!("{{rate}}*principal + {{fee}} - {{rate}}", template.to_string());
+
+        let values = HashMap::from([
+            ("rate".to_string(), Value::Float(0.05)),
+            ("fee".to_string(), Value::Int(10)),
+        ]);
+        let expr = template.bind(&values).unwrap();
+        assert_eq!("0.05*principal + 10 - 0.05", expr.to_string());
+        let mut env = Environment::new();
+        env.set("principal", Value::Int(1000));
+        assert_eq!(Value::Float(59.95), expr.eval(&mut env).unwrap());
+
+        let values = HashMap::from([("rate".to_string(), Expr::parse("base + 0.01").unwrap())]);
+        match template.bind(&values) {
+            Err(Error::UnknownVariable(unknown)) => assert_eq!("fee", unknown.name),
+            res => panic!("{:?}", res),
+        }
+
+        let template = Template::parse("__hole_0__ + \"{{rate}}\" + {{rate}}").unwrap();
+        assert_eq!(["rate"], template.holes());
+        let expr = template.bind(&HashMap::from([("rate".to_string(), Value::Int(5))])).unwrap();
+        assert_eq!("__hole_0__ + \"{{rate}}\" + 5", expr.to_string());
+
+        for src in ["{{rate * 2", "{{}} + 1", "{{1x}}", "rate}} + 1"] {
+            assert!(Template::parse(src).is_err(), "{}", src);
+        }
+    }
+}
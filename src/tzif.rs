@@ -0,0 +1,272 @@
+use std::path::PathBuf;
+
+use crate::clock::{days_from_civil, days_in_month};
+
+/// What a zone's clocks read during some span of time.
+#[derive(Debug, Clone, PartialEq)]
+struct LocalTime {
+    /// Seconds east of UTC.
+    offset: i32,
+    abbreviation: String,
+}
+
+/// When daylight saving time starts or ends in a year, as the `Mm.w.d/time`
+/// of a POSIX `TZ` string: day `weekday` of week `week` of `month`, the
+/// fifth week meaning the last, at `time` seconds past local midnight.
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    month: u32,
+    week: u32,
+    weekday: u32,
+    time: i64,
+}
+
+/// The rule a zone follows after its last listed transition, read from
+/// the `TZ` string at the end of the file.
+#[derive(Debug, Clone, PartialEq)]
+struct Posix {
+    std: LocalTime,
+    dst: Option<(LocalTime, Change, Change)>,
+}
+
+/// A zone read from the system's time zone database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TzData {
+    /// Instants at which the local time changes, and what it changes to.
+    transitions: Vec<(i64, usize)>,
+    types: Vec<LocalTime>,
+    rule: Option<Posix>,
+}
+
+impl TzData {
+    /// Reads the zone named `name`, such as `Europe/Paris`, from the
+    /// directory `TZDIR` names or else `/usr/share/zoneinfo`.
+    pub fn load(name: &str) -> Option<TzData> {
+        let valid = |part: &str| !part.is_empty() && !part.starts_with('.')
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || "_+-".contains(c));
+        if !name.split('/').all(valid) {
+            return None;
+        }
+        let dir = std::env::var_os("TZDIR").map_or_else(|| PathBuf::from("/usr/share/zoneinfo"), PathBuf::from);
+        TzData::parse(&std::fs::read(dir.join(name)).ok()?)
+    }
+
+    /// Reads a TZif file, using the 64-bit part of files of version 2 and up.
+    fn parse(data: &[u8]) -> Option<TzData> {
+        let mut r = Reader { data, pos: 0 };
+        let version = r.header()?;
+        let mut counts = r.counts()?;
+        let mut time_size = 4;
+        if version >= b'2' {
+            let [isut, isstd, leap, time, types, chars] = counts;
+            r.skip(time * 5 + types * 6 + chars + leap * 8 + isstd + isut)?;
+            r.header()?;
+            counts = r.counts()?;
+            time_size = 8;
+        }
+        let [isut, isstd, leap, time, types, chars] = counts;
+
+        let mut instants = Vec::with_capacity(time);
+        for _ in 0..time {
+            instants.push(if time_size == 8 { r.i64()? } else { r.i32()? as i64 });
+        }
+        let indices = r.bytes(time)?.to_vec();
+        let mut infos = Vec::with_capacity(types);
+        for _ in 0..types {
+            let offset = r.i32()?;
+            let [_, index] = <[u8; 2]>::try_from(r.bytes(2)?).ok()?;
+            infos.push((offset, index as usize));
+        }
+        let names = r.bytes(chars)?;
+        r.skip(leap * (time_size + 4) + isstd + isut)?;
+
+        let types = infos.into_iter()
+            .map(|(offset, index)| {
+                let name = names.get(index..)?.split(|b| *b == 0).next()?;
+                Some(LocalTime { offset, abbreviation: String::from_utf8_lossy(name).into_owned() })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let transitions: Vec<(i64, usize)> = instants.into_iter().zip(indices.into_iter().map(usize::from)).collect();
+        if types.is_empty() || transitions.iter().any(|(_, i)| *i >= types.len()) {
+            return None;
+        }
+        let rule = if version >= b'2' {
+            let footer = std::str::from_utf8(&data[r.pos..]).ok()?;
+            parse_posix(footer.trim())
+        } else {
+            None
+        };
+        Some(TzData { transitions, types, rule })
+    }
+
+    /// Seconds east of UTC at the instant `secs`, and the abbreviation
+    /// the zone goes by then.
+    pub fn offset(&self, secs: i64) -> (i32, &str) {
+        let local = match self.transitions.partition_point(|(at, _)| *at <= secs) {
+            0 => &self.types[0],
+            n if n == self.transitions.len() && self.rule.is_some() => {
+                return self.rule.as_ref().unwrap().offset(secs);
+            },
+            n => &self.types[self.transitions[n - 1].1],
+        };
+        (local.offset, &local.abbreviation)
+    }
+}
+
+impl Posix {
+    fn offset(&self, secs: i64) -> (i32, &str) {
+        let Some((dst, start, end)) = &self.dst else {
+            return (self.std.offset, &self.std.abbreviation);
+        };
+        let year = crate::clock::civil_from_days((secs + self.std.offset as i64).div_euclid(86400)).0;
+        let start = start.at(year) - self.std.offset as i64;
+        let end = end.at(year) - dst.offset as i64;
+        // In the southern hemisphere daylight saving time spans new year.
+        let in_dst = if start < end { start <= secs && secs < end } else { secs >= start || secs < end };
+        if in_dst { (dst.offset, &dst.abbreviation) } else { (self.std.offset, &self.std.abbreviation) }
+    }
+}
+
+impl Change {
+    /// Local seconds since 1970-01-01 at which the change happens in `year`.
+    fn at(&self, year: i64) -> i64 {
+        let first = days_from_civil(year, self.month, 1);
+        // 1970-01-01 was a Thursday, day 4 counting from Sunday.
+        let first_weekday = (first + 4).rem_euclid(7) as u32;
+        let mut day = 1 + (self.weekday + 7 - first_weekday) % 7 + (self.week - 1) * 7;
+        while day > days_in_month(year, self.month) {
+            day -= 7;
+        }
+        (first + day as i64 - 1) * 86400 + self.time
+    }
+}
+
+/// Reads a POSIX `TZ` string such as `CET-1CEST,M3.5.0,M10.5.0/3`. Only
+/// the `Mm.w.d` form of rules is understood, the one zoneinfo files use.
+fn parse_posix(s: &str) -> Option<Posix> {
+    let mut rest = s;
+    let std_name = abbreviation(&mut rest)?;
+    // POSIX offsets count hours west of UTC.
+    let std_offset = -time(&mut rest)? as i32;
+    let std = LocalTime { offset: std_offset, abbreviation: std_name };
+    if rest.is_empty() {
+        return Some(Posix { std, dst: None });
+    }
+    let dst_name = abbreviation(&mut rest)?;
+    let dst_offset = if rest.starts_with(',') { std_offset + 3600 } else { -time(&mut rest)? as i32 };
+    let mut changes = rest.strip_prefix(',')?.split(',').map(|rule| {
+        let (date, time_of_day) = rule.split_once('/').unwrap_or((rule, "2"));
+        let mut parts = date.strip_prefix('M')?.split('.').map(|n| n.parse::<u32>().ok());
+        let (month, week, weekday) = (parts.next()??, parts.next()??, parts.next()??);
+        let valid = (1..=12).contains(&month) && (1..=5).contains(&week) && weekday < 7;
+        valid.then_some(Change { month, week, weekday, time: time(&mut { time_of_day })? })
+    });
+    let (start, end) = (changes.next()??, changes.next()??);
+    Some(Posix { std, dst: Some((LocalTime { offset: dst_offset, abbreviation: dst_name }, start, end)) })
+}
+
+/// Takes a zone abbreviation, plain or quoted as in `<+0530>`, off the
+/// front of `s`.
+fn abbreviation(s: &mut &str) -> Option<String> {
+    let (name, rest) = match s.strip_prefix('<') {
+        Some(quoted) => {
+            let (name, rest) = quoted.split_once('>')?;
+            (name, rest)
+        },
+        None => s.split_at(s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(s.len())),
+    };
+    *s = rest;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Takes a time such as `-1`, `5:30` or `+2:00:00` off the front of `s`,
+/// in seconds.
+fn time(s: &mut &str) -> Option<i64> {
+    let end = s.find(|c: char| !(c.is_ascii_digit() || ":+-".contains(c))).unwrap_or(s.len());
+    let (text, rest) = s.split_at(end);
+    *s = rest;
+    let (sign, text) = match text.strip_prefix('-') {
+        Some(text) => (-1, text),
+        None => (1, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let mut secs = 0;
+    let mut scale = 3600;
+    for part in text.split(':') {
+        secs += part.parse::<i64>().ok()? * scale;
+        scale /= 60;
+    }
+    Some(sign * secs)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        Some(i32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        Some(i64::from_be_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    /// Checks the magic number and returns the version byte.
+    fn header(&mut self) -> Option<u8> {
+        let header = self.bytes(20)?;
+        (&header[..4] == b"TZif").then_some(header[4])
+    }
+
+    fn counts(&mut self) -> Option<[usize; 6]> {
+        let mut counts = [0; 6];
+        for count in &mut counts {
+            *count = usize::try_from(self.i32()?).ok()?;
+        }
+        Some(counts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_posix() {
+        let paris = parse_posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // 2024-03-31 01:00 UTC, when clocks in Paris went forward.
+        let change = days_from_civil(2024, 3, 31) * 86400 + 3600;
+        assert_eq!((3600, "CET"), paris.offset(change - 1));
+        assert_eq!((7200, "CEST"), paris.offset(change));
+        assert_eq!((3600, "CET"), paris.offset(days_from_civil(2024, 10, 27) * 86400 + 3600));
+
+        let sydney = parse_posix("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!((39600, "AEDT"), sydney.offset(days_from_civil(2024, 1, 15) * 86400));
+        assert_eq!((36000, "AEST"), sydney.offset(days_from_civil(2024, 7, 15) * 86400));
+
+        let india = parse_posix("<+0530>-5:30").unwrap();
+        assert_eq!((19800, "+0530"), india.offset(0));
+        assert!(parse_posix("EST5EDT,J60,J300").is_none());
+    }
+
+    #[test]
+    fn test_load() {
+        // Only where the system has a time zone database.
+        let Some(paris) = TzData::load("Europe/Paris") else {
+            return;
+        };
+        assert_eq!((7200, "CEST"), paris.offset(days_from_civil(2024, 7, 1) * 86400));
+        assert_eq!((3600, "CET"), paris.offset(days_from_civil(2050, 1, 1) * 86400));
+        assert!(TzData::load("../etc/passwd").is_none());
+    }
+}
@@ -0,0 +1,537 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::builtins;
+use crate::env::Environment;
+use crate::format;
+use crate::kalkul::{Error, Expr, OpKind, Result, Value};
+
+/// What a unit measures. Only quantities of the same dimension can be
+/// added, compared or converted into each other.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Dimension {
+    Mass,
+    Length,
+    Duration,
+    Temperature,
+    Storage,
+    /// What a unit declared with `define` as a base unit measures, by the
+    /// name of that unit.
+    Custom(Arc<str>),
+}
+
+impl Dimension {
+    /// The unit the dimension is written in by `Dims`.
+    pub fn base(&self) -> String {
+        match self {
+            Dimension::Mass        => "kg".to_string(),
+            Dimension::Length      => "m".to_string(),
+            Dimension::Duration    => "s".to_string(),
+            Dimension::Temperature => "K".to_string(),
+            Dimension::Storage     => "B".to_string(),
+            Dimension::Custom(name) => name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub name: String,
+    pub dim: Dimension,
+    /// How many of the dimension's base unit, such as bytes, one of
+    /// this unit is.
+    pub factor: f64,
+    /// The amount of this unit at the freezing point of water, for
+    /// temperatures, and zero for everything else. Converting from that
+    /// point rather than from absolute zero keeps `100 C in F` exact.
+    pub offset: f64,
+    /// Whether the unit is a temperature scale that does not start at
+    /// absolute zero, whose amounts can be converted but not added to
+    /// each other or multiplied.
+    pub absolute: bool,
+}
+
+/// The freezing point of water in kelvin.
+const FREEZING_POINT: f64 = 273.15;
+
+const UNITS: &[(&str, Dimension, f64)] = &[
+    ("B",   Dimension::Storage, 1.0),
+    ("kB",  Dimension::Storage, 1e3),
+    ("KB",  Dimension::Storage, 1e3),
+    ("MB",  Dimension::Storage, 1e6),
+    ("GB",  Dimension::Storage, 1e9),
+    ("TB",  Dimension::Storage, 1e12),
+    ("PB",  Dimension::Storage, 1e15),
+    ("KiB", Dimension::Storage, 1024.0),
+    ("MiB", Dimension::Storage, 1048576.0),
+    ("GiB", Dimension::Storage, 1073741824.0),
+    ("TiB", Dimension::Storage, 1099511627776.0),
+    ("PiB", Dimension::Storage, 1125899906842624.0),
+    ("mg",  Dimension::Mass, 1e-6),
+    ("g",   Dimension::Mass, 1e-3),
+    ("kg",  Dimension::Mass, 1.0),
+    ("mm",  Dimension::Length, 1e-3),
+    ("cm",  Dimension::Length, 1e-2),
+    ("m",   Dimension::Length, 1.0),
+    ("km",  Dimension::Length, 1e3),
+    ("ms",  Dimension::Duration, 0.001),
+    ("s",   Dimension::Duration, 1.0),
+    ("min", Dimension::Duration, 60.0),
+    ("h",   Dimension::Duration, 3600.0),
+    ("d",   Dimension::Duration, 86400.0),
+];
+
+/// Temperature scales, with their size in kelvin, their reading at the
+/// freezing point of water and whether they are absolute, see
+/// `Unit::absolute`.
+const SCALES: &[(&str, f64, f64, bool)] = &[
+    ("K",    1.0,       FREEZING_POINT, false),
+    ("C",    1.0,       0.0,            true),
+    ("degC", 1.0,       0.0,            true),
+    ("F",    5.0 / 9.0, 32.0,           true),
+    ("degF", 5.0 / 9.0, 32.0,           true),
+];
+
+/// The built-in unit named `name`. Those declared at runtime are kept
+/// by each environment, see `Environment::unit`.
+pub fn lookup(name: &str) -> Option<Unit> {
+    UNITS.iter()
+        .find(|(unit, _, _)| *unit == name)
+        .map(|(name, dim, factor)| Unit { name: name.to_string(), dim: dim.clone(), factor: *factor, offset: 0.0, absolute: false })
+        .or_else(|| SCALES.iter()
+            .find(|(scale, _, _, _)| *scale == name)
+            .map(|&(name, factor, offset, absolute)| Unit { name: name.to_string(), dim: Dimension::Temperature, factor, offset, absolute }))
+}
+
+/// Adds the unit `name` to those known in `env`, as `size` of another
+/// unit, or as the base unit of a dimension of its own for `None`. So
+/// after `unit widget` and `unit widgetbox = 24 widget` in the REPL,
+/// `2 widgetbox in widget` is `48 widget`. Declaring a unit again
+/// replaces it, but built-in units cannot be redefined.
+pub fn define(env: &mut Environment, name: &str, size: Option<&Quantity>) -> Result<Unit> {
+    let mut cs = name.chars();
+    if !cs.next().is_some_and(char::is_alphabetic) || !cs.all(char::is_alphanumeric) {
+        return Err(Error::ParseError);
+    }
+    if lookup(name).is_some() {
+        return Err(Error::Usage);
+    }
+    let unit = match size {
+        Some(q) => {
+            let factor = q.amount * q.unit.factor;
+            if q.unit.absolute || factor == 0.0 || !factor.is_finite() {
+                return Err(Error::TypeError);
+            }
+            // Temperatures declared this way are differences, like kelvin.
+            let offset = if q.unit.dim == Dimension::Temperature { FREEZING_POINT / factor } else { 0.0 };
+            Unit { name: name.to_string(), dim: q.unit.dim.clone(), factor, offset, absolute: false }
+        },
+        None => Unit { name: name.to_string(), dim: Dimension::Custom(name.into()), factor: 1.0, offset: 0.0, absolute: false },
+    };
+    env.set_unit(unit.clone());
+    Ok(unit)
+}
+
+/// An amount of some unit, such as `1.5 GiB`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    pub amount: f64,
+    pub unit: Unit,
+}
+
+impl Quantity {
+    /// The same quantity in another unit of its dimension, so
+    /// `1 GiB in MB` is `1073.741824 MB` and `32 F in C` is `0 C`.
+    pub fn to(&self, unit: Unit) -> Result<Quantity> {
+        if unit.dim != self.unit.dim {
+            return Err(Error::TypeError);
+        }
+        let amount = (self.amount - self.unit.offset) * self.unit.factor / unit.factor + unit.offset;
+        Ok(Quantity { amount, unit })
+    }
+
+    fn scaled(&self, by: f64) -> Result<Quantity> {
+        if self.unit.absolute {
+            return Err(Error::TypeError);
+        }
+        Ok(Quantity { amount: self.amount * by, unit: self.unit.clone() })
+    }
+
+    /// `self` moved by `by`, a difference of the same dimension, which for
+    /// temperatures cannot be on a scale of its own: `20 C + 5 K` is
+    /// `25 C` but `20 C + 5 C` is an error.
+    fn shifted(&self, by: &Quantity, sign: f64) -> Result<Quantity> {
+        if by.unit.absolute || by.unit.dim != self.unit.dim {
+            return Err(Error::TypeError);
+        }
+        let amount = self.amount + sign * by.amount * by.unit.factor / self.unit.factor;
+        Ok(Quantity { amount, unit: self.unit.clone() })
+    }
+}
+
+impl std::fmt::Display for Quantity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", format::shortest(self.amount), self.unit.name)
+    }
+}
+
+/// Converts a value to the unit named `name` in `env`, for `in`.
+pub fn convert(val: &Value, name: &str, env: &Environment) -> Result<Value> {
+    let unit = env.unit(name).ok_or_else(|| Error::UnknownUnit(name.to_string()))?;
+    match val {
+        Value::Quantity(q) => Ok(Value::Quantity(q.to(unit)?)),
+        _ => Err(Error::TypeError),
+    }
+}
+
+/// Applies an operator to operands of which at least one is a quantity,
+/// or returns `None` if neither is. Sums and differences come out in the
+/// unit on the left, and dividing two quantities gives a plain number.
+/// The difference of two temperatures is in kelvin.
+pub fn apply(kind: OpKind, lhs: &Value, rhs: &Value) -> Option<Result<Value>> {
+    let res = match (kind, lhs, rhs) {
+        (OpKind::Plus | OpKind::Minus, Value::Quantity(a), Value::Quantity(b)) => {
+            let sign = if kind == OpKind::Plus { 1.0 } else { -1.0 };
+            match (a.unit.absolute, b.unit.absolute) {
+                (false, false) => b.to(a.unit.clone()).map(|b| {
+                    Value::Quantity(Quantity { amount: a.amount + sign * b.amount, unit: a.unit.clone() })
+                }),
+                (true, false) => a.shifted(b, sign).map(Value::Quantity),
+                (false, true) if kind == OpKind::Plus => b.shifted(a, sign).map(Value::Quantity),
+                (false, true) => Err(Error::TypeError),
+                (true, true) if kind == OpKind::Plus => Err(Error::TypeError),
+                (true, true) => lookup("K").ok_or(Error::TypeError).and_then(|k| {
+                    let (a, b) = (a.to(k.clone())?, b.to(k)?);
+                    Ok(Value::Quantity(Quantity { amount: a.amount - b.amount, unit: a.unit }))
+                }),
+            }
+        },
+        (OpKind::Multiply, Value::Quantity(q), n) | (OpKind::Multiply, n, Value::Quantity(q)) => {
+            n.as_f64().and_then(|n| q.scaled(n)).map(Value::Quantity)
+        },
+        (OpKind::Divide, Value::Quantity(q), Value::Int(_) | Value::Float(_)) => {
+            rhs.as_f64().and_then(|n| q.scaled(1.0 / n)).map(Value::Quantity)
+        },
+        (OpKind::Divide, Value::Quantity(a), Value::Quantity(b)) if !a.unit.absolute => {
+            b.to(a.unit.clone()).map(|b| Value::Float(a.amount / b.amount))
+        },
+        (OpKind::Less | OpKind::LessEqual | OpKind::Greater | OpKind::GreaterEqual | OpKind::Equal | OpKind::NotEqual,
+         Value::Quantity(a), Value::Quantity(b)) => {
+            b.to(a.unit.clone()).and_then(|b| Value::apply(kind, Value::Float(a.amount), Value::Float(b.amount)))
+        },
+        (_, Value::Quantity(_), _) | (_, _, Value::Quantity(_)) => Err(Error::TypeError),
+        _ => return None,
+    };
+    Some(res)
+}
+
+/// The power each dimension is raised to in a quantity, such as
+/// `kg·m/s²` for a force. Plain numbers have none.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dims(BTreeMap<Dimension, i32>);
+
+impl Dims {
+    pub fn none() -> Self {
+        Dims::default()
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The power `dim` is raised to.
+    pub fn power(&self, dim: &Dimension) -> i32 {
+        self.0.get(dim).copied().unwrap_or(0)
+    }
+
+    /// Dimensions with the powers `f` makes of those of both, leaving
+    /// out the ones that come to zero.
+    fn combine(self, other: Dims, f: impl Fn(i32, i32) -> i32) -> Dims {
+        let dims = self.0.keys().chain(other.0.keys())
+            .map(|dim| (dim.clone(), f(self.power(dim), other.power(dim))))
+            .filter(|(_, p)| *p != 0)
+            .collect();
+        Dims(dims)
+    }
+
+    fn pow(self, n: i32) -> Dims {
+        match n {
+            0 => Dims::none(),
+            n => Dims(self.0.into_iter().map(|(dim, p)| (dim, p * n)).collect()),
+        }
+    }
+
+    /// The dimensions whose `n`th power these are, if there are any.
+    fn root(self, n: i32) -> Option<Dims> {
+        self.0.values().all(|p| p % n == 0).then(|| Dims(self.0.into_iter().map(|(dim, p)| (dim, p / n)).collect()))
+    }
+
+    /// `self`, if `other` is the same, for adding or comparing the two.
+    fn same(self, other: Dims) -> Result<Dims> {
+        match self == other {
+            true  => Ok(self),
+            false => Err(Error::DimensionMismatch(self.to_string(), other.to_string())),
+        }
+    }
+}
+
+impl From<Dimension> for Dims {
+    fn from(dim: Dimension) -> Self {
+        Dims(BTreeMap::from([(dim, 1)]))
+    }
+}
+
+impl std::fmt::Display for Dims {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let term = |dim: &Dimension, p: i32| {
+            let mut term = dim.base();
+            if p.abs() != 1 {
+                term.extend(p.abs().to_string().chars().map(superscript));
+            }
+            term
+        };
+        let terms = |positive: bool| self.0.iter()
+            .filter(|(_, p)| (**p > 0) == positive)
+            .map(|(dim, p)| term(dim, *p))
+            .collect::<Vec<String>>();
+        let (num, den) = (terms(true), terms(false));
+        let num = if num.is_empty() { "1".to_string() } else { num.join("·") };
+        match den.len() {
+            0 => write!(f, "{}", num),
+            1 => write!(f, "{}/{}", num, den[0]),
+            _ => write!(f, "{}/({})", num, den.join("·")),
+        }
+    }
+}
+
+fn superscript(digit: char) -> char {
+    "⁰¹²³⁴⁵⁶⁷⁸⁹".chars().nth(digit.to_digit(10).unwrap_or(0) as usize).unwrap_or(digit)
+}
+
+/// The dimensions of what `e` evaluates to, worked out from the units of
+/// its quantities without doing the arithmetic, so that `dims(5 kg *
+/// 3 m / (2 s)^2)` is `kg·m/s²`. Calls other than `sqrt` and `abs` are
+/// evaluated, as are exponents, which must be integers when raising a
+/// quantity.
+pub fn dims(e: &Expr, env: &mut Environment) -> Result<Dims> {
+    match e {
+        Expr::Lit(val) => value_dims(val),
+        Expr::Var(name) => value_dims(&env.read(name)?),
+        Expr::Neg(u) => dims(u, env),
+        Expr::Binary(OpKind::In, u, unit) => {
+            let name = match &**unit {
+                Expr::Var(name) | Expr::Lit(Value::Str(name)) => name,
+                _ => return Err(Error::TypeError),
+            };
+            let unit = env.unit(name).ok_or_else(|| Error::UnknownUnit(name.to_string()))?;
+            dims(u, env)?.same(unit.dim.into())
+        },
+        Expr::Binary(OpKind::Power, u, v) => {
+            let (base, exp) = (dims(u, env)?, dims(v, env)?);
+            exp.same(Dims::none())?;
+            if base.is_none() {
+                return Ok(base);
+            }
+            let n = v.eval(env)?.as_int()?;
+            Ok(base.pow(i32::try_from(n).map_err(|_| Error::Overflow)?))
+        },
+        Expr::Binary(kind, u, v) => {
+            let (a, b) = (dims(u, env)?, dims(v, env)?);
+            match kind {
+                OpKind::Multiply => Ok(a.combine(b, |p, q| p + q)),
+                OpKind::Divide | OpKind::FloorDivide => Ok(a.combine(b, |p, q| p - q)),
+                OpKind::Plus | OpKind::Minus | OpKind::Modulo => a.same(b),
+                OpKind::Less | OpKind::LessEqual | OpKind::Greater | OpKind::GreaterEqual |
+                OpKind::Equal | OpKind::NotEqual => a.same(b).map(|_| Dims::none()),
+                _ => a.same(Dims::none())?.same(b),
+            }
+        },
+        Expr::Call(name, args) if args.len() == 1 && builtins::lookup_form(name).is_none() => match name.as_str() {
+            "sqrt" => dims(&args[0], env)?.root(2).ok_or(Error::TypeError),
+            "abs"  => dims(&args[0], env),
+            _ => value_dims(&e.eval(env)?),
+        },
+        Expr::Call(..) => value_dims(&e.eval(env)?),
+        Expr::List(items) | Expr::Tuple(items) => {
+            let mut res = None;
+            for item in items {
+                let d = dims(item, env)?;
+                res = Some(match res {
+                    Some(res) => d.same(res)?,
+                    None => d,
+                });
+            }
+            Ok(res.unwrap_or_default())
+        },
+    }
+}
+
+fn value_dims(val: &Value) -> Result<Dims> {
+    match val {
+        Value::Int(_) | Value::Float(_) => Ok(Dims::none()),
+        Value::Quantity(q) => Ok(q.unit.dim.clone().into()),
+        Value::List(vs) | Value::Tuple(vs) => match vs.split_first() {
+            Some((first, rest)) => rest.iter().try_fold(value_dims(first)?, |d, v| d.same(value_dims(v)?)),
+            None => Ok(Dims::none()),
+        },
+        _ => Err(Error::TypeError),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Dimension, Quantity};
+    use crate::env::Environment;
+    use crate::kalkul::{Error, Value};
+
+    #[test]
+    fn test_storage() {
+        let exprs = [
+            "1.5 GiB + 300 MiB",
+            "1 GiB in MB",
+            "1.5GB in GiB",
+            "(1 GiB + 1 GB) in MiB",
+            "2 * 512 KiB",
+            "1 TB / 4",
+            "1 GiB / 1 MiB",
+            "1 GB < 1 GiB",
+            "-(1 kB) in B",
+        ];
+        let answers = [
+            "1.79296875 GiB",
+            "1073.741824 MB",
+            "1.3969838619232178 GiB",
+            "1977.67431640625 MiB",
+            "1024 KiB",
+            "0.25 TB",
+            "1024",
+            "1",
+            "-1000 B",
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        for expr in ["1 GB + 1", "1 GB in h", "2 in MB", "sqrt(4 GB)", "1 GB * 1 GB"] {
+            assert!(env.eval(expr).is_err(), "{}", expr);
+        }
+        assert!(matches!(env.eval("1 GB in parsecs"), Err(Error::UnknownUnit(_))));
+    }
+
+    #[test]
+    fn test_dims() {
+        let exprs = [
+            "dims(5 kg * 3 m / (2 s)^2)",
+            "dims(2 km + 300 m)",
+            "dims(1 GB / 1 s)",
+            "dims(sqrt(4 m * 9 m))",
+            "dims(1 / (1 kg * 1 s^2))",
+            "dims(3 * 4)",
+            "dims(distance / 2 h)",
+            "dims(1 m < 2 km)",
+        ];
+        let answers = [
+            "kg·m/s²",
+            "m",
+            "B/s",
+            "m",
+            "1/(kg·s²)",
+            "1",
+            "m/s",
+            "1",
+        ];
+
+        let mut env = Environment::new();
+        env.set("distance", Value::Quantity(Quantity { amount: 5.0, unit: super::lookup("km").unwrap() }));
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(Value::Str(ans.to_string()), env.eval(expr).unwrap(), "{}", expr);
+        }
+        assert_eq!("mismatched dimensions kg and s", env.eval("dims(1 kg + 2 s)").unwrap_err().to_string());
+        assert!(env.eval("dims(sqrt(2 s))").is_err());
+        assert!(env.eval("dims(2 m ^ 0.5)").is_err());
+    }
+
+    #[test]
+    fn test_temperature() {
+        let exprs = [
+            "32 F in C",
+            "100 C in F",
+            "0 K in C",
+            "-40 C in F",
+            "20 C + 5 K",
+            "5 K + 20 C",
+            "68 F - 10 K",
+            "30 C - 10 C",
+            "212 F - 100 C",
+            "300 K * 2",
+            "20 C < 70 F",
+            "dims(30 C - 10 C)",
+        ];
+        let answers = [
+            "0 C",
+            "212 F",
+            "-273.15 C",
+            "-40 F",
+            "25 C",
+            "25 C",
+            "50 F",
+            "20 K",
+            "0 K",
+            "600 K",
+            "1",
+            "K",
+        ];
+
+        let mut env = Environment::new();
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        for expr in ["20 C + 30 C", "2 * 20 C", "20 C / 2", "10 K - 5 C", "20 C / 10 C", "20 C + 1 m", "1 C in s"] {
+            assert!(env.eval(expr).is_err(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_define() {
+        let mut env = Environment::new();
+        let furlong = super::define(&mut env, "furlong", Some(&Quantity { amount: 201.168, unit: super::lookup("m").unwrap() })).unwrap();
+        assert_eq!(Dimension::Length, furlong.dim);
+        let sheep = super::define(&mut env, "sheep", None).unwrap();
+        super::define(&mut env, "flock", Some(&Quantity { amount: 40.0, unit: sheep })).unwrap();
+
+        let exprs = [
+            "8 furlong in km",
+            "1 km < 5 furlong",
+            "3 flock + 10 sheep",
+            "dims(1 flock / 1 furlong)",
+        ];
+        let answers = [
+            "1.609344 km",
+            "1",
+            "3.25 flock",
+            "sheep/m",
+        ];
+
+        for (expr, ans) in std::iter::zip(exprs, answers) {
+            assert_eq!(ans, env.eval(expr).unwrap().to_string(), "{}", expr);
+        }
+        assert!(env.eval("1 flock + 1 furlong").is_err());
+        assert!(matches!(super::define(&mut env, "2x", None), Err(Error::ParseError)));
+        assert!(matches!(super::define(&mut env, "GiB", None), Err(Error::Usage)));
+        assert!(super::define(&mut env, "freeze", Some(&Quantity { amount: 0.0, unit: super::lookup("C").unwrap() })).is_err());
+
+        // Units belong to the environment they were declared in.
+        let snapshot = env.snapshot();
+        let flock = env.unit("flock").unwrap();
+        super::define(&mut env, "herd", Some(&Quantity { amount: 10.0, unit: flock })).unwrap();
+        assert_eq!("400 sheep", env.eval("1 herd in sheep").unwrap().to_string());
+        env.restore(snapshot);
+        assert!(env.eval("1 herd in sheep").is_err());
+        assert!(Environment::new().eval("1 furlong in m").is_err());
+        // Declaring a base unit again gives the same dimension.
+        super::define(&mut env, "sheep", None).unwrap();
+        assert_eq!("1.25 flock", env.eval("1 flock + 10 sheep").unwrap().to_string());
+    }
+}